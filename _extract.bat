@@ -1 +1 @@
-"%~dp0gm_modding_tool.exe" -a -s -t
+"%~dp0gm_modding_tool.exe" -a -s -t -c
@@ -1,2 +1,2 @@
-"%~dp0gm_modding_tool.exe" -S -T
+"%~dp0gm_modding_tool.exe" -S -T -C -R
 "%~dp0gm_modding_tool.exe" -A
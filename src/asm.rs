@@ -0,0 +1,335 @@
+//! Encoding of the disassembly text format back into GameMaker VM bytecode
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::disasm::{instance_from_name, Comparison, DataType, Instruction, Opcode, Operand, Variable, VariableKind};
+use crate::file_structs::{CodeLayout, FormFile, MissingSection, Reference, Strg};
+
+/// Why a line of disassembly couldn't be assembled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// Line number, starting from 1, or 0 if the error isn't about the text
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+impl From<MissingSection> for AsmError {
+    fn from(err: MissingSection) -> Self {
+        AsmError { line: 0, message: err.to_string() }
+    }
+}
+
+/// Bytecode assembled from text, along with what its variable and function references
+/// refer to, for `FormFile::replace_bytecode` to link up
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub bytecode: Vec<u8>,
+    /// Offset of each instruction referring to a variable or function, and what it refers to
+    pub references: Vec<(u32, Reference)>,
+}
+
+/// Part of an instruction that can only be filled in once every line is parsed
+enum Pending {
+    Label(usize),
+    /// Text of a string, with the STRG index the disassembly listed it at
+    String(String, Option<u32>),
+    Reference(Reference),
+}
+
+const KINDS: &[VariableKind] = &[
+    VariableKind::Array,
+    VariableKind::StackTop,
+    VariableKind::Normal,
+    VariableKind::Instance,
+    VariableKind::ArrayPushAf,
+    VariableKind::ArrayPopAf,
+];
+
+/// Assemble the output of `disasm::disassemble`, adding any strings the bytecode pushes that
+/// aren't in `strg` yet
+pub fn assemble(text: &str, layout: CodeLayout, strg: &mut Strg) -> Result<Assembly, AsmError> {
+    let mut labels = HashMap::new();
+    let mut parsed = Vec::new();
+    let mut address = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: String| AsmError { line: line_number, message };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(label) = line.strip_prefix(":[").and_then(|label| label.strip_suffix(']')) {
+            let label = label.parse::<usize>()
+                .map_err(|_| error(format!("invalid label '{}'", line)))?;
+            labels.insert(label, address);
+            continue;
+        }
+
+        let (instruction, pending) = parse_instruction(line, layout, address).map_err(error)?;
+        address += instruction.size();
+        parsed.push((line_number, instruction, pending));
+    }
+
+    // resolve labels first, so nothing is added to STRG if there's an error
+    for (line, instruction, pending) in &mut parsed {
+        if let Some(Pending::Label(label)) = pending {
+            let target = labels.get(label).ok_or_else(|| AsmError {
+                line: *line,
+                message: format!("no label [{}]", label),
+            })?;
+            instruction.operand = Operand::Branch(*target as i32 - instruction.address as i32);
+        }
+    }
+
+    let mut assembly = Assembly::default();
+    for (_, mut instruction, pending) in parsed {
+        match pending {
+            Some(Pending::String(string, index)) => {
+                let index = index
+                    .filter(|&index| strg.strings.get(index as usize) == Some(&string))
+                    .unwrap_or_else(|| {
+                        let loc = strg.intern(&string);
+                        strg.index_of(loc).unwrap() as u32
+                    });
+                instruction.operand = Operand::String(index);
+            }
+            Some(Pending::Reference(reference)) => {
+                assembly.references.push((instruction.address, reference));
+            }
+            _ => {}
+        }
+        assembly.bytecode.extend_from_slice(&encode(&instruction, layout));
+    }
+
+    Ok(assembly)
+}
+
+/// Assemble `text` over the bytecode of the CODE entry at `index`
+pub fn assemble_into(file: &mut FormFile, index: usize, text: &str) -> Result<(), AsmError> {
+    if file.code.is_none() {
+        return Err(MissingSection("CODE").into());
+    }
+    let assembly = assemble(text, file.bytecode_layout(), file.strg.as_mut().ok_or(MissingSection("STRG"))?)?;
+    file.replace_bytecode(index, &assembly.bytecode, &assembly.references)?;
+    Ok(())
+}
+
+/// Encode a single instruction, the inverse of `disasm::instructions`
+pub fn encode(instruction: &Instruction, layout: CodeLayout) -> Vec<u8> {
+    let Instruction { opcode, type1, type2, operand, .. } = *instruction;
+    let comparison = match operand {
+        Operand::Comparison(comparison) => Some(comparison),
+        _ => None,
+    };
+    let opcode = (opcode.to_byte(layout, comparison) as u32) << 24;
+    let types = ((type1.nibble() | (type2.nibble() << 4)) as u32) << 16;
+
+    let mut low = 0;
+    let mut extra = Vec::new();
+    match operand {
+        Operand::None => {}
+        Operand::Int16(val) => low = val as u16,
+        Operand::Int32(val) => extra.push(val as u32),
+        Operand::Int64(val) => extra.extend_from_slice(&[val as u32, (val as u64 >> 32) as u32]),
+        Operand::Float(val) => extra.push(val.to_bits()),
+        Operand::Double(val) => {
+            let bits = val.to_bits();
+            extra.extend_from_slice(&[bits as u32, (bits >> 32) as u32]);
+        }
+        Operand::Bool(val) => extra.push(val as u32),
+        Operand::String(index) => extra.push(index),
+        Operand::Variable(Variable { instance, kind, reference }) => {
+            low = instance as u16;
+            extra.push(((kind.bits() as u32) << 24) | (reference & 0x07ff_ffff));
+        }
+        Operand::Call { argument_count, reference } => {
+            low = argument_count;
+            extra.push(reference & 0x07ff_ffff);
+        }
+        Operand::Branch(offset) => {
+            let mask = if layout == CodeLayout::Inline { 0xff_ffff } else { 0x7f_ffff };
+            return (opcode | ((offset / 4) as u32 & mask)).to_le_bytes().to_vec();
+        }
+        Operand::PopEnvExit => return (opcode | 0xf0_0000).to_le_bytes().to_vec(),
+        // GMS1 encodes the comparison in the opcode
+        Operand::Comparison(comparison) if layout != CodeLayout::Inline => low = (comparison as u16) << 8,
+        Operand::Comparison(_) => {}
+        Operand::Raw(val) => low = val,
+        Operand::Break { kind, value } => {
+            low = kind as u16;
+            extra.push(value as u32);
+        }
+    }
+
+    let mut bytes = (opcode | types | low as u32).to_le_bytes().to_vec();
+    for word in extra {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+fn parse_instruction(line: &str, layout: CodeLayout, address: u32) -> Result<(Instruction, Option<Pending>), String> {
+    let (head, text) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let text = text.trim();
+
+    let mut parts = head.split('.');
+    let mnemonic = parts.next().unwrap_or_default();
+    let opcode = Opcode::from_mnemonic(mnemonic)
+        .ok_or_else(|| format!("unknown instruction '{}'", mnemonic))?;
+    let mut data_type = || match parts.next() {
+        Some(suffix) => DataType::from_suffix(suffix).ok_or_else(|| format!("unknown type '{}'", suffix)),
+        None => Ok(DataType::Double),
+    };
+    let type1 = data_type()?;
+    let type2 = data_type()?;
+
+    let (operand, pending) = match opcode {
+        Opcode::Cmp => match Comparison::from_name(text) {
+            Some(comparison) => (Operand::Comparison(comparison), None),
+            None => (raw(text)?, None),
+        },
+        Opcode::PopEnv if text == "<drop>" && layout != CodeLayout::Inline => (Operand::PopEnvExit, None),
+        Opcode::B | Opcode::Bt | Opcode::Bf | Opcode::PushEnv | Opcode::PopEnv => {
+            let label = text.strip_prefix('[')
+                .and_then(|label| label.strip_suffix(']'))
+                .and_then(|label| label.parse().ok())
+                .ok_or_else(|| format!("expected a label like [0], found '{}'", text))?;
+            (Operand::Branch(0), Some(Pending::Label(label)))
+        }
+        Opcode::Pop if type1 == DataType::Int16 => (raw(text)?, None),
+        Opcode::Pop => variable(text)?,
+        Opcode::Push | Opcode::PushLoc | Opcode::PushGlb | Opcode::PushBltn | Opcode::PushI => {
+            let number = |what| format!("expected {}, found '{}'", what, text);
+            match type1 {
+                DataType::Int16 => (Operand::Int16(text.parse().map_err(|_| number("a 16-bit integer"))?), None),
+                DataType::Variable => variable(text)?,
+                DataType::Int32 => (Operand::Int32(text.parse().map_err(|_| number("an integer"))?), None),
+                DataType::Float => (Operand::Float(text.parse().map_err(|_| number("a number"))?), None),
+                DataType::Boolean => (Operand::Bool(text.parse().map_err(|_| number("true or false"))?), None),
+                DataType::String => {
+                    let (string, index) = string(text)?;
+                    (Operand::String(0), Some(Pending::String(string, index)))
+                }
+                DataType::Int64 => (Operand::Int64(text.parse().map_err(|_| number("an integer"))?), None),
+                DataType::Double => (Operand::Double(text.parse().map_err(|_| number("a number"))?), None),
+                _ => (raw(text)?, None),
+            }
+        }
+        Opcode::Call => {
+            let (name, argument_count) = text.strip_suffix(')')
+                .and_then(|call| call.rsplit_once("(argc="))
+                .and_then(|(name, count)| Some((name, count.parse().ok()?)))
+                .ok_or_else(|| format!("expected a call like name(argc=0), found '{}'", text))?;
+            let name = resolved(name)?;
+            (
+                Operand::Call { argument_count, reference: 0 },
+                Some(Pending::Reference(Reference::Function(name.to_owned()))),
+            )
+        }
+        Opcode::Break if type1 == DataType::Int32 => {
+            let (kind, value) = text.split_once(' ')
+                .and_then(|(kind, value)| Some((kind.parse().ok()?, value.trim().parse().ok()?)))
+                .ok_or_else(|| format!("expected a kind and value, found '{}'", text))?;
+            (Operand::Break { kind, value }, None)
+        }
+        Opcode::Break => (Operand::Int16(text.parse().map_err(|_| format!("expected an integer, found '{}'", text))?), None),
+        _ => (raw(text)?, None),
+    };
+
+    Ok((Instruction { address, opcode, type1, type2, operand }, pending))
+}
+
+fn raw(text: &str) -> Result<Operand, String> {
+    if text.is_empty() {
+        return Ok(Operand::Raw(0));
+    }
+    text.parse()
+        .map(Operand::Raw)
+        .map_err(|_| format!("unexpected operand '{}'", text))
+}
+
+/// Names the disassembler couldn't resolve are listed as `?` followed by the raw reference
+fn resolved(name: &str) -> Result<&str, String> {
+    match name {
+        "" => Err(String::from("missing name")),
+        name if name.starts_with('?') => Err(format!("unresolved reference '{}'", name)),
+        name => Ok(name),
+    }
+}
+
+/// Parse `[kind]instance.name`
+fn variable(text: &str) -> Result<(Operand, Option<Pending>), String> {
+    let (kind, rest) = match text.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((kind, rest)) => {
+            let prefix = format!("[{}]", kind);
+            let kind = KINDS.iter()
+                .copied()
+                .find(|known| known.prefix() == prefix)
+                .or_else(|| {
+                    let bits = u8::from_str_radix(kind.strip_prefix("0x")?, 16).ok()?;
+                    Some(VariableKind::from_bits(bits))
+                })
+                .ok_or_else(|| format!("unknown variable kind '{}'", prefix))?;
+            (kind, rest)
+        }
+        None => (VariableKind::Normal, text),
+    };
+
+    let (instance, name) = rest.split_once('.')
+        .ok_or_else(|| format!("expected a variable like self.x, found '{}'", text))?;
+    let instance = instance_from_name(instance)
+        .ok_or_else(|| format!("unknown instance '{}'", instance))?;
+    let name = resolved(name)?;
+
+    Ok((
+        Operand::Variable(Variable { instance, kind, reference: 0 }),
+        Some(Pending::Reference(Reference::Variable { name: name.to_owned(), instance })),
+    ))
+}
+
+/// Parse a string literal written by `disasm::escape`, optionally followed by `@index`
+fn string(text: &str) -> Result<(String, Option<u32>), String> {
+    let mut chars = text.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        return Err(format!("expected a string, found '{}'", text));
+    }
+
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = &text[i + 1..];
+                let index = match rest.strip_prefix('@') {
+                    Some(index) => Some(index.parse().map_err(|_| format!("invalid string index '{}'", index))?),
+                    None if rest.is_empty() => None,
+                    None => return Err(format!("unexpected '{}' after string", rest)),
+                };
+                return Ok((string, index));
+            }
+            '\\' => string.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some(c @ '"') | Some(c @ '\\') => c,
+                Some(c) => return Err(format!("unknown escape '\\{}'", c)),
+                None => break,
+            }),
+            c => string.push(c),
+        }
+    }
+
+    Err(String::from("unterminated string"))
+}
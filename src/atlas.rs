@@ -0,0 +1,286 @@
+//! Putting new sprite frames on the texture pages, making room for them where they don't fit
+
+use std::collections::BTreeMap;
+
+use image::{imageops, GenericImageView, RgbaImage};
+use lazy_init::Lazy;
+use rayon::prelude::*;
+
+use crate::file_structs::{FormFile, Rect16, SpriteId, TxtrEntry};
+
+/// Space left to the right of and below each frame on a rebuilt page, so frames drawn with
+/// filtering don't pick up their neighbours
+const PADDING: u32 = 2;
+
+/// Places rectangles on a page, each as low as it will go along the top edge of the ones
+/// placed so far
+#[derive(Debug, Clone)]
+pub struct Packer {
+    size: (u32, u32),
+    /// `(x, y, width)` of each step in the top edge of what's been placed, left to right
+    skyline: Vec<(u32, u32, u32)>,
+}
+
+impl Packer {
+    pub fn new(size: (u32, u32)) -> Self {
+        Packer { size, skyline: vec![(0, 0, size.0)] }
+    }
+
+    /// Find room for a rectangle of `(width, height)`, returning its top left corner, or
+    /// `None` if the page is too full
+    pub fn insert(&mut self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        let mut best: Option<(usize, u32, u32)> = None;
+        for (i, &(x, _, _)) in self.skyline.iter().enumerate() {
+            if x + width > self.size.0 {
+                break
+            }
+            let y = self.height_under(i, width);
+            if y + height <= self.size.1 && best.is_none_or(|(_, _, best_y)| y < best_y) {
+                best = Some((i, x, y));
+            }
+        }
+
+        let (i, x, y) = best?;
+        self.raise(i, x, y + height, width);
+        Some((x, y))
+    }
+
+    /// Height of the highest step under `width` pixels starting at step `i`
+    fn height_under(&self, i: usize, width: u32) -> u32 {
+        let end = self.skyline[i].0 + width;
+        self.skyline[i..].iter()
+            .take_while(|&&(x, _, _)| x < end)
+            .map(|&(_, y, _)| y)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Replace the steps under `width` pixels from `x` with one at `y`
+    fn raise(&mut self, i: usize, x: u32, y: u32, width: u32) {
+        let end = x + width;
+        self.skyline.insert(i, (x, y, width));
+        while let Some(&(step_x, step_y, step_width)) = self.skyline.get(i + 1) {
+            if step_x >= end {
+                break
+            }
+            if step_x + step_width <= end {
+                self.skyline.remove(i + 1);
+            } else {
+                self.skyline[i + 1] = (end, step_y, step_x + step_width - end);
+                break
+            }
+        }
+        self.skyline.dedup_by(|&mut (_, y, width), &mut (_, previous_y, ref mut previous_width)| {
+            let same = y == previous_y;
+            if same {
+                *previous_width += width;
+            }
+            same
+        });
+    }
+}
+
+/// Draw `image` as frame `frame` of `sprite`, in place of what the frame showed before
+#[derive(Debug, Clone)]
+pub struct FrameEdit {
+    pub sprite: SpriteId,
+    pub frame: usize,
+    pub image: RgbaImage,
+}
+
+/// Draw every edit onto the texture pages and write the pages that changed back into TXTR.
+///
+/// Frames no bigger than before are drawn where they were. Each page holding a frame that's
+/// grown is rebuilt with everything on it packed again, starting new pages for whatever
+/// doesn't fit. The TPAG entries of anything that moves are updated to match, as are the
+/// sizes and bounds of the sprites with frames that have grown. Frames that had nothing on a
+/// page yet, like those of a new sprite, are packed in the same way but otherwise left as
+/// they are.
+pub fn replace_frames(file: &mut FormFile, edits: Vec<FrameEdit>) {
+    let mut pages = BTreeMap::new();
+    let mut grown = Vec::new();
+    // whether each grown frame was on a page before
+    let mut placed = BTreeMap::new();
+    for edit in edits {
+        let tpag_offset = match file.resolve(edit.sprite).and_then(|sprite| sprite.tpag_offsets.get(edit.frame)) {
+            Some(&tpag_offset) => tpag_offset,
+            None => continue,
+        };
+        let index = match file.tpag.as_ref().and_then(|tpag| tpag.index_of(tpag_offset)) {
+            Some(index) => index,
+            None => continue,
+        };
+        let entry = &mut file.tpag.as_mut().unwrap().texture_pages[index];
+        let (width, height) = edit.image.dimensions();
+        let ((x, y), (old_width, old_height)) = entry.sprite_bounds;
+        let texture = entry.texture_index as usize;
+        if width > old_width as u32 || height > old_height as u32 {
+            placed.insert(index, old_width > 0 && old_height > 0);
+            grown.push((edit, index));
+            continue
+        }
+
+        // smaller frames are drawn in the top left of their old spot, with the rest cleared
+        let size = (width as u16, height as u16);
+        entry.sprite_bounds.1 = size;
+        entry.unk2.1 = size;
+        let page = pages.entry(texture).or_insert_with(|| file.get_texture(texture).to_rgba());
+        let clear = RgbaImage::new(old_width as u32, old_height as u32);
+        imageops::replace(page, &clear, x as u32, y as u32);
+        imageops::replace(page, &edit.image, x as u32, y as u32);
+    }
+
+    let mut rebuild = grown.iter()
+        .map(|&(_, index)| file.tpag.as_ref().unwrap().texture_pages[index].texture_index as usize)
+        .collect::<Vec<_>>();
+    rebuild.sort_unstable();
+    rebuild.dedup();
+    let replaced = grown.iter()
+        .map(|(edit, index)| (*index, &edit.image))
+        .collect::<BTreeMap<_, _>>();
+    for texture in rebuild {
+        let page = pages.remove(&texture).unwrap_or_else(|| file.get_texture(texture).to_rgba());
+        for (texture, page) in rebuild_page(file, texture, &page, &replaced) {
+            pages.insert(texture, page);
+        }
+    }
+
+    for (edit, index) in &grown {
+        if !placed[index] {
+            continue
+        }
+        grow_sprite(file, edit, *index);
+    }
+
+    save_pages(file, pages);
+}
+
+/// Clear each `(texture, rect)` of `parts` from the pages, leaving room for other frames
+pub fn clear_parts(file: &mut FormFile, parts: &[(usize, Rect16)]) {
+    let mut pages = BTreeMap::new();
+    for &(texture, ((x, y), (width, height))) in parts {
+        let page = pages.entry(texture).or_insert_with(|| file.get_texture(texture).to_rgba());
+        imageops::replace(page, &RgbaImage::new(width as u32, height as u32), x as u32, y as u32);
+    }
+    save_pages(file, pages);
+}
+
+/// Pack everything on `texture` into new pages the same size, with `replaced` TPAG entries
+/// drawn with a new image. Returns each new page by its texture index, where the first reuses
+/// `texture`'s.
+fn rebuild_page(
+    file: &mut FormFile,
+    texture: usize,
+    page: &RgbaImage,
+    replaced: &BTreeMap<usize, &RgbaImage>,
+) -> Vec<(usize, RgbaImage)> {
+    let tpag = file.tpag.as_ref().unwrap();
+    // entries showing the same part of the page keep sharing it, unless one is being replaced
+    let mut parts: Vec<(Vec<usize>, RgbaImage)> = Vec::new();
+    let mut shared = BTreeMap::new();
+    for (index, entry) in tpag.texture_pages.iter().enumerate() {
+        if entry.texture_index as usize != texture {
+            continue
+        }
+        if let Some(image) = replaced.get(&index) {
+            parts.push((vec![index], (*image).clone()));
+            continue
+        }
+        let ((x, y), (width, height)) = entry.sprite_bounds;
+        let part = *shared.entry(entry.sprite_bounds).or_insert_with(|| {
+            let image = page.view(x as u32, y as u32, width as u32, height as u32).to_image();
+            parts.push((Vec::new(), image));
+            parts.len() - 1
+        });
+        parts[part].0.push(index);
+    }
+    // tallest first packs tightest
+    parts.sort_by_key(|(_, image)| std::cmp::Reverse((image.height(), image.width())));
+
+    let page_size = page.dimensions();
+    // the padding can hang off the right and bottom edges
+    let new_page = |texture, (width, height)| {
+        (texture, Packer::new((width + PADDING, height + PADDING)), RgbaImage::new(width, height))
+    };
+    let mut packers = vec![new_page(texture, page_size)];
+    for (indices, image) in parts {
+        let padded = (image.width() + PADDING, image.height() + PADDING);
+        let placed = packers.iter_mut()
+            .enumerate()
+            .find_map(|(i, (_, packer, _))| Some((i, packer.insert(padded)?)));
+        let (i, (x, y)) = match placed {
+            Some(placed) => placed,
+            None => {
+                // anything bigger than the page gets a page big enough for it
+                let size = (
+                    page_size.0.max(padded.0.next_power_of_two()),
+                    page_size.1.max(padded.1.next_power_of_two()),
+                );
+                let mut page = new_page(add_texture(file, texture), size);
+                let position = page.1.insert(padded).unwrap();
+                packers.push(page);
+                (packers.len() - 1, position)
+            }
+        };
+
+        let (texture, _, page) = &mut packers[i];
+        imageops::replace(page, &image, x, y);
+        let tpag = file.tpag.as_mut().unwrap();
+        for index in indices {
+            let entry = &mut tpag.texture_pages[index];
+            entry.sprite_bounds = ((x as u16, y as u16), (image.width() as u16, image.height() as u16));
+            entry.texture_index = *texture as u16;
+        }
+    }
+
+    packers.into_iter().map(|(texture, _, page)| (texture, page)).collect()
+}
+
+/// Add an empty page to TXTR set up like `like`, returning its index
+fn add_texture(file: &mut FormFile, like: usize) -> usize {
+    let txtr = file.txtr.as_mut().unwrap();
+    let TxtrEntry { unk1, unk2, .. } = txtr.files[like];
+    txtr.files.push(TxtrEntry { unk1, unk2, png: Vec::new() });
+    file.textures.push(Lazy::new());
+    txtr.files.len() - 1
+}
+
+/// Fit the TPAG entry at `index` and its sprite to the bigger image it now has
+fn grow_sprite(file: &mut FormFile, edit: &FrameEdit, index: usize) {
+    let (width, height) = edit.image.dimensions();
+    let entry = &mut file.tpag.as_mut().unwrap().texture_pages[index];
+    // the frame grows right and down from where its old image was drawn
+    let (x, y) = entry.unk2.0;
+    entry.unk2.1 = (width as u16, height as u16);
+    entry.size = (entry.size.0.max(x + width as u16), entry.size.1.max(y + height as u16));
+    let size = entry.size;
+
+    let sprite = &mut file.sprt.as_mut().unwrap().sprites[edit.sprite.0];
+    sprite.size = (sprite.size.0.max(size.0 as u32), sprite.size.1.max(size.1 as u32));
+    let ((left, right), (bottom, top)) = sprite.bounds;
+    let (x, y) = (x as u32, y as u32);
+    sprite.bounds = (
+        (left.min(x), right.max(x + width - 1)),
+        (bottom.max(y + height - 1), top.min(y)),
+    );
+}
+
+/// Encode each of `pages` into TXTR, at its texture index
+fn save_pages(file: &mut FormFile, pages: BTreeMap<usize, RgbaImage>) {
+    let encoded = pages.into_par_iter()
+        .map(|(texture, page)| {
+            let mut png = Vec::with_capacity(0x8_0000);
+            image::DynamicImage::ImageRgba8(page)
+                .write_to(&mut png, image::ImageFormat::PNG)
+                .expect("Failed to encode texture");
+            (texture, png)
+        })
+        .collect::<Vec<_>>();
+
+    let txtr = file.txtr.as_mut().unwrap();
+    for (texture, png) in encoded {
+        txtr.files[texture].png = png;
+        // the old page may have been loaded already
+        file.textures[texture] = Lazy::new();
+    }
+}
@@ -0,0 +1,1238 @@
+//! Compilation of GML source into GameMaker VM bytecode
+//!
+//! Each construct is compiled to the same branch pattern GameMaker's own compiler emits for
+//! it, which is also what `decompile` looks for, so compiled code decompiles back to
+//! equivalent source.
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::asm::{encode, Assembly};
+use crate::disasm::{Comparison, DataType, Instruction, Opcode, Operand, Variable, VariableKind};
+use crate::file_structs::{CodeLayout, FormFile, MissingSection, Reference, Strg};
+
+/// Why GML source couldn't be compiled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// Line number, starting from 1, or 0 if the error isn't about the source
+    pub line: usize,
+    /// Column number in characters, starting from 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl From<MissingSection> for CompileError {
+    fn from(err: MissingSection) -> Self {
+        CompileError { line: 0, column: 0, message: err.to_string() }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+const SELF: i16 = -1;
+const GLOBAL: i16 = -5;
+const LOCAL: i16 = -7;
+
+/// Instances GML has keywords for
+const INSTANCE_KEYWORDS: &[(&str, i16)] = &[
+    ("self", SELF),
+    ("other", -2),
+    ("all", -3),
+    ("noone", -4),
+    ("global", GLOBAL),
+];
+
+/// Multi-dimensional arrays are flattened with this many elements per row
+const ARRAY_STRIDE: i64 = 32000;
+
+/// Local GameMaker's compiler stores return values in while it cleans up the stack
+const RETURN_TEMP: &str = "$$$$temp$$$$";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+impl Pos {
+    fn error(self, message: impl Into<String>) -> CompileError {
+        CompileError { line: self.line, column: self.column, message: message.into() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Real(f64),
+    Str(String),
+    Punct(&'static str),
+    Eof,
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name) => format!("'{}'", name),
+        Token::Int(_) | Token::Real(_) => String::from("number"),
+        Token::Str(_) => String::from("string"),
+        Token::Punct(punct) => format!("'{}'", punct),
+        Token::Eof => String::from("end of file"),
+    }
+}
+
+/// Longest first, so `<<=` isn't lexed as `<` `<=`
+const PUNCTUATION: &[&str] = &[
+    "<<=", ">>=",
+    "+=", "-=", "*=", "/=", "%=", "|=", "&=", "^=", "==", "!=", "<=", ">=", "&&", "||", "^^",
+    "<<", ">>", "++", "--", ":=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "(", ")", "[", "]", "{",
+    "}", ",", ";", ".", ":", "?",
+];
+
+/// Words GML accepts in place of punctuation
+const WORD_OPERATORS: &[(&str, &str)] = &[
+    ("and", "&&"),
+    ("or", "||"),
+    ("xor", "^^"),
+    ("not", "!"),
+    ("mod", "%"),
+    ("div", "div"),
+    ("begin", "{"),
+    ("end", "}"),
+];
+
+const KEYWORDS: &[&str] = &[
+    "var", "if", "then", "else", "while", "do", "until", "for", "repeat", "with", "switch",
+    "case", "default", "break", "continue", "exit", "return",
+];
+
+fn lex(source: &str) -> Result<Vec<(Token, Pos)>> {
+    let chars = source.chars().collect::<Vec<_>>();
+    let at = |i: usize| chars.get(i).copied().unwrap_or('\0');
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+    while i < chars.len() {
+        let pos = Pos { line, column: i - line_start + 1 };
+        let c = chars[i];
+        if c == '\n' {
+            i += 1;
+            line += 1;
+            line_start = i;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '/' && at(i + 1) == '/' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && at(i + 1) == '*' {
+            i += 2;
+            while !(at(i) == '*' && at(i + 1) == '/') {
+                if i >= chars.len() {
+                    return Err(pos.error("unterminated comment"));
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+            i += 2;
+            continue;
+        }
+
+        let start = i;
+        let token = if c.is_ascii_alphabetic() || c == '_' {
+            while at(i).is_ascii_alphanumeric() || at(i) == '_' {
+                i += 1;
+            }
+            let word = chars[start..i].iter().collect::<String>();
+            match WORD_OPERATORS.iter().find(|&&(other, _)| other == word) {
+                Some(&(_, punct)) => Token::Punct(punct),
+                None => Token::Ident(word),
+            }
+        } else if c == '$' || (c == '0' && matches!(at(i + 1), 'x' | 'X')) {
+            i += if c == '$' { 1 } else { 2 };
+            let digits = i;
+            while at(i).is_ascii_hexdigit() {
+                i += 1;
+            }
+            let digits = chars[digits..i].iter().collect::<String>();
+            Token::Int(i64::from_str_radix(&digits, 16).map_err(|_| pos.error("invalid hex number"))?)
+        } else if c.is_ascii_digit() || (c == '.' && at(i + 1).is_ascii_digit()) {
+            while at(i).is_ascii_digit() {
+                i += 1;
+            }
+            let real = at(i) == '.' && at(i + 1).is_ascii_digit();
+            if real {
+                i += 1;
+                while at(i).is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text = chars[start..i].iter().collect::<String>();
+            if real {
+                Token::Real(text.parse().map_err(|_| pos.error("invalid number"))?)
+            } else {
+                Token::Int(text.parse().map_err(|_| pos.error("number is too large"))?)
+            }
+        } else if c == '"' || c == '\'' {
+            // only double quoted strings have escapes
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(pos.error("unterminated string")),
+                    Some(&quote) if quote == c => break,
+                    Some('\\') if c == '"' => {
+                        i += 1;
+                        string.push(match at(i) {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            other => other,
+                        });
+                    }
+                    Some(&other) => {
+                        if other == '\n' {
+                            line += 1;
+                            line_start = i + 1;
+                        }
+                        string.push(other);
+                    }
+                }
+                i += 1;
+            }
+            i += 1;
+            Token::Str(string)
+        } else {
+            let punct = PUNCTUATION.iter()
+                .find(|punct| punct.chars().enumerate().all(|(j, other)| at(i + j) == other))
+                .ok_or_else(|| pos.error(format!("unexpected character '{}'", c)))?;
+            i += punct.len();
+            Token::Punct(punct)
+        };
+        tokens.push((token, pos));
+    }
+
+    tokens.push((Token::Eof, Pos { line, column: chars.len() - line_start + 1 }));
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+struct Expr {
+    kind: ExprKind,
+    pos: Pos,
+}
+
+#[derive(Debug, Clone)]
+enum ExprKind {
+    Int(i64),
+    Real(f64),
+    Str(String),
+    Ident(String),
+    /// `instance.name`
+    Field(Box<Expr>, String),
+    /// `array[i]` or `array[i, j]`
+    Index(Box<Expr>, Vec<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Var(Vec<(String, Pos, Option<Expr>)>),
+    /// Assignment, with the operator of compound assignments like `+=`
+    Assign(Expr, Option<&'static str>, Expr),
+    Expr(Expr),
+    Block(Vec<Stmt>),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    DoUntil(Box<Stmt>, Expr),
+    For(Box<Stmt>, Option<Expr>, Box<Stmt>, Box<Stmt>),
+    Repeat(Expr, Box<Stmt>),
+    With(Expr, Box<Stmt>),
+    /// The body holds `Case` labels between its statements
+    Switch(Expr, Vec<Stmt>),
+    /// `case value:`, or `default:` without a value
+    Case(Option<Expr>, Pos),
+    Break(Pos),
+    Continue(Pos),
+    Exit,
+    Return(Option<Expr>),
+}
+
+const ASSIGNMENTS: &[(&str, Option<&str>)] = &[
+    ("=", None),
+    (":=", None),
+    ("+=", Some("+")),
+    ("-=", Some("-")),
+    ("*=", Some("*")),
+    ("/=", Some("/")),
+    ("%=", Some("%")),
+    ("|=", Some("|")),
+    ("&=", Some("&")),
+    ("^=", Some("^")),
+    ("<<=", Some("<<")),
+    (">>=", Some(">>")),
+];
+
+/// Binary operators and how tightly they bind, matching `decompile`
+const BINARY: &[(&str, u8)] = &[
+    ("||", 2),
+    ("&&", 3),
+    ("^^", 3),
+    ("==", 4),
+    ("=", 4),
+    ("!=", 4),
+    ("<", 4),
+    ("<=", 4),
+    (">", 4),
+    (">=", 4),
+    ("|", 5),
+    ("&", 5),
+    ("^", 5),
+    ("<<", 6),
+    (">>", 6),
+    ("+", 7),
+    ("-", 7),
+    ("*", 8),
+    ("/", 8),
+    ("div", 8),
+    ("%", 8),
+];
+
+struct Parser {
+    tokens: Vec<(Token, Pos)>,
+    at: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.at].0
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.at].1
+    }
+
+    fn advance(&mut self) -> (Token, Pos) {
+        let token = self.tokens[self.at].clone();
+        if self.at + 1 < self.tokens.len() {
+            self.at += 1;
+        }
+        token
+    }
+
+    fn is(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(other) if *other == punct)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let is = self.is(punct);
+        if is {
+            self.advance();
+        }
+        is
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let is = matches!(self.peek(), Token::Ident(other) if other == word);
+        if is {
+            self.advance();
+        }
+        is
+    }
+
+    fn unexpected(&self, expected: &str) -> CompileError {
+        self.pos().error(format!("expected {}, found {}", expected, describe(self.peek())))
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", punct)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn program(&mut self) -> Result<Vec<Stmt>> {
+        let mut statements = Vec::new();
+        while *self.peek() != Token::Eof {
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut statements = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Token::Eof {
+                return Err(self.unexpected("'}'"));
+            }
+            statements.push(self.statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn boxed(&mut self) -> Result<Box<Stmt>> {
+        self.statement().map(Box::new)
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        let pos = self.pos();
+        if self.eat("{") {
+            return self.block().map(Stmt::Block);
+        }
+        if self.eat(";") {
+            return Ok(Stmt::Block(Vec::new()));
+        }
+
+        let keyword = match self.peek() {
+            Token::Ident(word) if KEYWORDS.contains(&word.as_str()) => word.clone(),
+            _ => String::new(),
+        };
+        if !keyword.is_empty() {
+            self.advance();
+        }
+        let statement = match keyword.as_str() {
+            "var" => {
+                let mut variables = Vec::new();
+                loop {
+                    let pos = self.pos();
+                    let name = self.ident()?;
+                    let value = if self.eat("=") || self.eat(":=") { Some(self.expr()?) } else { None };
+                    variables.push((name, pos, value));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                Stmt::Var(variables)
+            }
+            "if" => {
+                let condition = self.expr()?;
+                self.eat_word("then");
+                let then = self.boxed()?;
+                let other = if self.eat_word("else") { Some(self.boxed()?) } else { None };
+                Stmt::If(condition, then, other)
+            }
+            "while" => {
+                let condition = self.expr()?;
+                self.eat_word("do");
+                Stmt::While(condition, self.boxed()?)
+            }
+            "do" => {
+                let body = self.boxed()?;
+                if !self.eat_word("until") {
+                    return Err(self.unexpected("'until'"));
+                }
+                Stmt::DoUntil(body, self.expr()?)
+            }
+            "for" => {
+                self.expect("(")?;
+                // the initialiser's statement takes its `;` with it
+                let init = self.boxed()?;
+                let condition = if self.is(";") { None } else { Some(self.expr()?) };
+                self.expect(";")?;
+                let step = if self.is(")") { Stmt::Block(Vec::new()) } else { self.simple()? };
+                self.expect(")")?;
+                Stmt::For(init, condition, Box::new(step), self.boxed()?)
+            }
+            "repeat" => Stmt::Repeat(self.expr()?, self.boxed()?),
+            "with" => Stmt::With(self.expr()?, self.boxed()?),
+            "switch" => {
+                let value = self.expr()?;
+                self.expect("{")?;
+                Stmt::Switch(value, self.block()?)
+            }
+            "case" => {
+                let value = self.expr()?;
+                self.expect(":")?;
+                return Ok(Stmt::Case(Some(value), pos));
+            }
+            "default" => {
+                self.expect(":")?;
+                return Ok(Stmt::Case(None, pos));
+            }
+            "break" => Stmt::Break(pos),
+            "continue" => Stmt::Continue(pos),
+            "exit" => Stmt::Exit,
+            "return" => {
+                let end = self.is(";") || self.is("}") || *self.peek() == Token::Eof;
+                Stmt::Return(if end { None } else { Some(self.expr()?) })
+            }
+            "" => self.simple()?,
+            _ => return Err(pos.error(format!("unexpected '{}'", keyword))),
+        };
+        self.eat(";");
+        Ok(statement)
+    }
+
+    /// An assignment, increment or call
+    fn simple(&mut self) -> Result<Stmt> {
+        let pos = self.pos();
+        let one = Expr { kind: ExprKind::Int(1), pos };
+        for &(punct, op) in &[("++", "+"), ("--", "-")] {
+            if self.eat(punct) {
+                return Ok(Stmt::Assign(self.postfix()?, Some(op), one));
+            }
+        }
+
+        let target = self.postfix()?;
+        for &(punct, op) in &[("++", "+"), ("--", "-")] {
+            if self.eat(punct) {
+                return Ok(Stmt::Assign(target, Some(op), one));
+            }
+        }
+        if let Token::Punct(punct) = *self.peek() {
+            if let Some(&(_, op)) = ASSIGNMENTS.iter().find(|&&(other, _)| other == punct) {
+                self.advance();
+                return Ok(Stmt::Assign(target, op, self.expr()?));
+            }
+        }
+        match target.kind {
+            ExprKind::Call(..) => Ok(Stmt::Expr(target)),
+            _ => Err(self.unexpected("an assignment")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.expr()?;
+        self.expect(":")?;
+        let other = self.expr()?;
+        Ok(Expr {
+            pos: condition.pos,
+            kind: ExprKind::Ternary(Box::new(condition), Box::new(then), Box::new(other)),
+        })
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(&(op, precedence)) = BINARY.iter()
+            .find(|&&(op, precedence)| precedence >= min_precedence && self.is(op))
+        {
+            self.advance();
+            let rhs = self.binary(precedence + 1)?;
+            // `=` compares when it's inside an expression
+            let op = if op == "=" { "==" } else { op };
+            lhs = Expr { pos: lhs.pos, kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)) };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let pos = self.pos();
+        for &op in &["-", "!", "~", "+"] {
+            if !self.eat(op) {
+                continue;
+            }
+            let operand = self.unary()?;
+            let kind = match (op, operand.kind) {
+                ("+", kind) => kind,
+                ("-", ExprKind::Int(val)) => ExprKind::Int(-val),
+                ("-", ExprKind::Real(val)) => ExprKind::Real(-val),
+                (op, kind) => ExprKind::Unary(op, Box::new(Expr { kind, pos: operand.pos })),
+            };
+            return Ok(Expr { kind, pos });
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            let pos = expr.pos;
+            if self.eat(".") {
+                let name = self.ident()?;
+                expr = Expr { kind: ExprKind::Field(Box::new(expr), name), pos };
+            } else if self.eat("[") {
+                let mut indices = vec![self.expr()?];
+                while self.eat(",") {
+                    indices.push(self.expr()?);
+                }
+                self.expect("]")?;
+                expr = Expr { kind: ExprKind::Index(Box::new(expr), indices), pos };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let pos = self.pos();
+        let kind = match self.peek().clone() {
+            Token::Int(val) => ExprKind::Int(val),
+            Token::Real(val) => ExprKind::Real(val),
+            Token::Str(string) => ExprKind::Str(string),
+            Token::Ident(ref word) if word == "true" => ExprKind::Int(1),
+            Token::Ident(ref word) if word == "false" => ExprKind::Int(0),
+            Token::Ident(_) => {
+                let name = self.ident()?;
+                if !self.eat("(") {
+                    return Ok(Expr { kind: ExprKind::Ident(name), pos });
+                }
+                let mut arguments = Vec::new();
+                while !self.eat(")") {
+                    if !arguments.is_empty() {
+                        self.expect(",")?;
+                    }
+                    arguments.push(self.expr()?);
+                }
+                return Ok(Expr { kind: ExprKind::Call(name, arguments), pos });
+            }
+            Token::Punct("(") => {
+                self.advance();
+                let expr = self.expr()?;
+                self.expect(")")?;
+                return Ok(expr);
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+        self.advance();
+        Ok(Expr { kind, pos })
+    }
+}
+
+/// A construct `break`, `continue`, `exit` and `return` may have to leave, with the labels
+/// they jump to
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Loop { exit: usize, next: usize },
+    /// `repeat` keeps its counter on the stack
+    Repeat { exit: usize, next: usize },
+    /// `with` has to leave the instance it switched to
+    With { exit: usize, next: usize },
+    /// `switch` keeps the value being matched on the stack
+    Switch { exit: usize, value: DataType },
+}
+
+/// How a variable is reached, once the instance and array index it needs are on the stack
+struct Access {
+    instance: i16,
+    kind: VariableKind,
+    name: String,
+}
+
+const COMPARISONS: &[(&str, Comparison)] = &[
+    ("<", Comparison::Lt),
+    ("<=", Comparison::Le),
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    (">=", Comparison::Ge),
+    (">", Comparison::Gt),
+];
+
+const ARITHMETIC: &[(&str, Opcode)] = &[
+    ("*", Opcode::Mul),
+    ("/", Opcode::Div),
+    ("div", Opcode::Rem),
+    ("%", Opcode::Mod),
+    ("+", Opcode::Add),
+    ("-", Opcode::Sub),
+    ("&", Opcode::And),
+    ("|", Opcode::Or),
+    ("^", Opcode::Xor),
+    ("<<", Opcode::Shl),
+    (">>", Opcode::Shr),
+];
+
+struct Codegen<'a> {
+    layout: CodeLayout,
+    objects: &'a [String],
+    sprites: &'a [String],
+    instructions: Vec<Instruction>,
+    address: u32,
+    /// Address of each label, once it's placed
+    labels: Vec<Option<u32>>,
+    /// Branch instructions and the label they jump to
+    fixups: Vec<(usize, usize)>,
+    references: Vec<(u32, Reference)>,
+    /// String pushes and the string they push, which only go in STRG once the code compiles
+    strings: Vec<(usize, String)>,
+    locals: HashSet<String>,
+    scopes: Vec<Scope>,
+}
+
+impl Codegen<'_> {
+    fn gms1(&self) -> bool {
+        self.layout == CodeLayout::Inline
+    }
+
+    fn emit(&mut self, opcode: Opcode, type1: DataType, type2: DataType, operand: Operand) -> u32 {
+        let address = self.address;
+        let instruction = Instruction { address, opcode, type1, type2, operand };
+        self.address += instruction.size();
+        self.instructions.push(instruction);
+        address
+    }
+
+    fn op(&mut self, opcode: Opcode, type1: DataType, type2: DataType) {
+        self.emit(opcode, type1, type2, Operand::None);
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.address);
+    }
+
+    fn branch(&mut self, opcode: Opcode, label: usize) {
+        self.fixups.push((self.instructions.len(), label));
+        self.emit(opcode, DataType::Double, DataType::Double, Operand::Branch(0));
+    }
+
+    fn convert(&mut self, from: DataType, to: DataType) {
+        if from != to {
+            self.op(Opcode::Conv, from, to);
+        }
+    }
+
+    fn push_int(&mut self, val: i64) -> DataType {
+        if let Ok(val) = i16::try_from(val) {
+            self.emit(Opcode::PushI, DataType::Int16, DataType::Double, Operand::Int16(val));
+            DataType::Int32
+        } else if let Ok(val) = i32::try_from(val) {
+            self.emit(Opcode::Push, DataType::Int32, DataType::Double, Operand::Int32(val));
+            DataType::Int32
+        } else {
+            self.emit(Opcode::Push, DataType::Int64, DataType::Double, Operand::Int64(val));
+            DataType::Int64
+        }
+    }
+
+    fn finish(mut self, strg: &mut Strg) -> Assembly {
+        for (i, string) in self.strings.drain(..) {
+            let loc = strg.intern(&string);
+            let index = strg.index_of(loc).unwrap();
+            self.instructions[i].operand = Operand::String(index as u32);
+        }
+        for &(i, label) in &self.fixups {
+            let instruction = &mut self.instructions[i];
+            let target = self.labels[label].expect("label was never placed");
+            instruction.operand = Operand::Branch(target as i32 - instruction.address as i32);
+        }
+
+        let bytecode = self.instructions.iter()
+            .flat_map(|instruction| encode(instruction, self.layout))
+            .collect();
+        Assembly { bytecode, references: self.references }
+    }
+
+    /// Instance of `base` in `base.name`, when it's known without running any code
+    fn instance(&self, base: &Expr) -> Option<i16> {
+        match &base.kind {
+            ExprKind::Int(val) => i16::try_from(*val).ok(),
+            ExprKind::Ident(name) if !self.locals.contains(name) => INSTANCE_KEYWORDS.iter()
+                .find(|&&(keyword, _)| keyword == name)
+                .map(|&(_, instance)| instance)
+                .or_else(|| self.objects.iter().position(|object| object == name).map(|i| i as i16)),
+            _ => None,
+        }
+    }
+
+    /// Index of the sprite `name` refers to, unless a local hides it
+    fn sprite(&self, name: &str) -> Option<usize> {
+        if self.locals.contains(name) {
+            return None;
+        }
+        self.sprites.iter().position(|sprite| sprite == name)
+    }
+
+    /// Push whatever `target` needs on the stack to be read or assigned
+    fn access(&mut self, target: &Expr) -> Result<Access> {
+        match &target.kind {
+            ExprKind::Ident(name) => Ok(Access {
+                instance: if self.locals.contains(name) { LOCAL } else { SELF },
+                kind: VariableKind::Normal,
+                name: name.clone(),
+            }),
+            ExprKind::Field(base, name) => {
+                let name = name.clone();
+                if let Some(instance) = self.instance(base) {
+                    return Ok(Access { instance, kind: VariableKind::Normal, name });
+                }
+                let instance = self.expr(base)?;
+                self.convert(instance, DataType::Int32);
+                Ok(Access { instance: SELF, kind: VariableKind::StackTop, name })
+            }
+            ExprKind::Index(base, indices) => {
+                let Access { instance, kind, name } = self.access(base)?;
+                if kind != VariableKind::Normal {
+                    return Err(base.pos.error("only variables of known instances can be indexed"));
+                }
+                if indices.len() > 2 {
+                    return Err(indices[2].pos.error("arrays have at most two dimensions"));
+                }
+                // GMS2 pushes the instance along with the index
+                if !self.gms1() {
+                    self.push_int(instance as i64);
+                }
+                let index = self.expr(&indices[0])?;
+                self.convert(index, DataType::Int32);
+                if let Some(second) = indices.get(1) {
+                    self.push_int(ARRAY_STRIDE);
+                    self.op(Opcode::Mul, DataType::Int32, DataType::Int32);
+                    let index = self.expr(second)?;
+                    self.convert(index, DataType::Int32);
+                    self.op(Opcode::Add, DataType::Int32, DataType::Int32);
+                }
+                Ok(Access { instance, kind: VariableKind::Array, name })
+            }
+            _ => Err(target.pos.error("expected a variable")),
+        }
+    }
+
+    fn variable(&mut self, opcode: Opcode, value: DataType, access: Access) {
+        let Access { instance, kind, name } = access;
+        let operand = Operand::Variable(Variable { instance, kind, reference: 0 });
+        let address = self.emit(opcode, DataType::Variable, value, operand);
+        self.references.push((address, Reference::Variable { name, instance }));
+    }
+
+    fn read(&mut self, access: Access) -> DataType {
+        let opcode = match (access.instance, access.kind) {
+            _ if self.gms1() => Opcode::Push,
+            (LOCAL, VariableKind::Normal) => Opcode::PushLoc,
+            (GLOBAL, VariableKind::Normal) => Opcode::PushGlb,
+            _ => Opcode::Push,
+        };
+        self.variable(opcode, DataType::Double, access);
+        DataType::Variable
+    }
+
+    fn assign(&mut self, target: &Expr, op: Option<&str>, value: &Expr) -> Result<()> {
+        let value = match op {
+            None => self.expr(value)?,
+            Some(op) => {
+                let access = self.access(target)?;
+                let lhs = self.read(access);
+                let rhs = self.expr(value)?;
+                self.operator(op, lhs, rhs)
+            }
+        };
+        let access = self.access(target)?;
+        self.variable(Opcode::Pop, value, access);
+        Ok(())
+    }
+
+    /// Apply a comparison or arithmetic operator to the top two values on the stack
+    fn operator(&mut self, op: &str, lhs: DataType, rhs: DataType) -> DataType {
+        if let Some(&(_, comparison)) = COMPARISONS.iter().find(|&&(other, _)| other == op) {
+            self.emit(Opcode::Cmp, rhs, lhs, Operand::Comparison(comparison));
+            return DataType::Boolean;
+        }
+
+        let &(_, opcode) = ARITHMETIC.iter()
+            .find(|&&(other, _)| other == op)
+            .expect("unknown operator");
+        self.op(opcode, rhs, lhs);
+        if lhs == rhs {
+            return lhs;
+        }
+        [DataType::Variable, DataType::String, DataType::Double, DataType::Int64]
+            .iter()
+            .copied()
+            .find(|&ty| ty == lhs || ty == rhs)
+            .unwrap_or(DataType::Int32)
+    }
+
+    fn condition(&mut self, condition: &Expr) -> Result<()> {
+        let ty = self.expr(condition)?;
+        self.convert(ty, DataType::Boolean);
+        Ok(())
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<DataType> {
+        Ok(match &expr.kind {
+            ExprKind::Int(val) => self.push_int(*val),
+            ExprKind::Real(val) => {
+                self.emit(Opcode::Push, DataType::Double, DataType::Double, Operand::Double(*val));
+                DataType::Double
+            }
+            ExprKind::Str(string) => {
+                self.strings.push((self.instructions.len(), string.clone()));
+                self.emit(Opcode::Push, DataType::String, DataType::Double, Operand::String(0));
+                DataType::String
+            }
+            // instance keywords and object names stand for their index
+            ExprKind::Ident(_) if self.instance(expr).is_some() => {
+                self.push_int(self.instance(expr).unwrap() as i64)
+            }
+            // as do sprite names
+            ExprKind::Ident(name) if self.sprite(name).is_some() => {
+                self.push_int(self.sprite(name).unwrap() as i64)
+            }
+            ExprKind::Ident(_) | ExprKind::Field(..) | ExprKind::Index(..) => {
+                let access = self.access(expr)?;
+                self.read(access)
+            }
+            ExprKind::Call(name, arguments) => {
+                // the first argument ends up on top
+                for argument in arguments.iter().rev() {
+                    let ty = self.expr(argument)?;
+                    self.convert(ty, DataType::Variable);
+                }
+                let operand = Operand::Call { argument_count: arguments.len() as u16, reference: 0 };
+                let address = self.emit(Opcode::Call, DataType::Int32, DataType::Double, operand);
+                self.references.push((address, Reference::Function(name.clone())));
+                DataType::Variable
+            }
+            ExprKind::Unary(op, operand) => {
+                let ty = self.expr(operand)?;
+                match *op {
+                    "-" => {
+                        self.op(Opcode::Neg, ty, DataType::Double);
+                        ty
+                    }
+                    "!" => {
+                        self.convert(ty, DataType::Boolean);
+                        self.op(Opcode::Not, DataType::Boolean, DataType::Double);
+                        DataType::Boolean
+                    }
+                    _ => {
+                        self.op(Opcode::Not, ty, DataType::Double);
+                        ty
+                    }
+                }
+            }
+            ExprKind::Binary(op @ "&&", lhs, rhs) | ExprKind::Binary(op @ "||", lhs, rhs) => {
+                // jump straight to pushing the result when the left side decides it
+                let and = *op == "&&";
+                let (short, end) = (self.label(), self.label());
+                self.condition(lhs)?;
+                self.branch(if and { Opcode::Bf } else { Opcode::Bt }, short);
+                self.condition(rhs)?;
+                self.branch(Opcode::B, end);
+                self.place(short);
+                self.emit(Opcode::Push, DataType::Int16, DataType::Double, Operand::Int16(!and as i16));
+                self.place(end);
+                DataType::Boolean
+            }
+            ExprKind::Binary("^^", lhs, rhs) => {
+                self.condition(lhs)?;
+                self.condition(rhs)?;
+                self.op(Opcode::Xor, DataType::Boolean, DataType::Boolean);
+                DataType::Boolean
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                self.operator(op, lhs, rhs)
+            }
+            ExprKind::Ternary(condition, then, other) => {
+                let (other_label, end) = (self.label(), self.label());
+                self.condition(condition)?;
+                self.branch(Opcode::Bf, other_label);
+                let ty = self.expr(then)?;
+                self.convert(ty, DataType::Variable);
+                self.branch(Opcode::B, end);
+                self.place(other_label);
+                let ty = self.expr(other)?;
+                self.convert(ty, DataType::Variable);
+                self.place(end);
+                DataType::Variable
+            }
+        })
+    }
+
+    fn body(&mut self, body: &Stmt, scope: Scope) -> Result<()> {
+        self.scopes.push(scope);
+        let result = self.statement(body);
+        self.scopes.pop();
+        result
+    }
+
+    /// Clean up after every enclosing construct, before leaving the code entirely
+    fn leave_scopes(&mut self) {
+        for scope in self.scopes.clone().into_iter().rev() {
+            match scope {
+                Scope::Loop { .. } => {}
+                Scope::Repeat { .. } => self.op(Opcode::Popz, DataType::Int32, DataType::Double),
+                Scope::With { .. } => {
+                    self.emit(Opcode::PopEnv, DataType::Double, DataType::Double, Operand::PopEnvExit);
+                }
+                Scope::Switch { value, .. } => self.op(Opcode::Popz, value, DataType::Double),
+            }
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<()> {
+        match statement {
+            Stmt::Var(variables) => {
+                for (name, pos, value) in variables {
+                    self.locals.insert(name.clone());
+                    if let Some(value) = value {
+                        let target = Expr { kind: ExprKind::Ident(name.clone()), pos: *pos };
+                        self.assign(&target, None, value)?;
+                    }
+                }
+            }
+            Stmt::Assign(target, op, value) => self.assign(target, *op, value)?,
+            Stmt::Expr(expr) => {
+                let ty = self.expr(expr)?;
+                self.op(Opcode::Popz, ty, DataType::Double);
+            }
+            Stmt::Block(statements) => {
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+            }
+            Stmt::If(condition, then, other) => {
+                let other_label = self.label();
+                self.condition(condition)?;
+                self.branch(Opcode::Bf, other_label);
+                self.statement(then)?;
+                match other {
+                    Some(other) => {
+                        let end = self.label();
+                        self.branch(Opcode::B, end);
+                        self.place(other_label);
+                        self.statement(other)?;
+                        self.place(end);
+                    }
+                    None => self.place(other_label),
+                }
+            }
+            Stmt::While(condition, body) => {
+                let (top, exit) = (self.label(), self.label());
+                self.place(top);
+                self.condition(condition)?;
+                self.branch(Opcode::Bf, exit);
+                self.body(body, Scope::Loop { exit, next: top })?;
+                self.branch(Opcode::B, top);
+                self.place(exit);
+            }
+            Stmt::DoUntil(body, condition) => {
+                let (top, next, exit) = (self.label(), self.label(), self.label());
+                self.place(top);
+                self.body(body, Scope::Loop { exit, next })?;
+                self.place(next);
+                self.condition(condition)?;
+                self.branch(Opcode::Bf, top);
+                self.place(exit);
+            }
+            Stmt::For(init, condition, step, body) => {
+                let (top, next, exit) = (self.label(), self.label(), self.label());
+                self.statement(init)?;
+                self.place(top);
+                if let Some(condition) = condition {
+                    self.condition(condition)?;
+                    self.branch(Opcode::Bf, exit);
+                }
+                self.body(body, Scope::Loop { exit, next })?;
+                self.place(next);
+                self.statement(step)?;
+                self.branch(Opcode::B, top);
+                self.place(exit);
+            }
+            Stmt::Repeat(count, body) => {
+                let (top, next, exit) = (self.label(), self.label(), self.label());
+                let ty = self.expr(count)?;
+                self.convert(ty, DataType::Int32);
+                self.emit(Opcode::Dup, DataType::Int32, DataType::Double, Operand::Raw(0));
+                self.push_int(0);
+                self.emit(Opcode::Cmp, DataType::Int32, DataType::Int32, Operand::Comparison(Comparison::Le));
+                self.branch(Opcode::Bt, exit);
+                self.place(top);
+                self.body(body, Scope::Repeat { exit, next })?;
+                self.place(next);
+                self.push_int(1);
+                self.op(Opcode::Sub, DataType::Int32, DataType::Int32);
+                self.emit(Opcode::Dup, DataType::Int32, DataType::Double, Operand::Raw(0));
+                self.convert(DataType::Int32, DataType::Boolean);
+                self.branch(Opcode::Bt, top);
+                self.place(exit);
+                self.op(Opcode::Popz, DataType::Int32, DataType::Double);
+            }
+            Stmt::With(target, body) => {
+                let (top, next, exit) = (self.label(), self.label(), self.label());
+                let ty = self.expr(target)?;
+                self.convert(ty, DataType::Int32);
+                self.branch(Opcode::PushEnv, next);
+                self.place(top);
+                self.body(body, Scope::With { exit, next })?;
+                self.place(next);
+                self.branch(Opcode::PopEnv, top);
+                self.place(exit);
+            }
+            Stmt::Switch(value, body) => {
+                let value = self.expr(value)?;
+                let exit = self.label();
+                let mut cases = Vec::new();
+                let mut default = None;
+                for statement in body {
+                    let (case, pos) = match statement {
+                        Stmt::Case(case, pos) => (case, pos),
+                        _ => continue,
+                    };
+                    let label = self.label();
+                    cases.push(label);
+                    match case {
+                        Some(case) => {
+                            self.emit(Opcode::Dup, value, DataType::Double, Operand::Raw(0));
+                            let ty = self.expr(case)?;
+                            self.emit(Opcode::Cmp, ty, value, Operand::Comparison(Comparison::Eq));
+                            self.branch(Opcode::Bt, label);
+                        }
+                        None if default.is_some() => return Err(pos.error("switch has more than one default")),
+                        None => default = Some(label),
+                    }
+                }
+                self.branch(Opcode::B, default.unwrap_or(exit));
+
+                self.scopes.push(Scope::Switch { exit, value });
+                let mut cases = cases.into_iter();
+                for statement in body {
+                    match statement {
+                        Stmt::Case(..) => self.place(cases.next().unwrap()),
+                        statement => self.statement(statement)?,
+                    }
+                }
+                self.scopes.pop();
+                self.place(exit);
+                self.op(Opcode::Popz, value, DataType::Double);
+            }
+            Stmt::Case(_, pos) => return Err(pos.error("case outside of a switch")),
+            Stmt::Break(pos) => match self.scopes.last().copied() {
+                Some(Scope::With { exit, .. }) => {
+                    self.emit(Opcode::PopEnv, DataType::Double, DataType::Double, Operand::PopEnvExit);
+                    self.branch(Opcode::B, exit);
+                }
+                Some(Scope::Loop { exit, .. }) | Some(Scope::Repeat { exit, .. }) | Some(Scope::Switch { exit, .. }) => {
+                    self.branch(Opcode::B, exit);
+                }
+                None => return Err(pos.error("break outside of a loop or switch")),
+            },
+            Stmt::Continue(pos) => {
+                // continue skips past any switches to the loop around them
+                let mut switches = Vec::new();
+                let mut next = None;
+                for scope in self.scopes.iter().rev() {
+                    match *scope {
+                        Scope::Switch { value, .. } => switches.push(value),
+                        Scope::Loop { next: label, .. }
+                        | Scope::Repeat { next: label, .. }
+                        | Scope::With { next: label, .. } => {
+                            next = Some(label);
+                            break;
+                        }
+                    }
+                }
+                let next = next.ok_or_else(|| pos.error("continue outside of a loop"))?;
+                for value in switches {
+                    self.op(Opcode::Popz, value, DataType::Double);
+                }
+                self.branch(Opcode::B, next);
+            }
+            Stmt::Exit | Stmt::Return(None) => {
+                self.leave_scopes();
+                self.op(Opcode::Exit, DataType::Int32, DataType::Double);
+            }
+            Stmt::Return(Some(value)) => {
+                let ty = self.expr(value)?;
+                self.convert(ty, DataType::Variable);
+                // the value has to be put aside while the stack is cleaned up
+                if self.scopes.iter().any(|scope| !matches!(scope, Scope::Loop { .. })) {
+                    let temp = || Access { instance: LOCAL, kind: VariableKind::Normal, name: String::from(RETURN_TEMP) };
+                    self.variable(Opcode::Pop, DataType::Variable, temp());
+                    self.leave_scopes();
+                    self.read(temp());
+                }
+                self.op(Opcode::Ret, DataType::Variable, DataType::Double);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compile GML source to bytecode in the instruction set of `layout`, adding any strings it
+/// uses that aren't in `strg` yet. `objects` are the names in OBJT, which
+/// `object.variable` resolves against, and `sprites` the names in SPRT, which stand for
+/// their index.
+pub fn compile(
+    source: &str,
+    layout: CodeLayout,
+    objects: &[String],
+    sprites: &[String],
+    strg: &mut Strg,
+) -> Result<Assembly> {
+    let statements = Parser { tokens: lex(source)?, at: 0 }.program()?;
+
+    let mut codegen = Codegen {
+        layout,
+        objects,
+        sprites,
+        instructions: Vec::new(),
+        address: 0,
+        labels: Vec::new(),
+        fixups: Vec::new(),
+        references: Vec::new(),
+        strings: Vec::new(),
+        locals: HashSet::new(),
+        scopes: Vec::new(),
+    };
+    for statement in &statements {
+        codegen.statement(statement)?;
+    }
+    Ok(codegen.finish(strg))
+}
+
+/// Compile `source` over the bytecode of the CODE entry at `index`, in the instruction set
+/// `FormFile::bytecode_layout` gives
+pub fn compile_into(file: &mut FormFile, index: usize, source: &str) -> Result<()> {
+    if file.code.is_none() {
+        return Err(MissingSection("CODE").into());
+    }
+    let layout = file.bytecode_layout();
+    let name = |offset| file.strg.as_ref().and_then(|strg| strg.get(offset)).cloned().unwrap_or_default();
+    let objects = file.objects()
+        .iter()
+        .map(|object| name(object.name_offset))
+        .collect::<Vec<_>>();
+    let sprites = file.sprt.iter()
+        .flat_map(|sprt| &sprt.sprites)
+        .map(|sprite| name(sprite.name_offset))
+        .collect::<Vec<_>>();
+
+    let strg = file.strg.as_mut().ok_or(MissingSection("STRG"))?;
+    let assembly = compile(source, layout, &objects, &sprites, strg)?;
+    file.replace_bytecode(index, &assembly.bytecode, &assembly.references)?;
+    Ok(())
+}
@@ -0,0 +1,727 @@
+//! Reconstruction of GML source from the bytecode in CODE
+//!
+//! Control flow is recovered by matching the branch patterns GameMaker's compiler emits for
+//! each construct. Bytecode that doesn't match any of them is listed as commented-out
+//! disassembly instead.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::disasm::{
+    disassemble, instance_name, instructions, Comparison, DataType, Instruction, Opcode, Operand,
+    References, Variable, VariableKind,
+};
+use crate::file_structs::{CodeLayout, Error, FormFile};
+
+const ATOM: u8 = 10;
+const UNARY: u8 = 9;
+const TERNARY: u8 = 1;
+
+#[derive(Debug, Clone)]
+struct Expr {
+    text: String,
+    /// How tightly the expression binds, for deciding where parentheses are needed
+    precedence: u8,
+    /// Value of integer literals, which instances are referred to by
+    int: Option<i64>,
+    /// Calls are the only expressions worth keeping when their value is discarded
+    is_call: bool,
+}
+
+impl Expr {
+    fn atom(text: String) -> Self {
+        Expr { text, precedence: ATOM, int: None, is_call: false }
+    }
+
+    fn int(val: i64) -> Self {
+        Expr { int: Some(val), ..Expr::atom(val.to_string()) }
+    }
+
+    fn call(text: String) -> Self {
+        Expr { is_call: true, ..Expr::atom(text) }
+    }
+
+    fn wrap(&self, precedence: u8) -> String {
+        if self.precedence < precedence {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+
+    fn binary(self, op: &str, rhs: Expr, precedence: u8) -> Self {
+        Expr {
+            text: format!("{} {} {}", self.wrap(precedence), op, rhs.wrap(precedence + 1)),
+            precedence,
+            int: None,
+            is_call: false,
+        }
+    }
+
+    fn unary(self, op: &str) -> Self {
+        Expr {
+            text: format!("{}{}", op, self.wrap(UNARY)),
+            precedence: UNARY,
+            int: None,
+            is_call: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Assign(String, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    DoUntil(Vec<Stmt>, Expr),
+    Repeat(Expr, Vec<Stmt>),
+    With(Expr, Vec<Stmt>),
+    /// Each body with the case values leading to it, `None` being `default`
+    Switch(Expr, Vec<(Vec<Option<Expr>>, Vec<Stmt>)>),
+    Break,
+    Continue,
+    Exit,
+    Return(Expr),
+}
+
+struct Decompiler<'a> {
+    file: &'a FormFile,
+    layout: CodeLayout,
+    instructions: Vec<Instruction>,
+    /// Name of the variable or function each instruction refers to
+    names: Vec<Option<String>>,
+    indices: HashMap<u32, usize>,
+    stack: Vec<Expr>,
+    locals: BTreeSet<String>,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// GML source for the code entry at `index`
+pub fn decompile(file: &FormFile, index: usize) -> std::result::Result<String, Error> {
+    let code = match file.code.as_ref() {
+        Some(code) => code,
+        None => return Ok(String::new()),
+    };
+    let entry = code.entries.get(index).ok_or_else(|| Error::no_entry(b"CODE", index))?;
+    let references = References::new(file);
+
+    let instructions = instructions(code.bytecode_of(entry), file.bytecode_layout())
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let names = instructions.iter()
+        .map(|instruction| {
+            let address = entry.address + instruction.address;
+            match instruction.operand {
+                Operand::Variable(_) => references.variables.get(&address).cloned(),
+                Operand::Call { .. } => references.functions.get(&address).cloned(),
+                _ => None,
+            }
+        })
+        .collect();
+    let indices = instructions.iter()
+        .enumerate()
+        .map(|(i, instruction)| (instruction.address, i))
+        .chain(std::iter::once((entry.length, instructions.len())))
+        .collect::<HashMap<_, _>>();
+
+    let start = indices.get(&entry.offset).copied().unwrap_or(0);
+    let end = instructions.len();
+    let mut decompiler = Decompiler {
+        file,
+        layout: file.bytecode_layout(),
+        instructions,
+        names,
+        indices,
+        stack: Vec::new(),
+        locals: BTreeSet::new(),
+        breaks: Vec::new(),
+        continues: Vec::new(),
+    };
+
+    let mut text = String::new();
+    match decompiler.block(start, end) {
+        Ok(mut stmts) => {
+            // falling off the end is an implicit exit
+            if let Some(Stmt::Exit) = stmts.last() {
+                stmts.pop();
+            }
+            if !decompiler.locals.is_empty() {
+                let locals = decompiler.locals.iter().cloned().collect::<Vec<_>>();
+                let _ = writeln!(text, "var {};", locals.join(", "));
+            }
+            write_block(&mut text, &stmts, 0);
+        }
+        Err(message) => {
+            let _ = writeln!(text, "// Failed to decompile: {}", message);
+            for line in disassemble(file, index)?.lines() {
+                let _ = writeln!(text, "// {}", line);
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+impl Decompiler<'_> {
+    fn pop(&mut self) -> Result<Expr> {
+        self.stack.pop().ok_or_else(|| String::from("stack underflow"))
+    }
+
+    fn opcode(&self, i: usize) -> Option<Opcode> {
+        self.instructions.get(i).map(|instruction| instruction.opcode)
+    }
+
+    /// Address of the instruction at `i`, or of the end of the entry for the index past the
+    /// last one
+    fn address(&self, i: usize) -> u32 {
+        match self.instructions.get(i) {
+            Some(instruction) => instruction.address,
+            None => self.instructions.last().map_or(0, |last| last.address + last.size()),
+        }
+    }
+
+    /// Index of the instruction a branch at `i` goes to
+    fn target(&self, i: usize) -> Result<usize> {
+        let instruction = self.instructions.get(i)
+            .ok_or_else(|| format!("expected a branch at {:#x}", self.address(i)))?;
+        match instruction.operand {
+            Operand::Branch(offset) => {
+                let address = (instruction.address as i64 + offset as i64) as u32;
+                self.indices.get(&address)
+                    .copied()
+                    .ok_or_else(|| format!("branch into the middle of an instruction at {:#x}", instruction.address))
+            }
+            _ => Err(format!("expected a branch at {:#x}", instruction.address)),
+        }
+    }
+
+    /// Decompile `start..end` on a stack of its own, returning the statements and whatever
+    /// values were left on the stack
+    fn sub_block(&mut self, start: usize, end: usize) -> Result<(Vec<Stmt>, Vec<Expr>)> {
+        let outer = std::mem::take(&mut self.stack);
+        let stmts = self.block(start, end);
+        let rest = std::mem::replace(&mut self.stack, outer);
+        Ok((stmts?, rest))
+    }
+
+    /// Decompile `start..end`, which must leave the stack empty
+    fn statements(&mut self, start: usize, end: usize) -> Result<Vec<Stmt>> {
+        match self.sub_block(start, end)? {
+            (stmts, rest) if rest.is_empty() => Ok(stmts),
+            _ => Err(format!("values left on the stack at {:#x}", self.address(start))),
+        }
+    }
+
+    fn value(&mut self, start: usize, end: usize) -> Result<Expr> {
+        match self.sub_block(start, end)? {
+            (stmts, mut rest) if stmts.is_empty() && rest.len() == 1 => Ok(rest.pop().unwrap()),
+            _ => Err(format!("expected a single value at {:#x}", self.address(start))),
+        }
+    }
+
+    fn in_loop(&mut self, break_to: usize, continue_to: usize, start: usize, end: usize) -> Result<Vec<Stmt>> {
+        self.breaks.push(break_to);
+        self.continues.push(continue_to);
+        let stmts = self.statements(start, end);
+        self.breaks.pop();
+        self.continues.pop();
+        stmts
+    }
+
+    fn block(&mut self, start: usize, end: usize) -> Result<Vec<Stmt>> {
+        let mut stmts = Vec::new();
+        let mut statement_start = start;
+        let mut i = start;
+        while i < end {
+            if self.stack.is_empty() {
+                statement_start = i;
+
+                // do { } until (): a conditional branch back to the start of the body
+                let until = (i + 1..end).rev().find(|&j| {
+                    matches!(self.opcode(j), Some(Opcode::Bf) | Some(Opcode::Bt)) && self.target(j) == Ok(i)
+                });
+                if let Some(j) = until {
+                    self.breaks.push(j + 1);
+                    let body = self.sub_block(i, j);
+                    self.breaks.pop();
+                    let (body, mut rest) = body?;
+                    let condition = rest.pop().ok_or("missing loop condition")?;
+                    let condition = if self.opcode(j) == Some(Opcode::Bt) { condition.unary("!") } else { condition };
+                    stmts.push(Stmt::DoUntil(body, condition));
+                    i = j + 1;
+                    continue;
+                }
+            }
+
+            let instruction = self.instructions[i].clone();
+            match instruction.opcode {
+                Opcode::Bf | Opcode::Bt => {
+                    let target = self.target(i)?;
+                    if target <= i || target > end {
+                        return Err(format!("unexpected branch at {:#x}", instruction.address));
+                    }
+                    let condition = self.pop()?;
+                    let negated = instruction.opcode == Opcode::Bt;
+
+                    // `a && b` skips to pushing false, `a || b` to pushing true
+                    if self.jumps_to(target - 1, target + 1) && self.pushes_bool(target) == Some(negated) {
+                        let rhs = self.value(i + 1, target - 1)?;
+                        let (op, precedence) = if negated { ("||", 2) } else { ("&&", 3) };
+                        self.stack.push(condition.binary(op, rhs, precedence));
+                        i = target + 1;
+                        continue;
+                    }
+
+                    let condition = if negated { condition.unary("!") } else { condition };
+                    let jump = if target > i + 1 && self.opcode(target - 1) == Some(Opcode::B) {
+                        Some(self.target(target - 1)?)
+                    } else {
+                        None
+                    };
+                    match jump {
+                        Some(top) if top == statement_start => {
+                            let body = self.in_loop(target, top, i + 1, target - 1)?;
+                            stmts.push(Stmt::While(condition, body));
+                            i = target;
+                        }
+                        Some(after) if after > target && after <= end && !self.is_loop_exit(after) => {
+                            let (then, then_rest) = self.sub_block(i + 1, target - 1)?;
+                            let (otherwise, otherwise_rest) = self.sub_block(target, after)?;
+                            match (then_rest.len(), otherwise_rest.len()) {
+                                (0, 0) => stmts.push(Stmt::If(condition, then, otherwise)),
+                                (1, 1) if then.is_empty() && otherwise.is_empty() => {
+                                    let (then, otherwise) = (&then_rest[0], &otherwise_rest[0]);
+                                    self.stack.push(Expr {
+                                        text: format!("{} ? {} : {}", condition.wrap(TERNARY + 1), then.wrap(TERNARY), otherwise.wrap(TERNARY)),
+                                        precedence: TERNARY,
+                                        int: None,
+                                        is_call: false,
+                                    });
+                                }
+                                _ => return Err(format!("unbalanced stack in if at {:#x}", instruction.address)),
+                            }
+                            i = after;
+                        }
+                        _ => {
+                            let then = self.statements(i + 1, target)?;
+                            stmts.push(Stmt::If(condition, then, Vec::new()));
+                            i = target;
+                        }
+                    }
+                }
+                Opcode::B => {
+                    let target = self.target(i)?;
+                    if self.breaks.last() == Some(&target) {
+                        stmts.push(Stmt::Break);
+                    } else if self.continues.last() == Some(&target) {
+                        stmts.push(Stmt::Continue);
+                    } else {
+                        return Err(format!("unstructured branch at {:#x}", instruction.address));
+                    }
+                    i += 1;
+                }
+                Opcode::PushEnv => {
+                    let popenv = self.target(i)?;
+                    if popenv <= i || popenv >= end || self.opcode(popenv) != Some(Opcode::PopEnv) {
+                        return Err(format!("unmatched pushenv at {:#x}", instruction.address));
+                    }
+                    let target = self.pop()?;
+                    let target = match target.int {
+                        Some(instance) => Expr::atom(self.instance(instance)),
+                        None => target,
+                    };
+                    let body = self.in_loop(popenv + 1, popenv, i + 1, popenv)?;
+                    stmts.push(Stmt::With(target, body));
+                    i = popenv + 1;
+                }
+                // leaving a with block early, followed by the branch out of it
+                Opcode::PopEnv if instruction.operand == Operand::PopEnvExit => i += 1,
+                Opcode::Dup if self.is_repeat(i) => {
+                    let count = self.pop()?;
+                    let end_index = self.target(i + 3)?;
+                    if end_index < i + 9 || end_index > end || self.opcode(end_index) != Some(Opcode::Popz) {
+                        return Err(format!("unrecognised repeat at {:#x}", instruction.address));
+                    }
+                    // the body is followed by the decrement: push 1; sub; dup; conv; bt
+                    let decrement = end_index - 5;
+                    let body = self.in_loop(end_index, decrement, i + 4, decrement)?;
+                    stmts.push(Stmt::Repeat(count, body));
+                    i = end_index + 1;
+                }
+                Opcode::Dup if self.switch_case(i).is_some() => {
+                    let (switch, after) = self.switch(i, end)?;
+                    stmts.push(switch);
+                    i = after;
+                }
+                _ => {
+                    if let Some(stmt) = self.simple(&instruction, i)? {
+                        stmts.push(stmt);
+                    }
+                    i += 1;
+                }
+            }
+        }
+        Ok(stmts)
+    }
+
+    fn is_loop_exit(&self, target: usize) -> bool {
+        self.breaks.contains(&target) || self.continues.contains(&target)
+    }
+
+    /// Whether the instruction at `i` is `b target`
+    fn jumps_to(&self, i: usize, target: usize) -> bool {
+        self.opcode(i) == Some(Opcode::B) && self.target(i) == Ok(target)
+    }
+
+    /// The boolean pushed by the instruction at `i`, if it pushes a constant 0 or 1
+    fn pushes_bool(&self, i: usize) -> Option<bool> {
+        let instruction = self.instructions.get(i)?;
+        if !matches!(instruction.opcode, Opcode::Push | Opcode::PushI) {
+            return None;
+        }
+        match instruction.operand {
+            Operand::Int16(val @ 0..=1) => Some(val == 1),
+            Operand::Int32(val @ 0..=1) => Some(val == 1),
+            Operand::Bool(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// `repeat` keeps its counter on the stack: dup; push 0; cmp LE; bt end
+    fn is_repeat(&self, i: usize) -> bool {
+        self.pushes_bool(i + 1) == Some(false)
+            && self.instructions.get(i + 2).map(|instruction| instruction.operand) == Some(Operand::Comparison(Comparison::Le))
+            && self.opcode(i + 3) == Some(Opcode::Bt)
+    }
+
+    /// A switch case compares a copy of the value: dup; <case value>; cmp EQ; bt body.
+    /// Returns the index of the `cmp`.
+    fn switch_case(&self, i: usize) -> Option<usize> {
+        if self.opcode(i) != Some(Opcode::Dup) {
+            return None;
+        }
+        let cmp = (i + 1..self.instructions.len())
+            .take_while(|&j| !matches!(self.opcode(j), Some(Opcode::B | Opcode::Bt | Opcode::Bf | Opcode::Dup | Opcode::Pop | Opcode::Popz)))
+            .find(|&j| self.opcode(j) == Some(Opcode::Cmp))?;
+        let is_case = self.instructions[cmp].operand == Operand::Comparison(Comparison::Eq)
+            && self.opcode(cmp + 1) == Some(Opcode::Bt);
+        Some(cmp).filter(|_| is_case)
+    }
+
+    fn switch(&mut self, start: usize, end: usize) -> Result<(Stmt, usize)> {
+        let value = self.pop()?;
+        let mut labels = BTreeMap::<usize, Vec<Option<Expr>>>::new();
+
+        let mut i = start;
+        while let Some(cmp) = self.switch_case(i) {
+            let case = self.value(i + 1, cmp)?;
+            labels.entry(self.target(cmp + 1)?).or_default().push(Some(case));
+            i = cmp + 2;
+        }
+        if self.opcode(i) != Some(Opcode::B) {
+            return Err(format!("expected a branch after the switch cases at {:#x}", self.address(i)));
+        }
+        let default = self.target(i)?;
+        let bodies_start = i + 1;
+        let last_label = labels.keys().copied().chain(Some(default)).max().unwrap();
+
+        // breaks from before the last case give the end away, otherwise it's the first popz
+        let end_index = if self.opcode(default) == Some(Opcode::Popz) {
+            default
+        } else {
+            (bodies_start..last_label)
+                .filter(|&j| self.opcode(j) == Some(Opcode::B))
+                .filter_map(|j| self.target(j).ok())
+                .filter(|&target| target >= last_label && self.opcode(target) == Some(Opcode::Popz))
+                .max()
+                .or_else(|| (last_label..end).find(|&j| self.opcode(j) == Some(Opcode::Popz)))
+                .ok_or_else(|| format!("couldn't find the end of the switch at {:#x}", self.address(start)))?
+        };
+        if end_index >= end {
+            return Err(format!("switch at {:#x} runs past its block", self.address(start)));
+        }
+        if default != end_index {
+            labels.entry(default).or_default().push(None);
+        }
+
+        let starts = labels.keys().copied().collect::<Vec<_>>();
+        let mut cases = Vec::new();
+        for (n, (&label, values)) in labels.iter().enumerate() {
+            let body_end = starts.get(n + 1).copied().unwrap_or(end_index);
+            self.breaks.push(end_index);
+            let body = self.statements(label, body_end);
+            self.breaks.pop();
+            cases.push((values.clone(), body?));
+        }
+
+        Ok((Stmt::Switch(value, cases), end_index + 1))
+    }
+
+    fn name(&self, i: usize, reference: u32) -> String {
+        self.names[i].clone().unwrap_or_else(|| format!("unknown_{}", reference))
+    }
+
+    /// How GML refers to an instance, by object name where there is one
+    fn instance(&self, instance: i64) -> String {
+        let object = Some(instance)
+            .filter(|&instance| instance >= 0)
+            .and_then(|index| self.file.objects().get(index as usize))
+            .and_then(|object| self.file.strg.as_ref()?.get(object.name_offset));
+        match object {
+            Some(name) => name.clone(),
+            None if instance >= 0 => instance.to_string(),
+            None => instance_name(instance as i16),
+        }
+    }
+
+    /// Prefix for a variable accessed through `instance`
+    fn instance_prefix(&self, instance: i64) -> String {
+        match instance {
+            // self, builtin, local and static don't need a prefix
+            -1 | -6 | -7 | -16 => String::new(),
+            instance => format!("{}.", self.instance(instance)),
+        }
+    }
+
+    /// The variable at instruction `i`, popping the instance and array index it uses
+    fn variable(&mut self, i: usize, variable: Variable) -> Result<String> {
+        let Variable { instance, kind, reference } = variable;
+        let name = self.name(i, reference);
+        if instance == -7 {
+            self.locals.insert(name.clone());
+        }
+
+        let from_stack = |expr: Expr, this: &Self| match expr.int {
+            Some(instance) => this.instance_prefix(instance),
+            None => format!("{}.", expr.wrap(ATOM)),
+        };
+        Ok(match kind {
+            VariableKind::Array => {
+                let index = self.pop()?;
+                // GMS2 pushes the instance along with the index
+                let prefix = if self.layout == CodeLayout::Inline {
+                    self.instance_prefix(instance as i64)
+                } else {
+                    let instance = self.pop()?;
+                    from_stack(instance, self)
+                };
+                format!("{}{}[{}]", prefix, name, index.text)
+            }
+            VariableKind::StackTop => {
+                let instance = self.pop()?;
+                format!("{}{}", from_stack(instance, self), name)
+            }
+            VariableKind::Normal | VariableKind::Instance => {
+                format!("{}{}", self.instance_prefix(instance as i64), name)
+            }
+            kind => return Err(format!("unsupported variable kind {}", kind.prefix())),
+        })
+    }
+
+    /// Handle an instruction that doesn't affect control flow
+    fn simple(&mut self, instruction: &Instruction, i: usize) -> Result<Option<Stmt>> {
+        let Instruction { opcode, type1, operand, .. } = *instruction;
+        let binary = |op| match op {
+            Opcode::Mul => Some(("*", 8)),
+            Opcode::Div => Some(("/", 8)),
+            Opcode::Rem => Some(("div", 8)),
+            Opcode::Mod => Some(("%", 8)),
+            Opcode::Add => Some(("+", 7)),
+            Opcode::Sub => Some(("-", 7)),
+            Opcode::Shl => Some(("<<", 6)),
+            Opcode::Shr => Some((">>", 6)),
+            Opcode::And if type1 == DataType::Boolean => Some(("&&", 3)),
+            Opcode::Or if type1 == DataType::Boolean => Some(("||", 2)),
+            Opcode::Xor if type1 == DataType::Boolean => Some(("^^", 3)),
+            Opcode::And => Some(("&", 5)),
+            Opcode::Or => Some(("|", 5)),
+            Opcode::Xor => Some(("^", 5)),
+            _ => None,
+        };
+
+        match opcode {
+            Opcode::Conv => {}
+            Opcode::Cmp => {
+                let op = match operand {
+                    Operand::Comparison(Comparison::Lt) => "<",
+                    Operand::Comparison(Comparison::Le) => "<=",
+                    Operand::Comparison(Comparison::Eq) => "==",
+                    Operand::Comparison(Comparison::Ne) => "!=",
+                    Operand::Comparison(Comparison::Ge) => ">=",
+                    Operand::Comparison(Comparison::Gt) => ">",
+                    _ => return Err(format!("unknown comparison at {:#x}", instruction.address)),
+                };
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.stack.push(lhs.binary(op, rhs, 4));
+            }
+            op if binary(op).is_some() => {
+                let (op, precedence) = binary(op).unwrap();
+                let rhs = self.pop()?;
+                let lhs = self.pop()?;
+                self.stack.push(lhs.binary(op, rhs, precedence));
+            }
+            Opcode::Neg => {
+                let val = self.pop()?;
+                self.stack.push(val.unary("-"));
+            }
+            Opcode::Not => {
+                let val = self.pop()?;
+                self.stack.push(val.unary(if type1 == DataType::Boolean { "!" } else { "~" }));
+            }
+            Opcode::Dup => {
+                let count = match operand {
+                    Operand::Raw(extra) => (extra & 0xff) as usize + 1,
+                    _ => 1,
+                };
+                if self.stack.len() < count {
+                    return Err(String::from("stack underflow"));
+                }
+                let copies = self.stack[self.stack.len() - count..].to_vec();
+                self.stack.extend(copies);
+            }
+            Opcode::Push | Opcode::PushLoc | Opcode::PushGlb | Opcode::PushBltn | Opcode::PushI => {
+                let expr = match operand {
+                    Operand::Int16(val) => Expr::int(val as i64),
+                    Operand::Int32(val) => Expr::int(val as i64),
+                    Operand::Int64(val) => Expr::int(val),
+                    Operand::Float(val) => Expr::atom(val.to_string()),
+                    Operand::Double(val) => Expr::atom(val.to_string()),
+                    Operand::Bool(val) => Expr::atom(val.to_string()),
+                    Operand::String(index) => {
+                        let string = self.file.strg.as_ref()
+                            .and_then(|strg| strg.strings.get(index as usize))
+                            .ok_or_else(|| format!("missing string {}", index))?;
+                        Expr::atom(crate::disasm::escape(string))
+                    }
+                    Operand::Variable(variable) => Expr::atom(self.variable(i, variable)?),
+                    _ => return Err(format!("unsupported push at {:#x}", instruction.address)),
+                };
+                // negative literals bind like a unary minus
+                let precedence = if expr.text.starts_with('-') { UNARY } else { expr.precedence };
+                self.stack.push(Expr { precedence, ..expr });
+            }
+            Opcode::Pop => match operand {
+                Operand::Variable(variable) => {
+                    // the value is pushed before the instance and index of the variable
+                    let value_slot = match variable.kind {
+                        VariableKind::Array if self.layout != CodeLayout::Inline => 2,
+                        VariableKind::Array | VariableKind::StackTop => 1,
+                        _ => 0,
+                    };
+                    if self.stack.len() <= value_slot {
+                        return Err(String::from("stack underflow"));
+                    }
+                    let value = self.stack.remove(self.stack.len() - 1 - value_slot);
+                    let target = self.variable(i, variable)?;
+                    return Ok(Some(Stmt::Assign(target, value)));
+                }
+                _ => return Err(format!("unsupported pop at {:#x}", instruction.address)),
+            },
+            Opcode::Popz => {
+                let val = self.pop()?;
+                if val.is_call {
+                    return Ok(Some(Stmt::Expr(val)));
+                }
+            }
+            Opcode::Call => {
+                let (argument_count, reference) = match operand {
+                    Operand::Call { argument_count, reference } => (argument_count, reference),
+                    _ => unreachable!(),
+                };
+                let arguments = (0..argument_count)
+                    .map(|_| self.pop().map(|argument| argument.text))
+                    .collect::<Result<Vec<_>>>()?;
+                self.stack.push(Expr::call(format!("{}({})", self.name(i, reference), arguments.join(", "))));
+            }
+            Opcode::CallV => {
+                let argument_count = match operand {
+                    Operand::Raw(extra) => extra,
+                    _ => 0,
+                };
+                let function = self.pop()?;
+                let _this = self.pop()?;
+                let arguments = (0..argument_count)
+                    .map(|_| self.pop().map(|argument| argument.text))
+                    .collect::<Result<Vec<_>>>()?;
+                self.stack.push(Expr::call(format!("{}({})", function.wrap(ATOM), arguments.join(", "))));
+            }
+            Opcode::Ret => return Ok(Some(Stmt::Return(self.pop()?))),
+            Opcode::Exit => return Ok(Some(Stmt::Exit)),
+            // chkindex only checks the array index on top of the stack
+            Opcode::Break if operand == Operand::Int16(-1) => {}
+            _ => return Err(format!("unsupported instruction {} at {:#x}", opcode.mnemonic(), instruction.address)),
+        }
+        Ok(None)
+    }
+}
+
+fn write_block(text: &mut String, stmts: &[Stmt], depth: usize) {
+    for stmt in stmts {
+        write_stmt(text, stmt, depth);
+    }
+}
+
+fn write_stmt(text: &mut String, stmt: &Stmt, depth: usize) {
+    let indent = "    ".repeat(depth);
+    let _ = match stmt {
+        Stmt::Assign(target, value) => writeln!(text, "{}{} = {};", indent, target, value.text),
+        Stmt::Expr(expr) => writeln!(text, "{}{};", indent, expr.text),
+        Stmt::If(condition, then, otherwise) => {
+            let _ = writeln!(text, "{}if ({}) {{", indent, condition.text);
+            write_block(text, then, depth + 1);
+            let mut otherwise = otherwise;
+            // else if chains
+            while let [Stmt::If(condition, then, next)] = &otherwise[..] {
+                let _ = writeln!(text, "{}}} else if ({}) {{", indent, condition.text);
+                write_block(text, then, depth + 1);
+                otherwise = next;
+            }
+            if !otherwise.is_empty() {
+                let _ = writeln!(text, "{}}} else {{", indent);
+                write_block(text, otherwise, depth + 1);
+            }
+            writeln!(text, "{}}}", indent)
+        }
+        Stmt::While(condition, body) => {
+            let _ = writeln!(text, "{}while ({}) {{", indent, condition.text);
+            write_block(text, body, depth + 1);
+            writeln!(text, "{}}}", indent)
+        }
+        Stmt::DoUntil(body, condition) => {
+            let _ = writeln!(text, "{}do {{", indent);
+            write_block(text, body, depth + 1);
+            writeln!(text, "{}}} until ({});", indent, condition.text)
+        }
+        Stmt::Repeat(count, body) => {
+            let _ = writeln!(text, "{}repeat ({}) {{", indent, count.text);
+            write_block(text, body, depth + 1);
+            writeln!(text, "{}}}", indent)
+        }
+        Stmt::With(target, body) => {
+            let _ = writeln!(text, "{}with ({}) {{", indent, target.text);
+            write_block(text, body, depth + 1);
+            writeln!(text, "{}}}", indent)
+        }
+        Stmt::Switch(value, cases) => {
+            let _ = writeln!(text, "{}switch ({}) {{", indent, value.text);
+            for (values, body) in cases {
+                for value in values {
+                    let _ = match value {
+                        Some(value) => writeln!(text, "{}    case {}:", indent, value.text),
+                        None => writeln!(text, "{}    default:", indent),
+                    };
+                }
+                write_block(text, body, depth + 2);
+            }
+            writeln!(text, "{}}}", indent)
+        }
+        Stmt::Break => writeln!(text, "{}break;", indent),
+        Stmt::Continue => writeln!(text, "{}continue;", indent),
+        Stmt::Exit => writeln!(text, "{}exit;", indent),
+        Stmt::Return(value) => writeln!(text, "{}return {};", indent, value.text),
+    };
+}
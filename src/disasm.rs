@@ -0,0 +1,676 @@
+//! Decoding of GameMaker VM bytecode from the CODE section
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write};
+
+use crate::file_structs::{Code, CodeLayout, Error, Expected, FormFile, StringRef, Strg};
+
+/// Type of a value on the VM stack, stored in the type nibbles of an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Boolean,
+    Variable,
+    String,
+    Instance,
+    Delete,
+    Undefined,
+    UnsignedInt,
+    Int16,
+    Unknown(u8),
+}
+
+impl DataType {
+    pub fn from_nibble(nibble: u8) -> Self {
+        match nibble {
+            0x0 => DataType::Double,
+            0x1 => DataType::Float,
+            0x2 => DataType::Int32,
+            0x3 => DataType::Int64,
+            0x4 => DataType::Boolean,
+            0x5 => DataType::Variable,
+            0x6 => DataType::String,
+            0x7 => DataType::Instance,
+            0x8 => DataType::Delete,
+            0x9 => DataType::Undefined,
+            0xa => DataType::UnsignedInt,
+            0xf => DataType::Int16,
+            nibble => DataType::Unknown(nibble),
+        }
+    }
+
+    pub fn nibble(self) -> u8 {
+        match self {
+            DataType::Double => 0x0,
+            DataType::Float => 0x1,
+            DataType::Int32 => 0x2,
+            DataType::Int64 => 0x3,
+            DataType::Boolean => 0x4,
+            DataType::Variable => 0x5,
+            DataType::String => 0x6,
+            DataType::Instance => 0x7,
+            DataType::Delete => 0x8,
+            DataType::Undefined => 0x9,
+            DataType::UnsignedInt => 0xa,
+            DataType::Int16 => 0xf,
+            DataType::Unknown(nibble) => nibble,
+        }
+    }
+
+    /// Suffix used for the type in the disassembly (`push.i`, `conv.v.b`, ...)
+    pub fn suffix(self) -> String {
+        match self {
+            DataType::Double => String::from("d"),
+            DataType::Float => String::from("f"),
+            DataType::Int32 => String::from("i"),
+            DataType::Int64 => String::from("l"),
+            DataType::Boolean => String::from("b"),
+            DataType::Variable => String::from("v"),
+            DataType::String => String::from("s"),
+            DataType::Instance => String::from("inst"),
+            DataType::Delete => String::from("del"),
+            DataType::Undefined => String::from("u"),
+            DataType::UnsignedInt => String::from("ui"),
+            DataType::Int16 => String::from("e"),
+            DataType::Unknown(nibble) => format!("t{:x}", nibble),
+        }
+    }
+
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        Some(match suffix {
+            "d" => DataType::Double,
+            "f" => DataType::Float,
+            "i" => DataType::Int32,
+            "l" => DataType::Int64,
+            "b" => DataType::Boolean,
+            "v" => DataType::Variable,
+            "s" => DataType::String,
+            "inst" => DataType::Instance,
+            "del" => DataType::Delete,
+            "u" => DataType::Undefined,
+            "ui" => DataType::UnsignedInt,
+            "e" => DataType::Int16,
+            _ => DataType::Unknown(u8::from_str_radix(suffix.strip_prefix('t')?, 16).ok()?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Conv,
+    Mul,
+    Div,
+    Rem,
+    Mod,
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Neg,
+    Not,
+    Shl,
+    Shr,
+    Cmp,
+    Pop,
+    Dup,
+    Ret,
+    Exit,
+    Popz,
+    B,
+    Bt,
+    Bf,
+    PushEnv,
+    PopEnv,
+    Push,
+    PushLoc,
+    PushGlb,
+    PushBltn,
+    PushI,
+    Call,
+    CallV,
+    Break,
+}
+
+/// Comparison done by `cmp`, which GMS1 encodes as separate opcodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Lt = 1,
+    Le = 2,
+    Eq = 3,
+    Ne = 4,
+    Ge = 5,
+    Gt = 6,
+}
+
+const OPCODES: &[(Opcode, &str, u8, u8)] = &[
+    // (opcode, mnemonic, GMS2 byte, GMS1 byte)
+    (Opcode::Conv, "conv", 0x07, 0x03),
+    (Opcode::Mul, "mul", 0x08, 0x04),
+    (Opcode::Div, "div", 0x09, 0x05),
+    (Opcode::Rem, "rem", 0x0a, 0x06),
+    (Opcode::Mod, "mod", 0x0b, 0x07),
+    (Opcode::Add, "add", 0x0c, 0x08),
+    (Opcode::Sub, "sub", 0x0d, 0x09),
+    (Opcode::And, "and", 0x0e, 0x0a),
+    (Opcode::Or, "or", 0x0f, 0x0b),
+    (Opcode::Xor, "xor", 0x10, 0x0c),
+    (Opcode::Neg, "neg", 0x11, 0x0d),
+    (Opcode::Not, "not", 0x12, 0x0e),
+    (Opcode::Shl, "shl", 0x13, 0x0f),
+    (Opcode::Shr, "shr", 0x14, 0x10),
+    (Opcode::Cmp, "cmp", 0x15, 0x11),
+    (Opcode::Pop, "pop", 0x45, 0x41),
+    (Opcode::Dup, "dup", 0x86, 0x82),
+    (Opcode::Ret, "ret", 0x9c, 0x9d),
+    (Opcode::Exit, "exit", 0x9d, 0x9e),
+    (Opcode::Popz, "popz", 0x9e, 0x9f),
+    (Opcode::B, "b", 0xb6, 0xb7),
+    (Opcode::Bt, "bt", 0xb7, 0xb8),
+    (Opcode::Bf, "bf", 0xb8, 0xb9),
+    (Opcode::PushEnv, "pushenv", 0xba, 0xbb),
+    (Opcode::PopEnv, "popenv", 0xbb, 0xbc),
+    (Opcode::Push, "push", 0xc0, 0xc0),
+    (Opcode::PushLoc, "pushloc", 0xc1, 0xc0),
+    (Opcode::PushGlb, "pushglb", 0xc2, 0xc0),
+    (Opcode::PushBltn, "pushbltn", 0xc3, 0xc0),
+    (Opcode::PushI, "pushi", 0x84, 0xc0),
+    (Opcode::Call, "call", 0xd9, 0xda),
+    (Opcode::CallV, "callv", 0x99, 0x99),
+    (Opcode::Break, "break", 0xff, 0xff),
+];
+
+/// GMS1 opcodes for `cmp`, one per comparison starting from `Comparison::Lt`
+const GMS1_COMPARISONS: u8 = 0x11;
+
+impl Opcode {
+    /// Decode an opcode byte, along with the comparison for GMS1's comparison opcodes
+    pub fn from_byte(byte: u8, layout: CodeLayout) -> Option<(Self, Option<Comparison>)> {
+        if layout == CodeLayout::Inline && (GMS1_COMPARISONS..GMS1_COMPARISONS + 6).contains(&byte) {
+            return Some((Opcode::Cmp, Comparison::from_u8(byte - GMS1_COMPARISONS + 1)));
+        }
+
+        OPCODES.iter()
+            .find(|&&(_, _, gms2, gms1)| byte == if layout == CodeLayout::Inline { gms1 } else { gms2 })
+            .map(|&(opcode, ..)| (opcode, None))
+    }
+
+    /// Encode the opcode, with `comparison` picking the opcode for GMS1's `cmp`
+    pub fn to_byte(self, layout: CodeLayout, comparison: Option<Comparison>) -> u8 {
+        match (self, layout, comparison) {
+            (Opcode::Cmp, CodeLayout::Inline, Some(comparison)) => {
+                GMS1_COMPARISONS + comparison as u8 - 1
+            }
+            _ => {
+                let &(_, _, gms2, gms1) = OPCODES.iter().find(|&&(opcode, ..)| opcode == self).unwrap();
+                if layout == CodeLayout::Inline { gms1 } else { gms2 }
+            }
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        OPCODES.iter().find(|&&(opcode, ..)| opcode == self).unwrap().1
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        OPCODES.iter()
+            .find(|&&(_, name, ..)| name == mnemonic)
+            .map(|&(opcode, ..)| opcode)
+    }
+}
+
+impl Comparison {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        Some(match val {
+            1 => Comparison::Lt,
+            2 => Comparison::Le,
+            3 => Comparison::Eq,
+            4 => Comparison::Ne,
+            5 => Comparison::Ge,
+            6 => Comparison::Gt,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Comparison::Lt => "LT",
+            Comparison::Le => "LE",
+            Comparison::Eq => "EQ",
+            Comparison::Ne => "NEQ",
+            Comparison::Ge => "GE",
+            Comparison::Gt => "GT",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        (1..=6).filter_map(Self::from_u8).find(|comparison| comparison.name() == name)
+    }
+}
+
+/// How a variable reference accesses the variable, from the top bits of the reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariableKind {
+    Array,
+    StackTop,
+    Normal,
+    Instance,
+    ArrayPushAf,
+    ArrayPopAf,
+    Unknown(u8),
+}
+
+impl VariableKind {
+    pub fn from_bits(bits: u8) -> Self {
+        match bits {
+            0x00 => VariableKind::Array,
+            0x80 => VariableKind::StackTop,
+            0xa0 => VariableKind::Normal,
+            0xe0 => VariableKind::Instance,
+            0x10 => VariableKind::ArrayPushAf,
+            0x90 => VariableKind::ArrayPopAf,
+            bits => VariableKind::Unknown(bits),
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            VariableKind::Array => 0x00,
+            VariableKind::StackTop => 0x80,
+            VariableKind::Normal => 0xa0,
+            VariableKind::Instance => 0xe0,
+            VariableKind::ArrayPushAf => 0x10,
+            VariableKind::ArrayPopAf => 0x90,
+            VariableKind::Unknown(bits) => bits,
+        }
+    }
+
+    /// Prefix for the variable in the disassembly, empty for `Normal`
+    pub fn prefix(self) -> String {
+        match self {
+            VariableKind::Array => String::from("[array]"),
+            VariableKind::StackTop => String::from("[stacktop]"),
+            VariableKind::Normal => String::new(),
+            VariableKind::Instance => String::from("[instance]"),
+            VariableKind::ArrayPushAf => String::from("[arraypushaf]"),
+            VariableKind::ArrayPopAf => String::from("[arraypopaf]"),
+            VariableKind::Unknown(bits) => format!("[{:#x}]", bits),
+        }
+    }
+}
+
+const INSTANCES: &[(i16, &str)] = &[
+    (-1, "self"),
+    (-2, "other"),
+    (-3, "all"),
+    (-4, "noone"),
+    (-5, "global"),
+    (-6, "builtin"),
+    (-7, "local"),
+    (-9, "stacktop"),
+    (-15, "arg"),
+    (-16, "static"),
+];
+
+/// Name of an instance type, or the object index for non-negative ones
+pub fn instance_name(instance: i16) -> String {
+    INSTANCES.iter()
+        .find(|&&(id, _)| id == instance)
+        .map(|&(_, name)| String::from(name))
+        .unwrap_or_else(|| instance.to_string())
+}
+
+pub fn instance_from_name(name: &str) -> Option<i16> {
+    INSTANCES.iter()
+        .find(|&&(_, other)| other == name)
+        .map(|&(id, _)| id)
+        .or_else(|| name.parse().ok())
+}
+
+/// Reference to a VARI or FUNC entry. In the file, the low 27 bits hold the distance to the
+/// next use of the same entry (or, for the last use, the id of its name).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Variable {
+    pub instance: i16,
+    pub kind: VariableKind,
+    pub reference: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    None,
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Float(f32),
+    Double(f64),
+    Bool(bool),
+    /// Index into STRG
+    String(u32),
+    Variable(Variable),
+    Call { argument_count: u16, reference: u32 },
+    /// Distance to the target in bytes
+    Branch(i32),
+    /// `popenv` that leaves a `with` block early
+    PopEnvExit,
+    Comparison(Comparison),
+    /// The raw low 16 bits, for instructions where their meaning isn't known
+    Raw(u16),
+    /// `break` with an extra 32-bit operand
+    Break { kind: i16, value: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    /// Offset of the instruction within the bytecode it was decoded from
+    pub address: u32,
+    pub opcode: Opcode,
+    pub type1: DataType,
+    pub type2: DataType,
+    pub operand: Operand,
+}
+
+impl Instruction {
+    /// Size in bytes, including any operand words
+    pub fn size(&self) -> u32 {
+        match (self.opcode, self.operand) {
+            (_, Operand::Variable(_)) | (_, Operand::Call { .. }) => 8,
+            (_, Operand::Int32(_)) | (_, Operand::Float(_)) | (_, Operand::Bool(_)) => 8,
+            (_, Operand::String(_)) | (_, Operand::Break { .. }) => 8,
+            (_, Operand::Int64(_)) | (_, Operand::Double(_)) => 12,
+            _ => 4,
+        }
+    }
+
+    /// Offset of the variable or function reference word, if the instruction has one
+    pub fn reference_offset(&self) -> Option<u32> {
+        match self.operand {
+            Operand::Variable(_) | Operand::Call { .. } => Some(self.address + 4),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the instructions in a piece of bytecode
+pub struct Instructions<'a> {
+    bytecode: &'a [u8],
+    pos: usize,
+    layout: CodeLayout,
+}
+
+/// Decode `bytecode`, which uses the opcodes matching `layout`'s GameMaker version
+pub fn instructions(bytecode: &[u8], layout: CodeLayout) -> Instructions<'_> {
+    Instructions { bytecode, pos: 0, layout }
+}
+
+impl Instructions<'_> {
+    fn word(&self, pos: usize) -> Result<u32, Error> {
+        self.bytecode.get(pos..pos + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or_else(|| Error::new(pos, Expected::MoreData).in_section(b"CODE"))
+    }
+
+    fn decode(&self) -> Result<Instruction, Error> {
+        let address = self.pos;
+        let word = self.word(address)?;
+        let extra = (word & 0xffff) as u16;
+        let types = (word >> 16) as u8;
+        let type1 = DataType::from_nibble(types & 0xf);
+        let type2 = DataType::from_nibble(types >> 4);
+
+        let byte = (word >> 24) as u8;
+        let (opcode, comparison) = Opcode::from_byte(byte, self.layout)
+            .ok_or_else(|| Error::new(address, Expected::Opcode(byte)).in_section(b"CODE"))?;
+
+        let next = |n: usize| self.word(address + (n * 4));
+        let operand = match opcode {
+            Opcode::Cmp => match comparison {
+                Some(comparison) => Operand::Comparison(comparison),
+                None => Comparison::from_u8((extra >> 8) as u8)
+                    .map(Operand::Comparison)
+                    .unwrap_or(Operand::Raw(extra)),
+            },
+            Opcode::B | Opcode::Bt | Opcode::Bf | Opcode::PushEnv | Opcode::PopEnv => {
+                let offset = word & 0xff_ffff;
+                if self.layout == CodeLayout::Inline {
+                    Operand::Branch(((offset << 8) as i32 >> 8) * 4)
+                } else if opcode == Opcode::PopEnv && offset == 0xf0_0000 {
+                    Operand::PopEnvExit
+                } else {
+                    Operand::Branch((((offset << 9) as i32) >> 9) * 4)
+                }
+            }
+            Opcode::Pop if type1 == DataType::Int16 => Operand::Raw(extra),
+            Opcode::Pop => variable(extra, next(1)?),
+            Opcode::Push | Opcode::PushLoc | Opcode::PushGlb | Opcode::PushBltn | Opcode::PushI => {
+                match type1 {
+                    DataType::Int16 => Operand::Int16(extra as i16),
+                    DataType::Variable => variable(extra, next(1)?),
+                    DataType::Int32 => Operand::Int32(next(1)? as i32),
+                    DataType::Float => Operand::Float(f32::from_bits(next(1)?)),
+                    DataType::Boolean => Operand::Bool(next(1)? != 0),
+                    DataType::String => Operand::String(next(1)?),
+                    DataType::Int64 => Operand::Int64((next(1)? as u64 | ((next(2)? as u64) << 32)) as i64),
+                    DataType::Double => Operand::Double(f64::from_bits(next(1)? as u64 | ((next(2)? as u64) << 32))),
+                    _ => Operand::Raw(extra),
+                }
+            }
+            Opcode::Call => Operand::Call {
+                argument_count: extra,
+                reference: next(1)? & 0x07ff_ffff,
+            },
+            Opcode::Break if type1 == DataType::Int32 => Operand::Break {
+                kind: extra as i16,
+                value: next(1)? as i32,
+            },
+            Opcode::Break => Operand::Int16(extra as i16),
+            _ => Operand::Raw(extra),
+        };
+
+        Ok(Instruction { address: address as u32, opcode, type1, type2, operand })
+    }
+}
+
+fn variable(instance: u16, reference: u32) -> Operand {
+    Operand::Variable(Variable {
+        instance: instance as i16,
+        kind: VariableKind::from_bits((reference >> 24) as u8 & 0xf8),
+        reference: reference & 0x07ff_ffff,
+    })
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.bytecode.len() {
+            return None;
+        }
+
+        let instruction = self.decode();
+        match &instruction {
+            Ok(instruction) => self.pos += instruction.size() as usize,
+            Err(_) => self.pos = self.bytecode.len(),
+        }
+        Some(instruction)
+    }
+}
+
+/// Names of the variables and functions referenced at each address in CODE, found by
+/// following the occurrence chains of every VARI and FUNC entry
+#[derive(Debug, Default, Clone)]
+pub struct References {
+    pub variables: HashMap<u32, String>,
+    pub functions: HashMap<u32, String>,
+}
+
+impl References {
+    pub fn new(file: &FormFile) -> Self {
+        let mut references = Self::default();
+        let (code, strg) = match (file.code.as_ref(), file.strg.as_ref()) {
+            (Some(code), Some(strg)) => (code, strg),
+            _ => return references,
+        };
+
+        let variables = file.vari.iter()
+            .flat_map(|vari| &vari.variables)
+            .map(|variable| (variable.name_offset, variable.first_address, variable.occurrences));
+        for (name_offset, first_address, occurrences) in variables {
+            record(code, strg, name_offset, first_address, occurrences, &mut references.variables);
+        }
+
+        let functions = file.func.iter()
+            .flat_map(|func| &func.functions)
+            .map(|function| (function.name_offset, function.first_address, function.occurrences));
+        for (name_offset, first_address, occurrences) in functions {
+            record(code, strg, name_offset, first_address, occurrences, &mut references.functions);
+        }
+
+        references
+    }
+}
+
+/// Record the name at `name_offset` at the address of every instruction in an occurrence chain
+fn record(code: &Code, strg: &Strg, name_offset: StringRef, first_address: u32, occurrences: u32, names: &mut HashMap<u32, String>) {
+    let name = match strg.get(name_offset) {
+        Some(name) => name,
+        None => return,
+    };
+
+    for pos in code.occurrences(first_address, occurrences) {
+        if let Some(address) = code.address_of(pos) {
+            names.insert(address, name.clone());
+        }
+    }
+}
+
+/// Text listing of a code entry, with branch targets replaced by `[n]` labels
+pub fn disassemble(file: &FormFile, index: usize) -> Result<String, Error> {
+    let code = match file.code.as_ref() {
+        Some(code) => code,
+        None => return Ok(String::new()),
+    };
+    let entry = code.entries.get(index).ok_or_else(|| Error::no_entry(b"CODE", index))?;
+    let base = entry.address;
+    let references = References::new(file);
+
+    let instructions = instructions(code.bytecode_of(entry), file.bytecode_layout())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let labels = instructions.iter()
+        .filter_map(|instruction| match instruction.operand {
+            Operand::Branch(offset) => Some((instruction.address as i64 + offset as i64) as u32),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .enumerate()
+        .map(|(label, address)| (address, label))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut text = String::new();
+    for instruction in &instructions {
+        if let Some(label) = labels.get(&instruction.address) {
+            let _ = writeln!(text, ":[{}]", label);
+        }
+        let listing = Listing {
+            instruction,
+            address: base + instruction.address,
+            references: &references,
+            strg: file.strg.as_ref(),
+            labels: &labels,
+        };
+        let _ = writeln!(text, "{}", listing);
+    }
+    // branches past the last instruction
+    for label in labels.range(entry.length..).map(|(_, label)| label) {
+        let _ = writeln!(text, ":[{}]", label);
+    }
+
+    Ok(text)
+}
+
+/// Escape a string literal for the disassembly
+pub fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len() + 2);
+    escaped.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+struct Listing<'a> {
+    instruction: &'a Instruction,
+    /// Address of the instruction in the parsed file
+    address: u32,
+    references: &'a References,
+    strg: Option<&'a Strg>,
+    labels: &'a BTreeMap<u32, usize>,
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Instruction { opcode, type1, type2, operand, address } = self.instruction;
+
+        write!(f, "{}", opcode.mnemonic())?;
+        match opcode {
+            Opcode::B | Opcode::Bt | Opcode::Bf | Opcode::PushEnv | Opcode::PopEnv => {}
+            Opcode::Conv | Opcode::Mul | Opcode::Div | Opcode::Rem | Opcode::Mod | Opcode::Add |
+            Opcode::Sub | Opcode::And | Opcode::Or | Opcode::Xor | Opcode::Shl | Opcode::Shr |
+            Opcode::Cmp | Opcode::Pop => write!(f, ".{}.{}", type1.suffix(), type2.suffix())?,
+            _ => write!(f, ".{}", type1.suffix())?,
+        }
+
+        let name = |names: &HashMap<u32, String>, reference: u32| {
+            names.get(&self.address).cloned().unwrap_or_else(|| format!("?{}", reference))
+        };
+
+        match *operand {
+            Operand::None => Ok(()),
+            Operand::Int16(val) => write!(f, " {}", val),
+            Operand::Int32(val) => write!(f, " {}", val),
+            Operand::Int64(val) => write!(f, " {}", val),
+            Operand::Float(val) => write!(f, " {:?}", val),
+            Operand::Double(val) => write!(f, " {:?}", val),
+            Operand::Bool(val) => write!(f, " {}", val),
+            Operand::String(index) => {
+                match self.strg.and_then(|strg| strg.strings.get(index as usize)) {
+                    Some(string) => write!(f, " {}@{}", escape(string), index),
+                    None => write!(f, " ?@{}", index),
+                }
+            }
+            Operand::Variable(Variable { instance, kind, reference }) => write!(
+                f, " {}{}.{}",
+                kind.prefix(),
+                instance_name(instance),
+                name(&self.references.variables, reference)
+            ),
+            Operand::Call { argument_count, reference } => write!(
+                f, " {}(argc={})",
+                name(&self.references.functions, reference),
+                argument_count
+            ),
+            Operand::Branch(offset) => {
+                let target = (*address as i64 + offset as i64) as u32;
+                write!(f, " [{}]", self.labels[&target])
+            }
+            Operand::PopEnvExit => write!(f, " <drop>"),
+            Operand::Comparison(comparison) => write!(f, " {}", comparison.name()),
+            Operand::Raw(0) => Ok(()),
+            Operand::Raw(val) => write!(f, " {}", val),
+            Operand::Break { kind, value } => write!(f, " {} {}", kind, value),
+        }
+    }
+}
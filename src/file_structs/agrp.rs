@@ -2,33 +2,43 @@ use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
 pub struct Agrp {
-    pub audio_groups: Vec<u32>,
+    pub audio_groups: Vec<StringRef>,
     pub locations: HashMap<u32, usize>,
+    pub end_align: u32,
 }
 
-use nom::{IResult, multi::count};
-use super::PosSlice;
+use nom::{combinator::map, multi::count};
+use super::{PosSlice, PResult, StringRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::end_alignment;
 use super::byte_parsers::le_u32;
 
-fn get_agrp_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, u32> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_agrp_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<StringRef> {
+    let input = at_offset(input, offset, from)?;
 
-    le_u32(input)
+    map(le_u32, StringRef)(input)
 }
 
 impl super::ParseSection for Agrp {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let audio_groups =
             offsets.iter()
-            .map(|offset|{
-                get_agrp_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_agrp_entry_at_offset(input, *offset, from)
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let content_end = audio_groups.iter()
+            .map(|(rest, _)| rest.pos())
+            .max()
+            .unwrap_or_else(|| input.pos());
+
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let audio_groups = audio_groups.into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
@@ -38,7 +48,14 @@ impl super::ParseSection for Agrp {
             .collect::<HashMap<u32, usize>>();
 
         Ok((input, Self {
-            audio_groups, locations
+            audio_groups, locations, end_align
         }))
     }
 }
+
+impl WriteSection for Agrp {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.audio_groups, 1, |w, &name_offset| w.string(name_offset));
+        w.align(self.end_align);
+    }
+}
@@ -5,32 +5,44 @@ pub struct Audo {
     pub offset: u32,
     pub files: Vec<Vec<u8>>,
     pub locations: HashMap<u32, usize>,
+    pub entry_align: u32,
+    pub end_align: u32,
 }
 
-use nom::{IResult, multi::count};
-use super::PosSlice;
+use nom::multi::count;
+use super::{PosSlice, PResult, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::{common_alignment, end_alignment};
 use super::byte_parsers::{le_u32, le_u8};
 
-fn get_sond_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, Vec<u8>> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_sond_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<Vec<u8>> {
+    let input = at_offset(input, offset, from)?;
 
     take_audo_entry(input)
 }
 
 impl super::ParseSection for Audo {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let offset = input.pos() as u32 - 8;
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let files =
             offsets.iter()
-            .map(|offset|{
-                get_sond_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_sond_entry_at_offset(input, *offset, from)
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let content_end = files.iter()
+            .map(|(rest, _)| rest.pos())
+            .max()
+            .unwrap_or_else(|| input.pos());
+
+        let entry_align = common_alignment(&offsets, 4);
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let files = files.into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
@@ -40,12 +52,12 @@ impl super::ParseSection for Audo {
             .collect::<HashMap<u32, usize>>();
 
         Ok((input, Self {
-            offset, files, locations
+            offset, files, locations, entry_align, end_align
         }))
     }
 }
 
-pub fn take_audo_entry(input: PosSlice) -> IResult<PosSlice, Vec<u8>> {
+pub fn take_audo_entry(input: PosSlice) -> PResult<Vec<u8>> {
     let (input, size) = le_u32(input)?;
     count(le_u8, size as _)(input)
 }
@@ -71,7 +83,7 @@ impl Audo {
             pos += ((file_size + 3) & !3) + 4;
             (((file_size + 3) & !3) - file_size) as usize
         }).collect();
-        for (file, padding_amount) in self.files.iter().zip(padding_amounts.into_iter()) {
+        for (file, padding_amount) in self.files.iter().zip(padding_amounts) {
             f.write_all(&(file.len() as u32).to_le_bytes())?;
             f.write_all(file)?;
             f.write_all(&vec![0; padding_amount])?;
@@ -80,3 +92,13 @@ impl Audo {
         Ok(())
     }
 }
+
+impl WriteSection for Audo {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.files, self.entry_align, |w, file| {
+            w.u32(file.len() as u32);
+            w.bytes(file);
+        });
+        w.align(self.end_align);
+    }
+}
@@ -0,0 +1,142 @@
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, PResult, StringRef, TpagRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::{common_alignment, entry_ends};
+use super::byte_parsers::le_u32;
+
+/// Backgrounds, which GMS2 uses as the tile sets of tile layers
+#[derive(Debug, Clone)]
+pub struct Bgnd {
+    pub backgrounds: Vec<BgndEntry>,
+    /// Alignment of each entry
+    pub alignment: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BgndEntry {
+    pub name_offset: StringRef,
+    pub transparent: bool,
+    pub smooth: bool,
+    pub preload: bool,
+    pub tpag_offset: TpagRef,
+    /// GMS2 only
+    pub tile_set: Option<TileSet>,
+    /// Unparsed data between this entry and the next
+    pub trailing: Vec<u8>,
+}
+
+/// How a GMS2 background is cut up into tiles. On the texture each tile is surrounded by a
+/// border of `border` pixels.
+#[derive(Debug, Clone)]
+pub struct TileSet {
+    pub unk: u32, // 2
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub border_x: u32,
+    pub border_y: u32,
+    pub columns: u32,
+    pub frames_per_tile: u32,
+    pub tile_count: u32,
+    pub unk2: u32, // 0
+    /// Microseconds each frame of an animated tile is shown for
+    pub frame_time: u64,
+    /// For each tile, the tile shown on each of its frames
+    pub tile_ids: Vec<u32>,
+}
+
+/// Size of a GMS1 entry, which a GMS2 entry adds a tile set to
+const GMS1_ENTRY_SIZE: usize = 5 * 4;
+const TILE_SET_SIZE: usize = 11 * 4;
+
+impl super::ParseSection for Bgnd {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let (input, index_count) = le_u32(input)?;
+        let (input, offsets) = count(le_u32, index_count as _)(input)?;
+
+        let ends = entry_ends(&offsets, input.pos() + input.len());
+        let backgrounds = offsets.iter()
+            .zip(pointer_positions(input, &offsets))
+            .zip(ends)
+            .map(|((&offset, from), end)| BgndEntry::take_at(input, offset, from, end).map(|(_, entry)| entry))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((input, Self {
+            backgrounds,
+            alignment: common_alignment(&offsets, 8),
+        }))
+    }
+}
+
+impl BgndEntry {
+    fn take_at(input: PosSlice, offset: u32, from: usize, end: usize) -> PResult<Self> {
+        let input = at_offset(input, offset, from)?;
+        let is_gms2 = end.saturating_sub(input.pos()) >= GMS1_ENTRY_SIZE + TILE_SET_SIZE;
+
+        let (input, (name_offset, transparent, smooth, preload, tpag_offset)) =
+            tuple((le_u32, le_u32, le_u32, le_u32, le_u32))(input)?;
+
+        let (input, tile_set) = if is_gms2 {
+            let (input, (unk, tile_width, tile_height, border_x, border_y, columns)) =
+                tuple((le_u32, le_u32, le_u32, le_u32, le_u32, le_u32))(input)?;
+            let (input, (frames_per_tile, tile_count, unk2, frame_time_low, frame_time_high)) =
+                tuple((le_u32, le_u32, le_u32, le_u32, le_u32))(input)?;
+            let (input, tile_ids) = count(le_u32, (tile_count * frames_per_tile) as _)(input)?;
+            (input, Some(TileSet {
+                unk,
+                tile_width,
+                tile_height,
+                border_x,
+                border_y,
+                columns,
+                frames_per_tile,
+                tile_count,
+                unk2,
+                frame_time: frame_time_low as u64 | ((frame_time_high as u64) << 32),
+                tile_ids,
+            }))
+        } else {
+            (input, None)
+        };
+
+        let len = end.saturating_sub(input.pos()).min(input.len());
+        Ok((input, BgndEntry {
+            name_offset: StringRef(name_offset),
+            transparent: transparent != 0,
+            smooth: smooth != 0,
+            preload: preload != 0,
+            tpag_offset: TpagRef(tpag_offset),
+            tile_set,
+            trailing: input.1[..len].to_vec(),
+        }))
+    }
+
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.bool(self.transparent);
+        w.bool(self.smooth);
+        w.bool(self.preload);
+        w.tpag(self.tpag_offset);
+        if let Some(tile_set) = &self.tile_set {
+            w.u32(tile_set.unk);
+            w.u32(tile_set.tile_width);
+            w.u32(tile_set.tile_height);
+            w.u32(tile_set.border_x);
+            w.u32(tile_set.border_y);
+            w.u32(tile_set.columns);
+            w.u32(tile_set.frames_per_tile);
+            w.u32(tile_set.tile_count);
+            w.u32(tile_set.unk2);
+            w.u32(tile_set.frame_time as u32);
+            w.u32((tile_set.frame_time >> 32) as u32);
+            for &id in &tile_set.tile_ids {
+                w.u32(id);
+            }
+        }
+        w.bytes(&self.trailing);
+    }
+}
+
+impl WriteSection for Bgnd {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.backgrounds, self.alignment, |w, background| background.write(w));
+    }
+}
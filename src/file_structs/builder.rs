@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+use bimap::BiBTreeMap;
+use image::RgbaImage;
+use super::*;
+
+use super::writer::{placeholder, PLACEHOLDER_BASE};
+use super::references::relink;
+
+/// `(code, offset)` of an instruction referring to a variable or function
+type Use = (usize, u32);
+
+/// Assembles a FORM from scratch, for fixtures that don't depend on a real game
+///
+/// Resources refer to each other by the index returned when adding them:
+///
+/// ```
+/// use gm_data_win::file_structs::DataWinBuilder;
+///
+/// let mut builder = DataWinBuilder::new();
+/// let texture = builder.texture(&image::RgbaImage::new(16, 16));
+/// let frame = builder.texture_page(texture, ((0, 0), (16, 16)));
+/// let sprite = builder.sprite("spr_player", &[frame]);
+/// let object = builder.object("obj_player", sprite as i32);
+/// builder.room("rm_start", (320, 240), &[(16, 16, object)]);
+/// let data_win = builder.build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DataWinBuilder {
+    strings: Vec<String>,
+    sounds: Vec<SondEntry>,
+    audio_groups: Vec<Vec<Vec<u8>>>,
+    textures: Vec<TxtrEntry>,
+    texture_pages: Vec<TpagEntry>,
+    sprites: Vec<SprtEntry>,
+    backgrounds: Vec<BgndEntry>,
+    fonts: Vec<FontEntry>,
+    objects: Vec<ObjtEntry>,
+    rooms: Vec<RoomEntry>,
+    code_entries: Vec<CodeEntry>,
+    bytecode: Vec<u8>,
+    inline_code: bool,
+    /// Name and instance type of each variable, with the uses
+    variables: Vec<(StringRef, i32, Vec<Use>)>,
+    functions: Vec<(StringRef, Vec<Use>)>,
+    /// Name and CODE index of each script
+    scripts: Vec<(StringRef, u32)>,
+    global_inits: Vec<u32>,
+    /// Bytecode version declared in GEN8, if not the one CODE's layout implies
+    bytecode_version: Option<u8>,
+}
+
+impl DataWinBuilder {
+    pub fn new() -> Self {
+        let mut builder = Self::default();
+        builder.audio_groups.push(Vec::new());
+        builder
+    }
+
+    /// Add a string to STRG, returning the offset other entries should use to refer to it
+    ///
+    /// Adding the same string twice returns the same offset.
+    pub fn string(&mut self, string: &str) -> StringRef {
+        intern(&mut self.strings, string)
+    }
+
+    /// Add a string to STRG, returning its index, which is how bytecode refers to strings
+    pub fn string_id(&mut self, string: &str) -> u32 {
+        (self.string(string).0 - PLACEHOLDER_BASE) / 4
+    }
+
+    /// Add a sound stored in `audio_group`, where group 0 is data.win's own AUDO section
+    pub fn sound(&mut self, name: &str, audio_group: u32, data: &[u8]) -> usize {
+        let name_offset = self.string(name);
+        let group = audio_group as usize;
+        if self.audio_groups.len() <= group {
+            self.audio_groups.resize_with(group + 1, Vec::new);
+        }
+        self.audio_groups[group].push(data.to_vec());
+
+        self.sounds.push(SondEntry {
+            name_offset,
+            unk1: 0,
+            unk2: StringRef::default(),
+            name_offset2: name_offset,
+            unk3: 0,
+            play_speed: 1.0,
+            unk4: 0,
+            audiogroup_index: audio_group,
+            index_in_audiogroup: self.audio_groups[group].len() as u32 - 1,
+        });
+        self.sounds.len() - 1
+    }
+
+    /// Add a texture page, encoded as PNG
+    pub fn texture(&mut self, image: &RgbaImage) -> u16 {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(image.clone())
+            .write_to(&mut png, image::ImageFormat::PNG)
+            .expect("Failed to encode texture");
+
+        self.textures.push(TxtrEntry { unk1: 0, unk2: 0, png });
+        self.textures.len() as u16 - 1
+    }
+
+    /// Add a TPAG entry covering `bounds` on `texture`, returning the offset sprites and fonts
+    /// should use to refer to it
+    pub fn texture_page(&mut self, texture: u16, bounds: Rect16) -> TpagRef {
+        let (_, size) = bounds;
+        self.texture_pages.push(TpagEntry {
+            sprite_bounds: bounds,
+            unk2: ((0, 0), size),
+            size,
+            texture_index: texture,
+        });
+        TpagRef(placeholder(self.texture_pages.len() - 1))
+    }
+
+    /// Add a sprite with one frame per texture page, sized to the first frame
+    pub fn sprite(&mut self, name: &str, frames: &[TpagRef]) -> usize {
+        let name_offset = self.string(name);
+        let (width, height) = frames.first()
+            .map(|&TpagRef(frame)| self.texture_pages[((frame - PLACEHOLDER_BASE) / 4) as usize].size)
+            .unwrap_or((0, 0));
+        let (width, height) = (width as u32, height as u32);
+
+        self.sprites.push(SprtEntry {
+            name_offset,
+            size: (width, height),
+            bounds: ((0, width.saturating_sub(1)), (height.saturating_sub(1), 0)),
+            transparent: 0,
+            smooth: 0,
+            preload: 0,
+            bbox_mode: 0,
+            collision_type: 0,
+            origin: (0, 0),
+            unk3: 0,
+            unk4: 0,
+            playback_speed: 1.0,
+            playback_speed_type: 0,
+            tpag_offsets: frames.to_vec(),
+            unk_floats: Vec::new(),
+            masks: Some(Vec::new()),
+            trailing: Vec::new(),
+        });
+        self.sprites.len() - 1
+    }
+
+    /// Add a GMS2 tile set cut from `page` into tiles of `tile_size`, `columns` to a row and
+    /// with no border
+    pub fn tile_set(&mut self, name: &str, page: TpagRef, tile_size: (u32, u32), columns: u32) -> usize {
+        let name_offset = self.string(name);
+        let (width, height) = self.texture_pages[((page.0 - PLACEHOLDER_BASE) / 4) as usize].size;
+        let rows = height as u32 / tile_size.1.max(1);
+        let tile_count = columns.min(width as u32 / tile_size.0.max(1)) * rows;
+
+        self.backgrounds.push(BgndEntry {
+            name_offset,
+            transparent: false,
+            smooth: false,
+            preload: false,
+            tpag_offset: page,
+            tile_set: Some(TileSet {
+                unk: 2,
+                tile_width: tile_size.0,
+                tile_height: tile_size.1,
+                border_x: 0,
+                border_y: 0,
+                columns,
+                frames_per_tile: 1,
+                tile_count,
+                unk2: 0,
+                frame_time: 0,
+                tile_ids: (0..tile_count).collect(),
+            }),
+            trailing: Vec::new(),
+        });
+        self.backgrounds.len() - 1
+    }
+
+    /// Add a font drawn from `sheet`, with each glyph's bounds relative to the sheet
+    pub fn font(&mut self, name: &str, font_name: &str, size: f32, sheet: TpagRef, glyphs: &[(char, Rect16)]) -> usize {
+        let name = self.string(name);
+        let font_name = self.string(font_name);
+        let chars = glyphs.iter()
+            .map(|&(character, bounds)| FontChar {
+                character,
+                bounds,
+                origin: ((bounds.1).0, 0),
+                // no kerning pairs
+                trailing: 0u16.to_le_bytes().to_vec(),
+            })
+            .collect();
+
+        self.fonts.push(FontEntry {
+            name,
+            font_name,
+            size,
+            unk: (0, 0, 0, 0),
+            entire_font_tpag: sheet,
+            unk2: (1.0, 1.0),
+            unk3: 0,
+            chars,
+            trailing: Vec::new(),
+        });
+        self.fonts.len() - 1
+    }
+
+    /// Add a visible object with no events, using `sprite_index` (-1 for none)
+    pub fn object(&mut self, name: &str, sprite_index: i32) -> i32 {
+        let name_offset = self.string(name);
+        self.objects.push(ObjtEntry {
+            name_offset,
+            sprite_index,
+            is_visible: true,
+            is_solid: false,
+            depth: 0,
+            is_persistent: false,
+            parent_index: -100,
+            texture_mask_index: -1,
+            uses_physics: false,
+            is_sensor: false,
+            physics: ObjtPhysics {
+                shape: CollisionShape::Box,
+                density: 0.5,
+                restitution: 0.1,
+                collision_group: 0,
+                linear_damping: 0.1,
+                angular_damping: 0.1,
+                friction: 0.2,
+                start_awake: true,
+                kinematic: false,
+                collision_points: Vec::new(),
+            },
+            // one (empty) list per event type
+            event_lists: vec![ObjtEventList { events: Vec::new() }; 15],
+        });
+        self.objects.len() as i32 - 1
+    }
+
+    /// Make `object` inherit from `parent`
+    pub fn parent(&mut self, object: i32, parent: i32) -> &mut Self {
+        self.objects[object as usize].parent_index = parent;
+        self
+    }
+
+    /// Have `object` run the CODE entry at `code` on `event`
+    pub fn event(&mut self, object: i32, event: ObjectEvent, code: usize) -> &mut Self {
+        let action_name_offset = self.string("");
+        let list = &mut self.objects[object as usize].event_lists[event.event_type() as usize];
+        list.events.push(ObjtEvent {
+            subtype: event.subtype(),
+            actions: vec![EventAction {
+                lib_id: 1,
+                id: 603,
+                kind: 7,
+                use_relative: false,
+                is_question: false,
+                use_apply_to: true,
+                exe_type: 2,
+                action_name_offset,
+                code_id: code as i32,
+                argument_count: 1,
+                who: -1,
+                relative: false,
+                is_not: false,
+                unk: 0,
+            }],
+        });
+        self
+    }
+
+    /// Add a room with an instance of an object at each `(x, y, object)`
+    pub fn room(&mut self, name: &str, (width, height): (u32, u32), instances: &[(i32, i32, i32)]) -> usize {
+        let name_offset = self.string(name);
+        let caption_offset = self.string("");
+        let game_objects = instances.iter()
+            .enumerate()
+            .map(|(i, &(x, y, object))| GameObject {
+                x,
+                y,
+                bg_def_index: object,
+                instance_id: 100_000 + i as i32,
+                creation_code_id: -1,
+                scale_x: 1.0,
+                scale_y: 1.0,
+                image_speed: 1.0,
+                frame: 0,
+                color: RgbaColor { r: 0xff, g: 0xff, b: 0xff, a: 0xff },
+                rotation: 0.0,
+                vari_index: -1,
+            })
+            .collect();
+
+        self.rooms.push(RoomEntry {
+            name_offset,
+            caption_offset,
+            width,
+            height,
+            speed: 30,
+            persistent: false,
+            argb: 0,
+            draw_bg_color: true,
+            _unk1: 0,
+            flags: 0,
+            world: 0,
+            top: 0,
+            left: 0,
+            right: 1024,
+            bottom: 768,
+            gravity_x: 0.0,
+            gravity_y: 10.0,
+            meters_per_pixel: 0.1,
+            backgrounds: Vec::new(),
+            views: Vec::new(),
+            game_objects,
+            tiles: Vec::new(),
+            layers: Vec::new(),
+            sequences: Vec::new(),
+            has_sequences: false,
+        });
+        self.rooms.len() - 1
+    }
+
+    /// Add a layer to the room at `room`, drawn at `depth`
+    pub fn layer(&mut self, room: usize, name: &str, depth: i32, kind: LayerKind) -> &mut Self {
+        let name_offset = self.string(name);
+        let layers = &mut self.rooms[room].layers;
+        layers.push(Layer {
+            name_offset,
+            index: layers.len() as u32,
+            depth,
+            x_offset: 0.0,
+            y_offset: 0.0,
+            horizontal_speed: 0.0,
+            vertical_speed: 0.0,
+            is_visible: true,
+            kind,
+            trailing: Vec::new(),
+        });
+        self
+    }
+
+    /// Add a script's bytecode to CODE
+    pub fn code(&mut self, name: &str, bytecode: &[u8], locals_count: u16, arguments_count: u16) -> usize {
+        let name_offset = self.string(name);
+        self.code_entries.push(CodeEntry {
+            name_offset,
+            length: bytecode.len() as u32,
+            locals_count,
+            arguments_count,
+            unk_flag: false,
+            bytecode_start: self.bytecode.len() as u32,
+            address: PLACEHOLDER_BASE + self.bytecode.len() as u32,
+            offset: 0,
+        });
+        self.bytecode.extend_from_slice(bytecode);
+        self.code_entries.len() - 1
+    }
+
+    /// Add a VARI entry for a variable used by the instruction at each `(code, offset)`, with
+    /// `instance_type` -1 for self, -5 for global or -7 for local
+    pub fn variable(&mut self, name: &str, instance_type: i32, occurrences: &[(usize, u32)]) -> usize {
+        let name_offset = self.string(name);
+        self.variables.push((name_offset, instance_type, occurrences.to_vec()));
+        self.variables.len() - 1
+    }
+
+    /// Add a FUNC entry for a function called by the instruction at each `(code, offset)`
+    pub fn function(&mut self, name: &str, occurrences: &[(usize, u32)]) -> usize {
+        let name_offset = self.string(name);
+        self.functions.push((name_offset, occurrences.to_vec()));
+        self.functions.len() - 1
+    }
+
+    /// Add a SCPT entry naming the CODE entry at `code`
+    pub fn script(&mut self, name: &str, code: usize) -> usize {
+        let name_offset = self.string(name);
+        self.scripts.push((name_offset, code as u32));
+        self.scripts.len() - 1
+    }
+
+    /// Run the CODE entry at `code` when the game starts, from GLOB
+    pub fn global_init(&mut self, code: usize) -> &mut Self {
+        self.global_inits.push(code as u32);
+        self
+    }
+
+    /// Lay out CODE the way GMS1 does, with each entry's bytecode inline
+    pub fn inline_code(&mut self) -> &mut Self {
+        self.inline_code = true;
+        self
+    }
+
+    /// Declare `version` as the bytecode version in GEN8, instead of the last GMS1 version for
+    /// inline CODE or the first GMS2 one otherwise
+    pub fn bytecode_version(&mut self, version: u8) -> &mut Self {
+        self.bytecode_version = Some(version);
+        self
+    }
+
+    fn gen8(&self, strings: &mut Vec<String>) -> Gen8 {
+        let version = self.bytecode_version
+            .unwrap_or(if self.inline_code { GMS1_BYTECODE_VERSION } else { GMS2_BYTECODE_VERSION });
+        let name = intern(strings, "Fixture");
+        let last_instance_id = self.rooms.iter()
+            .flat_map(|room| &room.game_objects)
+            .map(|instance| instance.instance_id as u32)
+            .max()
+            .unwrap_or(100_000);
+        Gen8 {
+            unk: (version as u32) << 8,
+            internal_name_offset: name,
+            config_name_offset: intern(strings, "Default"),
+            last_instance_id,
+            last_tile_id: 10_000_000,
+            unk4: 0,
+            unk5: [0; 0x10],
+            internal_name_offset2: name,
+            unk6: if self.inline_code { 1 } else { 2 },
+            unk7: [0; 3],
+            window_size: (1024, 768),
+            game_options: GameOptions::new(),
+            unk8: 0,
+            unk9: 0,
+            unk10: [0; 3],
+            build_time: Default::default(),
+            game_name_offset: name,
+            unk12: 0,
+            unk13: 0,
+            unk14: [0; 3],
+            server_port: 0,
+            numbers: Vec::new(),
+            nonsense: [0; 0x40],
+            trailing: Vec::new(),
+        }
+    }
+
+    fn audo(files: &[Vec<u8>]) -> Audo {
+        Audo {
+            offset: 0,
+            files: files.to_vec(),
+            locations: HashMap::new(),
+            entry_align: 4,
+            end_align: 1,
+        }
+    }
+
+    /// The sections of data.win, in the order GameMaker writes them
+    pub fn sections(&self) -> Vec<Section> {
+        let mut strings = self.strings.clone();
+        let gen8 = self.gen8(&mut strings);
+        let audio_groups = (0..self.audio_groups.len())
+            .map(|i| {
+                let name = if i == 0 { String::from("audiogroup_default") } else { format!("audiogroup{}", i) };
+                intern(&mut strings, &name)
+            })
+            .collect();
+
+        let mut code = Code {
+            offset: 0,
+            entries: self.code_entries.clone(),
+            locations: HashMap::new(),
+            bytecode: self.bytecode.clone(),
+            layout: if self.inline_code { CodeLayout::Inline } else { CodeLayout::Shared },
+            end_align: 1,
+        };
+        // link up the occurrence chain running through the bytecode for each entry
+        let mut link = |StringRef(name_offset): StringRef, occurrences: &[(usize, u32)]| {
+            let mut positions = occurrences.iter()
+                .map(|&(index, offset)| (code.entries[index].bytecode_start + offset) as usize)
+                .collect::<Vec<_>>();
+            relink(&mut code, &mut positions, (name_offset - PLACEHOLDER_BASE) / 4)
+        };
+
+        let gms2 = !self.inline_code;
+        let mut ids = (0, 0);
+        let variables = self.variables.iter()
+            .map(|(name_offset, instance_type, occurrences)| {
+                let (occurrences, first_address) = link(*name_offset, occurrences);
+                let id = if *instance_type == -7 { &mut ids.1 } else { &mut ids.0 };
+                *id += 1;
+                VariEntry {
+                    name_offset: *name_offset,
+                    instance_type: if gms2 { *instance_type } else { 0 },
+                    id: if gms2 { *id - 1 } else { 0 },
+                    occurrences,
+                    first_address,
+                }
+            })
+            .collect();
+        let functions = self.functions.iter()
+            .map(|(name_offset, occurrences)| {
+                let (occurrences, first_address) = link(*name_offset, occurrences);
+                FuncEntry { name_offset: *name_offset, occurrences, first_address }
+            })
+            .collect();
+        let string_locations = (0..strings.len()).map(|i| (placeholder(i), i)).collect::<BiBTreeMap<_, _>>();
+
+        vec![
+            Section::Gen8(gen8),
+            Section::Sond(Sond {
+                sounds: self.sounds.clone(),
+                locations: HashMap::new(),
+                end_align: 1,
+            }),
+            Section::Agrp(Agrp {
+                audio_groups,
+                locations: HashMap::new(),
+                end_align: 1,
+            }),
+            Section::Sprt(Sprt {
+                sprites: self.sprites.clone(),
+                locations: HashMap::new(),
+            }),
+            Section::Bgnd(Bgnd {
+                backgrounds: self.backgrounds.clone(),
+                alignment: 8,
+            }),
+            Section::Scpt(Scpt {
+                scripts: self.scripts.iter()
+                    .map(|&(name_offset, code_id)| ScptEntry { name_offset, code_id })
+                    .collect(),
+                end_align: 1,
+            }),
+            Section::Glob(Glob {
+                code_ids: self.global_inits.clone(),
+                end_align: 1,
+            }),
+            Section::Font(Font {
+                fonts: self.fonts.clone(),
+                locations: HashMap::new(),
+            }),
+            Section::Objt(Objt {
+                entries: self.objects.clone(),
+                end_align: 1,
+            }),
+            Section::Room(Room {
+                entries: self.rooms.clone(),
+            }),
+            Section::Tpag(Tpag {
+                texture_pages: self.texture_pages.clone(),
+                locations: (0..self.texture_pages.len()).map(|i| (placeholder(i), i)).collect(),
+                entry_align: 4,
+                end_align: 4,
+            }),
+            Section::Code(code),
+            Section::Vari(Vari {
+                header: Some((ids.0 as u32, ids.0 as u32, ids.1 as u32)).filter(|_| gms2),
+                variables,
+                end_align: 1,
+            }),
+            Section::Func(Func {
+                functions,
+                code_locals: Some(Vec::new()).filter(|_| gms2),
+                end_align: 1,
+            }),
+            Section::Strg(Strg::new(strings, string_locations, 4, 4)),
+            Section::Txtr(Txtr {
+                offset: 0,
+                files: self.textures.clone(),
+                locations: HashMap::new(),
+                png_align: 0x80,
+            }),
+            Section::Audo(Self::audo(&self.audio_groups[0])),
+        ]
+    }
+
+    /// Write out data.win
+    pub fn build(&self) -> Vec<u8> {
+        write_data_win_file(&self.sections())
+    }
+
+    /// Write out the audiogroup files for every group after the default one, in order
+    pub fn build_audio_groups(&self) -> Vec<Vec<u8>> {
+        self.audio_groups[1..].iter()
+            .map(|files| write_data_win_file(&[Section::Audo(Self::audo(files))]))
+            .collect()
+    }
+}
+
+/// Reference to `string` in `strings`, adding it if it isn't there yet
+fn intern(strings: &mut Vec<String>, string: &str) -> StringRef {
+    let index = strings.iter().position(|existing| existing == string).unwrap_or_else(|| {
+        strings.push(string.to_owned());
+        strings.len() - 1
+    });
+    StringRef(placeholder(index))
+}
@@ -3,11 +3,11 @@ mod nom_impl {
 }
 
 use super::PosSlice;
-use nom::IResult;
+use super::PResult;
 use nom::error::*;
 use nom::Err;
 
-pub fn le_u32(i: PosSlice) -> IResult<PosSlice, u32> {
+pub fn le_u32(i: PosSlice) -> PResult<u32> {
     let input = i.1;
     nom_impl::le_u32(input)
         .map(|(_, val)| (i.slice(4, i.1.len()), val))
@@ -16,7 +16,7 @@ pub fn le_u32(i: PosSlice) -> IResult<PosSlice, u32> {
         )
 }
 
-pub fn le_f32(i: PosSlice) -> IResult<PosSlice, f32> {
+pub fn le_f32(i: PosSlice) -> PResult<f32> {
     let input = i.1;
     nom_impl::le_f32(input)
         .map(|(_, val)| (i.slice(4, i.1.len()), val))
@@ -25,7 +25,7 @@ pub fn le_f32(i: PosSlice) -> IResult<PosSlice, f32> {
         )
 }
 
-pub fn le_u16(i: PosSlice) -> IResult<PosSlice, u16> {
+pub fn le_u16(i: PosSlice) -> PResult<u16> {
     let input = i.1;
     nom_impl::le_u16(input)
         .map(|(_, val)| (i.slice(2, i.1.len()), val))
@@ -34,7 +34,7 @@ pub fn le_u16(i: PosSlice) -> IResult<PosSlice, u16> {
         )
 }
 
-pub fn le_u8(i: PosSlice) -> IResult<PosSlice, u8> {
+pub fn le_u8(i: PosSlice) -> PResult<u8> {
     let input = i.1;
     nom_impl::le_u8(input)
         .map(|(_, val)| (i.slice(1, i.1.len()), val))
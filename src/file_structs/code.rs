@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Code {
+    pub offset: usize,
+    pub entries: Vec<CodeEntry>,
+    pub locations: HashMap<u32, usize>,
+    /// Bytecode of every entry, laid out the way the file stores it
+    pub bytecode: Vec<u8>,
+    pub layout: CodeLayout,
+    pub end_align: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeLayout {
+    /// GMS1: each entry is directly followed by its bytecode
+    Inline,
+    /// GMS2: all bytecode is stored in one blob ahead of the entries, which point into it
+    Shared,
+    /// YYC games compile scripts to native code and leave CODE empty
+    Empty,
+}
+
+#[derive(Debug, Clone)]
+pub struct CodeEntry {
+    pub name_offset: StringRef,
+    pub length: u32,
+    /// Always 0 for `CodeLayout::Inline`, where these are stored elsewhere
+    pub locals_count: u16,
+    pub arguments_count: u16,
+    pub unk_flag: bool, // high bit of the arguments count
+    /// Start of the entry's bytecode in `Code::bytecode`
+    pub bytecode_start: u32,
+    /// Address of the bytecode in the parsed file, which is how VARI and FUNC refer to it
+    pub address: u32,
+    /// Where execution starts within the bytecode. Non-zero for functions sharing the
+    /// bytecode of the script they're declared in.
+    pub offset: u32,
+}
+
+use nom::{bytes::complete::take, sequence::tuple};
+use nom::multi::count;
+use super::{PosSlice, PResult, StringRef, Error, Expected, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::end_alignment;
+use super::byte_parsers::{le_u32, le_u16};
+
+impl super::ParseSection for Code {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let offset = input.pos() - 8;
+        if input.is_empty() {
+            return Ok((input, Self {
+                offset,
+                entries: Vec::new(),
+                locations: HashMap::new(),
+                bytecode: Vec::new(),
+                layout: CodeLayout::Empty,
+                end_align: 1,
+            }));
+        }
+
+        let (input, index_count) = le_u32(input)?;
+        let (input, offsets) = count(le_u32, index_count as _)(input)?;
+
+        let layout = match offsets.first() {
+            Some(&first) if first as usize == input.pos() => CodeLayout::Inline,
+            _ => CodeLayout::Shared,
+        };
+
+        let mut bytecode = Vec::new();
+        let mut content_end = input.pos();
+        let mut entries = Vec::with_capacity(offsets.len());
+        match layout {
+            CodeLayout::Inline => for (&offset, from) in offsets.iter().zip(pointer_positions(input, &offsets)) {
+                let (rest, (name_offset, length)) = tuple((le_u32, le_u32))(at_offset(input, offset, from)?)?;
+                let address = rest.pos() as u32;
+                let (rest, code) = take(length as usize)(rest)?;
+                entries.push(CodeEntry {
+                    name_offset: StringRef(name_offset),
+                    length,
+                    locals_count: 0,
+                    arguments_count: 0,
+                    unk_flag: false,
+                    bytecode_start: bytecode.len() as u32,
+                    address,
+                    offset: 0,
+                });
+                bytecode.extend_from_slice(code.1);
+                content_end = content_end.max(rest.pos());
+            },
+            _ => {
+                let blob_end = offsets.iter().min().map(|&min| min as usize).unwrap_or(content_end);
+                let blob = input.1.get(..blob_end.saturating_sub(input.pos()))
+                    .ok_or_else(|| nom::Err::Error(Error::new(input.pos(), Expected::OffsetInSection(blob_end as u32))))?;
+
+                for (&offset, from) in offsets.iter().zip(pointer_positions(input, &offsets)) {
+                    let (rest, (name_offset, length, locals_count, arguments_count)) =
+                        tuple((le_u32, le_u32, le_u16, le_u16))(at_offset(input, offset, from)?)?;
+                    let address_pos = rest.pos();
+                    let (rest, (relative_address, code_offset)) = tuple((le_u32, le_u32))(rest)?;
+
+                    let address = (address_pos as i64 + relative_address as i32 as i64) as usize;
+                    let bytecode_start = address.checked_sub(input.pos())
+                        .filter(|&start| start + length as usize <= blob.len())
+                        .ok_or_else(|| nom::Err::Error(
+                            Error::new(address_pos, Expected::OffsetInSection(address as u32))
+                        ))?;
+
+                    entries.push(CodeEntry {
+                        name_offset: StringRef(name_offset),
+                        length,
+                        locals_count,
+                        arguments_count: arguments_count & 0x7fff,
+                        unk_flag: arguments_count & 0x8000 != 0,
+                        bytecode_start: bytecode_start as u32,
+                        address: address as u32,
+                        offset: code_offset,
+                    });
+                    content_end = content_end.max(rest.pos());
+                }
+                bytecode = blob.to_vec();
+            }
+        }
+
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let locations = offsets.iter()
+            .enumerate()
+            .map(|(a,b)| (*b, a))
+            .collect::<HashMap<u32, usize>>();
+
+        Ok((input, Self {
+            offset, entries, locations, bytecode, layout, end_align
+        }))
+    }
+}
+
+impl Code {
+    pub fn get(&self, loc: u32) -> Option<&CodeEntry> {
+        Some(&self.entries[*self.locations.get(&loc)?])
+    }
+
+    /// Index of the entry whose bytecode contains `pos` in `bytecode`
+    pub fn entry_at(&self, pos: usize) -> Option<usize> {
+        self.entries.iter().position(|entry| {
+            let start = entry.bytecode_start as usize;
+            (start..start + entry.length as usize).contains(&pos)
+        })
+    }
+
+    /// Position in `bytecode` of an address in the parsed file
+    pub fn bytecode_pos(&self, address: u32) -> Option<usize> {
+        self.entries.iter().find_map(|entry| {
+            let offset = address.checked_sub(entry.address).filter(|&offset| offset < entry.length)?;
+            Some((entry.bytecode_start + offset) as usize)
+        })
+    }
+
+    /// Address in the parsed file of `pos` in `bytecode`
+    pub fn address_of(&self, pos: usize) -> Option<u32> {
+        let entry = &self.entries[self.entry_at(pos)?];
+        Some(entry.address + (pos as u32 - entry.bytecode_start))
+    }
+
+    /// Where `pos` in `bytecode` will be written, relative to the start of the bytecode.
+    /// Inline bytecode is interleaved with the 8 byte entry headers.
+    pub(crate) fn relative_address(&self, pos: usize) -> Option<u32> {
+        match self.layout {
+            CodeLayout::Inline => Some(pos as u32 + (8 * (self.entry_at(pos)? as u32 + 1))),
+            _ => Some(pos as u32),
+        }
+    }
+
+    /// Inverse of `relative_address`
+    pub(crate) fn pos_at_relative(&self, relative: u32) -> Option<usize> {
+        match self.layout {
+            CodeLayout::Inline => (0..self.entries.len()).find_map(|index| {
+                let pos = relative.checked_sub(8 * (index as u32 + 1))? as usize;
+                Some(pos).filter(|&pos| self.entry_at(pos) == Some(index))
+            }),
+            _ => Some(relative as usize).filter(|&pos| pos < self.bytecode.len()),
+        }
+    }
+
+    /// Positions in `bytecode` of the `count` instructions in the occurrence chain starting at
+    /// `first_address`. Each reference word holds the distance to the next instruction in
+    /// the chain in its low 27 bits.
+    pub fn occurrences(&self, first_address: u32, count: u32) -> Vec<usize> {
+        let mut positions = Vec::new();
+        let mut pos = self.bytecode_pos(first_address);
+        while let Some(current) = pos {
+            positions.push(current);
+            if positions.len() >= count as usize {
+                break;
+            }
+            let distance = self.bytecode.get(current + 4..current + 8)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) & 0x07ff_ffff);
+            pos = distance
+                .and_then(|distance| Some(self.relative_address(current)? + distance))
+                .and_then(|relative| self.pos_at_relative(relative));
+        }
+        positions
+    }
+
+    /// Replace the bytecode of the entry at `index` (and of any entries sharing it), moving
+    /// everything after it along. Returns the range the old bytecode covered.
+    ///
+    /// The replaced entries get an address past every other entry's, so addresses in the new
+    /// bytecode can't be mistaken for ones in the parsed file.
+    pub fn splice(&mut self, index: usize, bytecode: &[u8]) -> std::ops::Range<usize> {
+        let start = self.entries[index].bytecode_start;
+        let old_len = self.entries[index].length;
+        let new_len = bytecode.len() as u32;
+        let range = start as usize..(start + old_len) as usize;
+        self.bytecode.splice(range.clone(), bytecode.iter().copied());
+
+        let address = self.entries.iter()
+            .map(|entry| entry.address + entry.length)
+            .max()
+            .unwrap_or(0);
+        for entry in &mut self.entries {
+            if entry.bytecode_start == start {
+                entry.length = new_len;
+                entry.address = address;
+            } else if entry.bytecode_start >= start + old_len {
+                entry.bytecode_start = entry.bytecode_start - old_len + new_len;
+            }
+        }
+
+        range
+    }
+
+    /// The bytecode `entry` runs, starting from the beginning of the script it belongs to
+    pub fn bytecode_of(&self, entry: &CodeEntry) -> &[u8] {
+        let start = entry.bytecode_start as usize;
+        &self.bytecode[start..start + entry.length as usize]
+    }
+}
+
+impl WriteSection for Code {
+    fn write(&self, w: &mut Writer) {
+        match self.layout {
+            CodeLayout::Empty => {}
+            CodeLayout::Inline => {
+                w.ptr_list(&self.entries, 1, |w, entry| {
+                    w.string(entry.name_offset);
+                    w.u32(entry.length);
+                    let pos = w.pos();
+                    w.record_code(entry.address, pos, entry.length);
+                    w.bytes(self.bytecode_of(entry));
+                });
+            }
+            CodeLayout::Shared => {
+                w.u32(self.entries.len() as u32);
+                let ptrs = self.entries.iter().map(|_| w.reserve_u32()).collect::<Vec<_>>();
+                let blob_start = w.pos();
+                w.bytes(&self.bytecode);
+
+                for (ptr, entry) in ptrs.into_iter().zip(&self.entries) {
+                    let pos = w.pos();
+                    w.patch_u32(ptr, pos);
+                    w.string(entry.name_offset);
+                    w.u32(entry.length);
+                    w.u16(entry.locals_count);
+                    w.u16(entry.arguments_count | if entry.unk_flag { 0x8000 } else { 0 });
+                    let address = blob_start + entry.bytecode_start;
+                    w.record_code(entry.address, address, entry.length);
+                    let pos = w.pos();
+                    w.i32(address as i32 - pos as i32);
+                    w.u32(entry.offset);
+                }
+            }
+        }
+        w.align(self.end_align);
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::io;
+
+use nom::error::{ErrorKind, ParseError};
+use super::PosSlice;
+
+/// Result of a parser over a `PosSlice`, failing with a crate [`Error`]
+pub type PResult<'a, T> = nom::IResult<PosSlice<'a>, T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse {
+        /// Magic of the section being parsed, if the error happened inside one
+        section: Option<String>,
+        /// Absolute offset into the file where parsing failed
+        offset: usize,
+        expected: Expected,
+    },
+    /// An entry was asked for by an index past the end of its section
+    NoEntry {
+        /// Magic of the section
+        section: String,
+        index: usize,
+    },
+    /// Writing would move something an unknown section may point to. Its pointers can't be
+    /// relocated without knowing its layout, so they'd be left pointing at the wrong data.
+    Unrelocatable {
+        /// Magic of the unknown section
+        section: String,
+        /// Magic of the section that moved, which is `section` itself if that's what moved
+        moved: String,
+    },
+}
+
+/// A section an edit needs isn't in the file, by its magic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingSection(pub &'static str);
+
+/// What the parser was expecting to find when it failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    MoreData,
+    Magic(&'static [u8]),
+    SectionHeader,
+    OffsetInSection(u32),
+    Utf8String,
+    Utf16Char(u16),
+    Opcode(u8),
+    Structure(String),
+    Nom(ErrorKind),
+}
+
+impl Error {
+    pub fn new(offset: usize, expected: Expected) -> Self {
+        Error::Parse {
+            section: None,
+            offset,
+            expected,
+        }
+    }
+
+    /// There's no entry `index` in the section with `magic`
+    pub fn no_entry(magic: &[u8], index: usize) -> Self {
+        Error::NoEntry {
+            section: String::from_utf8_lossy(magic).into_owned(),
+            index,
+        }
+    }
+
+    /// Attach the magic of the section the error occurred in, unless one is already set
+    pub fn in_section(self, magic: &[u8]) -> Self {
+        match self {
+            Error::Parse { section: None, offset, expected } => Error::Parse {
+                section: Some(String::from_utf8_lossy(magic).into_owned()),
+                offset,
+                expected,
+            },
+            err => err,
+        }
+    }
+
+    pub fn section(&self) -> Option<&str> {
+        match self {
+            Error::Parse { section, .. } => section.as_deref(),
+            Error::NoEntry { section, .. } | Error::Unrelocatable { section, .. } => Some(section),
+            Error::Io(_) => None,
+        }
+    }
+
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::Parse { offset, .. } => Some(*offset),
+            Error::Io(_) | Error::NoEntry { .. } | Error::Unrelocatable { .. } => None,
+        }
+    }
+
+    /// Convert a binrw error from reading at `fallback_pos`, preferring the position binrw reports
+    pub(crate) fn from_binrw(err: binrw::Error, fallback_pos: usize) -> Self {
+        let (pos, message) = match err {
+            binrw::Error::BadMagic { pos, found } => (pos, format!("magic (found {:?})", found)),
+            binrw::Error::AssertFail { pos, message } => (pos, message),
+            binrw::Error::Custom { pos, err } => (pos, format!("{:?}", err)),
+            binrw::Error::NoVariantMatch { pos } | binrw::Error::EnumErrors { pos, .. } => {
+                (pos, String::from("a known enum variant"))
+            }
+            binrw::Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Error::new(fallback_pos, Expected::MoreData)
+            }
+            err => (fallback_pos as u64, err.to_string()),
+        };
+
+        Error::new(pos as usize, Expected::Structure(message))
+    }
+}
+
+/// Turn a `nom::Err` into a hard failure so `alt` doesn't fall back to `Section::Unk`
+pub(crate) fn failure(err: nom::Err<Error>) -> nom::Err<Error> {
+    match err {
+        nom::Err::Error(err) => nom::Err::Failure(err),
+        err => err,
+    }
+}
+
+impl From<nom::Err<Error>> for Error {
+    fn from(err: nom::Err<Error>) -> Self {
+        match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => err,
+            nom::Err::Incomplete(_) => Error::new(0, Expected::MoreData),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl<'a> ParseError<PosSlice<'a>> for Error {
+    fn from_error_kind(input: PosSlice<'a>, kind: ErrorKind) -> Self {
+        let expected = match kind {
+            ErrorKind::Eof | ErrorKind::Count => Expected::MoreData,
+            kind => Expected::Nom(kind),
+        };
+
+        Error::new(input.pos(), expected)
+    }
+
+    fn append(_: PosSlice<'a>, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::MoreData => write!(f, "unexpected end of data"),
+            Expected::Magic(magic) => {
+                write!(f, "expected magic '{}'", String::from_utf8_lossy(magic))
+            }
+            Expected::SectionHeader => write!(f, "expected a section header"),
+            Expected::OffsetInSection(offset) => {
+                write!(f, "offset {:#x} points outside section", offset)
+            }
+            Expected::Utf8String => write!(f, "expected a UTF-8 string"),
+            Expected::Utf16Char(c) => write!(f, "expected a UTF-16 character, found {:#x}", c),
+            Expected::Opcode(opcode) => write!(f, "expected an instruction, found opcode {:#x}", opcode),
+            Expected::Structure(message) => write!(f, "expected {}", message),
+            Expected::Nom(kind) => write!(f, "parser error ({:?})", kind),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Parse { section: Some(section), offset, expected } => {
+                write!(f, "{} at {:#x}: {}", section, offset, expected)
+            }
+            Error::Parse { section: None, offset, expected } => {
+                write!(f, "at {:#x}: {}", offset, expected)
+            }
+            Error::NoEntry { section, index } => write!(f, "{} has no entry {}", section, index),
+            Error::Unrelocatable { section, moved } if section == moved => {
+                write!(f, "{} would move, and the pointers in it can't be relocated", section)
+            }
+            Error::Unrelocatable { section, moved } => {
+                write!(f, "{} would move, and the pointers {} may have into it can't be relocated", moved, section)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for MissingSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no {} section", self.0)
+    }
+}
+
+impl std::error::Error for MissingSection {}
@@ -6,13 +6,13 @@ pub struct Font {
     pub locations: HashMap<u32, usize>,
 }
 
-use nom::{IResult, multi::count, sequence::tuple};
-use super::PosSlice;
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, Rect16, Point16, PResult, StringRef, TpagRef, Error, Expected, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::entry_ends;
 use super::byte_parsers::{le_u32, le_f32, le_u16};
 
-fn get_font_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, FontEntry> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_font_entry_at_offset(input: PosSlice, offset: u32, from: usize, end: usize) -> PResult<FontEntry> {
+    let input = at_offset(input, offset, from)?;
 
     let (input, (
         name,
@@ -38,29 +38,46 @@ fn get_font_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, F
 
     let chars =
         offsets.iter()
-        .map(|offset|{
-            get_char_entry_at_offset(input, *offset)
+        .zip(pointer_positions(input, &offsets))
+        .map(|(offset, from)|{
+            get_char_entry_at_offset(input, *offset, from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // anything between one glyph and the next is kept as-is
+    let glyphs_end = chars.iter()
+        .map(|(rest, _)| rest.pos())
+        .max()
+        .unwrap_or_else(|| input.pos());
+    let char_ends = entry_ends(&offsets, glyphs_end);
+
+    let chars = chars.into_iter()
+        .zip(char_ends)
+        .map(|((rest, mut font_char), end)| {
+            font_char.trailing = rest.1[..end.saturating_sub(rest.pos()).min(rest.len())].to_vec();
+            font_char
         })
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .map(|(_, a)| a)
         .collect::<Vec<_>>();
 
+    let trailing = input.at(glyphs_end)
+        .map(|rest| rest.1[..end.saturating_sub(glyphs_end).min(rest.len())].to_vec())
+        .unwrap_or_default();
+
     Ok((input, FontEntry {
-        name,
-        font_name,
+        name: StringRef(name),
+        font_name: StringRef(font_name),
         size,
         unk,
-        entire_font_tpag,
+        entire_font_tpag: TpagRef(entire_font_tpag),
         unk2,
         unk3,
-        chars
+        chars,
+        trailing,
     }))
 }
 
-fn get_char_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, FontChar> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_char_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<FontChar> {
+    let input = at_offset(input, offset, from)?;
 
     let (input, (
         character,
@@ -75,48 +92,56 @@ fn get_char_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, F
         tuple((le_u16, le_u16)),
     ))(input)?;
 
-    let character: char = std::char::decode_utf16(std::iter::once(character))
-                        .collect::<Result<Vec<_>, _>>()
-                        .unwrap()[0];
+    let character = std::char::decode_utf16(std::iter::once(character))
+                        .next()
+                        .and_then(Result::ok)
+                        .ok_or_else(|| nom::Err::Error(
+                            Error::new(input.pos() - 14, Expected::Utf16Char(character))
+                        ))?;
 
     Ok((input, FontChar{
         character,
         bounds,
-        origin
+        origin,
+        trailing: Vec::new(),
     }))
 }
 
-type Rect16 = (Point16, Point16);
-type Point16 = (u16, u16);
-
 #[derive(Debug, Clone)]
 pub struct FontChar {
     pub character: char,
     pub bounds: Rect16,
     pub origin: Point16,
+    /// Unparsed data between this glyph and the next (kerning)
+    pub trailing: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FontEntry {
-    pub name: u32,
-    pub font_name: u32,
+    pub name: StringRef,
+    pub font_name: StringRef,
     pub size: f32,
     pub unk: (u32, u32, u32, u32),
-    pub entire_font_tpag: u32,
+    pub entire_font_tpag: TpagRef,
     pub unk2: (f32, f32),
     pub unk3: u32,
     pub chars: Vec<FontChar>,
+    /// Unparsed data between the last glyph and the next font
+    pub trailing: Vec<u8>,
 }
 
 impl super::ParseSection for Font {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
+        let ends = entry_ends(&offsets, input.pos() + input.len());
         let fonts =
             offsets.iter()
-            .map(|offset|{
-                get_font_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .zip(ends)
+            .map(|((offset, from), end)|{
+                get_font_entry_at_offset(input, *offset, from, end)
             })
             .collect::<Result<Vec<_>, _>>()?
             .into_iter()
@@ -139,3 +164,39 @@ impl Font {
         Some(&self.fonts[*self.locations.get(&loc)?])
     }
 }
+
+impl FontChar {
+    pub fn write(&self, w: &mut Writer) {
+        let mut utf16 = [0; 2];
+        self.character.encode_utf16(&mut utf16);
+        let ((x, y), (width, height)) = self.bounds;
+        for val in [utf16[0], x, y, width, height, self.origin.0, self.origin.1] {
+            w.u16(val);
+        }
+        w.bytes(&self.trailing);
+    }
+}
+
+impl FontEntry {
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name);
+        w.string(self.font_name);
+        w.f32(self.size);
+        let (unk0, unk1, unk2, unk3) = self.unk;
+        for val in [unk0, unk1, unk2, unk3] {
+            w.u32(val);
+        }
+        w.tpag(self.entire_font_tpag);
+        w.f32(self.unk2.0);
+        w.f32(self.unk2.1);
+        w.u32(self.unk3);
+        w.ptr_list(&self.chars, 1, |w, font_char| font_char.write(w));
+        w.bytes(&self.trailing);
+    }
+}
+
+impl WriteSection for Font {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.fonts, 1, |w, font| font.write(w));
+    }
+}
@@ -0,0 +1,113 @@
+#[derive(Debug, Clone)]
+pub struct Func {
+    pub functions: Vec<FuncEntry>,
+    /// Names of the local variables of each script. GMS1 doesn't store these.
+    pub code_locals: Option<Vec<CodeLocals>>,
+    pub end_align: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuncEntry {
+    pub name_offset: StringRef,
+    pub occurrences: u32,
+    /// Address in CODE of the first instruction calling the function
+    pub first_address: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct CodeLocals {
+    /// Name of the CODE entry the locals belong to
+    pub name_offset: StringRef,
+    /// Index and name of each local
+    pub locals: Vec<(u32, StringRef)>,
+}
+
+use nom::{combinator::map, multi::count, sequence::tuple};
+use super::{PosSlice, PResult, Error, Expected, StringRef, Writer, WriteSection};
+use super::helpers::end_alignment;
+use super::byte_parsers::le_u32;
+
+fn take_function(input: PosSlice) -> PResult<FuncEntry> {
+    let (input, (name_offset, occurrences, first_address)) = tuple((le_u32, le_u32, le_u32))(input)?;
+
+    Ok((input, FuncEntry {
+        name_offset: StringRef(name_offset), occurrences, first_address
+    }))
+}
+
+fn take_code_locals(input: PosSlice) -> PResult<CodeLocals> {
+    let (input, (local_count, name_offset)) = tuple((le_u32, le_u32))(input)?;
+    let (input, locals) = count(tuple((le_u32, map(le_u32, StringRef))), local_count as _)(input)?;
+
+    Ok((input, CodeLocals {
+        name_offset: StringRef(name_offset), locals
+    }))
+}
+
+/// GMS2 layout: a count before the functions, followed by the code locals
+fn take_gms2(input: PosSlice) -> PResult<(Vec<FuncEntry>, Vec<CodeLocals>)> {
+    let (input, function_count) = le_u32(input)?;
+    if function_count as usize > input.len() / 12 {
+        return Err(nom::Err::Error(Error::new(input.pos(), Expected::MoreData)));
+    }
+    let (input, functions) = count(take_function, function_count as _)(input)?;
+    let (input, locals_count) = le_u32(input)?;
+    let (input, code_locals) = count(take_code_locals, locals_count as _)(input)?;
+
+    Ok((input, (functions, code_locals)))
+}
+
+impl super::ParseSection for Func {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let section_end = input.pos() + input.len();
+
+        // GMS1 is just the functions, up to the end of the section
+        let (rest, (functions, code_locals)) = match take_gms2(input) {
+            Ok((rest, (functions, code_locals))) if section_end - rest.pos() < 0x80 => {
+                (rest, (functions, Some(code_locals)))
+            }
+            _ if input.len().is_multiple_of(12) => {
+                let (rest, functions) = count(take_function, input.len() / 12)(input)?;
+                (rest, (functions, None))
+            }
+            _ => return Err(nom::Err::Error(Error::new(input.pos(), Expected::Structure(
+                String::from("FUNC entries")
+            )))),
+        };
+
+        let end_align = end_alignment(rest.pos(), section_end);
+
+        Ok((rest, Self {
+            functions, code_locals, end_align
+        }))
+    }
+}
+
+impl WriteSection for Func {
+    fn write(&self, w: &mut Writer) {
+        if self.code_locals.is_some() {
+            w.u32(self.functions.len() as u32);
+        }
+        for function in &self.functions {
+            w.string(function.name_offset);
+            w.u32(function.occurrences);
+            if function.occurrences > 0 {
+                w.code_address(function.first_address);
+            } else {
+                w.u32(function.first_address);
+            }
+        }
+        if let Some(code_locals) = &self.code_locals {
+            w.u32(code_locals.len() as u32);
+            for code in code_locals {
+                w.u32(code.locals.len() as u32);
+                w.string(code.name_offset);
+                for &(index, name_offset) in &code.locals {
+                    w.u32(index);
+                    w.string(name_offset);
+                }
+            }
+        }
+        w.align(self.end_align);
+    }
+}
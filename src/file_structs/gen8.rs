@@ -1,20 +1,24 @@
-use binrw::{derive_binread, BinReaderExt};
-use nom::{IResult, error::ErrorKind};
-use super::{PosSlice, PosCursor};
+// code generated by modular_bitfield's #[bitfield] trips these
+#![allow(unused_parens, clippy::new_without_default)]
+
+use binrw::{derive_binread, BinReaderExt, io::Seek};
+use super::{PosSlice, PResult, StringRef, Error, PosCursor, Writer, WriteSection};
 use modular_bitfield::prelude::*;
-use chrono::naive::NaiveDateTime;
+use chrono::{DateTime, naive::NaiveDateTime};
 
 #[derive_binread]
 #[derive(Debug, Clone)]
 pub struct Gen8 {
     pub unk: u32,
-    pub internal_name_offset: u32,
-    pub config_name_offset: u32,
-    pub unk2: u32,
-    pub unk3: u32, // 10000000 by default
+    pub internal_name_offset: StringRef,
+    pub config_name_offset: StringRef,
+    /// Highest instance id used by any room
+    pub last_instance_id: u32,
+    /// Highest id used by any tile, 10000000 by default
+    pub last_tile_id: u32,
     pub unk4: u32,
     pub unk5: [u8; 0x10], // always 0'd out?
-    pub internal_name_offset2: u32,
+    pub internal_name_offset2: StringRef,
     pub unk6: u32, // gamemaker major version?
     pub unk7: [u32; 3],
     pub window_size: (u32, u32),
@@ -23,11 +27,11 @@ pub struct Gen8 {
     pub unk9: u32,
     pub unk10: [u32; 3],
 
-    #[br(map = |time: u64| NaiveDateTime::from_timestamp(time as i64, 0))]
+    #[br(map = |time: u64| DateTime::from_timestamp(time as i64, 0).unwrap_or_default().naive_utc())]
     pub build_time: NaiveDateTime,
 
     // display name
-    pub game_name_offset: u32,
+    pub game_name_offset: StringRef,
     pub unk12: u32,
     pub unk13: u32,
 
@@ -42,6 +46,10 @@ pub struct Gen8 {
     pub numbers: Vec<u32>, // ????
 
     pub nonsense: [u8; 0x40],
+
+    /// Unparsed data at the end of the section
+    #[br(calc = Vec::new())]
+    pub trailing: Vec<u8>,
 }
 
 // 0x0001 - start fullscreen
@@ -79,6 +87,18 @@ pub struct GameOptions {
     #[skip] __: B17,
 }
 
+/// Last bytecode version using the GMS1 instruction set
+pub const GMS1_BYTECODE_VERSION: u8 = 14;
+/// First bytecode version using the GMS2 instruction set
+pub const GMS2_BYTECODE_VERSION: u8 = 16;
+
+impl Gen8 {
+    /// Version of the VM bytecode in CODE, kept in the second byte of the section
+    pub fn bytecode_version(&self) -> u8 {
+        (self.unk >> 8) as u8
+    }
+}
+
 #[derive(Debug, BitfieldSpecifier, Clone, Copy)]
 pub enum SaveLocation {
     LocalAppData,
@@ -86,13 +106,57 @@ pub enum SaveLocation {
 }
 
 impl super::ParseSection for Gen8 {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
-        let mut cursor = PosCursor::from(input.clone());
+    fn take(input: PosSlice) -> PResult<Self> {
+        let mut cursor = PosCursor::from(input);
 
-        if let Ok(gen8) = cursor.read_le() {
-            Ok((input, gen8))
-        } else {
-            Err(nom::Err::Error((input, ErrorKind::ParseTo)))
+        let mut gen8: Gen8 = cursor.read_le()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err, input.pos())))?;
+
+        let end = cursor.stream_position()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err.into(), input.pos())))?;
+        if let Some(rest) = input.at(end as usize) {
+            gen8.trailing = rest.1.to_vec();
+        }
+
+        Ok((input, gen8))
+    }
+}
+
+impl WriteSection for Gen8 {
+    fn write(&self, w: &mut Writer) {
+        w.u32(self.unk);
+        w.string(self.internal_name_offset);
+        w.string(self.config_name_offset);
+        w.u32(self.last_instance_id);
+        w.u32(self.last_tile_id);
+        w.u32(self.unk4);
+        w.bytes(&self.unk5);
+        w.string(self.internal_name_offset2);
+        w.u32(self.unk6);
+        for &val in &self.unk7 {
+            w.u32(val);
+        }
+        w.u32(self.window_size.0);
+        w.u32(self.window_size.1);
+        w.bytes(&self.game_options.clone().into_bytes());
+        w.u32(self.unk8);
+        w.u32(self.unk9);
+        for &val in &self.unk10 {
+            w.u32(val);
+        }
+        w.bytes(&(self.build_time.and_utc().timestamp() as u64).to_le_bytes());
+        w.string(self.game_name_offset);
+        w.u32(self.unk12);
+        w.u32(self.unk13);
+        for &val in &self.unk14 {
+            w.u32(val);
+        }
+        w.u32(self.server_port);
+        w.u32(self.numbers.len() as u32);
+        for &val in &self.numbers {
+            w.u32(val);
         }
+        w.bytes(&self.nonsense);
+        w.bytes(&self.trailing);
     }
 }
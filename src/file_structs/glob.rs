@@ -0,0 +1,43 @@
+use binrw::{derive_binread, BinReaderExt, io::Seek};
+use super::{PosSlice, PResult, Error, PosCursor, Writer, WriteSection};
+use super::helpers::end_alignment;
+
+impl super::ParseSection for Glob {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let mut cursor = PosCursor::from(input);
+
+        let mut glob: Glob = cursor.read_le()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err, input.pos())))?;
+
+        let content_end = cursor.stream_position()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err.into(), input.pos())))?;
+        glob.end_align = end_alignment(content_end as usize, input.pos() + input.len());
+
+        Ok((input, glob))
+    }
+}
+
+/// CODE entries run once when the game starts, to initialise globals
+#[derive_binread]
+#[derive(Debug, Clone)]
+pub struct Glob {
+    #[br(temp)]
+    count: u32,
+
+    /// Index into CODE of each script
+    #[br(count = count)]
+    pub code_ids: Vec<u32>,
+
+    #[br(calc = 1)]
+    pub end_align: u32,
+}
+
+impl WriteSection for Glob {
+    fn write(&self, w: &mut Writer) {
+        w.u32(self.code_ids.len() as u32);
+        for &code_id in &self.code_ids {
+            w.u32(code_id);
+        }
+        w.align(self.end_align);
+    }
+}
@@ -1,4 +1,4 @@
-use super::PosSlice;
+use super::{PosSlice, Error, Expected};
 
 use binrw::{BinRead, BinResult, BinReaderExt, ReadOptions};
 use binrw::io::{Cursor, Read, Seek, SeekFrom};
@@ -23,10 +23,7 @@ impl<'a> Seek for PosCursor<'a> {
         match pos {
             SeekFrom::Start(pos) => {
                 let pos = pos.checked_sub(self.pos as u64)
-                    .ok_or_else(|| io::Error::new(
-                        io::ErrorKind::Other,
-                        "seek position out of PosCursor range"
-                    ))?;
+                    .ok_or_else(|| io::Error::other("seek position out of PosCursor range"))?;
 
                 self.inner.seek(SeekFrom::Start(pos))
                     .map(|pos| pos + (self.pos as u64))
@@ -61,3 +58,58 @@ pub(crate) fn ptr_list<R: Read + Seek, T: BinRead<Args = ()>>(
         })
         .collect()
 }
+
+/// Move `input` to the absolute file offset `offset`, failing if it lies outside of `input`.
+/// `from` is where the pointer to it was read, which is what the error points at.
+pub(crate) fn at_offset(input: PosSlice, offset: u32, from: usize) -> Result<PosSlice, nom::Err<Error>> {
+    input.at(offset as usize)
+        .ok_or_else(|| nom::Err::Error(Error::new(from, Expected::OffsetInSection(offset))))
+}
+
+/// Where each of `offsets` was read from, for a list of pointers `input` has just been moved past
+pub(crate) fn pointer_positions(input: PosSlice, offsets: &[u32]) -> impl Iterator<Item = usize> {
+    let start = input.pos() - offsets.len() * 4;
+    (0..offsets.len()).map(move |i| start + i * 4)
+}
+
+/// Largest power of two, up to `max`, that every offset is a multiple of
+pub(crate) fn common_alignment(offsets: &[u32], max: u32) -> u32 {
+    let mut alignment = max;
+    while alignment > 1 && offsets.iter().any(|offset| offset % alignment != 0) {
+        alignment /= 2;
+    }
+    alignment
+}
+
+/// Alignment (up to 0x80) the end of a section was padded to, given where its contents end
+pub(crate) fn end_alignment(content_end: usize, section_end: usize) -> u32 {
+    let mut alignment = 0x80;
+    while alignment > 1 && (!section_end.is_multiple_of(alignment) || section_end - content_end >= alignment) {
+        alignment /= 2;
+    }
+    alignment as u32
+}
+
+/// For each entry offset, the offset of the next entry in the file (or `end` for the last one)
+pub(crate) fn entry_ends(offsets: &[u32], end: usize) -> Vec<usize> {
+    let mut sorted = offsets.to_vec();
+    sorted.sort_unstable();
+
+    offsets.iter()
+        .map(|&offset| {
+            let next = sorted.partition_point(|&other| other <= offset);
+            sorted.get(next).map(|&next| next as usize).unwrap_or(end)
+        })
+        .collect()
+}
+
+/// Original offset of each of `len` entries, ordered by entry index
+pub(crate) fn offsets_by_index(locations: &std::collections::HashMap<u32, usize>, len: usize) -> Vec<Option<u32>> {
+    let mut offsets = vec![None; len];
+    for (&offset, &index) in locations {
+        if let Some(slot) = offsets.get_mut(index) {
+            *slot = Some(offset);
+        }
+    }
+    offsets
+}
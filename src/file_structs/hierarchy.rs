@@ -0,0 +1,181 @@
+use super::*;
+
+/// Parent/child links between the objects in OBJT
+#[derive(Debug, Clone)]
+pub struct ObjectHierarchy {
+    names: Vec<String>,
+    parents: Vec<Option<usize>>,
+    children: Vec<Vec<usize>>,
+}
+
+/// What an object ends up with once what it leaves unset is taken from its parents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveObject {
+    /// -1 if neither the object nor any parent has a sprite
+    pub sprite_index: i32,
+    pub texture_mask_index: i32,
+    /// Flags are stored already resolved for each object, so these are its own
+    pub is_visible: bool,
+    pub is_solid: bool,
+    pub is_persistent: bool,
+    pub depth: i32,
+}
+
+impl ObjectHierarchy {
+    pub fn new(file: &FormFile) -> Self {
+        let objects = file.objects();
+        let names = objects.iter()
+            .map(|object| file.strg.as_ref()
+                .and_then(|strg| strg.get(object.name_offset))
+                .cloned()
+                .unwrap_or_default())
+            .collect();
+
+        let mut hierarchy = ObjectHierarchy {
+            names,
+            parents: vec![None; objects.len()],
+            children: vec![Vec::new(); objects.len()],
+        };
+        for (object, entry) in objects.iter().enumerate() {
+            // -100 means no parent; anything else out of range is treated the same
+            let parent = entry.parent()
+                .map(|ObjectId(parent)| parent)
+                .filter(|&parent| parent < objects.len());
+            if let Some(parent) = parent {
+                // a broken file could loop, which would make every walk up the tree endless
+                if parent != object && !hierarchy.ancestors(parent).any(|ancestor| ancestor == object) {
+                    hierarchy.parents[object] = Some(parent);
+                    hierarchy.children[parent].push(object);
+                }
+            }
+        }
+        hierarchy
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name(&self, object: usize) -> &str {
+        &self.names[object]
+    }
+
+    pub fn parent(&self, object: usize) -> Option<usize> {
+        self.parents[object]
+    }
+
+    pub fn children(&self, object: usize) -> &[usize] {
+        &self.children[object]
+    }
+
+    /// Objects without a parent, in OBJT order
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len()).filter(move |&object| self.parents[object].is_none())
+    }
+
+    /// The object's parent, its parent's parent and so on
+    pub fn ancestors(&self, object: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.parents[object], move |&parent| self.parents[parent])
+    }
+
+    /// Every object inheriting from `object`, depth first
+    pub fn descendants(&self, object: usize) -> Vec<usize> {
+        let mut descendants = Vec::new();
+        let mut stack = self.children[object].iter().rev().copied().collect::<Vec<_>>();
+        while let Some(child) = stack.pop() {
+            descendants.push(child);
+            stack.extend(self.children[child].iter().rev());
+        }
+        descendants
+    }
+
+    /// Each object on its own line, indented under its parent
+    pub fn tree(&self) -> String {
+        let mut tree = String::new();
+        for root in self.roots() {
+            let mut stack = vec![(root, 0)];
+            while let Some((object, depth)) = stack.pop() {
+                tree.push_str(&"    ".repeat(depth));
+                tree.push_str(&self.names[object]);
+                tree.push('\n');
+                stack.extend(self.children[object].iter().rev().map(|&child| (child, depth + 1)));
+            }
+        }
+        tree
+    }
+
+    /// Graphviz DOT graph with an edge from each parent to its children
+    pub fn to_dot(&self) -> String {
+        let quote = |object: usize| format!("\"{}\"", self.names[object].replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph objects {\n");
+        for object in 0..self.len() {
+            dot.push_str(&format!("    {};\n", quote(object)));
+        }
+        for (child, parent) in self.parents.iter().enumerate() {
+            if let Some(parent) = *parent {
+                dot.push_str(&format!("    {} -> {};\n", quote(parent), quote(child)));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+impl FormFile {
+    pub fn object_hierarchy(&self) -> ObjectHierarchy {
+        ObjectHierarchy::new(self)
+    }
+
+    /// The handler that runs for `event` on the object at `object`, which is the nearest
+    /// parent's if the object doesn't have its own, along with the index of the object it
+    /// belongs to
+    pub fn inherited_event(&self, object: usize, event: ObjectEvent) -> Option<(usize, &ObjtEvent)> {
+        let hierarchy = self.object_hierarchy();
+        let lineage = std::iter::once(object)
+            .chain(hierarchy.ancestors(object))
+            .collect::<Vec<_>>();
+        lineage.into_iter()
+            .find_map(|owner| Some((owner, self.objects()[owner].event(event)?)))
+    }
+
+    /// Every event the object at `object` responds to, including those it inherits, with the
+    /// index of the object whose handler runs
+    pub fn inherited_events(&self, object: usize) -> Vec<(ObjectEvent, usize, &ObjtEvent)> {
+        let hierarchy = self.object_hierarchy();
+        let mut events: Vec<(ObjectEvent, usize, &ObjtEvent)> = Vec::new();
+        for owner in std::iter::once(object).chain(hierarchy.ancestors(object)) {
+            for (event, handler) in self.objects()[owner].events() {
+                if !events.iter().any(|&(other, _, _)| other == event) {
+                    events.push((event, owner, handler));
+                }
+            }
+        }
+        events
+    }
+
+    /// The sprite and mask of the object at `object`, taking any it doesn't set from its
+    /// parents, along with its flags
+    pub fn effective_object(&self, object: usize) -> EffectiveObject {
+        let hierarchy = self.object_hierarchy();
+        let objects = self.objects();
+        let lineage = || std::iter::once(object).chain(hierarchy.ancestors(object));
+        let inherit = |field: fn(&ObjtEntry) -> i32| lineage()
+            .map(|owner| field(&objects[owner]))
+            .find(|&index| index >= 0)
+            .unwrap_or(-1);
+
+        let entry = &objects[object];
+        EffectiveObject {
+            sprite_index: inherit(|object| object.sprite_index),
+            texture_mask_index: inherit(|object| object.texture_mask_index),
+            is_visible: entry.is_visible,
+            is_solid: entry.is_solid,
+            is_persistent: entry.is_persistent,
+            depth: entry.depth,
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Typed references between resources, so a texture page offset can't be passed where a
+//! string was expected.
+//!
+//! [`StringRef`] and [`TpagRef`] hold the offsets the file was read with (or placeholders for
+//! things added since), which writing relocates to wherever their targets end up, so they stay
+//! valid however much the file is changed. The other ids are indices into their sections.
+
+use binrw::BinRead;
+use super::{
+    BgndEntry, CodeEntry, FontEntry, FormFile, ObjtEntry, RoomEntry, ScptEntry, SondEntry, SprtEntry, TpagEntry,
+};
+
+/// A string in STRG, by the offset other sections refer to it with
+#[derive(BinRead, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct StringRef(pub u32);
+
+/// A TPAG entry, by the offset other sections refer to it with
+#[derive(BinRead, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct TpagRef(pub u32);
+
+macro_rules! define_ids {
+    ($($(#[$attr:meta])* $name:ident => $target:ty, |$file:ident| $list:expr;)*) => {
+        $(
+            $(#[$attr])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+            pub struct $name(pub usize);
+
+            impl $name {
+                /// The id for an index stored as an `i32`, which is negative for none
+                pub fn from_index(index: i32) -> Option<Self> {
+                    if index < 0 { None } else { Some($name(index as usize)) }
+                }
+            }
+
+            impl Resolve for $name {
+                type Target = $target;
+
+                fn resolve(self, $file: &FormFile) -> Option<&$target> {
+                    $list.get(self.0)
+                }
+            }
+        )*
+    };
+}
+
+define_ids! {
+    /// An entry in SPRT
+    SpriteId => SprtEntry, |file| file.sprt.as_ref()?.sprites;
+    /// An entry in SOND
+    SoundId => SondEntry, |file| file.sond.as_ref()?.sounds;
+    /// An entry in BGND
+    BackgroundId => BgndEntry, |file| file.backgrounds();
+    /// An entry in FONT
+    FontId => FontEntry, |file| file.font.as_ref()?.fonts;
+    /// An entry in OBJT
+    ObjectId => ObjtEntry, |file| file.objects();
+    /// An entry in ROOM
+    RoomId => RoomEntry, |file| file.rooms();
+    /// An entry in CODE
+    CodeId => CodeEntry, |file| file.code_entries();
+    /// An entry in SCPT
+    ScriptId => ScptEntry, |file| file.scpt.as_ref()?.scripts;
+}
+
+/// A reference that can be looked up in a [`FormFile`], with [`FormFile::resolve`]
+pub trait Resolve: Copy {
+    type Target: ?Sized;
+
+    fn resolve(self, file: &FormFile) -> Option<&Self::Target>;
+}
+
+impl Resolve for StringRef {
+    type Target = str;
+
+    fn resolve(self, file: &FormFile) -> Option<&str> {
+        file.strg.as_ref()?.get(self).map(|string| &string[..])
+    }
+}
+
+impl Resolve for TpagRef {
+    type Target = TpagEntry;
+
+    fn resolve(self, file: &FormFile) -> Option<&TpagEntry> {
+        file.tpag.as_ref()?.get(self)
+    }
+}
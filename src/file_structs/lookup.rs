@@ -0,0 +1,248 @@
+//! Finding resources by name without scanning every entry, and views of what's found
+//!
+//! The tables are built when a file is loaded. Entries renamed since are never returned under
+//! their old names, but anything added or renamed needs [`FormFile::reindex`] before it can be
+//! found under its new one.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use super::*;
+
+/// A resource entry with a name in STRG
+pub trait Named {
+    fn name_offset(&self) -> StringRef;
+}
+
+macro_rules! impl_named {
+    ($($entry:ty => $field:ident,)*) => {
+        $(
+            impl Named for $entry {
+                fn name_offset(&self) -> StringRef {
+                    self.$field
+                }
+            }
+        )*
+    };
+}
+
+impl_named! {
+    SprtEntry => name_offset,
+    SondEntry => name_offset,
+    BgndEntry => name_offset,
+    FontEntry => name,
+    ObjtEntry => name_offset,
+    RoomEntry => name_offset,
+    ScptEntry => name_offset,
+    CodeEntry => name_offset,
+}
+
+/// Id of each sprite, sound, background, font, object, room, script and CODE entry by name
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NameIndex {
+    sprites: HashMap<String, SpriteId>,
+    sounds: HashMap<String, SoundId>,
+    backgrounds: HashMap<String, BackgroundId>,
+    fonts: HashMap<String, FontId>,
+    objects: HashMap<String, ObjectId>,
+    rooms: HashMap<String, RoomId>,
+    scripts: HashMap<String, ScriptId>,
+    codes: HashMap<String, CodeId>,
+}
+
+impl NameIndex {
+    pub(crate) fn new(file: &FormFile) -> Self {
+        let sprites = file.sprt.as_ref().map(|sprt| &sprt.sprites[..]).unwrap_or_default();
+        let sounds = file.sond.as_ref().map(|sond| &sond.sounds[..]).unwrap_or_default();
+        let fonts = file.font.as_ref().map(|font| &font.fonts[..]).unwrap_or_default();
+        let scripts = file.scpt.as_ref().map(|scpt| &scpt.scripts[..]).unwrap_or_default();
+
+        NameIndex {
+            sprites: index(file, sprites, SpriteId),
+            sounds: index(file, sounds, SoundId),
+            backgrounds: index(file, file.backgrounds(), BackgroundId),
+            fonts: index(file, fonts, FontId),
+            objects: index(file, file.objects(), ObjectId),
+            rooms: index(file, file.rooms(), RoomId),
+            scripts: index(file, scripts, ScriptId),
+            codes: index(file, file.code_entries(), CodeId),
+        }
+    }
+}
+
+/// Map the name of each of `entries` to its id. Where names are shared, the first entry wins,
+/// as it's the one the game finds too.
+fn index<E: Named, Id>(file: &FormFile, entries: &[E], id: fn(usize) -> Id) -> HashMap<String, Id> {
+    let mut names = HashMap::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(name) = file.resolve(entry.name_offset()) {
+            names.entry(name.to_owned()).or_insert_with(|| id(i));
+        }
+    }
+    names
+}
+
+/// A resource along with its id, name and the file it's in, which derefs to its entry
+pub struct View<'a, Id: Resolve> {
+    pub file: &'a FormFile,
+    pub id: Id,
+    pub name: &'a str,
+    pub entry: &'a Id::Target,
+}
+
+pub type SpriteView<'a> = View<'a, SpriteId>;
+pub type SoundView<'a> = View<'a, SoundId>;
+pub type BackgroundView<'a> = View<'a, BackgroundId>;
+pub type FontView<'a> = View<'a, FontId>;
+pub type ObjectView<'a> = View<'a, ObjectId>;
+pub type RoomView<'a> = View<'a, RoomId>;
+pub type ScriptView<'a> = View<'a, ScriptId>;
+pub type CodeView<'a> = View<'a, CodeId>;
+
+impl<Id: Resolve> Clone for View<'_, Id> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Id: Resolve> Copy for View<'_, Id> {}
+
+impl<Id: Resolve> Deref for View<'_, Id> {
+    type Target = Id::Target;
+
+    fn deref(&self) -> &Id::Target {
+        self.entry
+    }
+}
+
+impl<'a> SpriteView<'a> {
+    /// Each frame's TPAG entry, in order
+    pub fn frames(&self) -> impl Iterator<Item = &'a TpagEntry> + 'a {
+        let file = self.file;
+        self.entry.tpag_offsets.iter().filter_map(move |&frame| file.resolve(frame))
+    }
+
+    /// A frame at the sprite's full size, see [`FormFile::get_sprite_frame`]
+    pub fn frame_image(&self, frame: usize) -> Option<image::RgbaImage> {
+        self.file.get_sprite_frame(self.id.0, frame)
+    }
+}
+
+impl<'a> SoundView<'a> {
+    /// The audio file, if its audio group has been loaded
+    pub fn data(&self) -> Option<&'a [u8]> {
+        let audo = self.file.audos.get(self.entry.audiogroup_index as usize)?;
+        audo.files.get(self.entry.index_in_audiogroup as usize).map(|data| &data[..])
+    }
+}
+
+impl<'a> FontView<'a> {
+    /// Name of the typeface the font was made from
+    pub fn font_name(&self) -> Option<&'a str> {
+        self.file.resolve(self.entry.font_name)
+    }
+
+    /// The TPAG entry of the sheet holding every glyph
+    pub fn sheet(&self) -> Option<&'a TpagEntry> {
+        self.file.resolve(self.entry.entire_font_tpag)
+    }
+}
+
+impl<'a> ObjectView<'a> {
+    pub fn sprite(&self) -> Option<SpriteView<'a>> {
+        self.file.view(self.entry.sprite()?)
+    }
+
+    pub fn parent(&self) -> Option<ObjectView<'a>> {
+        self.file.view(self.entry.parent()?)
+    }
+
+    /// CODE entries the object runs for `event`, with their indices
+    pub fn event_code(&self, event: ObjectEvent) -> Vec<(usize, &'a CodeEntry)> {
+        let file = self.file;
+        self.entry.event(event)
+            .map(|handler| handler.code_ids()
+                .filter_map(|index| Some((index, file.code_entries().get(index)?)))
+                .collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<'a> RoomView<'a> {
+    pub fn caption(&self) -> Option<&'a str> {
+        self.file.resolve(self.entry.caption_offset)
+    }
+
+    /// Each instance placed in the room, with its object
+    pub fn instances(&self) -> impl Iterator<Item = (&'a GameObject, Option<ObjectView<'a>>)> + 'a {
+        let file = self.file;
+        self.entry.game_objects.iter()
+            .map(move |instance| (instance, instance.object().and_then(|object| file.view(object))))
+    }
+}
+
+impl<'a> ScriptView<'a> {
+    /// The CODE entry the script runs
+    pub fn code(&self) -> Option<&'a CodeEntry> {
+        self.file.resolve(self.entry.code())
+    }
+}
+
+impl FormFile {
+    /// Rebuild the tables the resources are found by name with, after adding or renaming any
+    pub fn reindex(&mut self) {
+        self.names = NameIndex::new(self);
+    }
+
+    /// View of the resource at `id`, see [`View`]
+    pub fn view<Id>(&self, id: Id) -> Option<View<'_, Id>>
+    where
+        Id: Resolve,
+        Id::Target: Named,
+    {
+        let entry = self.resolve(id)?;
+        let name = self.resolve(entry.name_offset()).unwrap_or_default();
+        Some(View { file: self, id, name, entry })
+    }
+
+    fn lookup<Id>(&self, names: &HashMap<String, Id>, name: &str) -> Option<View<'_, Id>>
+    where
+        Id: Resolve,
+        Id::Target: Named,
+    {
+        // the entry could have been renamed since the tables were built
+        self.view(*names.get(name)?).filter(|view| view.name == name)
+    }
+
+    pub fn sprite(&self, name: &str) -> Option<SpriteView<'_>> {
+        self.lookup(&self.names.sprites, name)
+    }
+
+    pub fn sound(&self, name: &str) -> Option<SoundView<'_>> {
+        self.lookup(&self.names.sounds, name)
+    }
+
+    pub fn background(&self, name: &str) -> Option<BackgroundView<'_>> {
+        self.lookup(&self.names.backgrounds, name)
+    }
+
+    pub fn font(&self, name: &str) -> Option<FontView<'_>> {
+        self.lookup(&self.names.fonts, name)
+    }
+
+    pub fn object(&self, name: &str) -> Option<ObjectView<'_>> {
+        self.lookup(&self.names.objects, name)
+    }
+
+    pub fn room(&self, name: &str) -> Option<RoomView<'_>> {
+        self.lookup(&self.names.rooms, name)
+    }
+
+    pub fn script(&self, name: &str) -> Option<ScriptView<'_>> {
+        self.lookup(&self.names.scripts, name)
+    }
+
+    /// The CODE entry called `name`, such as `gml_Script_scr_jump`
+    pub fn code_entry(&self, name: &str) -> Option<CodeView<'_>> {
+        self.lookup(&self.names.codes, name)
+    }
+}
@@ -7,18 +7,34 @@ mod audo;
 mod txtr;
 mod tpag;
 mod sprt;
+mod bgnd;
 mod font;
 mod agrp;
 mod objt;
 mod room;
+mod code;
+mod vari;
+mod func;
+mod scpt;
+mod glob;
+mod references;
+mod hierarchy;
+mod ids;
+mod lookup;
 mod byte_parsers;
+mod error;
+mod writer;
+mod roundtrip;
+mod builder;
 
 mod helpers;
 
 use section_header::{take_section, ParseSection};
-use nom::IResult;
 use track_slice::PosSlice;
-use helpers::{PosCursor, ptr_list};
+use helpers::{PosCursor, ptr_list, at_offset, pointer_positions};
+use std::collections::HashMap;
+use std::io::Write;
+use error::failure;
 use byte_parsers::le_u32;
 use std::path::Path;
 
@@ -38,7 +54,9 @@ macro_rules! define_sections {
             )*
             Unk {
                 name: String,
-                data: Vec<u8>
+                data: Vec<u8>,
+                /// Where the section's header was in the file it was parsed from
+                offset: u32,
             }
         }
 
@@ -49,27 +67,33 @@ macro_rules! define_sections {
         };
 
         impl $enum_name {
-            fn _take_unk(input: PosSlice) -> IResult<PosSlice, Self> {
+            fn _take_unk(input: PosSlice) -> PResult<Self> {
+                let offset = input.pos() as u32;
                 let (input, (name, size)) = tuple((take(4usize), le_u32))(input)?;
 
-                let name = std::str::from_utf8(name.1).unwrap().to_string();
+                let name = std::str::from_utf8(name.1)
+                    .map_err(|_| nom::Err::Error(Error::new(name.pos(), Expected::SectionHeader)))?
+                    .to_string();
 
-                let (input, data) = take(size as usize)(input)?;
+                let (input, data) = take(size as usize)(input)
+                    .map_err(|err: nom::Err<Error>| err.map(|err| err.in_section(name.as_bytes())))?;
                 let data = data.1.to_vec();
 
                 Ok((
                     input,
                     Self::Unk {
                         name,
-                        data
+                        data,
+                        offset
                     }
                 ))
             }
 
             $(
-                fn $take_section(input: PosSlice) -> IResult<PosSlice, Self>{
+                fn $take_section(input: PosSlice) -> PResult<Self>{
                     let (remains, input) = take_section($magic)(input)?;
-                    let (_, section) = $section_inner::take(input)?;
+                    let (_, section) = $section_inner::take(input)
+                        .map_err(|err| failure(err.map(|err| err.in_section($magic))))?;
                     Ok((
                         remains,
                         Self::$section(
@@ -79,18 +103,32 @@ macro_rules! define_sections {
                 }
             )*
 
-            pub fn take(input: PosSlice) -> IResult<PosSlice, Self> {
-                Ok(alt((
+            pub fn take(input: PosSlice) -> PResult<Self> {
+                alt((
                     $(
                         Self::$take_section,
                     )*
                     Self::_take_unk
-                ))(input)?)
+                ))(input)
+            }
+
+            pub fn write(&self, w: &mut Writer) {
+                match self {
+                    $(
+                        Self::$section(section) => w.section($magic, |w| section.write(w)),
+                    )*
+                    Self::Unk { name, data, .. } => w.section(name.as_bytes(), |w| w.bytes(data)),
+                }
             }
         }
     }
 }
 
+pub use error::{Error, Expected, MissingSection, PResult};
+pub use writer::{Writer, WriteSection, Relocations, write_form};
+use writer::{write_form_relocated, PLACEHOLDER_BASE};
+pub use roundtrip::{Mismatch, verify_roundtrip};
+pub use builder::DataWinBuilder;
 pub use gen8::*;
 pub use strg::*;
 pub use sond::*;
@@ -98,10 +136,21 @@ pub use audo::*;
 pub use txtr::*;
 pub use tpag::*;
 pub use sprt::*;
+pub use bgnd::*;
 pub use font::*;
 pub use agrp::*;
 pub use objt::*;
 pub use room::*;
+pub use code::*;
+pub use vari::*;
+pub use func::*;
+pub use scpt::*;
+pub use glob::*;
+pub use references::{Reference, Occurrence};
+pub use hierarchy::{ObjectHierarchy, EffectiveObject};
+pub use ids::*;
+pub use lookup::{Named, View, SpriteView, SoundView, BackgroundView, FontView, ObjectView, RoomView, ScriptView, CodeView};
+use lookup::NameIndex;
 
 define_sections!{
     Section,
@@ -113,20 +162,52 @@ define_sections!{
         (b"TXTR", Txtr, Txtr, _txtr),
         (b"TPAG", Tpag, Tpag, _tpag),
         (b"SPRT", Sprt, Sprt, _sprt),
+        (b"BGND", Bgnd, Bgnd, _bgnd),
         (b"FONT", Font, Font, _font),
         (b"AGRP", Agrp, Agrp, _agrp),
         (b"OBJT", Objt, Objt, _objt),
         (b"ROOM", Room, Room, _room),
+        (b"CODE", Code, Code, _code),
+        (b"VARI", Vari, Vari, _vari),
+        (b"FUNC", Func, Func, _func),
+        (b"SCPT", Scpt, Scpt, _scpt),
+        (b"GLOB", Glob, Glob, _glob),
     }
 }
 
+/// Sections of the data.win or audio group in `input`
+///
+/// # Panics
+///
+/// If `input` can't be parsed. [`try_take_data_win_file`] returns the error instead, for
+/// files that may not be well formed.
 pub fn take_data_win_file(input: &[u8]) -> Vec<Section> {
+    try_take_data_win_file(input).unwrap_or_else(|err| panic!("Failed to parse data.win: {}", err))
+}
+
+/// Sections of the data.win or audio group in `input`, or where and why they couldn't be parsed
+pub fn try_take_data_win_file(input: &[u8]) -> Result<Vec<Section>, Error> {
     let input = PosSlice::from_slice(input);
 
-    let (_, input) = take_section(b"FORM")(input).unwrap();
-    
+    let (_, mut input) = take_section(b"FORM")(input)
+        .map_err(|err| Error::from(err).in_section(b"FORM"))?;
+
+    let mut sections = Vec::new();
+    while !input.is_empty() {
+        let (remains, section) = Section::take(input)?;
+        sections.push(section);
+        input = remains;
+    }
 
-    nom::multi::many0(Section::take)(input).unwrap().1
+    Ok(sections)
+}
+
+pub fn write_data_win_file(sections: &[Section]) -> Vec<u8> {
+    write_form(|w| {
+        for section in sections {
+            section.write(w);
+        }
+    })
 }
 
 //#[cfg(textures)]
@@ -139,6 +220,29 @@ use {
     lazy_init::Lazy,
 };
 
+/// Where each section of the original file goes when writing it back out
+#[derive(Debug, Clone, Copy)]
+enum SectionSlot {
+    Gen8,
+    Strg,
+    Sond,
+    Audo(usize),
+    Txtr,
+    Tpag,
+    Sprt,
+    Bgnd,
+    Font,
+    Agrp,
+    Objt,
+    Room,
+    Code,
+    Vari,
+    Func,
+    Scpt,
+    Glob,
+    Unk(usize),
+}
+
 #[derive(Default)]
 pub struct FormFile {
     pub audos: Vec<Audo>,
@@ -147,51 +251,277 @@ pub struct FormFile {
     pub txtr: Option<Txtr>,
     pub tpag: Option<Tpag>,
     pub sprt: Option<Sprt>,
+    pub bgnd: Option<Bgnd>,
     pub font: Option<Font>,
     pub agrp: Option<Agrp>,
+    pub gen8: Option<Gen8>,
+    pub objt: Option<Objt>,
+    pub room: Option<Room>,
+    pub code: Option<Code>,
+    pub vari: Option<Vari>,
+    pub func: Option<Func>,
+    pub scpt: Option<Scpt>,
+    pub glob: Option<Glob>,
+    /// Sections that aren't parsed, as `(magic, data, offset of the header)`
+    unknown: Vec<(String, Vec<u8>, u32)>,
+    layout: Vec<SectionSlot>,
+    names: NameIndex,
     //#[cfg(textures)]
     pub textures: Vec<Lazy<Arc<DynamicImage>>>,
 }
 
 impl FormFile {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Self {
-        let data = std::fs::read(path.as_ref()).unwrap();
-        FormFile::from_sections(take_data_win_file(&data))
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let data = std::fs::read(path.as_ref())?;
+        Ok(FormFile::from_sections(try_take_data_win_file(&data)?))
     }
 
     pub fn from_sections(sections: Vec<Section>) -> Self {
         let mut file = FormFile::default();
 
         for section in sections {
-            match section {
+            let slot = match section {
+                Section::Gen8(gen8) => {
+                    file.gen8 = Some(gen8);
+                    SectionSlot::Gen8
+                }
                 Section::Audo(audo) => {
-                    file.audos.push(audo)
+                    file.audos.push(audo);
+                    SectionSlot::Audo(file.audos.len() - 1)
                 }
                 Section::Strg(strg) => {
-                    file.strg = Some(strg)
+                    file.strg = Some(strg);
+                    SectionSlot::Strg
                 }
                 Section::Sond(sond) => {
-                    file.sond = Some(sond)
+                    file.sond = Some(sond);
+                    SectionSlot::Sond
                 }
                 Section::Txtr(txtr) => {
                     file.textures = (0..txtr.files.len()).map(|_| Lazy::new()).collect();
                     file.txtr = Some(txtr);
+                    SectionSlot::Txtr
                 }
                 Section::Tpag(tpag) => {
                     file.tpag = Some(tpag);
+                    SectionSlot::Tpag
                 }
                 Section::Sprt(sprt) => {
                     file.sprt = Some(sprt);
+                    SectionSlot::Sprt
+                }
+                Section::Bgnd(bgnd) => {
+                    file.bgnd = Some(bgnd);
+                    SectionSlot::Bgnd
                 }
                 Section::Font(font) => {
                     file.font = Some(font);
+                    SectionSlot::Font
                 }
-                _ => {}
-            }
+                Section::Agrp(agrp) => {
+                    file.agrp = Some(agrp);
+                    SectionSlot::Agrp
+                }
+                Section::Objt(objt) => {
+                    file.objt = Some(objt);
+                    SectionSlot::Objt
+                }
+                Section::Room(room) => {
+                    file.room = Some(room);
+                    SectionSlot::Room
+                }
+                Section::Code(code) => {
+                    file.code = Some(code);
+                    SectionSlot::Code
+                }
+                Section::Vari(vari) => {
+                    file.vari = Some(vari);
+                    SectionSlot::Vari
+                }
+                Section::Func(func) => {
+                    file.func = Some(func);
+                    SectionSlot::Func
+                }
+                Section::Scpt(scpt) => {
+                    file.scpt = Some(scpt);
+                    SectionSlot::Scpt
+                }
+                Section::Glob(glob) => {
+                    file.glob = Some(glob);
+                    SectionSlot::Glob
+                }
+                Section::Unk { name, data, offset } => {
+                    file.unknown.push((name, data, offset));
+                    SectionSlot::Unk(file.unknown.len() - 1)
+                }
+            };
+            file.layout.push(slot);
         }
+        file.reindex();
 
         file
     }
+
+    pub fn gen8(&self) -> Option<&Gen8> {
+        self.gen8.as_ref()
+    }
+
+    /// Instruction set of the bytecode in CODE, as the layout it comes with: GMS1's for the
+    /// bytecode versions up to 14 GEN8 declares, otherwise GMS2's. Without GEN8 it goes by how
+    /// CODE is laid out. Anything encoding or decoding bytecode goes by this, so they agree.
+    pub fn bytecode_layout(&self) -> CodeLayout {
+        match (self.gen8(), self.code.as_ref()) {
+            (Some(gen8), _) if gen8.bytecode_version() <= GMS1_BYTECODE_VERSION => CodeLayout::Inline,
+            (None, Some(code)) if code.layout == CodeLayout::Inline => CodeLayout::Inline,
+            _ => CodeLayout::Shared,
+        }
+    }
+
+    pub fn backgrounds(&self) -> &[BgndEntry] {
+        self.bgnd.as_ref().map(|bgnd| &bgnd.backgrounds[..]).unwrap_or_default()
+    }
+
+    pub fn objects(&self) -> &[ObjtEntry] {
+        self.objt.as_ref().map(|objt| &objt.entries[..]).unwrap_or_default()
+    }
+
+    pub fn rooms(&self) -> &[RoomEntry] {
+        self.room.as_ref().map(|room| &room.entries[..]).unwrap_or_default()
+    }
+
+    pub fn code_entries(&self) -> &[CodeEntry] {
+        self.code.as_ref().map(|code| &code.entries[..]).unwrap_or_default()
+    }
+
+    /// Every section that isn't parsed, as `(magic, data)` in file order
+    pub fn unknown_sections(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.unknown.iter().map(|(name, data, _)| (&name[..], &data[..]))
+    }
+
+    pub fn unknown_section(&self, magic: &str) -> Option<&[u8]> {
+        self.unknown_sections()
+            .find(|&(name, _)| name == magic)
+            .map(|(_, data)| data)
+    }
+
+    /// Magic of every section in the order they appear in the file
+    pub fn section_order(&self) -> impl Iterator<Item = &str> {
+        self.layout.iter().map(move |slot| match *slot {
+            SectionSlot::Gen8 => "GEN8",
+            SectionSlot::Strg => "STRG",
+            SectionSlot::Sond => "SOND",
+            SectionSlot::Audo(_) => "AUDO",
+            SectionSlot::Txtr => "TXTR",
+            SectionSlot::Tpag => "TPAG",
+            SectionSlot::Sprt => "SPRT",
+            SectionSlot::Bgnd => "BGND",
+            SectionSlot::Font => "FONT",
+            SectionSlot::Agrp => "AGRP",
+            SectionSlot::Objt => "OBJT",
+            SectionSlot::Room => "ROOM",
+            SectionSlot::Code => "CODE",
+            SectionSlot::Vari => "VARI",
+            SectionSlot::Func => "FUNC",
+            SectionSlot::Scpt => "SCPT",
+            SectionSlot::Glob => "GLOB",
+            SectionSlot::Unk(i) => &self.unknown[i].0[..],
+        })
+    }
+
+    /// Write the file back out in its original section order, relocating every pointer to
+    /// match the current contents of each section.
+    ///
+    /// Unknown sections are written unchanged, so pointers inside them can't be relocated.
+    /// This fails with [`Error::Unrelocatable`] rather than leave them dangling if an unknown
+    /// section would move, or the strings, texture pages or textures it may point to would.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// The file as [`FormFile::write_to`] would write it
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let (data, moved) = write_form_relocated(|w| {
+            for &slot in &self.layout {
+                self.write_slot(w, slot);
+            }
+        });
+        self.check_unknown_pointers(&moved)?;
+        Ok(data)
+    }
+
+    /// Make sure nothing the unknown sections may point to has `moved`. Anything that wasn't in
+    /// the parsed file can't be pointed to by them, so it's free to go anywhere.
+    fn check_unknown_pointers(&self, moved: &Relocations) -> Result<(), Error> {
+        let first = match self.unknown.first() {
+            Some((name, ..)) => name,
+            None => return Ok(()),
+        };
+        let unrelocatable = |section: &str, moved: &str| Error::Unrelocatable {
+            section: section.to_owned(),
+            moved: moved.to_owned(),
+        };
+        let stays = |map: &HashMap<u32, u32>| map.iter().all(|(old, new)| *old >= PLACEHOLDER_BASE || old == new);
+
+        for (name, _, offset) in &self.unknown {
+            if moved.sections.get(offset) != Some(offset) {
+                return Err(unrelocatable(name, name));
+            }
+        }
+        if !stays(&moved.strings) {
+            return Err(unrelocatable(first, "STRG"));
+        }
+        if !stays(&moved.tpags) {
+            return Err(unrelocatable(first, "TPAG"));
+        }
+        if let Some(txtr) = &self.txtr {
+            let offset = txtr.offset as u32;
+            if moved.sections.get(&offset).is_some_and(|new| *new != offset) {
+                return Err(unrelocatable(first, "TXTR"));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_slot(&self, w: &mut Writer, slot: SectionSlot) {
+        fn section<S: WriteSection>(w: &mut Writer, magic: &[u8], section: &Option<S>) {
+            if let Some(section) = section {
+                w.section(magic, |w| section.write(w));
+            }
+        }
+
+        match slot {
+            SectionSlot::Gen8 => section(w, b"GEN8", &self.gen8),
+            SectionSlot::Strg => section(w, b"STRG", &self.strg),
+            SectionSlot::Sond => section(w, b"SOND", &self.sond),
+            SectionSlot::Audo(i) => w.section(b"AUDO", |w| self.audos[i].write(w)),
+            SectionSlot::Txtr => {
+                if let Some(txtr) = &self.txtr {
+                    let pos = w.pos();
+                    w.record_section(txtr.offset as u32, pos);
+                }
+                section(w, b"TXTR", &self.txtr)
+            }
+            SectionSlot::Tpag => section(w, b"TPAG", &self.tpag),
+            SectionSlot::Sprt => section(w, b"SPRT", &self.sprt),
+            SectionSlot::Bgnd => section(w, b"BGND", &self.bgnd),
+            SectionSlot::Font => section(w, b"FONT", &self.font),
+            SectionSlot::Agrp => section(w, b"AGRP", &self.agrp),
+            SectionSlot::Objt => section(w, b"OBJT", &self.objt),
+            SectionSlot::Room => section(w, b"ROOM", &self.room),
+            SectionSlot::Code => section(w, b"CODE", &self.code),
+            SectionSlot::Vari => section(w, b"VARI", &self.vari),
+            SectionSlot::Func => section(w, b"FUNC", &self.func),
+            SectionSlot::Scpt => section(w, b"SCPT", &self.scpt),
+            SectionSlot::Glob => section(w, b"GLOB", &self.glob),
+            SectionSlot::Unk(i) => {
+                let (name, data, offset) = &self.unknown[i];
+                let pos = w.pos();
+                w.record_section(*offset, pos);
+                w.section(name.as_bytes(), |w| w.bytes(data));
+            }
+        }
+    }
     
     //#[cfg(textures)]
     pub fn get_texture(&self, index: usize) -> Arc<DynamicImage> {
@@ -204,31 +534,57 @@ impl FormFile {
         }))
     }
 
-    pub fn get_tpag_info(&self, loc: u32) -> (((u16, u16), (u16, u16)), usize) {
+    pub fn get_tpag_info(&self, loc: TpagRef) -> (Rect16, usize) {
         let tpag = self.tpag.as_ref().unwrap().get(loc).unwrap();
         (tpag.sprite_bounds, tpag.texture_index as usize)
     }
 
-    pub fn get_tpag_subimage(&self, loc: u32) -> image::ImageBuffer<image::Rgba<u8>, std::vec::Vec<u8>> {
+    pub fn get_tpag_subimage(&self, loc: TpagRef) -> image::ImageBuffer<image::Rgba<u8>, std::vec::Vec<u8>> {
         let (((x, y), (w, h)), texture_index) = self.get_tpag_info(loc);
         self.get_texture(texture_index).view(
             x as u32, y as u32, w as u32, h as u32
         ).to_image()
     }
 
-    pub fn get_tpag_from_name_and_frame(&self, sprite_name: &str, frame: usize) -> Option<(((u16, u16), (u16, u16)), usize)> {
-        let offset = self.name_to_offset(sprite_name)? as u32;
+    /// A frame of a sprite at the sprite's full size, with the part of it that's on a texture
+    /// page put back where it was trimmed from
+    pub fn get_sprite_frame(&self, sprite: usize, frame: usize) -> Option<image::RgbaImage> {
+        let sprite = self.sprt.as_ref()?.sprites.get(sprite)?;
+        let &tpag_offset = sprite.tpag_offsets.get(frame)?;
+        let part = self.get_tpag_subimage(tpag_offset);
+        let ((x, y), _) = self.tpag.as_ref()?.get(tpag_offset)?.unk2;
+        let (width, height) = match sprite.size {
+            (0, _) | (_, 0) => part.dimensions(),
+            size => size,
+        };
+        let mut image = image::RgbaImage::new(width, height);
+        image::imageops::overlay(&mut image, &part, x as u32, y as u32);
+        Some(image)
+    }
+
+    pub fn get_tpag_from_name_and_frame(&self, sprite_name: &str, frame: usize) -> Option<(Rect16, usize)> {
+        Some(self.get_tpag_info(*self.sprite(sprite_name)?.tpag_offsets.get(frame)?))
+    }
+
+    /// The script called `script_name`, along with the CODE entry it runs
+    pub fn script_by_name(&self, script_name: &str) -> Option<(&ScptEntry, &CodeEntry)> {
+        let script = self.script(script_name)?;
+        Some((script.entry, script.code()?))
+    }
+
+    /// CODE entries the object called `object_name` runs for `event`, with their indices
+    pub fn object_event_code(&self, object_name: &str, event: ObjectEvent) -> Vec<(usize, &CodeEntry)> {
+        self.object(object_name)
+            .map(|object| object.event_code(event))
+            .unwrap_or_default()
+    }
 
-        Some(self.get_tpag_info(
-            self.sprt.as_ref().unwrap().sprites.iter().find(
-                |sprite| sprite.name_offset == offset
-            )?.tpag_offsets.get(frame).copied()?
-        ))
+    pub fn name_to_offset(&self, name: &str) -> Option<StringRef> {
+        self.strg.as_ref().unwrap().find(name)
     }
 
-    pub fn name_to_offset(&self, name: &str) -> Option<u32> {
-        let strg = self.strg.as_ref().unwrap();
-        let index = strg.strings.iter().position(|string| string == name)?;
-        strg.locations.get_by_right(&index).copied()
+    /// Look up what `reference` refers to, if it's in the file
+    pub fn resolve<R: Resolve>(&self, reference: R) -> Option<&R::Target> {
+        reference.resolve(self)
     }
 }
@@ -1,18 +1,21 @@
-use binrw::{derive_binread, BinRead, BinReaderExt};
-use nom::{IResult, error::ErrorKind};
-use super::{PosSlice, PosCursor, ptr_list};
+use binrw::{derive_binread, BinRead, BinReaderExt, io::Seek};
+use super::{PosSlice, PResult, StringRef, ObjectId, SpriteId, Error, PosCursor, Writer, WriteSection, ptr_list};
+use super::helpers::end_alignment;
 
 use std::fmt;
 
 impl super::ParseSection for Objt {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
-        let mut cursor = PosCursor::from(input.clone());
+    fn take(input: PosSlice) -> PResult<Self> {
+        let mut cursor = PosCursor::from(input);
 
-        if let Ok(objt) = cursor.read_le() {
-            Ok((input, objt))
-        } else {
-            Err(nom::Err::Error((input, ErrorKind::ParseTo)))
-        }
+        let mut objt: Objt = cursor.read_le()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err, input.pos())))?;
+
+        let content_end = cursor.stream_position()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err.into(), input.pos())))?;
+        objt.end_align = end_alignment(content_end as usize, input.pos() + input.len());
+
+        Ok((input, objt))
     }
 }
 
@@ -20,11 +23,14 @@ impl super::ParseSection for Objt {
 pub struct Objt {
     #[br(parse_with = ptr_list)]
     pub entries: Vec<ObjtEntry>,
+
+    #[br(calc = 1)]
+    pub end_align: u32,
 }
 
 #[derive(BinRead, Debug, Clone)]
 pub struct ObjtEntry {
-    pub name_offset: u32,
+    pub name_offset: StringRef,
     pub sprite_index: i32,
 
     #[br(map = gm_bool)]
@@ -49,8 +55,9 @@ pub struct ObjtEntry {
 
     pub physics: ObjtPhysics,
 
+    /// One list per event type, indexed by `ObjectEvent::event_type`
     #[br(parse_with = ptr_list)]
-    pub unk_list: Vec<Unk>,
+    pub event_lists: Vec<ObjtEventList>,
 }
 
 #[derive_binread]
@@ -79,38 +86,202 @@ pub struct ObjtPhysics {
 }
 
 #[derive(Debug, BinRead, Clone)]
-pub struct Unk {
+pub struct ObjtEventList {
     #[br(parse_with = ptr_list)]
-    unk_list: Vec<Unk2>,
+    pub events: Vec<ObjtEvent>,
 }
 
 #[derive(Debug, BinRead, Clone)]
-pub struct Unk2 {
-    unk: u32,
+pub struct ObjtEvent {
+    pub subtype: u32,
 
     #[br(parse_with = ptr_list)]
-    unk_list: Vec<Unk3>,
+    pub actions: Vec<EventAction>,
 }
 
-#[derive_binread]
-#[derive(Debug, Clone)]
-pub struct Unk3 {
-    #[br(temp)]
-    mask_count: u32,
+/// A drag and drop action. GMS only ever uses the one that runs a CODE entry.
+#[derive(Debug, BinRead, Clone)]
+pub struct EventAction {
+    pub lib_id: u32,
+    pub id: u32,
+    pub kind: u32,
 
-    #[br(count = mask_count)]
-    mask_offets: Vec<u32>,
+    #[br(map = gm_bool)]
+    pub use_relative: bool,
 
-    #[br(temp)]
-    unk_count: u32,
+    #[br(map = gm_bool)]
+    pub is_question: bool,
+
+    #[br(map = gm_bool)]
+    pub use_apply_to: bool,
+
+    pub exe_type: u32,
+    pub action_name_offset: StringRef,
+    /// Index into CODE
+    pub code_id: i32,
+    pub argument_count: u32,
+    pub who: i32,
+
+    #[br(map = gm_bool)]
+    pub relative: bool,
 
-    #[br(count = unk_count)]
-    unk_indices: Vec<u32>,
+    #[br(map = gm_bool)]
+    pub is_not: bool,
+
+    pub unk: u32,
+}
+
+/// Which event of an object a list of actions runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectEvent {
+    Create,
+    Destroy,
+    /// Alarm 0 to 11
+    Alarm(u32),
+    Step(StepEvent),
+    /// Collision with the object at this OBJT index
+    Collision(u32),
+    /// A key being held, by virtual key code
+    Keyboard(u32),
+    Mouse(u32),
+    Other(u32),
+    /// Draw, Draw GUI, Draw Begin, ... by GameMaker's subtype number
+    Draw(u32),
+    KeyPress(u32),
+    KeyRelease(u32),
+    Trigger(u32),
+    CleanUp,
+    Gesture(u32),
+    PreCreate,
+    /// An event type or step subtype this doesn't know about
+    Unknown { event_type: u32, subtype: u32 },
+}
 
-    unk_index: i32,
-    unk: u32,
-    unk2: u32,
-    unk3: u32,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StepEvent {
+    Step,
+    BeginStep,
+    EndStep,
+}
+
+const EVENT_NAMES: &[&str] = &[
+    "Create", "Destroy", "Alarm", "Step", "Collision", "Keyboard", "Mouse", "Other", "Draw",
+    "KeyPress", "KeyRelease", "Trigger", "CleanUp", "Gesture", "PreCreate",
+];
+
+impl ObjectEvent {
+    pub fn new(event_type: u32, subtype: u32) -> Self {
+        match (event_type, subtype) {
+            (0, _) => ObjectEvent::Create,
+            (1, _) => ObjectEvent::Destroy,
+            (2, alarm) => ObjectEvent::Alarm(alarm),
+            (3, 0) => ObjectEvent::Step(StepEvent::Step),
+            (3, 1) => ObjectEvent::Step(StepEvent::BeginStep),
+            (3, 2) => ObjectEvent::Step(StepEvent::EndStep),
+            (4, object) => ObjectEvent::Collision(object),
+            (5, key) => ObjectEvent::Keyboard(key),
+            (6, subtype) => ObjectEvent::Mouse(subtype),
+            (7, subtype) => ObjectEvent::Other(subtype),
+            (8, subtype) => ObjectEvent::Draw(subtype),
+            (9, key) => ObjectEvent::KeyPress(key),
+            (10, key) => ObjectEvent::KeyRelease(key),
+            (11, subtype) => ObjectEvent::Trigger(subtype),
+            (12, _) => ObjectEvent::CleanUp,
+            (13, subtype) => ObjectEvent::Gesture(subtype),
+            (14, _) => ObjectEvent::PreCreate,
+            (event_type, subtype) => ObjectEvent::Unknown { event_type, subtype },
+        }
+    }
+
+    /// Index of the event's list in `ObjtEntry::event_lists`
+    pub fn event_type(self) -> u32 {
+        match self {
+            ObjectEvent::Create => 0,
+            ObjectEvent::Destroy => 1,
+            ObjectEvent::Alarm(_) => 2,
+            ObjectEvent::Step(_) => 3,
+            ObjectEvent::Collision(_) => 4,
+            ObjectEvent::Keyboard(_) => 5,
+            ObjectEvent::Mouse(_) => 6,
+            ObjectEvent::Other(_) => 7,
+            ObjectEvent::Draw(_) => 8,
+            ObjectEvent::KeyPress(_) => 9,
+            ObjectEvent::KeyRelease(_) => 10,
+            ObjectEvent::Trigger(_) => 11,
+            ObjectEvent::CleanUp => 12,
+            ObjectEvent::Gesture(_) => 13,
+            ObjectEvent::PreCreate => 14,
+            ObjectEvent::Unknown { event_type, .. } => event_type,
+        }
+    }
+
+    pub fn subtype(self) -> u32 {
+        match self {
+            ObjectEvent::Create | ObjectEvent::Destroy | ObjectEvent::CleanUp | ObjectEvent::PreCreate => 0,
+            ObjectEvent::Step(step) => step as u32,
+            ObjectEvent::Alarm(subtype)
+            | ObjectEvent::Collision(subtype)
+            | ObjectEvent::Keyboard(subtype)
+            | ObjectEvent::Mouse(subtype)
+            | ObjectEvent::Other(subtype)
+            | ObjectEvent::Draw(subtype)
+            | ObjectEvent::KeyPress(subtype)
+            | ObjectEvent::KeyRelease(subtype)
+            | ObjectEvent::Trigger(subtype)
+            | ObjectEvent::Gesture(subtype)
+            | ObjectEvent::Unknown { subtype, .. } => subtype,
+        }
+    }
+
+    /// Name GameMaker uses for the event type in CODE entry names, like
+    /// `gml_Object_obj_player_Step_0`
+    pub fn name(self) -> String {
+        EVENT_NAMES.get(self.event_type() as usize)
+            .map(|&name| String::from(name))
+            .unwrap_or_else(|| format!("Event{}", self.event_type()))
+    }
+}
+
+impl fmt::Display for ObjectEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.name(), self.subtype())
+    }
+}
+
+impl ObjtEntry {
+    /// The sprite the object is drawn with, if it has one
+    pub fn sprite(&self) -> Option<SpriteId> {
+        SpriteId::from_index(self.sprite_index)
+    }
+
+    /// The object it inherits from, if any
+    pub fn parent(&self) -> Option<ObjectId> {
+        ObjectId::from_index(self.parent_index)
+    }
+
+    /// Every event the object handles itself, in the order GameMaker stores them
+    pub fn events(&self) -> impl Iterator<Item = (ObjectEvent, &ObjtEvent)> {
+        self.event_lists.iter()
+            .enumerate()
+            .flat_map(|(event_type, list)| list.events.iter()
+                .map(move |event| (ObjectEvent::new(event_type as u32, event.subtype), event)))
+    }
+
+    /// The object's own handler for `event`, if it has one
+    pub fn event(&self, event: ObjectEvent) -> Option<&ObjtEvent> {
+        self.events()
+            .find(|&(other, _)| other == event)
+            .map(|(_, handler)| handler)
+    }
+}
+
+impl ObjtEvent {
+    /// Index into CODE of each action's code, in the order they run
+    pub fn code_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.actions.iter()
+            .filter(|action| action.code_id >= 0)
+            .map(|action| action.code_id as usize)
+    }
 }
 
 #[derive(BinRead, Clone)]
@@ -139,3 +310,70 @@ pub enum CollisionShape {
 fn gm_bool(var: u32) -> bool {
     var != 0
 }
+
+impl ObjtEntry {
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.i32(self.sprite_index);
+        w.bool(self.is_visible);
+        w.bool(self.is_solid);
+        w.i32(self.depth);
+        w.bool(self.is_persistent);
+        w.i32(self.parent_index);
+        w.i32(self.texture_mask_index);
+        w.bool(self.uses_physics);
+        w.bool(self.is_sensor);
+        self.physics.write(w);
+        w.ptr_list(&self.event_lists, 1, |w, list| {
+            w.ptr_list(&list.events, 1, |w, event| {
+                w.u32(event.subtype);
+                w.ptr_list(&event.actions, 1, |w, action| action.write(w));
+            });
+        });
+    }
+}
+
+impl ObjtPhysics {
+    pub fn write(&self, w: &mut Writer) {
+        w.u32(self.shape.clone() as u32);
+        w.f32(self.density);
+        w.f32(self.restitution);
+        w.u32(self.collision_group);
+        w.f32(self.linear_damping);
+        w.f32(self.angular_damping);
+        w.u32(self.collision_points.len() as u32);
+        w.f32(self.friction);
+        w.bool(self.start_awake);
+        w.bool(self.kinematic);
+        for point in &self.collision_points {
+            w.f32(point.x);
+            w.f32(point.y);
+        }
+    }
+}
+
+impl EventAction {
+    pub fn write(&self, w: &mut Writer) {
+        w.u32(self.lib_id);
+        w.u32(self.id);
+        w.u32(self.kind);
+        w.bool(self.use_relative);
+        w.bool(self.is_question);
+        w.bool(self.use_apply_to);
+        w.u32(self.exe_type);
+        w.string(self.action_name_offset);
+        w.i32(self.code_id);
+        w.u32(self.argument_count);
+        w.i32(self.who);
+        w.bool(self.relative);
+        w.bool(self.is_not);
+        w.u32(self.unk);
+    }
+}
+
+impl WriteSection for Objt {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.entries, 1, |w, entry| entry.write(w));
+        w.align(self.end_align);
+    }
+}
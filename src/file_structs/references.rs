@@ -0,0 +1,279 @@
+use super::*;
+
+/// What a variable or function reference in new bytecode refers to, by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    /// A variable accessed through `instance` (`-1` for self, `-5` for global, ...)
+    Variable { name: String, instance: i16 },
+    Function(String),
+}
+
+/// A place in CODE that refers to a variable or function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Occurrence {
+    /// Index of the CODE entry containing the instruction
+    pub code_entry: usize,
+    /// Offset of the instruction within the entry's bytecode
+    pub offset: u32,
+    /// Address of the instruction in the parsed file, as VARI and FUNC store it
+    pub address: u32,
+}
+
+/// Which occurrence chain a reference belongs to
+#[derive(Debug, Clone, Copy)]
+enum Chain {
+    Variable(usize),
+    Function(usize),
+}
+
+const GLOBAL: i16 = -5;
+const LOCAL: i16 = -7;
+
+/// Instance type GMS2 stores in VARI for variables accessed through `instance`
+fn instance_type(instance: i16) -> i32 {
+    match instance {
+        GLOBAL | LOCAL => instance as i32,
+        _ => -1,
+    }
+}
+
+impl FormFile {
+    pub fn variables(&self) -> &[VariEntry] {
+        self.vari.as_ref().map(|vari| &vari.variables[..]).unwrap_or_default()
+    }
+
+    pub fn functions(&self) -> &[FuncEntry] {
+        self.func.as_ref().map(|func| &func.functions[..]).unwrap_or_default()
+    }
+
+    /// Every instruction referring to the VARI entry at `index`, in the order of the chain
+    pub fn variable_occurrences(&self, index: usize) -> Vec<Occurrence> {
+        let variable = &self.variables()[index];
+        self.occurrences(variable.first_address, variable.occurrences)
+    }
+
+    /// Every instruction calling the FUNC entry at `index`, in the order of the chain
+    pub fn function_occurrences(&self, index: usize) -> Vec<Occurrence> {
+        let function = &self.functions()[index];
+        self.occurrences(function.first_address, function.occurrences)
+    }
+
+    fn occurrences(&self, first_address: u32, count: u32) -> Vec<Occurrence> {
+        let code = match self.code.as_ref() {
+            Some(code) => code,
+            None => return Vec::new(),
+        };
+
+        code.occurrences(first_address, count)
+            .into_iter()
+            .filter_map(|pos| {
+                let code_entry = code.entry_at(pos)?;
+                Some(Occurrence {
+                    code_entry,
+                    offset: pos as u32 - code.entries[code_entry].bytecode_start,
+                    address: code.address_of(pos)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Replace the bytecode of the CODE entry at `index`, relinking the occurrence chain of
+    /// every variable and function so they still cover the bytecode that moved.
+    ///
+    /// `references` gives the offset of each instruction in `bytecode` that refers to a
+    /// variable or function, and what it refers to. Strings, VARI and FUNC entries (and the
+    /// sections themselves) are added for names the file doesn't have yet. Fails if there's no
+    /// CODE or STRG section to add to.
+    pub fn replace_bytecode(
+        &mut self,
+        index: usize,
+        bytecode: &[u8],
+        references: &[(u32, Reference)],
+    ) -> Result<(), MissingSection> {
+        let layout = self.code.as_ref().ok_or(MissingSection("CODE"))?.layout;
+        if self.strg.is_none() {
+            return Err(MissingSection("STRG"));
+        }
+        if self.vari.is_none() {
+            self.vari = Some(Vari {
+                header: if layout == CodeLayout::Inline { None } else { Some((0, 0, 0)) },
+                variables: Vec::new(),
+                end_align: 1,
+            });
+            self.insert_after_code(SectionSlot::Vari);
+        }
+        if self.func.is_none() {
+            self.func = Some(Func {
+                functions: Vec::new(),
+                code_locals: if layout == CodeLayout::Inline { None } else { Some(Vec::new()) },
+                end_align: 1,
+            });
+            self.insert_after_code(SectionSlot::Func);
+        }
+
+        let chains = references.iter()
+            .map(|(offset, reference)| (*offset as usize, self.chain(index, reference)))
+            .collect::<Vec<_>>();
+
+        let code = self.code.as_mut().unwrap();
+        let vari = self.vari.as_mut().unwrap();
+        let func = self.func.as_mut().unwrap();
+
+        let mut variables = vari.variables.iter()
+            .map(|variable| code.occurrences(variable.first_address, variable.occurrences))
+            .collect::<Vec<_>>();
+        let mut functions = func.functions.iter()
+            .map(|function| code.occurrences(function.first_address, function.occurrences))
+            .collect::<Vec<_>>();
+
+        let range = code.splice(index, bytecode);
+        let new_end = range.start + bytecode.len();
+        for positions in variables.iter_mut().chain(functions.iter_mut()) {
+            *positions = positions.iter()
+                .filter(|pos| !range.contains(pos))
+                .map(|&pos| if pos >= range.end { pos - range.end + new_end } else { pos })
+                .collect();
+        }
+        for (offset, chain) in chains {
+            match chain {
+                Chain::Variable(i) => variables[i].push(range.start + offset),
+                Chain::Function(i) => functions[i].push(range.start + offset),
+            }
+        }
+
+        let strg = self.strg.as_ref().unwrap();
+        let name_id = |name_offset| strg.index_of(name_offset).unwrap_or(0) as u32;
+        for (variable, positions) in vari.variables.iter_mut().zip(&mut variables) {
+            let (occurrences, first_address) = relink(code, positions, name_id(variable.name_offset));
+            variable.occurrences = occurrences;
+            variable.first_address = first_address;
+        }
+        for (function, positions) in func.functions.iter_mut().zip(&mut functions) {
+            let (occurrences, first_address) = relink(code, positions, name_id(function.name_offset));
+            function.occurrences = occurrences;
+            function.first_address = first_address;
+        }
+        Ok(())
+    }
+
+    fn insert_after_code(&mut self, slot: SectionSlot) {
+        let at = self.layout.iter()
+            .rposition(|slot| matches!(slot, SectionSlot::Code | SectionSlot::Vari))
+            .map(|i| i + 1)
+            .unwrap_or(self.layout.len());
+        self.layout.insert(at, slot);
+    }
+
+    /// Find the VARI or FUNC entry for a reference from the CODE entry at `index`, adding one
+    /// if there isn't one yet
+    fn chain(&mut self, index: usize, reference: &Reference) -> Chain {
+        match reference {
+            Reference::Function(name) => {
+                let strg = self.strg.as_mut().unwrap();
+                let func = self.func.as_mut().unwrap();
+                let existing = func.functions.iter()
+                    .position(|function| strg.get(function.name_offset) == Some(name));
+                Chain::Function(existing.unwrap_or_else(|| {
+                    func.functions.push(FuncEntry {
+                        name_offset: strg.intern(name),
+                        occurrences: 0,
+                        first_address: u32::MAX,
+                    });
+                    func.functions.len() - 1
+                }))
+            }
+            Reference::Variable { name, instance } => {
+                let strg = self.strg.as_ref().unwrap();
+                let gms2 = self.vari.as_ref().unwrap().header.is_some();
+                let group = instance_type(*instance);
+                let local = gms2 && group == LOCAL as i32;
+                let existing = self.variables().iter().position(|variable| {
+                    strg.get(variable.name_offset) == Some(name)
+                        && (!gms2 || instance_type(variable.instance_type as i16) == group)
+                });
+                if let Some(existing) = existing {
+                    // locals are shared between entries, but each entry lists its own
+                    if local {
+                        self.declare_local(index, self.variables()[existing].name_offset);
+                    }
+                    return Chain::Variable(existing);
+                }
+
+                let name_offset = self.strg.as_mut().unwrap().intern(name);
+                let id = if local {
+                    self.declare_local(index, name_offset) as i32
+                } else {
+                    match self.vari.as_mut().unwrap().header.as_mut() {
+                        None => 0,
+                        Some(header) => {
+                            let id = header.0 as i32;
+                            header.0 += 1;
+                            header.1 += 1;
+                            id
+                        }
+                    }
+                };
+
+                let vari = self.vari.as_mut().unwrap();
+                vari.variables.push(VariEntry {
+                    name_offset,
+                    instance_type: if gms2 { group } else { 0 },
+                    id,
+                    occurrences: 0,
+                    first_address: u32::MAX,
+                });
+                Chain::Variable(vari.variables.len() - 1)
+            }
+        }
+    }
+
+    /// Add a local to the ones FUNC lists for the CODE entry at `index`, if it isn't there
+    /// yet. Returns its id within the entry.
+    fn declare_local(&mut self, index: usize, name_offset: StringRef) -> u32 {
+        let entry = &mut self.code.as_mut().unwrap().entries[index];
+        let code_locals = self.func.as_mut()
+            .and_then(|func| func.code_locals.as_mut())
+            .unwrap();
+        let position = code_locals.iter()
+            .position(|locals| locals.name_offset == entry.name_offset)
+            .unwrap_or_else(|| {
+                code_locals.push(CodeLocals { name_offset: entry.name_offset, locals: Vec::new() });
+                code_locals.len() - 1
+            });
+
+        let locals = &mut code_locals[position].locals;
+        if let Some(&(id, _)) = locals.iter().find(|&&(_, other)| other == name_offset) {
+            return id;
+        }
+        let id = locals.len() as u32;
+        locals.push((id, name_offset));
+        entry.locals_count = locals.len() as u16;
+        let header = self.vari.as_mut().and_then(|vari| vari.header.as_mut()).unwrap();
+        header.2 = header.2.max(locals.len() as u32);
+        id
+    }
+}
+
+/// Point each reference at the next one in `positions`, with the last holding the id of the
+/// name. Returns the occurrence count and first address to store in VARI or FUNC.
+pub(crate) fn relink(code: &mut Code, positions: &mut [usize], name_id: u32) -> (u32, u32) {
+    positions.sort_unstable();
+    for i in 0..positions.len() {
+        let value = match positions.get(i + 1) {
+            Some(&next) => code.relative_address(next).unwrap_or(0)
+                - code.relative_address(positions[i]).unwrap_or(0),
+            None => name_id,
+        };
+        let at = positions[i] + 4;
+        let word = u32::from_le_bytes([
+            code.bytecode[at], code.bytecode[at + 1], code.bytecode[at + 2], code.bytecode[at + 3]
+        ]);
+        let word = (word & 0xf800_0000) | (value & 0x07ff_ffff);
+        code.bytecode[at..at + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    match positions.first() {
+        Some(&first) => (positions.len() as u32, code.address_of(first).unwrap_or(u32::MAX)),
+        None => (0, u32::MAX),
+    }
+}
@@ -1,15 +1,67 @@
-use binrw::{derive_binread, BinRead, BinReaderExt, io::SeekFrom};
-use nom::{IResult, error::ErrorKind};
-use super::{PosSlice, PosCursor, ptr_list};
+use binrw::{derive_binread, BinRead, BinReaderExt, BinResult, ReadOptions};
+use binrw::io::{Read, Seek, SeekFrom};
+use super::{PosSlice, PResult, StringRef, ObjectId, Error, PosCursor, Writer, WriteSection, ptr_list};
+use super::helpers::entry_ends;
 
 impl super::ParseSection for Room {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
-        let mut cursor = PosCursor::from(input.clone());
+    fn take(input: PosSlice) -> PResult<Self> {
+        let mut cursor = PosCursor::from(input);
 
-        if let Ok(room) = cursor.read_le() {
-            Ok((input, room))
-        } else {
-            Err(nom::Err::Error((input, ErrorKind::ParseTo)))
+        let mut room: Room = cursor.read_le()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err, input.pos())))?;
+
+        room.take_unparsed(input);
+
+        Ok((input, room))
+    }
+}
+
+fn u32_at(input: PosSlice, pos: u32) -> Option<u32> {
+    let bytes = input.at(pos as usize)?.1.get(..4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn ptrs_at(input: PosSlice, pos: u32) -> Vec<u32> {
+    let count = u32_at(input, pos).unwrap_or(0);
+    (0..count)
+        .map_while(|i| u32_at(input, pos + 4 + (i * 4)))
+        .collect()
+}
+
+fn bytes_between(input: PosSlice, start: usize, end: usize) -> Vec<u8> {
+    input.at(start)
+        .map(|rest| rest.1[..end.saturating_sub(start).min(rest.len())].to_vec())
+        .unwrap_or_default()
+}
+
+impl Room {
+    /// Keep the bytes following each layer (and the contents of `sequences`) that aren't parsed
+    /// yet, so they can be written back out unchanged
+    fn take_unparsed(&mut self, input: PosSlice) {
+        let room_offsets = ptrs_at(input, input.pos() as u32);
+        let room_ends = entry_ends(&room_offsets, input.pos() + input.len());
+
+        for ((entry, &offset), room_end) in self.entries.iter_mut().zip(&room_offsets).zip(room_ends) {
+            // layers and sequences are the last two pointers in the room header
+            let layers_offset = u32_at(input, offset + (22 * 4)).unwrap_or(0);
+            let sequences_offset = u32_at(input, offset + (23 * 4)).unwrap_or(0);
+
+            let layer_offsets = ptrs_at(input, layers_offset);
+            let layers_end = if sequences_offset > layers_offset { sequences_offset as usize } else { room_end };
+            let layer_ends = entry_ends(&layer_offsets, layers_end);
+            for ((layer, &start), end) in entry.layers.iter_mut().zip(&layer_offsets).zip(layer_ends) {
+                let mut scratch = Writer::default();
+                layer.write(&mut scratch);
+                layer.trailing = bytes_between(input, start as usize + scratch.pos() as usize, end);
+            }
+
+            if entry.has_sequences {
+                let sequence_offsets = ptrs_at(input, sequences_offset);
+                let sequence_ends = entry_ends(&sequence_offsets, room_end);
+                for ((sequence, &start), end) in entry.sequences.iter_mut().zip(&sequence_offsets).zip(sequence_ends) {
+                    sequence.data = bytes_between(input, start as usize, end);
+                }
+            }
         }
     }
 }
@@ -23,8 +75,8 @@ pub struct Room {
 #[derive_binread]
 #[derive(Debug, Clone)]
 pub struct RoomEntry {
-    pub name_offset: u32,
-    pub caption_offset: u32,
+    pub name_offset: StringRef,
+    pub caption_offset: StringRef,
     pub width: u32,
     pub height: u32,
     pub speed: u32,
@@ -60,8 +112,8 @@ pub struct RoomEntry {
     #[br(temp)]
     pub layers_offset: u32,
 
-    //#[br(temp)]
-    pub unk_pointer2: u32,
+    #[br(temp)]
+    sequences_offset: u32,
 
     #[br(seek_before = SeekFrom::Start(bgs_offset as u64), parse_with = ptr_list)]
     pub backgrounds: Vec<Background>,
@@ -79,38 +131,55 @@ pub struct RoomEntry {
     pub layers: Vec<Layer>,
 
     #[br(
-        if(unk_pointer2 != 0),
-        seek_before = SeekFrom::Start(unk_pointer2 as u64),
+        if(sequences_offset != 0),
+        seek_before = SeekFrom::Start(sequences_offset as u64),
         parse_with = ptr_list
     )]
-    pub unk2: Vec<binrw::PosValue<Unk2>>,
+    pub sequences: Vec<RoomSequence>,
 
-    #[br(calc = unk_pointer2 != 0)]
-    pub has_unk2: bool,
+    /// Whether the room header points to a sequence list (GMS 2.3+), even an empty one
+    #[br(calc = sequences_offset != 0)]
+    pub has_sequences: bool,
 }
 
 #[derive_binread]
 #[derive(Debug, Clone)]
 pub struct Layer {
-    pub name_offset: u32,
+    pub name_offset: StringRef,
     pub index: u32,
+    #[br(temp)]
+    kind_id: u32,
+    pub depth: i32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub horizontal_speed: f32,
+    pub vertical_speed: f32,
+    #[br(map = gm_bool)]
+    pub is_visible: bool,
+    #[br(args(kind_id))]
     pub kind: LayerKind,
+
+    /// Unparsed data between this layer and the next
+    #[br(calc = Vec::new())]
+    pub trailing: Vec<u8>,
 }
 
 #[derive_binread]
 #[derive(Debug, Clone)]
+#[br(import(kind: u32))]
 pub enum LayerKind {
-    #[br(magic = 1u32)]
+    #[br(pre_assert(kind == 0))]
+    Path,
+
+    #[br(pre_assert(kind == 1))]
     Background {
-        depth: i32,
-        x_offset: f32,
-        y_offset: f32,
-        horizontal_speed: f32,
-        vertical_speed: f32,
-        unk: u32, // 1
-        unk2: u32, // 1
-        unk3: u32, // 0
-        sprite_index: i32, 
+        #[br(map = gm_bool)]
+        visible: bool,
+
+        #[br(map = gm_bool)]
+        foreground: bool,
+
+        sprite_index: i32,
 
         #[br(map = gm_bool)]
         horizontal_tile: bool,
@@ -122,38 +191,129 @@ pub enum LayerKind {
         stretch: bool,
 
         color: RgbaColor,
-        unk4: u32,
+        first_frame: f32,
         animation_speed: f32,
         animation_speed_unit: SpeedUnit,
     },
 
-    #[br(magic = 2u32)]
-    Instance {
-        depth: i32,
-        x_offset: f32,
-        y_offset: f32,
-        horizontal_speed: f32,
-        vertical_speed: f32,
-        unk: u32, // 1
-        unk2: u32, // 1
+    #[br(pre_assert(kind == 2))]
+    Instances {
+        #[br(temp)]
+        count: u32,
+
+        /// `instance_id`s of the room's game objects on this layer
+        #[br(count = count)]
+        instance_ids: Vec<u32>,
     },
 
-    #[br(magic = 3u32)]
-    Tile {
-        depth: i32,
+    #[br(pre_assert(kind == 3))]
+    Assets(#[br(parse_with = asset_layer)] AssetLayer),
 
+    #[br(pre_assert(kind == 4))]
+    Tiles {
+        /// Index of the background (tile set) the tiles come from
+        background_index: i32,
+        width: u32,
+        height: u32,
+
+        /// `width * height` tiles, row by row. See [`TILE_INDEX_MASK`] for the flags.
+        #[br(count = width * height)]
+        tiles: Vec<u32>,
     },
 
-    #[br(magic = 4u32)]
-    Path {
-        depth: i32,
+    #[br(pre_assert(kind == 6))]
+    Effect {
+        effect_type_offset: StringRef,
+
+        #[br(temp)]
+        count: u32,
 
+        #[br(count = count)]
+        properties: Vec<EffectProperty>,
     },
+
+    /// A kind of layer this doesn't know about, with its data left in `trailing`
+    Unknown {
+        #[br(calc = kind)]
+        kind: u32,
+    },
+}
+
+/// Bits of a tile layer entry holding the tile index. The bits above are flags.
+pub const TILE_INDEX_MASK: u32 = 0x0007_ffff;
+/// Flags of a tile layer entry. A tile is mirrored and flipped before it's rotated 90 degrees
+/// clockwise.
+pub const TILE_MIRROR: u32 = 0x1000_0000;
+pub const TILE_FLIP: u32 = 0x2000_0000;
+pub const TILE_ROTATE: u32 = 0x4000_0000;
+
+/// Contents of an asset layer
+#[derive(Debug, Clone, Default)]
+pub struct AssetLayer {
+    /// Tiles converted from a GMS1 room
+    pub legacy_tiles: Vec<Tile>,
+    pub sprites: Vec<AssetInstance>,
+    /// GMS 2.3 and later only
+    pub sequences: Option<Vec<AssetInstance>>,
+    /// Only in versions that also have `sequences`
+    pub nine_slices: Option<Vec<AssetInstance>>,
+}
+
+/// A sprite, sequence or nine slice sprite placed on an asset layer
+#[derive_binread]
+#[derive(Debug, Clone)]
+pub struct AssetInstance {
+    pub name_offset: StringRef,
+    /// Index of the SPRT (or sequence) entry
+    pub asset_index: i32,
+    pub x: i32,
+    pub y: i32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub color: RgbaColor,
+    pub animation_speed: f32,
+    pub animation_speed_unit: SpeedUnit,
+    pub frame_index: f32,
+    pub rotation: f32,
 }
 
 #[derive_binread]
 #[derive(Debug, Clone)]
-pub struct Unk2 {
+pub struct EffectProperty {
+    /// 0 for a number, 1 for a color, 2 for a sampler
+    pub kind: i32,
+    pub name_offset: StringRef,
+    pub value_offset: StringRef,
+}
+
+fn asset_layer<R: Read + Seek>(reader: &mut R, options: &ReadOptions, _: ()) -> BinResult<AssetLayer> {
+    // the lists come right after the pointers to them, which tells how many there are
+    let start = reader.stream_position()?;
+    let first = reader.read_le::<u32>()?;
+    let count = ((first as u64).saturating_sub(start) / 4).clamp(2, 4);
+    let mut offsets = vec![first];
+    for _ in 1..count {
+        offsets.push(reader.read_le()?);
+    }
+
+    let legacy_tiles = list_at(reader, options, offsets[0])?;
+    let sprites = list_at(reader, options, offsets[1])?;
+    let sequences = offsets.get(2).map(|&offset| list_at(reader, options, offset)).transpose()?;
+    let nine_slices = offsets.get(3).map(|&offset| list_at(reader, options, offset)).transpose()?;
+    Ok(AssetLayer { legacy_tiles, sprites, sequences, nine_slices })
+}
+
+fn list_at<R: Read + Seek, T: BinRead<Args = ()>>(reader: &mut R, options: &ReadOptions, offset: u32) -> BinResult<Vec<T>> {
+    reader.seek(SeekFrom::Start(offset as u64))?;
+    ptr_list(reader, options, ())
+}
+
+/// Sequences placed in a GMS 2.3+ room, kept as they are
+#[derive_binread]
+#[derive(Debug, Clone)]
+pub struct RoomSequence {
+    #[br(calc = Vec::new())]
+    pub data: Vec<u8>,
 }
 
 #[derive_binread]
@@ -230,10 +390,10 @@ pub struct Tile {
 #[derive_binread]
 #[derive(Debug, Clone)]
 pub struct RgbaColor {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8
 }
 
 #[derive_binread]
@@ -247,3 +407,262 @@ pub enum SpeedUnit {
 fn gm_bool(var: u32) -> bool {
     var != 0
 }
+
+impl WriteSection for Room {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.entries, 1, |w, entry| entry.write(w));
+    }
+}
+
+impl RoomEntry {
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.string(self.caption_offset);
+        w.u32(self.width);
+        w.u32(self.height);
+        w.u32(self.speed);
+        w.bool(self.persistent);
+        w.u32(self.argb);
+        w.bool(self.draw_bg_color);
+        w.u32(self._unk1);
+        w.u32(self.flags);
+        let bgs_offset = w.reserve_u32();
+        let views_offset = w.reserve_u32();
+        let objs_offset = w.reserve_u32();
+        let tiles_offset = w.reserve_u32();
+        w.u32(self.world);
+        w.u32(self.top);
+        w.u32(self.left);
+        w.u32(self.right);
+        w.u32(self.bottom);
+        w.f32(self.gravity_x);
+        w.f32(self.gravity_y);
+        w.f32(self.meters_per_pixel);
+        let layers_offset = w.reserve_u32();
+        let sequences_offset = w.reserve_u32();
+
+        let pos = w.pos();
+        w.patch_u32(bgs_offset, pos);
+        w.ptr_list(&self.backgrounds, 1, |w, bg| bg.write(w));
+
+        let pos = w.pos();
+        w.patch_u32(views_offset, pos);
+        w.ptr_list(&self.views, 1, |w, view| view.write(w));
+
+        let pos = w.pos();
+        w.patch_u32(objs_offset, pos);
+        w.ptr_list(&self.game_objects, 1, |w, obj| obj.write(w));
+
+        let pos = w.pos();
+        w.patch_u32(tiles_offset, pos);
+        w.ptr_list(&self.tiles, 1, |w, tile| tile.write(w));
+
+        let pos = w.pos();
+        w.patch_u32(layers_offset, pos);
+        w.ptr_list(&self.layers, 1, |w, layer| {
+            layer.write(w);
+            w.bytes(&layer.trailing);
+        });
+
+        if self.has_sequences {
+            let pos = w.pos();
+            w.patch_u32(sequences_offset, pos);
+            w.ptr_list(&self.sequences, 1, |w, sequence| w.bytes(&sequence.data));
+        }
+    }
+}
+
+impl Layer {
+    /// Write the parsed part of the layer, excluding `trailing`
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.u32(self.index);
+        w.u32(self.kind.id());
+        w.i32(self.depth);
+        w.f32(self.x_offset);
+        w.f32(self.y_offset);
+        w.f32(self.horizontal_speed);
+        w.f32(self.vertical_speed);
+        w.bool(self.is_visible);
+        match &self.kind {
+            LayerKind::Path | LayerKind::Unknown { .. } => {}
+            LayerKind::Background {
+                visible, foreground, sprite_index, horizontal_tile, vertical_tile, stretch, color,
+                first_frame, animation_speed, animation_speed_unit,
+            } => {
+                w.bool(*visible);
+                w.bool(*foreground);
+                w.i32(*sprite_index);
+                w.bool(*horizontal_tile);
+                w.bool(*vertical_tile);
+                w.bool(*stretch);
+                color.write(w);
+                w.f32(*first_frame);
+                w.f32(*animation_speed);
+                w.u32(animation_speed_unit.clone() as u32);
+            }
+            LayerKind::Instances { instance_ids } => {
+                w.u32(instance_ids.len() as u32);
+                for &id in instance_ids {
+                    w.u32(id);
+                }
+            }
+            LayerKind::Assets(assets) => assets.write(w),
+            LayerKind::Tiles { background_index, width, height, tiles } => {
+                w.i32(*background_index);
+                w.u32(*width);
+                w.u32(*height);
+                for &tile in tiles {
+                    w.u32(tile);
+                }
+            }
+            LayerKind::Effect { effect_type_offset, properties } => {
+                w.string(*effect_type_offset);
+                w.u32(properties.len() as u32);
+                for property in properties {
+                    w.i32(property.kind);
+                    w.string(property.name_offset);
+                    w.string(property.value_offset);
+                }
+            }
+        }
+    }
+}
+
+impl LayerKind {
+    /// The layer type GameMaker stores for this kind
+    pub fn id(&self) -> u32 {
+        match self {
+            LayerKind::Path => 0,
+            LayerKind::Background { .. } => 1,
+            LayerKind::Instances { .. } => 2,
+            LayerKind::Assets(_) => 3,
+            LayerKind::Tiles { .. } => 4,
+            LayerKind::Effect { .. } => 6,
+            LayerKind::Unknown { kind } => *kind,
+        }
+    }
+}
+
+impl AssetLayer {
+    pub fn write(&self, w: &mut Writer) {
+        let mut lists = vec![&self.sprites];
+        lists.extend(&self.sequences);
+        lists.extend(&self.nine_slices);
+        let tiles_offset = w.reserve_u32();
+        let offsets = lists.iter().map(|_| w.reserve_u32()).collect::<Vec<_>>();
+
+        let pos = w.pos();
+        w.patch_u32(tiles_offset, pos);
+        w.ptr_list(&self.legacy_tiles, 1, |w, tile| tile.write(w));
+        for (offset, list) in offsets.into_iter().zip(lists) {
+            let pos = w.pos();
+            w.patch_u32(offset, pos);
+            w.ptr_list(list, 1, |w, instance| instance.write(w));
+        }
+    }
+}
+
+impl AssetInstance {
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.i32(self.asset_index);
+        w.i32(self.x);
+        w.i32(self.y);
+        w.f32(self.scale_x);
+        w.f32(self.scale_y);
+        self.color.write(w);
+        w.f32(self.animation_speed);
+        w.u32(self.animation_speed_unit.clone() as u32);
+        w.f32(self.frame_index);
+        w.f32(self.rotation);
+    }
+}
+
+impl RoomEntry {
+    /// The game objects on an instance layer, in the layer's order
+    pub fn layer_instances<'a>(&'a self, layer: &'a Layer) -> impl Iterator<Item = &'a GameObject> + 'a {
+        let ids = match &layer.kind {
+            LayerKind::Instances { instance_ids } => &instance_ids[..],
+            _ => &[],
+        };
+        ids.iter().filter_map(move |&id| self.game_objects.iter().find(|object| object.instance_id as u32 == id))
+    }
+}
+
+impl Background {
+    pub fn write(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        w.bool(self.foreground);
+        w.u32(self.bg_def_index);
+        w.u32(self.x);
+        w.u32(self.y);
+        w.bool(self.tile_x);
+        w.bool(self.tile_y);
+        w.u32(self.speed_x);
+        w.u32(self.speed_y);
+        w.i32(self.object_id);
+    }
+}
+
+impl View {
+    pub fn write(&self, w: &mut Writer) {
+        w.bool(self.enabled);
+        w.i32(self.view_x);
+        w.i32(self.view_y);
+        w.i32(self.port_x);
+        w.i32(self.port_y);
+        w.i32(self.port_width);
+        w.i32(self.port_height);
+        w.u32(self.border_x);
+        w.i32(self.border_y);
+        w.u32(self.speed_x);
+        w.u32(self.speed_y);
+        w.i32(self.object_id);
+    }
+}
+
+impl GameObject {
+    /// The object this is an instance of
+    pub fn object(&self) -> Option<ObjectId> {
+        ObjectId::from_index(self.bg_def_index)
+    }
+
+    pub fn write(&self, w: &mut Writer) {
+        w.i32(self.x);
+        w.i32(self.y);
+        w.i32(self.bg_def_index);
+        w.i32(self.instance_id);
+        w.i32(self.creation_code_id);
+        w.f32(self.scale_x);
+        w.f32(self.scale_y);
+        w.f32(self.image_speed);
+        w.u32(self.frame);
+        self.color.write(w);
+        w.f32(self.rotation);
+        w.i32(self.vari_index);
+    }
+}
+
+impl Tile {
+    pub fn write(&self, w: &mut Writer) {
+        w.i32(self.x);
+        w.i32(self.y);
+        w.i32(self.bg_def_index);
+        w.i32(self.source_x);
+        w.i32(self.source_y);
+        w.u32(self.width);
+        w.u32(self.height);
+        w.i32(self.tile_depth);
+        w.i32(self.instance_id);
+        w.f32(self.scale_x);
+        w.f32(self.scale_y);
+        w.u32(self.argb_tint);
+    }
+}
+
+impl RgbaColor {
+    pub fn write(&self, w: &mut Writer) {
+        w.bytes(&[self.r, self.g, self.b, self.a]);
+    }
+}
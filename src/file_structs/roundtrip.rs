@@ -0,0 +1,58 @@
+use std::fmt;
+use super::{FormFile, Error, try_take_data_win_file};
+
+/// Where a re-serialised file first stops matching the original
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub offset: usize,
+    /// Magic of the section containing `offset` in the original file, `FORM` for the
+    /// container header or anything past the last section
+    pub section: String,
+    pub original_len: usize,
+    pub written_len: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "first difference at 0x{:x} in {}", self.offset, self.section)?;
+        if self.original_len != self.written_len {
+            write!(f, " (original is 0x{:x} bytes, written is 0x{:x})", self.original_len, self.written_len)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse `data`, write it back out unchanged and compare the two
+///
+/// Returns `Ok(None)` when the output is byte-identical to the input.
+pub fn verify_roundtrip(data: &[u8]) -> Result<Option<Mismatch>, Error> {
+    let written = FormFile::from_sections(try_take_data_win_file(data)?).to_bytes()?;
+
+    let offset = match data.iter().zip(&written).position(|(a, b)| a != b) {
+        Some(offset) => offset,
+        None if data.len() == written.len() => return Ok(None),
+        None => data.len().min(written.len()),
+    };
+
+    Ok(Some(Mismatch {
+        offset,
+        section: section_at(data, offset),
+        original_len: data.len(),
+        written_len: written.len(),
+    }))
+}
+
+/// Walk the section headers of a FORM to find which section `offset` falls in
+fn section_at(data: &[u8], offset: usize) -> String {
+    let mut pos = 8;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = pos + 8 + size;
+        if (pos..end).contains(&offset) {
+            return String::from_utf8_lossy(&header[..4]).into_owned();
+        }
+        pos = end;
+    }
+
+    String::from("FORM")
+}
@@ -0,0 +1,62 @@
+use binrw::{BinRead, BinReaderExt, io::Seek};
+use super::{PosSlice, PResult, StringRef, CodeId, Error, PosCursor, Writer, WriteSection, ptr_list};
+use super::helpers::end_alignment;
+
+impl super::ParseSection for Scpt {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let mut cursor = PosCursor::from(input);
+
+        let mut scpt: Scpt = cursor.read_le()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err, input.pos())))?;
+
+        let content_end = cursor.stream_position()
+            .map_err(|err| nom::Err::Error(Error::from_binrw(err.into(), input.pos())))?;
+        scpt.end_align = end_alignment(content_end as usize, input.pos() + input.len());
+
+        Ok((input, scpt))
+    }
+}
+
+/// Names of the scripts, and the CODE entry each one runs
+#[derive(BinRead, Debug, Clone)]
+pub struct Scpt {
+    #[br(parse_with = ptr_list)]
+    pub scripts: Vec<ScptEntry>,
+
+    #[br(calc = 1)]
+    pub end_align: u32,
+}
+
+#[derive(BinRead, Debug, Clone)]
+pub struct ScptEntry {
+    pub name_offset: StringRef,
+    /// Index into CODE, with the top bit set for GMS2.3 constructors
+    pub code_id: u32,
+}
+
+const CONSTRUCTOR: u32 = 0x8000_0000;
+
+impl ScptEntry {
+    pub fn code_index(&self) -> usize {
+        (self.code_id & !CONSTRUCTOR) as usize
+    }
+
+    /// The CODE entry the script runs
+    pub fn code(&self) -> CodeId {
+        CodeId(self.code_index())
+    }
+
+    pub fn is_constructor(&self) -> bool {
+        self.code_id & CONSTRUCTOR != 0
+    }
+}
+
+impl WriteSection for Scpt {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.scripts, 1, |w, script| {
+            w.string(script.name_offset);
+            w.u32(script.code_id);
+        });
+        w.align(self.end_align);
+    }
+}
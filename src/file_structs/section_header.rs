@@ -1,21 +1,19 @@
-use nom::{
-    IResult,
-    bytes::complete::{tag, take},
-    sequence::tuple,
-};
+use nom::bytes::complete::{tag, take};
 
-use super::PosSlice;
+use super::{PosSlice, PResult, Error, Expected};
 use super::byte_parsers::le_u32;
 
-pub fn take_section(magic: &'static [u8]) -> impl Fn(PosSlice) -> IResult<PosSlice, PosSlice> {
+pub fn take_section(magic: &'static [u8]) -> impl Fn(PosSlice) -> PResult<PosSlice> {
     move |input: PosSlice| {
-        let (input, (tag0, file_size)) = tuple((tag(magic), le_u32))(input)?;
-        let magic0 = std::str::from_utf8(tag0.1).unwrap();
-        //dbg!(magic0);
-        Ok(take(file_size)(input)?)
+        let (input, _) = tag(magic)(input)
+            .map_err(|_: nom::Err<Error>| {
+                nom::Err::Error(Error::new(input.pos(), Expected::Magic(magic)))
+            })?;
+        let (input, file_size) = le_u32(input)?;
+        take(file_size)(input)
     }
 }
 
 pub trait ParseSection: Sized {
-    fn take<'a>(input: PosSlice) -> IResult<PosSlice, Self>;
+    fn take(input: PosSlice) -> PResult<Self>;
 }
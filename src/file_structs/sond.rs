@@ -4,31 +4,41 @@ use std::collections::HashMap;
 pub struct Sond {
     pub sounds: Vec<SondEntry>,
     pub locations: HashMap<u32, usize>,
+    pub end_align: u32,
 }
 
-use nom::{IResult, multi::count, sequence::tuple};
-use super::PosSlice;
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, PResult, StringRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::end_alignment;
 use super::byte_parsers::{le_u32, le_f32};
 
-fn get_sond_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, SondEntry> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_sond_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<SondEntry> {
+    let input = at_offset(input, offset, from)?;
 
     SondEntry::take(input)
 }
 
 impl super::ParseSection for Sond {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let sounds =
             offsets.iter()
-            .map(|offset|{
-                get_sond_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_sond_entry_at_offset(input, *offset, from)
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let content_end = sounds.iter()
+            .map(|(rest, _)| rest.pos())
+            .max()
+            .unwrap_or_else(|| input.pos());
+
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let sounds = sounds.into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
@@ -38,17 +48,17 @@ impl super::ParseSection for Sond {
             .collect::<HashMap<u32, usize>>();
 
         Ok((input, Self {
-            sounds, locations
+            sounds, locations, end_align
         }))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct SondEntry {
-    pub name_offset: u32,
+    pub name_offset: StringRef,
     pub unk1: u32, // Always zero
-    pub unk2: u32, // Always zero
-    pub name_offset2: u32,
+    pub unk2: StringRef, // file type, e.g. ".ogg"
+    pub name_offset2: StringRef,
     pub unk3: u32, // Always zero
     pub play_speed: f32,
     pub unk4: u32, // Always zero
@@ -57,7 +67,7 @@ pub struct SondEntry {
 }
 
 impl SondEntry {
-    pub fn take(input: PosSlice) -> IResult<PosSlice, SondEntry> {
+    pub fn take(input: PosSlice) -> PResult<SondEntry> {
         let (input, (
             name_offset,
             unk1,
@@ -83,10 +93,10 @@ impl SondEntry {
         Ok((
             input,
             SondEntry {
-                name_offset,
+                name_offset: StringRef(name_offset),
                 unk1,
-                unk2,
-                name_offset2,
+                unk2: StringRef(unk2),
+                name_offset2: StringRef(name_offset2),
                 unk3,
                 play_speed,
                 unk4,
@@ -102,3 +112,24 @@ impl Sond {
         Some(&self.sounds[*self.locations.get(&loc)?])
     }
 }
+
+impl SondEntry {
+    pub fn write(&self, w: &mut Writer) {
+        w.string(self.name_offset);
+        w.u32(self.unk1);
+        w.string(self.unk2);
+        w.string(self.name_offset2);
+        w.u32(self.unk3);
+        w.f32(self.play_speed);
+        w.u32(self.unk4);
+        w.u32(self.audiogroup_index);
+        w.u32(self.index_in_audiogroup);
+    }
+}
+
+impl WriteSection for Sond {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.sounds, 1, |w, sound| sound.write(w));
+        w.align(self.end_align);
+    }
+}
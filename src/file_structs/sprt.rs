@@ -6,26 +6,64 @@ pub struct Sprt {
     pub locations: HashMap<u32, usize>,
 }
 
-use nom::{IResult, multi::count, sequence::tuple};
-use super::PosSlice;
+use nom::{combinator::map, multi::count, sequence::tuple};
+use super::{PosSlice, PResult, StringRef, TpagRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::entry_ends;
 use super::byte_parsers::{le_u32, le_f32};
 
-fn get_sprt_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, SprtEntry> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_sprt_entry_at_offset(input: PosSlice, offset: u32, from: usize, end: usize) -> PResult<SprtEntry> {
+    let input = at_offset(input, offset, from)?;
+
+    let (input, mut entry) = SprtEntry::take(input)?;
+    let len = end.saturating_sub(input.pos()).min(input.len());
+    let rest = &input.1[..len];
+    // only plain sprites have masks, not ones with the extra floats
+    let masks = if entry.unk_floats.is_empty() { take_masks(rest, entry.size) } else { None };
+    entry.trailing = match &masks {
+        Some(masks) => rest[masks_len(masks.len(), entry.size)..].to_vec(),
+        None => rest.to_vec(),
+    };
+    entry.masks = masks;
+
+    Ok((input, entry))
+}
+
+/// Bytes in each collision mask of a sprite of `size`: a bit per pixel, each row padded to a byte
+pub fn mask_len((width, height): Point32) -> usize {
+    width.div_ceil(8) as usize * height as usize
+}
 
-    SprtEntry::take(input)
+/// Bytes `count` masks take up, with their count before them and padding after
+fn masks_len(count: usize, size: Point32) -> usize {
+    (4 + count * mask_len(size)).div_ceil(4) * 4
+}
+
+/// The collision masks at the start of `data`, if it holds as many as it says with nothing but
+/// padding after them
+fn take_masks(data: &[u8], size: Point32) -> Option<Vec<Vec<u8>>> {
+    let bytes = data.get(..4)?;
+    let count = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+    let len = mask_len(size);
+    let end = masks_len(count, size);
+    let padding = data.get(4 + count * len..end)?;
+    if (len == 0 && count > 0) || padding.iter().any(|&byte| byte != 0) {
+        return None
+    }
+    Some(data[4..4 + count * len].chunks(len).map(Vec::from).collect())
 }
 
 impl super::ParseSection for Sprt {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
+        let ends = entry_ends(&offsets, input.pos() + input.len());
         let sprites =
             offsets.iter()
-            .map(|offset|{
-                get_sprt_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .zip(ends)
+            .map(|((offset, from), end)|{
+                get_sprt_entry_at_offset(input, *offset, from, end)
             })
             .collect::<Result<Vec<_>, _>>()?
             .into_iter()
@@ -48,44 +86,58 @@ type Point32 = (u32, u32);
 
 #[derive(Debug, Clone)]
 pub struct SprtEntry {
-    pub name_offset: u32,
+    pub name_offset: StringRef,
     pub size: Point32,
+    /// `((left, right), (bottom, top))` edges of the part of the sprite that isn't transparent
     pub bounds: Rect32,
-    //pub unk2: [u32; 5],
+    pub transparent: u32,
+    pub smooth: u32,
+    pub preload: u32,
+    /// 0 for bounds fit to the frames automatically, 1 for the full sprite and 2 for manual
+    pub bbox_mode: u32,
+    /// 0 for a rectangle, 1 for precise masks and 2 for a rotated rectangle
+    pub collision_type: u32,
     pub origin: Point32,
     pub unk3: i32,
-    //pub unk4: [u32; 2],
-    pub opacity_maybe: f32,
-    pub unk5: u32,
-    pub tpag_offsets: Vec<u32>,
-    //pub transparencies: Vec<Vec<u8>>,
+    pub unk4: u32,
+    /// Frames shown per second, or per game frame, depending on `playback_speed_type`
+    pub playback_speed: f32,
+    /// 0 for frames per second, 1 for frames per game frame
+    pub playback_speed_type: u32,
+    pub tpag_offsets: Vec<TpagRef>,
     pub unk_floats: Vec<f32>,
+    /// Collision masks, one shared by every frame or one per frame, each [`mask_len`] bytes of
+    /// rows of bits from the top left, most significant first. `None` where they couldn't be
+    /// read, which leaves them in `trailing`.
+    pub masks: Option<Vec<Vec<u8>>>,
+    /// Unparsed data between the collision masks and the next sprite
+    pub trailing: Vec<u8>,
 }
 
-pub fn take_point32(input: PosSlice) -> IResult<PosSlice, Point32> {
+pub fn take_point32(input: PosSlice) -> PResult<Point32> {
     tuple((le_u32, le_u32))(input)
 }
 
-pub fn take_rect32(input: PosSlice) -> IResult<PosSlice, Rect32> {
+pub fn take_rect32(input: PosSlice) -> PResult<Rect32> {
     tuple((take_point32, take_point32))(input)
 }
 
 impl SprtEntry {
-    pub fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    pub fn take(input: PosSlice) -> PResult<Self> {
         let (input, (
             name_offset,
             size,
             bounds,
-            _unk6,
+            (transparent, smooth, preload, bbox_mode, collision_type),
             origin,
             unk3,
-            _unk7,
+            unk4,
             some_float_count,
         )) = tuple((
             le_u32,
             take_point32,
             take_rect32,
-            count(le_u32, 5),
+            tuple((le_u32, le_u32, le_u32, le_u32, le_u32)),
             take_point32,
             le_u32,
             le_u32,
@@ -95,8 +147,8 @@ impl SprtEntry {
         let (input, unk_floats) = count(le_f32, some_float_count as _)(input)?;
 
         let (input, (
-            opacity_maybe,
-            unk5,
+            playback_speed,
+            playback_speed_type,
             frame_count,
         ))= tuple((
             le_f32,
@@ -104,34 +156,28 @@ impl SprtEntry {
             le_u32
         ))(input)?;
 
-        let (input, tpag_offsets) = count(le_u32, frame_count as _)(input)?;
-        //let (input, trans_count) = le_u32(input)?;
-        //let trans_count = trans_count & 0xffff;
-
-        //let data_size = (((size.0 * size.1) + 7) / 8) as usize;
-        //let transparencies =
-        //    if some_float_count == 0 {
-        //        Vec::from(&input.1[..data_size * trans_count as usize])
-        //            .chunks(data_size)
-        //            .map(Vec::from)
-        //            .collect::<Vec<_>>()
-        //    } else {
-        //        Vec::new()
-        //    };
+        let (input, tpag_offsets) = count(map(le_u32, TpagRef), frame_count as _)(input)?;
 
         Ok((
             input,
             SprtEntry {
-                name_offset,
+                name_offset: StringRef(name_offset),
                 size,
                 bounds,
+                transparent,
+                smooth,
+                preload,
+                bbox_mode,
+                collision_type,
                 origin,
                 unk3: unk3 as i32,
-                opacity_maybe,
-                unk5,
+                unk4,
+                playback_speed,
+                playback_speed_type,
                 tpag_offsets,
-                //transparencies,
                 unk_floats,
+                masks: None,
+                trailing: Vec::new(),
             }
         ))
     }
@@ -142,3 +188,47 @@ impl Sprt {
         Some(&self.sprites[*self.locations.get(&loc)?])
     }
 }
+
+impl SprtEntry {
+    pub fn write(&self, w: &mut Writer) {
+        let ((left, right), (bottom, top)) = self.bounds;
+        w.string(self.name_offset);
+        w.u32(self.size.0);
+        w.u32(self.size.1);
+        for val in [left, right, bottom, top] {
+            w.u32(val);
+        }
+        for val in [self.transparent, self.smooth, self.preload, self.bbox_mode, self.collision_type] {
+            w.u32(val);
+        }
+        w.u32(self.origin.0);
+        w.u32(self.origin.1);
+        w.i32(self.unk3);
+        w.u32(self.unk4);
+        w.u32(self.unk_floats.len() as u32);
+        for &val in &self.unk_floats {
+            w.f32(val);
+        }
+        w.f32(self.playback_speed);
+        w.u32(self.playback_speed_type);
+        w.u32(self.tpag_offsets.len() as u32);
+        for &tpag in &self.tpag_offsets {
+            w.tpag(tpag);
+        }
+        if let Some(masks) = &self.masks {
+            w.u32(masks.len() as u32);
+            for mask in masks {
+                w.bytes(mask);
+            }
+            let written = 4 + masks.iter().map(Vec::len).sum::<usize>();
+            w.bytes(&vec![0; written.div_ceil(4) * 4 - written]);
+        }
+        w.bytes(&self.trailing);
+    }
+}
+
+impl WriteSection for Sprt {
+    fn write(&self, w: &mut Writer) {
+        w.ptr_list(&self.sprites, 1, |w, sprite| sprite.write(w));
+    }
+}
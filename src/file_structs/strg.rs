@@ -1,36 +1,60 @@
+use std::collections::HashMap;
+
 use bimap::BiBTreeMap;
 
 #[derive(Debug, Clone)]
 pub struct Strg {
     pub strings: Vec<String>,
     pub locations: BiBTreeMap<u32, usize>,
+    /// Index of each string in `strings`, the first one for strings that are in there twice,
+    /// that `find` and `intern` look strings up by. Only `intern` keeps it up to date, so add
+    /// strings through that
+    indices: HashMap<String, usize>,
+    pub string_align: u32,
+    pub end_align: u32,
 }
 
-use nom::{IResult, multi::count};
-use super::PosSlice;
+use nom::multi::count;
+use super::{PosSlice, PResult, Error, Expected, StringRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::{common_alignment, end_alignment};
+use super::writer::placeholder;
 use super::byte_parsers::{le_u32, le_u8};
 
-fn get_strg_string_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, String> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_strg_string_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<String> {
+    let input = at_offset(input, offset, from)?;
     let (input, char_count) = le_u32(input)?;
+    let start = input.pos();
     let (input, chars) = count(le_u8, char_count as _)(input)?;
 
-    Ok((input, std::str::from_utf8(&chars).unwrap().to_string()))
+    let string = String::from_utf8(chars)
+        .map_err(|_| nom::Err::Error(Error::new(start, Expected::Utf8String)))?;
+
+    Ok((input, string))
 }
 
 impl super::ParseSection for Strg {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let strings =
             offsets.iter()
-            .map(|offset|{
-                get_strg_string_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_strg_string_at_offset(input, *offset, from)
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // each string is followed by a null terminator
+        let content_end = strings.iter()
+            .map(|(rest, _)| rest.pos() + 1)
+            .max()
+            .unwrap_or_else(|| input.pos());
+
+        let string_align = common_alignment(&offsets, 4);
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let strings = strings.into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
@@ -39,14 +63,69 @@ impl super::ParseSection for Strg {
             .map(|(a,b)| (b + 4, a))
             .collect::<BiBTreeMap<u32, usize>>();
 
-        Ok((input, Strg {
-            strings, locations
-        }))
+        Ok((input, Strg::new(strings, locations, string_align, end_align)))
     }
 }
 
 impl Strg {
-    pub fn get(&self, loc: u32) -> Option<&String> {
+    pub fn new(strings: Vec<String>, locations: BiBTreeMap<u32, usize>, string_align: u32, end_align: u32) -> Self {
+        let mut indices = HashMap::with_capacity(strings.len());
+        for (index, string) in strings.iter().enumerate() {
+            indices.entry(string.clone()).or_insert(index);
+        }
+        Strg { strings, locations, indices, string_align, end_align }
+    }
+
+    pub fn get(&self, StringRef(loc): StringRef) -> Option<&String> {
         Some(&self.strings[*self.locations.get_by_left(&loc)?])
     }
+
+    /// Index of the string at `loc`, which is how bytecode refers to strings
+    pub fn index_of(&self, StringRef(loc): StringRef) -> Option<usize> {
+        self.locations.get_by_left(&loc).copied()
+    }
+
+    /// Reference to `string`, if it's in the section
+    pub fn find(&self, string: &str) -> Option<StringRef> {
+        let index = self.indices.get(string)?;
+        self.locations.get_by_right(index).copied().map(StringRef)
+    }
+
+    /// Reference to `string`, adding it to the end of the section if it isn't there yet
+    pub fn intern(&mut self, string: &str) -> StringRef {
+        let index = match self.indices.get(string) {
+            Some(&index) => index,
+            None => {
+                self.strings.push(string.to_owned());
+                self.indices.insert(string.to_owned(), self.strings.len() - 1);
+                self.strings.len() - 1
+            }
+        };
+
+        match self.locations.get_by_right(&index) {
+            Some(&loc) => StringRef(loc),
+            None => {
+                let loc = placeholder(index);
+                self.locations.insert(loc, index);
+                StringRef(loc)
+            }
+        }
+    }
+}
+
+impl WriteSection for Strg {
+    fn write(&self, w: &mut Writer) {
+        let indices = (0..self.strings.len()).collect::<Vec<_>>();
+        w.ptr_list(&indices, self.string_align, |w, &i| {
+            if let Some(&old) = self.locations.get_by_right(&i) {
+                let pos = w.pos();
+                w.record_string(old, pos + 4);
+            }
+            let string = &self.strings[i];
+            w.u32(string.len() as u32);
+            w.bytes(string.as_bytes());
+            w.u8(0);
+        });
+        w.align(self.end_align);
+    }
 }
@@ -4,31 +4,44 @@ use std::collections::HashMap;
 pub struct Tpag {
     pub texture_pages: Vec<TpagEntry>,
     pub locations: HashMap<u32, usize>,
+    pub entry_align: u32,
+    pub end_align: u32,
 }
 
-use nom::{IResult, multi::count, sequence::tuple};
-use super::PosSlice;
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, PResult, TpagRef, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::{common_alignment, end_alignment, offsets_by_index};
+use super::writer::placeholder;
 use super::byte_parsers::{le_u32, le_u16};
 
-fn get_tpag_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, TpagEntry> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_tpag_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<TpagEntry> {
+    let input = at_offset(input, offset, from)?;
 
     TpagEntry::take(input)
 }
 
 impl super::ParseSection for Tpag {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let texture_pages =
             offsets.iter()
-            .map(|offset|{
-                get_tpag_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_tpag_entry_at_offset(input, *offset, from)
             })
-            .collect::<Result<Vec<_>, _>>()?
-            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let content_end = texture_pages.iter()
+            .map(|(rest, _)| rest.pos())
+            .max()
+            .unwrap_or_else(|| input.pos());
+
+        let entry_align = common_alignment(&offsets, 4);
+        let end_align = end_alignment(content_end, input.pos() + input.len());
+
+        let texture_pages = texture_pages.into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
@@ -38,13 +51,13 @@ impl super::ParseSection for Tpag {
             .collect::<HashMap<u32, usize>>();
 
         Ok((input, Self {
-            texture_pages, locations
+            texture_pages, locations, entry_align, end_align
         }))
     }
 }
 
-type Rect16 = (Point16, Point16);
-type Point16 = (u16, u16);
+pub type Rect16 = (Point16, Point16);
+pub type Point16 = (u16, u16);
 
 #[derive(Debug, Clone)]
 pub struct TpagEntry {
@@ -54,16 +67,16 @@ pub struct TpagEntry {
     pub texture_index: u16,
 }
 
-pub fn take_point16(input: PosSlice) -> IResult<PosSlice, Point16> {
+pub fn take_point16(input: PosSlice) -> PResult<Point16> {
     tuple((le_u16, le_u16))(input)
 }
 
-pub fn take_rect16(input: PosSlice) -> IResult<PosSlice, Rect16> {
+pub fn take_rect16(input: PosSlice) -> PResult<Rect16> {
     tuple((take_point16, take_point16))(input)
 }
 
 impl TpagEntry {
-    pub fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    pub fn take(input: PosSlice) -> PResult<Self> {
         let (input, (
             sprite_bounds,
             unk2,
@@ -89,7 +102,60 @@ impl TpagEntry {
 }
 
 impl Tpag {
-    pub fn get(&self, loc: u32) -> Option<&TpagEntry> {
+    pub fn get(&self, TpagRef(loc): TpagRef) -> Option<&TpagEntry> {
         Some(&self.texture_pages[*self.locations.get(&loc)?])
     }
+
+    /// Index of the entry at `loc` in `texture_pages`
+    pub fn index_of(&self, TpagRef(loc): TpagRef) -> Option<usize> {
+        self.locations.get(&loc).copied()
+    }
+
+    /// Add an entry to the end of the section, returning the reference to use for it
+    pub fn push(&mut self, entry: TpagEntry) -> TpagRef {
+        self.texture_pages.push(entry);
+        // entries removed since may have left a later placeholder in use
+        let loc = (self.texture_pages.len() - 1..)
+            .map(placeholder)
+            .find(|loc| !self.locations.contains_key(loc))
+            .unwrap();
+        self.locations.insert(loc, self.texture_pages.len() - 1);
+        TpagRef(loc)
+    }
+
+    /// Take the entry at `loc` out of the section. Anything still referring to it is left
+    /// pointing at nothing.
+    pub fn remove(&mut self, TpagRef(loc): TpagRef) -> Option<TpagEntry> {
+        let index = self.locations.remove(&loc)?;
+        for later in self.locations.values_mut().filter(|later| **later > index) {
+            *later -= 1;
+        }
+        Some(self.texture_pages.remove(index))
+    }
+}
+
+impl TpagEntry {
+    pub fn write(&self, w: &mut Writer) {
+        let ((x, y), (width, height)) = self.sprite_bounds;
+        let ((x2, y2), (width2, height2)) = self.unk2;
+        for val in [x, y, width, height, x2, y2, width2, height2, self.size.0, self.size.1] {
+            w.u16(val);
+        }
+        w.u16(self.texture_index);
+    }
+}
+
+impl WriteSection for Tpag {
+    fn write(&self, w: &mut Writer) {
+        let offsets = offsets_by_index(&self.locations, self.texture_pages.len());
+        let entries = self.texture_pages.iter().zip(offsets).collect::<Vec<_>>();
+        w.ptr_list(&entries, self.entry_align, |w, &(entry, old)| {
+            if let Some(old) = old {
+                let pos = w.pos();
+                w.record_tpag(old, pos);
+            }
+            entry.write(w);
+        });
+        w.align(self.end_align);
+    }
 }
@@ -34,11 +34,25 @@ impl<'a> PosSlice<'a> {
     pub fn len(&self) -> usize {
         self.1.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.1.is_empty()
+    }
+
+    /// Slice starting at the absolute position `pos`, if it lies within this slice
+    pub fn at(&self, pos: usize) -> Option<Self> {
+        let off = pos.checked_sub(self.0)?;
+        if off <= self.1.len() {
+            Some(self.offset(off))
+        } else {
+            None
+        }
+    }
 }
 
 use std::iter::{
+    Copied,
     Enumerate,
-    Map,
 };
 
 use nom::{
@@ -49,7 +63,7 @@ use nom::{
 impl<'a> nom::InputIter for PosSlice<'a> {
     type Item = u8;
     type Iter = Enumerate<Self::IterElem>;
-    type IterElem = Map<std::slice::Iter<'a, Self::Item>, fn(&u8) -> u8>;
+    type IterElem = Copied<std::slice::Iter<'a, Self::Item>>;
 
     #[inline]
     fn iter_indices(&self) -> Self::Iter {
@@ -58,7 +72,7 @@ impl<'a> nom::InputIter for PosSlice<'a> {
 
     #[inline]
     fn iter_elements(&self) -> Self::IterElem {
-        self.1.iter().map(|a| *a)
+        self.1.iter().copied()
     }
 
     #[inline]
@@ -161,7 +175,7 @@ impl<'a> nom::InputTakeAtPosition for PosSlice<'a> {
                         Self::new(self.0, &self.1[..i])
                     )),
             None => {
-                if self.1.len() == 0 {
+                if self.1.is_empty() {
                     Err(Err::Error(E::from_error_kind(*self, e)))
                 } else {
                     Ok(self.take_split(self.input_len()))
@@ -5,50 +5,61 @@ pub struct Txtr {
     pub offset: usize,
     pub files: Vec<TxtrEntry>,
     pub locations: HashMap<u32, usize>,
+    pub png_align: u32,
 }
 
 use std::iter;
-use nom::{IResult, multi::count, sequence::tuple};
-use super::PosSlice;
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, PResult, Error, Expected, Writer, WriteSection, at_offset, pointer_positions};
+use super::helpers::common_alignment;
 use super::byte_parsers::le_u32;
 
-fn get_txtr_entry_at_offset(input: PosSlice, offset: u32) -> IResult<PosSlice, (u32, u32, u32)> {
-    let off = (offset as usize) - input.pos();
-    let input = input.offset(off);
+fn get_txtr_entry_at_offset(input: PosSlice, offset: u32, from: usize) -> PResult<(u32, u32, u32)> {
+    let input = at_offset(input, offset, from)?;
 
     tuple((le_u32, le_u32, le_u32))(input)
 }
 
 impl super::ParseSection for Txtr {
-    fn take(input: PosSlice) -> IResult<PosSlice, Self> {
+    fn take(input: PosSlice) -> PResult<Self> {
         let offset = input.pos() - 8;
         let (input, index_count) = le_u32(input)?;
         let (input, offsets) = count(le_u32, index_count as _)(input)?;
 
         let files =
             offsets.iter()
-            .map(|offset|{
-                get_txtr_entry_at_offset(input, *offset)
+            .zip(pointer_positions(input, &offsets))
+            .map(|(offset, from)|{
+                get_txtr_entry_at_offset(input, *offset, from)
             })
             .collect::<Result<Vec<_>, _>>()?
             .into_iter()
             .map(|a| a.1)
             .collect::<Vec<_>>();
 
-        let pos = input.pos();
-        let png_offsets = files.iter()
-            .map(|a| a.2 as usize - pos)
-            .chain(iter::once(input.len()))
-            .collect::<Vec<_>>();
+        let png_align = common_alignment(
+            &files.iter().map(|&(_, _, png_offset)| png_offset).collect::<Vec<_>>(),
+            0x80
+        );
+
+        // each PNG's pointer is the last field of its entry
+        let png_inputs = offsets.iter()
+            .zip(&files)
+            .map(|(&offset, &(_, _, png_offset))| at_offset(input, png_offset, offset as usize + 8))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pngs = png_inputs.iter()
+            .zip(png_inputs.iter().skip(1).map(|next| next.pos()).chain(iter::once(input.pos() + input.len())))
+            .map(|(start, end)| {
+                let len = end.checked_sub(start.pos()).ok_or_else(|| nom::Err::Error(
+                    Error::new(start.pos(), Expected::OffsetInSection(end as u32))
+                ))?;
+                Ok(Vec::from(&start.1[..len]))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let pngs = png_offsets[..files.len()]
-            .iter()
-            .zip(png_offsets[1..].iter())
-            .map(|(&start, &end)| Vec::from(&input.1[start..end]))
-            .collect::<Vec<_>>();
-        
         let files = files.into_iter()
-            .zip(pngs.into_iter())
+            .zip(pngs)
             .map(|((unk1, unk2, _), png)| {
                 TxtrEntry {
                     unk1, unk2, png
@@ -62,7 +73,7 @@ impl super::ParseSection for Txtr {
             .collect::<HashMap<u32, usize>>();
 
         Ok((input, Self {
-            offset, files, locations
+            offset, files, locations, png_align
         }))
     }
 }
@@ -77,5 +88,23 @@ impl Txtr {
 pub struct TxtrEntry {
     pub unk1: u32, // either 0 or 1
     pub unk2: u32, // always 0
-    pub png: Vec<u8>,
+    pub png: Vec<u8>, // includes any padding up to the next png
+}
+
+impl WriteSection for Txtr {
+    fn write(&self, w: &mut Writer) {
+        let mut png_ptrs = Vec::with_capacity(self.files.len());
+        w.ptr_list(&self.files, 1, |w, entry| {
+            w.u32(entry.unk1);
+            w.u32(entry.unk2);
+            png_ptrs.push(w.reserve_u32());
+        });
+
+        for (ptr, entry) in png_ptrs.into_iter().zip(&self.files) {
+            w.align(self.png_align);
+            let pos = w.pos();
+            w.patch_u32(ptr, pos);
+            w.bytes(&entry.png);
+        }
+    }
 }
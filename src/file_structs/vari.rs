@@ -0,0 +1,103 @@
+#[derive(Debug, Clone)]
+pub struct Vari {
+    /// GMS2 header: the instance variable count (stored twice) and the most locals any one
+    /// script has. GMS1 has no header.
+    pub header: Option<(u32, u32, u32)>,
+    pub variables: Vec<VariEntry>,
+    pub end_align: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct VariEntry {
+    pub name_offset: StringRef,
+    /// Always 0 in GMS1
+    pub instance_type: i32,
+    pub id: i32,
+    pub occurrences: u32,
+    /// Address in CODE of the first instruction referencing the variable
+    pub first_address: u32,
+}
+
+use nom::{multi::count, sequence::tuple};
+use super::{PosSlice, PResult, Error, Expected, StringRef, Writer, WriteSection};
+use super::helpers::end_alignment;
+use super::byte_parsers::le_u32;
+
+impl super::ParseSection for Vari {
+    fn take(input: PosSlice) -> PResult<Self> {
+        let (_, (first, second)) = tuple((le_u32, le_u32))(input).unwrap_or((input, (0, 0)));
+
+        // GMS1 entries start with a pointer to a string further into the file, where GMS2
+        // starts with a (much smaller) variable count, stored twice
+        let is_gms2 = input.len() >= 12 && first == second && (first as usize) < input.pos();
+        let (input, header) = if is_gms2 {
+            let (input, header) = tuple((le_u32, le_u32, le_u32))(input)?;
+            (input, Some(header))
+        } else {
+            (input, None)
+        };
+
+        // the entries fill the section, apart from any zeroes it was padded with
+        let entry_size = if is_gms2 { 20 } else { 12 };
+        let entries_len = input.len() - input.len() % entry_size;
+        if let Some(extra) = input.1[entries_len..].iter().position(|&byte| byte != 0) {
+            return Err(nom::Err::Error(Error::new(input.pos() + entries_len + extra, Expected::Structure(
+                String::from("VARI entries")
+            ))));
+        }
+        let (rest, variables) = count(
+            |input| VariEntry::take(input, is_gms2),
+            input.len() / entry_size
+        )(input)?;
+
+        let end_align = end_alignment(rest.pos(), input.pos() + input.len());
+
+        Ok((rest, Self {
+            header, variables, end_align
+        }))
+    }
+}
+
+impl VariEntry {
+    fn take(input: PosSlice, is_gms2: bool) -> PResult<Self> {
+        let (input, name_offset) = le_u32(input)?;
+        let (input, (instance_type, id)) = if is_gms2 {
+            tuple((le_u32, le_u32))(input)?
+        } else {
+            (input, (0, 0))
+        };
+        let (input, (occurrences, first_address)) = tuple((le_u32, le_u32))(input)?;
+
+        Ok((input, Self {
+            name_offset: StringRef(name_offset),
+            instance_type: instance_type as i32,
+            id: id as i32,
+            occurrences,
+            first_address,
+        }))
+    }
+}
+
+impl WriteSection for Vari {
+    fn write(&self, w: &mut Writer) {
+        if let Some((instance_count, instance_count2, max_locals)) = self.header {
+            w.u32(instance_count);
+            w.u32(instance_count2);
+            w.u32(max_locals);
+        }
+        for variable in &self.variables {
+            w.string(variable.name_offset);
+            if self.header.is_some() {
+                w.i32(variable.instance_type);
+                w.i32(variable.id);
+            }
+            w.u32(variable.occurrences);
+            if variable.occurrences > 0 {
+                w.code_address(variable.first_address);
+            } else {
+                w.u32(variable.first_address);
+            }
+        }
+        w.align(self.end_align);
+    }
+}
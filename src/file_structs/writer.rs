@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use super::{StringRef, TpagRef};
+
+/// Offsets handed out for strings, texture pages and bytecode that didn't come from a parsed
+/// file. They're relocated to the real offsets when writing, the same as parsed offsets.
+pub(crate) const PLACEHOLDER_BASE: u32 = 0x8000_0000;
+
+pub(crate) fn placeholder(index: usize) -> u32 {
+    PLACEHOLDER_BASE + (index as u32 * 4)
+}
+
+/// Mapping from offsets in the parsed file to offsets in the file being written, for data
+/// that's referenced from other sections
+#[derive(Debug, Default, Clone)]
+pub struct Relocations {
+    pub strings: HashMap<u32, u32>,
+    pub tpags: HashMap<u32, u32>,
+    /// Start of each piece of bytecode, mapped to where it was written and its length
+    pub code: BTreeMap<u32, (u32, u32)>,
+    /// Header of each unknown section, and of TXTR, which unknown sections may point into
+    pub sections: HashMap<u32, u32>,
+}
+
+/// Serialises sections into a FORM buffer, tracking absolute positions
+///
+/// Sections are written twice: the first pass records where referenced data ends up, the
+/// second pass uses those locations to rewrite every cross-section pointer.
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+    relocations: Relocations,
+    recorded: Relocations,
+}
+
+impl Writer {
+    pub fn new(relocations: Relocations) -> Self {
+        Self {
+            buf: Vec::new(),
+            relocations,
+            recorded: Relocations::default(),
+        }
+    }
+
+    pub fn into_parts(self) -> (Vec<u8>, Relocations) {
+        (self.buf, self.recorded)
+    }
+
+    pub fn pos(&self) -> u32 {
+        self.buf.len() as u32
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn i16(&mut self, val: i16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, val: i32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, val: f32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u32(val as u32);
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Pad with zeroes until the position is a multiple of `alignment`
+    pub fn align(&mut self, alignment: u32) {
+        while alignment > 1 && !self.pos().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    /// Reserve a u32 to be filled in later with `patch_u32`
+    pub fn reserve_u32(&mut self) -> usize {
+        let at = self.buf.len();
+        self.u32(0);
+        at
+    }
+
+    pub fn patch_u32(&mut self, at: usize, val: u32) {
+        self.buf[at..at + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    /// Write a pointer to a string from the original file
+    pub fn string(&mut self, StringRef(offset): StringRef) {
+        let offset = self.relocations.strings.get(&offset).copied().unwrap_or(offset);
+        self.u32(offset);
+    }
+
+    /// Write a pointer to a TPAG entry from the original file
+    pub fn tpag(&mut self, TpagRef(offset): TpagRef) {
+        let offset = self.relocations.tpags.get(&offset).copied().unwrap_or(offset);
+        self.u32(offset);
+    }
+
+    /// Write a pointer to an instruction in CODE, given the address it had in the original file
+    pub fn code_address(&mut self, address: u32) {
+        let relocated = self.relocations.code.range(..=address)
+            .next_back()
+            .filter(|&(&start, &(_, len))| address < start + len)
+            .map(|(&start, &(new, _))| new + (address - start))
+            .unwrap_or(address);
+        self.u32(relocated);
+    }
+
+    pub(crate) fn record_code(&mut self, old: u32, new: u32, len: u32) {
+        self.recorded.code.insert(old, (new, len));
+    }
+
+    pub(crate) fn record_string(&mut self, old: u32, new: u32) {
+        self.recorded.strings.insert(old, new);
+    }
+
+    pub(crate) fn record_tpag(&mut self, old: u32, new: u32) {
+        self.recorded.tpags.insert(old, new);
+    }
+
+    pub(crate) fn record_section(&mut self, old: u32, new: u32) {
+        self.recorded.sections.insert(old, new);
+    }
+
+    /// Write a count followed by a pointer to each item, writing each item (aligned to
+    /// `alignment`) right after the previous one
+    pub fn ptr_list<T>(&mut self, items: &[T], alignment: u32, mut f: impl FnMut(&mut Self, &T)) {
+        self.u32(items.len() as u32);
+        let ptrs = items.iter().map(|_| self.reserve_u32()).collect::<Vec<_>>();
+        for (ptr, item) in ptrs.into_iter().zip(items) {
+            self.align(alignment);
+            let pos = self.pos();
+            self.patch_u32(ptr, pos);
+            f(self, item);
+        }
+    }
+
+    /// Write a section header and body, filling in the section size afterwards
+    pub fn section(&mut self, magic: &[u8], f: impl FnOnce(&mut Self)) {
+        self.bytes(magic);
+        let size = self.reserve_u32();
+        f(self);
+        let len = self.buf.len() - (size + 4);
+        self.patch_u32(size, len as u32);
+    }
+}
+
+pub trait WriteSection {
+    /// Write the section body, excluding the magic and size
+    fn write(&self, w: &mut Writer);
+}
+
+/// Write a FORM containing whatever `write_sections` writes, with every cross-section pointer
+/// relocated to where its target ended up
+pub fn write_form(write_sections: impl Fn(&mut Writer)) -> Vec<u8> {
+    write_form_relocated(write_sections).0
+}
+
+/// [`write_form`], also returning where everything that was recorded ended up
+pub(crate) fn write_form_relocated(write_sections: impl Fn(&mut Writer)) -> (Vec<u8>, Relocations) {
+    let mut first_pass = Writer::default();
+    first_pass.section(b"FORM", &write_sections);
+    let (_, relocations) = first_pass.into_parts();
+
+    let mut second_pass = Writer::new(relocations);
+    second_pass.section(b"FORM", &write_sections);
+    second_pass.into_parts()
+}
@@ -0,0 +1,278 @@
+//! Just enough JSON to read and write Tiled's JSON maps and the settings saved with sprites
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(name, value)| (name.to_owned(), value)).collect())
+    }
+
+    pub fn string(text: &str) -> Self {
+        Json::String(text.to_owned())
+    }
+
+    /// The value, indented with each field and array item on its own line, except for arrays
+    /// of numbers which are kept on one line
+    pub fn to_pretty(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        match self {
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(value) => out.push_str(&number(*value)),
+            Json::String(text) => out.push_str(&quote(text)),
+            Json::Array(items) if items.iter().all(|item| matches!(item, Json::Number(_))) => {
+                out.push('[');
+                out.push_str(&items.iter()
+                    .map(|item| match item {
+                        Json::Number(value) => number(*value),
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "));
+                out.push(']');
+            }
+            Json::Array(items) => {
+                write_items(out, depth, "[", "]", items.iter().map(|item| (None, item)));
+            }
+            Json::Object(fields) => {
+                write_items(out, depth, "{", "}", fields.iter().map(|(name, value)| (Some(name), value)));
+            }
+        }
+    }
+}
+
+fn write_items<'a>(
+    out: &mut String,
+    depth: usize,
+    open: &str,
+    close: &str,
+    items: impl ExactSizeIterator<Item = (Option<&'a String>, &'a Json)>,
+) {
+    if items.len() == 0 {
+        out.push_str(open);
+        out.push_str(close);
+        return;
+    }
+
+    out.push_str(open);
+    out.push('\n');
+    let count = items.len();
+    for (i, (name, value)) in items.enumerate() {
+        out.push_str(&"  ".repeat(depth + 1));
+        if let Some(name) = name {
+            out.push_str(&quote(name));
+            out.push_str(": ");
+        }
+        value.write(out, depth + 1);
+        out.push_str(if i + 1 < count { ",\n" } else { "\n" });
+    }
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(close);
+}
+
+/// Integers without a fractional part, so tile ids and sizes read as whole numbers
+pub(crate) fn number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Json {
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(other, _)| other == name).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+/// Parse a document. `null` is read as an empty object, which reads the same as a missing field.
+pub(crate) fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("content after the value"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        if self.eat("{") {
+            let mut fields = Vec::new();
+            if self.eat("}") {
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                self.skip_whitespace();
+                let name = self.string()?;
+                if !self.eat(":") {
+                    return Err(self.error("expected ':'"));
+                }
+                fields.push((name, self.value()?));
+                if self.eat("}") {
+                    return Ok(Json::Object(fields));
+                }
+                if !self.eat(",") {
+                    return Err(self.error("expected ',' or '}'"));
+                }
+            }
+        } else if self.eat("[") {
+            let mut items = Vec::new();
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(self.value()?);
+                if self.eat("]") {
+                    return Ok(Json::Array(items));
+                }
+                if !self.eat(",") {
+                    return Err(self.error("expected ',' or ']'"));
+                }
+            }
+        } else if self.rest().starts_with('"') {
+            Ok(Json::String(self.string()?))
+        } else if self.eat("true") {
+            Ok(Json::Bool(true))
+        } else if self.eat("false") {
+            Ok(Json::Bool(false))
+        } else if self.eat("null") {
+            Ok(Json::Object(Vec::new()))
+        } else {
+            let len = self.rest()
+                .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+                .unwrap_or(self.rest().len());
+            let value = self.rest()[..len].parse().map_err(|_| self.error("expected a value"))?;
+            self.pos += len;
+            Ok(Json::Number(value))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if !self.rest().starts_with('"') {
+            return Err(self.error("expected a string"));
+        }
+        self.pos += 1;
+        let mut text = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(text);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'u')) => {
+                            let hex = chars.by_ref().take(4).map(|(_, c)| c).collect::<String>();
+                            u32::from_str_radix(&hex, 16).ok()
+                                .and_then(std::char::from_u32)
+                                .unwrap_or('\u{fffd}')
+                        }
+                        Some((_, c)) => c,
+                        None => break,
+                    };
+                    text.push(escaped);
+                }
+                c => text.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+}
@@ -1,15 +1,25 @@
 pub mod file_structs;
-pub use file_structs::take_data_win_file;
-use file_structs::{FormFile, Txtr, TxtrEntry, SondEntry};
+pub mod disasm;
+pub mod asm;
+pub mod decompile;
+pub mod compile;
+pub mod tiled;
+pub mod render;
+pub mod atlas;
+pub mod sprites;
+pub mod masks;
+mod json;
+pub use file_structs::{take_data_win_file, try_take_data_win_file, verify_roundtrip, Error};
+use file_structs::{FormFile, Section, Txtr, SondEntry};
+use sprites::SpriteSettings;
 
 use std::fs;
-use std::iter;
 use std::path::Path;
+use std::iter;
 use std::io::{prelude::*, SeekFrom};
-use std::collections::{BTreeSet, BTreeMap};
 
 use rayon::prelude::*;
-use image::{GenericImage, GenericImageView};
+use image::GenericImage;
 
 pub use structopt;
 
@@ -27,7 +37,20 @@ fn extension_from_magic(magic: &[u8]) -> &'static str {
     }
 }
 
-pub fn main(mut args: Args, wait_for_user: bool) {
+pub fn main(args: Args, wait_for_user: bool) {
+    if let Err(err) = run(args) {
+        println!("Error: {}", err);
+    }
+
+    if wait_for_user {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(b"Press Enter to continue...").unwrap();
+        stdout.flush().unwrap();
+        let _ = std::io::stdin().read(&mut [0]).unwrap();
+    }
+}
+
+fn run(mut args: Args) -> Result<(), Error> {
     args.audio_groups.get_or_insert_with(|| {
         (1..=4)
             .map(|i| format!("audiogroup{}.dat", i))
@@ -35,31 +58,54 @@ pub fn main(mut args: Args, wait_for_user: bool) {
             .collect()
     });
 
+    if let Some(command) = &args.command {
+        let file = FormFile::from_file(&args.data_win)?;
+        return match command {
+            Command::Disasm { script } => disasm_script(&file, script),
+            Command::Objects { dot } => {
+                let hierarchy = file.object_hierarchy();
+                print!("{}", if *dot { hierarchy.to_dot() } else { hierarchy.tree() });
+                Ok(())
+            }
+            Command::RenderRoom { room, output } => render_room(&file, room, output.as_deref()),
+        };
+    }
+
+    if args.verify_roundtrip {
+        for path in iter::once(&args.data_win).chain(args.audio_groups.as_ref().unwrap()) {
+            let data = fs::read(path)?;
+            match verify_roundtrip(&data)? {
+                None => println!("{}: round-trips unchanged", path),
+                Some(mismatch) => println!("{}: {}", path, mismatch),
+            }
+        }
+        return Ok(());
+    }
+
     if !args.extract_sprites && !args.extract_textures && !args.extract_fonts &&
-        !args.extract_audio && !args.mod_sprites && !args.mod_audio && !args.mod_textures {
+        !args.extract_audio && !args.extract_code && !args.extract_rooms && !args.mod_sprites && !args.mod_audio && !args.mod_textures &&
+        !args.mod_code && !args.mod_rooms {
         args.mod_audio = true;
         args.mod_sprites = true;
         args.mod_textures = true;
+        args.mod_code = true;
+        args.mod_rooms = true;
     }
 
-    let files: Vec<Vec<u8>> = iter::once(&args.data_win)
-        .chain(args.audio_groups.as_ref().unwrap().iter())
-        .map(|path| fs::read(path).unwrap_or_else(|_| panic!("Failed to read file '{}'", path)))
-        .collect();
-
     let path = &args.data_win;
-    let data_win = take_data_win_file(&fs::read(path).unwrap_or_else(
-            |_| panic!("Failed to read file '{}'", path)
-        ));
+    let data_win = try_take_data_win_file(&fs::read(path)?)?;
 
-    let sections = files
-        .into_iter()
-        .map(|bytes| take_data_win_file(&bytes).into_iter())
-        .chain(iter::once(data_win.clone().into_iter()))
-        .flatten()
-        .collect::<Vec<_>>();
+    let mut file = FormFile::from_sections(data_win);
 
-    let mut file = FormFile::from_sections(sections);
+    // Audio groups are indexed after data.win's own AUDO section
+    for path in args.audio_groups.as_ref().unwrap() {
+        let audio_group = try_take_data_win_file(&fs::read(path)?)?;
+        for section in audio_group {
+            if let Section::Audo(audo) = section {
+                file.audos.push(audo);
+            }
+        }
+    }
 
     if args.extract_audio {
         let strg = file.strg.as_ref().unwrap();
@@ -76,7 +122,7 @@ pub fn main(mut args: Args, wait_for_user: bool) {
             let name = strg.get(name).unwrap();
 
             std::fs::write(
-                &format!(
+                format!(
                     "{}/{}.{}",
                     sounds_folder,
                     name,
@@ -94,7 +140,7 @@ pub fn main(mut args: Args, wait_for_user: bool) {
         let textures = txtr.files.iter().enumerate().collect::<Vec<_>>();
         textures.par_iter().for_each(|(i, texture)| {
             let _ = std::fs::write(
-                &format!("{}/{}.png", textures_folder, i),
+                format!("{}/{}.png", textures_folder, i),
                 &texture.png
             );
         });
@@ -104,19 +150,29 @@ pub fn main(mut args: Args, wait_for_user: bool) {
         let sprt = file.sprt.as_ref().unwrap();
         let strg = file.strg.as_ref().unwrap();
         let sprites_folder = format!("{}/sprites", args.originals_folder);
+        let masks_folder = format!("{}/masks", args.originals_folder);
         let _ = fs::create_dir(&sprites_folder);
         sprt.sprites.par_iter().for_each(|sprite| {
             let name = strg.get(sprite.name_offset).unwrap();
             println!("Saving '{}'...", name);
-            let _ = fs::create_dir_all(&format!("{}/{}", sprites_folder, name));
+            let _ = fs::create_dir_all(format!("{}/{}", sprites_folder, name));
             let tpags = sprite.tpag_offsets.iter().enumerate().collect::<Vec<_>>();
             tpags.par_iter().for_each(|(i, &tpag)| {
                 file.get_tpag_subimage(tpag)
                     .save_with_format(
-                        &format!("{}/{}/{}.png", sprites_folder, name, i),
+                        format!("{}/{}/{}.png", sprites_folder, name, i),
                         image::ImageFormat::PNG
                     ).unwrap();
             });
+            fs::write(
+                format!("{}/{}/sprite.json", sprites_folder, name),
+                SpriteSettings::of(sprite).to_json()
+            ).unwrap();
+            // kept apart from the frames, so a copy of the sprite's folder can be modded as it is
+            for (i, mask) in sprite.masks.iter().flatten().enumerate() {
+                let _ = fs::create_dir_all(format!("{}/{}", masks_folder, name));
+                fs::write(format!("{}/{}/{}.png", masks_folder, name, i), masks::mask_png(sprite.size, mask)).unwrap();
+            }
         });
     }
 
@@ -129,7 +185,7 @@ pub fn main(mut args: Args, wait_for_user: bool) {
             let name = strg.get(font.font_name).unwrap();
             let alias = strg.get(font.name).unwrap();
             println!("Saving font '{}' (alias: '{}')", name, alias);
-            let _ = fs::create_dir(&format!("{}/{}", fonts_folder, name));
+            let _ = fs::create_dir(format!("{}/{}", fonts_folder, name));
             let mut font_sheet = file.get_tpag_subimage(font.entire_font_tpag);
             for font_char in &font.chars {
                 let ((x, y), (w, h)) = font_char.bounds;
@@ -140,18 +196,38 @@ pub fn main(mut args: Args, wait_for_user: bool) {
                 font_sheet.sub_image(
                     x as _, y as _, w as _, h as _
                 ).to_image().save_with_format(
-                    &format!("{}/{}/{}.png", fonts_folder, name, font_char.character),
+                    format!("{}/{}/{}.png", fonts_folder, name, font_char.character),
                     image::ImageFormat::PNG
                 ).unwrap();
             }
         }
     }
 
-    let mut f = fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(&args.data_win)
-                    .unwrap();
+    if args.extract_code {
+        let strg = file.strg.as_ref().unwrap();
+        let code_folder = format!("{}/code", args.originals_folder);
+        let _ = fs::create_dir_all(&code_folder);
+        for (i, entry) in file.code_entries().iter().enumerate() {
+            let name = strg.get(entry.name_offset).unwrap();
+            println!("Decompiling '{}'...", name);
+            match decompile::decompile(&file, i) {
+                Ok(source) => fs::write(format!("{}/{}.gml", code_folder, name), source)?,
+                Err(err) => println!("Warning: failed to decompile '{}': {}", name, err),
+            }
+        }
+    }
+
+    if args.extract_rooms {
+        let strg = file.strg.as_ref().unwrap();
+        let rooms_folder = format!("{}/rooms", args.originals_folder);
+        let _ = fs::create_dir_all(&rooms_folder);
+        let extension = if args.tiled_json { "json" } else { "tmx" };
+        for (i, room) in file.rooms().iter().enumerate() {
+            let name = strg.get(room.name_offset).unwrap();
+            println!("Exporting room '{}'...", name);
+            tiled::export_room(&file, i).save(format!("{}/{}.{}", rooms_folder, name, extension))?;
+        }
+    }
 
     if args.mod_textures {
         let textures_folder = format!("{}/textures", args.mod_folder);
@@ -176,20 +252,22 @@ pub fn main(mut args: Args, wait_for_user: bool) {
         }
     }
 
-    // TODO: seperate texture writing from sprite modifying
     if args.mod_sprites {
         let sprites_folder = format!("{}/sprites", args.mod_folder);
 
         if let Ok(sprites_dir) = fs::read_dir(&sprites_folder) {
             let sprites_dir = sprites_dir.collect::<Result<Vec<_>, _>>().unwrap();
 
-            let anims = sprites_dir.par_iter().filter_map(|anim| {
+            let mut anims = sprites_dir.par_iter().filter_map(|anim| {
                 let anim_dir = anim.path();
                 if anim_dir.is_dir() {
                     let anim_name = anim.file_name();
-                    let frames = fs::read_dir(anim_dir).unwrap().collect::<Result<Vec<_>, _>>()
+                    let settings = anim_dir.join("sprite.json");
+                    let settings = if settings.exists() { Some(SpriteSettings::load(settings)) } else { None };
+                    let mut frames = fs::read_dir(anim_dir).unwrap().collect::<Result<Vec<_>, _>>()
                         .unwrap()
                         .par_iter()
+                        .filter(|frame| frame.path().extension().is_none_or(|extension| extension != "json"))
                         .map(|frame|{
                             let filename = frame.path();
                             let filename = filename.file_stem().unwrap().to_str().unwrap();
@@ -203,121 +281,97 @@ pub fn main(mut args: Args, wait_for_user: bool) {
                                         ))
                                 });
 
-                            (frame_num, image::open(frame.path()).unwrap())
+                            (frame_num, image::open(frame.path()).unwrap().to_rgba())
                         })
                         .collect::<Vec<_>>();
-                    Some((anim_name, frames))
+                    frames.sort_by_key(|&(frame_num, _)| frame_num);
+                    Some((anim_name.to_str().unwrap().to_string(), frames, settings))
                 } else {
                     None
                 }
             }).collect::<Vec<_>>();
-
-            let needed_edits = anims.par_iter().map(|(anim_name, frames)| {
-                let anim_name = anim_name.to_str().unwrap().to_string();
-                frames.par_iter()
-                    .filter_map(|(frame_num, image)|{
-                        let name = format!("Sprite '{}' frame {}", anim_name, frame_num);
-                        match file.get_tpag_from_name_and_frame(&anim_name, *frame_num) {
-                            Some(tpag_data) => Some((tpag_data, image, name)),
-                            None => {
-                                println!(
-                                    "Warning: {} not found in data.win but exists in mods folder",
-                                    name
-                                );
-                                None
+            // new sprites are added in a predictable order
+            anims.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut edits = Vec::new();
+            let mut settings_edits = Vec::new();
+            for (anim_name, frames, settings) in anims {
+                let settings = match settings.unwrap_or_else(|| Ok(SpriteSettings::default())) {
+                    Ok(settings) => settings,
+                    Err(err) => {
+                        println!("Warning: failed to read sprite.json for '{}', {}", anim_name, err);
+                        continue
+                    }
+                };
+                let (sprite, frame_count) = match file.sprite(&anim_name) {
+                    Some(sprite) => (sprite.id, sprite.tpag_offsets.len()),
+                    None => {
+                        if let Some(missing) = (0..frames.len().max(1)).find(|&i| frames.get(i).is_none_or(|frame| frame.0 != i)) {
+                            println!("Warning: new sprite '{}' has no frame {}, skipping it", anim_name, missing);
+                            continue
+                        }
+                        println!("Adding sprite '{}'...", anim_name);
+                        let frames = frames.into_iter().map(|(_, image)| image).collect();
+                        match sprites::add_sprite(&mut file, &anim_name, frames, &settings) {
+                            Ok(added) => edits.extend(added),
+                            Err(err) => {
+                                println!("Warning: can't add sprite '{}', {}", anim_name, err);
+                                continue
                             }
                         }
-                    })
-                    .collect::<Vec<_>>()
-                    .into_par_iter()
-            }).flatten().collect::<Vec<_>>();
-
-            let needed_textures: BTreeSet<usize> = needed_edits.iter().map(|edit| (edit.0).1).collect();
-
-            let mut textures_to_edit = needed_textures
-                .into_par_iter()
-                .map(|texture_num|{
-                    let txtr = file.txtr.as_ref().unwrap();
-
-                    let texture = image::load_from_memory_with_format(
-                                        &txtr.files[texture_num].png,
-                                        image::ImageFormat::PNG
-                                    ).unwrap_or_else(|_| {
-                                        panic!("Texture {} failed to load.", texture_num)
-                                    });
-
-                    (texture_num, texture)
-                })
-                .collect::<BTreeMap<usize, _>>();
-
-            // Apply edits to textures
-            for ((((x, y), (w, h)), texture_num), sprite, name) in needed_edits {
-                let ((x, y), (w, h)) = ((x as u32, y as u32), (w as u32, h as u32));
-                if sprite.dimensions() != (w, h) {
-                    println!(
-                        "Warning: {} incorrect size. Should be ({}, {}), is ({}, {})",
-                        name,
-                        w, h, sprite.width(), sprite.height()
-                    );
+                        settings_edits.push((file.sprite(&anim_name).unwrap().id, settings, true, anim_name));
+                        continue
+                    }
+                };
+                // set once the frames are in, so they don't grow the sprite past a size it's given
+                let original = SpriteSettings::of(file.resolve(sprite).unwrap());
+                let settings = settings.changed_from(&original);
+                // the old masks no longer match if the pixels or the bounds change
+                let changed = !frames.is_empty() || settings != SpriteSettings::default();
+                settings_edits.push((sprite, settings, changed, anim_name.clone()));
+                // a full set of frames from 0 replaces the whole animation, however long it was
+                if !frames.is_empty() && frames.len() < frame_count && frames.iter().enumerate().all(|(i, &(frame_num, _))| frame_num == i) {
+                    println!("Shortening sprite '{}' to {} frames...", anim_name, frames.len());
+                    if let Err(err) = sprites::truncate_frames(&mut file, sprite, frames.len()) {
+                        println!("Warning: can't shorten sprite '{}', {}", anim_name, err);
+                    }
+                }
+                let mut added = Vec::new();
+                for (frame_num, image) in frames {
+                    if frame_num < frame_count {
+                        edits.push(atlas::FrameEdit { sprite, frame: frame_num, image });
+                    } else if frame_num == frame_count + added.len() {
+                        added.push(image);
+                    } else {
+                        println!(
+                            "Warning: Sprite '{}' frame {} found in mods folder without frame {} before it, skipping it",
+                            anim_name, frame_num, frame_count + added.len()
+                        );
+                    }
+                }
+                if !added.is_empty() {
+                    println!("Lengthening sprite '{}' to {} frames...", anim_name, frame_count + added.len());
+                    match sprites::add_frames(&mut file, sprite, added) {
+                        Ok(added) => edits.extend(added),
+                        Err(err) => println!("Warning: can't lengthen sprite '{}', {}", anim_name, err),
+                    }
                 }
-                textures_to_edit
-                    .get_mut(&texture_num)
-                    .unwrap()
-                    .copy_from(
-                        &sprite.view(0, 0, w, h),
-                        x,
-                        y
-                    );
             }
 
-            if let Some(&mut Txtr { ref mut files, .. }) = file.txtr.as_mut() {
-                *files = files
-                    .into_par_iter()
-                    .enumerate()
-                    .map(|(i, &mut TxtrEntry { unk1, unk2, ref png })|{
-                        let png = textures_to_edit
-                            .get(&i)
-                            .map(|texture| {
-                                let mut buffer = Vec::with_capacity(0x8_0000);
-                                texture.write_to(&mut buffer, image::ImageFormat::PNG).unwrap();
-                                buffer
-                            })
-                            .unwrap_or_else(|| remove_padding(png.clone()));
-                        TxtrEntry {
-                            unk1, unk2, png
-                        }
-                    })
-                    .collect();
+            atlas::replace_frames(&mut file, edits);
+            for (sprite, settings, changed, anim_name) in settings_edits {
+                if let Err(err) = settings.apply(&mut file, sprite) {
+                    println!("Warning: can't apply sprite.json to sprite '{}', {}", anim_name, err);
+                    continue
+                }
+                if changed {
+                    settings.regenerate_masks(&mut file, sprite);
+                }
             }
-
         }
 
     }
 
-    if args.mod_sprites | args.mod_textures {
-        if let FormFile { txtr: Some(Txtr { ref offset, ref files, .. }), .. } = file {
-
-            let loc = f.seek(SeekFrom::Start((offset + 0xC + (4 * files.len())) as u64)).unwrap();
-            let mut file_pos = (loc as usize + (0xC * files.len())) as u32;
-            for &TxtrEntry { ref unk1, ref unk2, ref png } in files {
-                f.write(&unk1.to_le_bytes()).unwrap();
-                f.write(&unk2.to_le_bytes()).unwrap();
-                f.write(&file_pos.to_le_bytes()).unwrap();
-                file_pos += png.len() as u32;
-            }
-            for TxtrEntry { png, .. } in files {
-                f.write(&png).unwrap();
-            }
-            let padding = ((file_pos + 0x1f) & !0x1f) - file_pos;
-            // Rewrite TXTR size
-            let loc = f.seek(SeekFrom::Start((offset + 4) as u64)).unwrap() as u32;
-            f.write(&((file_pos + padding) - (loc + 4)).to_le_bytes()).unwrap();
-            // Write TXTR padding
-            f.seek(SeekFrom::Start(file_pos as u64)).unwrap() as u32;
-            f.write(&vec![0; padding as usize]).unwrap();
-        }
-    }
-
     /*if args.mod_fonts {
         todo!()
     }*/
@@ -329,12 +383,9 @@ pub fn main(mut args: Args, wait_for_user: bool) {
             let sounds = audio_dir.par_iter().filter_map(|sound| {
                 let sound_dir = sound.path();
                 if !sound_dir.is_dir() {
-                    let name_offset = file.name_to_offset(sound.path().file_stem()?.to_str()?)?;
                     let &SondEntry {
                         audiogroup_index, index_in_audiogroup, ..
-                    } = file.sond.as_ref().unwrap().sounds
-                        .iter()
-                        .find(|entry| entry.name_offset == name_offset)?;
+                    } = file.sound(sound.path().file_stem()?.to_str()?)?.entry;
                     let data = fs::read(sound.path()).ok()?;
                     Some(((audiogroup_index as usize, index_in_audiogroup as usize), data))
                 } else {
@@ -350,51 +401,139 @@ pub fn main(mut args: Args, wait_for_user: bool) {
                 let mut audio = fs::OpenOptions::new()
                         .read(true)
                         .write(true)
-                        .open(file)
-                        .unwrap();
-                audio.seek(SeekFrom::Start(8)).unwrap();
-                audo.write_to(&mut audio, 8).unwrap();
-                let file_size = audio.seek(SeekFrom::Current(0)).unwrap();
-                audio.set_len(file_size).unwrap();
-                audio.seek(SeekFrom::Start(4)).unwrap();
-                audio.write(&(file_size as u32 - 8).to_le_bytes()).unwrap();
+                        .open(file)?;
+                audio.seek(SeekFrom::Start(8))?;
+                audo.write_to(&mut audio, 8)?;
+                let file_size = audio.stream_position()?;
+                audio.set_len(file_size)?;
+                audio.seek(SeekFrom::Start(4))?;
+                audio.write_all(&(file_size as u32 - 8).to_le_bytes())?;
             }
-            if !args.mod_sprites | args.mod_textures {
-                f.seek(SeekFrom::Start(file.audos[0].offset as u64)).unwrap();
+        }
+    }
+
+    if args.mod_code {
+        let code_folder = format!("{}/code", args.mod_folder);
+        if let Ok(code_dir) = fs::read_dir(&code_folder) {
+            let mut code_dir = code_dir.collect::<Result<Vec<_>, _>>().unwrap();
+            code_dir.sort_by_key(|entry| entry.path());
+            for entry in code_dir {
+                let path = entry.path();
+                let extension = match path.extension().and_then(|extension| extension.to_str()) {
+                    Some(extension @ "asm") | Some(extension @ "gml") => extension,
+                    _ => continue,
+                };
+                let name = match path.file_stem().and_then(|name| name.to_str()) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let index = match code_index(&file, name) {
+                    Some(index) => index,
+                    None => {
+                        println!("Warning: code '{}' not found in data.win but exists in mods folder", name);
+                        continue
+                    }
+                };
+                let text = fs::read_to_string(&path)?;
+                if extension == "gml" {
+                    println!("Compiling '{}'...", name);
+                    if let Err(err) = compile::compile_into(&mut file, index, &text) {
+                        println!("Warning: failed to compile '{}', {}", path.display(), err);
+                    }
+                } else {
+                    println!("Assembling '{}'...", name);
+                    if let Err(err) = asm::assemble_into(&mut file, index, &text) {
+                        println!("Warning: failed to assemble '{}', {}", path.display(), err);
+                    }
+                }
             }
         }
     }
 
-    // NOTE: must be seeked to where you want the AUDO section written beforehand!!
-    if args.mod_audio | args.mod_sprites {
-        let pos = f.seek(SeekFrom::Current(0)).unwrap() as u32;
-        // Write AUDO section back (since it's been pushed back)
-        file.audos[0].write_to(&mut f, pos).unwrap();
-        // Rewrite FORM size
-        let form_size = f.seek(SeekFrom::Current(0)).unwrap() as u32 - 8;
-        f.seek(SeekFrom::Start(4)).unwrap();
-        f.write_all(&form_size.to_le_bytes()).unwrap();
-        f.set_len(form_size as u64 + 8).unwrap();
+    if args.mod_rooms {
+        let rooms_folder = format!("{}/rooms", args.mod_folder);
+        if let Ok(rooms_dir) = fs::read_dir(&rooms_folder) {
+            let mut rooms_dir = rooms_dir.collect::<Result<Vec<_>, _>>().unwrap();
+            rooms_dir.sort_by_key(|entry| entry.path());
+            for entry in rooms_dir {
+                let path = entry.path();
+                match path.extension().and_then(|extension| extension.to_str()) {
+                    Some("tmx") | Some("json") => {}
+                    _ => continue,
+                }
+                let name = match path.file_stem().and_then(|name| name.to_str()) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let index = match file.room(name) {
+                    Some(room) => room.id.0,
+                    None => {
+                        println!("Warning: room '{}' not found in data.win but exists in mods folder", name);
+                        continue
+                    }
+                };
+                println!("Importing room '{}'...", name);
+                if let Err(err) = tiled::TiledMap::load(&path).and_then(|map| tiled::import_room(&mut file, index, &map)) {
+                    println!("Warning: failed to import '{}', {}", path.display(), err);
+                }
+            }
+        }
     }
 
-    if wait_for_user {
-        let mut stdout = std::io::stdout();
-        stdout.write(b"Press Enter to continue...").unwrap();
-        stdout.flush().unwrap();
-        std::io::stdin().read(&mut [0]).unwrap();
+    if args.mod_audio | args.mod_sprites | args.mod_textures | args.mod_code | args.mod_rooms {
+        replace_file(&args.data_win, &file.to_bytes()?)?;
     }
+
+    Ok(())
 }
 
-fn remove_padding(mut data: Vec<u8>) -> Vec<u8> {
-    if !data.is_empty() {
-        for i in (0..data.len()).rev() {
-            if data[i] != 0u8 {
-                data.truncate(i+1);
-                return data;
-            }
+/// Write `data` over the file at `path` without ever leaving it half written: it goes to a file
+/// next to it first, which is only moved over the original once it's all on disk
+fn replace_file(path: &str, data: &[u8]) -> Result<(), Error> {
+    let temp = format!("{}.tmp", path);
+    let written = fs::File::create(&temp).and_then(|mut out| {
+        out.write_all(data)?;
+        out.sync_all()
+    });
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(err.into());
+    }
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Index of the CODE entry called `name`, with or without the `gml_Script_` prefix, or run by
+/// the script called `name`
+fn code_index(file: &FormFile, name: &str) -> Option<usize> {
+    if let Some((script, _)) = file.script_by_name(name) {
+        return Some(script.code_index());
+    }
+    file.code_entry(name)
+        .or_else(|| file.code_entry(&format!("gml_Script_{}", name)))
+        .map(|code| code.id.0)
+}
+
+fn render_room(file: &FormFile, name: &str, output: Option<&str>) -> Result<(), Error> {
+    match file.room(name) {
+        Some(room) => {
+            let path = output.map(String::from).unwrap_or_else(|| format!("{}.png", name));
+            render::render_room(file, room.id.0).save_with_format(&path, image::ImageFormat::PNG)?;
+            println!("Rendered room '{}' to {}", name, path);
         }
+        None => println!("No room named '{}' found", name),
     }
-    vec![]
+
+    Ok(())
+}
+
+fn disasm_script(file: &FormFile, script: &str) -> Result<(), Error> {
+    match code_index(file, script) {
+        Some(index) => print!("{}", disasm::disassemble(file, index)?),
+        None => println!("No script named '{}' found", script),
+    }
+
+    Ok(())
 }
 
 use structopt::StructOpt;
@@ -413,6 +552,18 @@ pub struct Args {
     #[structopt(short = "t", long)]
     pub extract_textures: bool,
 
+    /// Decompile every CODE entry to `<originals folder>/code/<name>.gml`
+    #[structopt(short = "c", long)]
+    pub extract_code: bool,
+
+    /// Export every room to `<originals folder>/rooms/<name>.tmx` as a Tiled map
+    #[structopt(short = "r", long)]
+    pub extract_rooms: bool,
+
+    /// Export rooms as Tiled JSON maps instead of TMX
+    #[structopt(long)]
+    pub tiled_json: bool,
+
     #[structopt(short = "A", long)]
     pub mod_audio: bool,
 
@@ -425,6 +576,18 @@ pub struct Args {
     #[structopt(short = "T", long)]
     pub mod_textures: bool,
 
+    /// Compile `<mod folder>/code/<name>.gml`, or assemble `<name>.asm`, over the CODE entry `<name>`
+    #[structopt(short = "C", long)]
+    pub mod_code: bool,
+
+    /// Import `<mod folder>/rooms/<name>.tmx` (or `.json`), a Tiled map, over the room `<name>`
+    #[structopt(short = "R", long)]
+    pub mod_rooms: bool,
+
+    /// Check that writing the unmodified files reproduces them byte for byte
+    #[structopt(long)]
+    pub verify_roundtrip: bool,
+
     #[structopt(short, long, long, default_value = "mods")]
     pub mod_folder: String,
 
@@ -435,7 +598,33 @@ pub struct Args {
     pub data_win: String,
 
     #[structopt(long)]
-    pub audio_groups: Option<Vec<String>>
+    pub audio_groups: Option<Vec<String>>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Print the bytecode of a script
+    Disasm {
+        /// Name of the CODE entry, with or without the `gml_Script_` prefix
+        script: String,
+    },
+    /// Print the object inheritance tree
+    Objects {
+        /// Print a Graphviz DOT graph instead
+        #[structopt(long)]
+        dot: bool,
+    },
+    /// Draw a room to a PNG
+    RenderRoom {
+        /// Name of the ROOM entry
+        room: String,
+        /// Where to save the image, `<room>.png` by default
+        #[structopt(short, long)]
+        output: Option<String>,
+    },
 }
 
 impl Default for Args {
@@ -445,13 +634,20 @@ impl Default for Args {
             extract_sprites: false,
             extract_fonts: false,
             extract_textures: false,
+            extract_code: false,
+            extract_rooms: false,
+            tiled_json: false,
             mod_audio: false,
             mod_sprites: false,
             mod_textures: false,
+            mod_code: false,
+            mod_rooms: false,
+            verify_roundtrip: false,
             mod_folder: String::from("mods"),
             originals_folder: String::from("files"),
             data_win: String::from("data.win"),
             audio_groups: None,
+            command: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+//! Sprites' collision masks: saving them as images, and making them again from the frames
+
+use image::{png::PNGEncoder, ColorType, RgbaImage};
+
+use crate::file_structs::{mask_len, FormFile, SpriteId};
+
+/// Collision type of sprites whose masks are checked pixel by pixel, rather than just their bounds
+pub const PRECISE: u32 = 1;
+
+type Bounds = ((u32, u32), (u32, u32));
+
+/// How to fill in a sprite's bounds when making its masks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskShape {
+    /// Every pixel that isn't transparent
+    Precise,
+    Rectangle,
+    /// The ellipse that fits in the bounds
+    Ellipse,
+}
+
+impl MaskShape {
+    pub const ALL: [MaskShape; 3] = [MaskShape::Precise, MaskShape::Rectangle, MaskShape::Ellipse];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MaskShape::Precise => "precise",
+            MaskShape::Rectangle => "rectangle",
+            MaskShape::Ellipse => "ellipse",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|shape| shape.name() == name)
+    }
+
+    /// The shape a sprite's masks were most likely made in, going by its collision type
+    pub fn of_collision(collision: u32) -> Self {
+        if collision == PRECISE { MaskShape::Precise } else { MaskShape::Rectangle }
+    }
+}
+
+/// A mask as a 1-bit greyscale PNG, white where it's solid
+pub fn mask_png((width, height): (u32, u32), mask: &[u8]) -> Vec<u8> {
+    // masks are already laid out the way PNG stores 1-bit pixels
+    let mut png = Vec::new();
+    PNGEncoder::new(&mut png)
+        .encode(mask, width, height, ColorType::Gray(1))
+        .expect("Failed to encode mask");
+    png
+}
+
+/// Make `sprite`'s masks again from its frames, in `shape`, with one per frame if `per_frame`.
+///
+/// Bounds set automatically are first fit to the frames again. Masks in a shape other than a
+/// rectangle are only used by the game if the sprite is precise, so it's made precise. Sprites
+/// whose masks couldn't be read are left alone.
+pub fn regenerate(file: &mut FormFile, sprite: SpriteId, shape: MaskShape, per_frame: bool) {
+    let entry = match file.resolve(sprite) {
+        Some(entry) if entry.masks.is_some() => entry,
+        _ => return,
+    };
+    let frames = (0..entry.tpag_offsets.len())
+        .filter_map(|frame| file.get_sprite_frame(sprite.0, frame))
+        .collect::<Vec<_>>();
+    let size = entry.size;
+    let bounds = match entry.bbox_mode {
+        0 => opaque_bounds(&frames, size),
+        1 => full_bounds(size),
+        _ => entry.bounds,
+    };
+
+    let masks = match shape {
+        MaskShape::Precise if per_frame => frames.iter()
+            .map(|frame| mask(size, bounds, |x, y| frame.get_pixel(x, y)[3] > 0))
+            .collect(),
+        MaskShape::Precise => vec![mask(size, bounds, |x, y| frames.iter().any(|frame| frame.get_pixel(x, y)[3] > 0))],
+        MaskShape::Rectangle | MaskShape::Ellipse => {
+            let ((left, right), (bottom, top)) = bounds;
+            let center = ((left + right + 1) as f64 / 2.0, (top + bottom + 1) as f64 / 2.0);
+            let radius = ((right + 1 - left) as f64 / 2.0, (bottom + 1 - top) as f64 / 2.0);
+            let mask = mask(size, bounds, |x, y| {
+                let dx = (x as f64 + 0.5 - center.0) / radius.0;
+                let dy = (y as f64 + 0.5 - center.1) / radius.1;
+                shape == MaskShape::Rectangle || dx * dx + dy * dy <= 1.0
+            });
+            vec![mask; if per_frame { frames.len() } else { 1 }]
+        }
+    };
+
+    let entry = &mut file.sprt.as_mut().unwrap().sprites[sprite.0];
+    entry.bounds = bounds;
+    entry.masks = Some(masks);
+    if shape != MaskShape::Rectangle {
+        entry.collision_type = PRECISE;
+    }
+}
+
+/// A mask of a sprite of `size` that's solid wherever `solid` is within `bounds`
+fn mask((width, height): (u32, u32), bounds: Bounds, solid: impl Fn(u32, u32) -> bool) -> Vec<u8> {
+    let ((left, right), (bottom, top)) = bounds;
+    let row_len = width.div_ceil(8) as usize;
+    let mut mask = vec![0u8; mask_len((width, height))];
+    for y in top..=bottom.min(height.saturating_sub(1)) {
+        for x in left..=right.min(width.saturating_sub(1)) {
+            if solid(x, y) {
+                mask[y as usize * row_len + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    mask
+}
+
+fn full_bounds((width, height): (u32, u32)) -> Bounds {
+    ((0, width.saturating_sub(1)), (height.saturating_sub(1), 0))
+}
+
+/// `((left, right), (bottom, top))` of the pixels that aren't transparent in any of `frames`,
+/// or the whole sprite if they all are
+pub fn opaque_bounds(frames: &[RgbaImage], size: (u32, u32)) -> Bounds {
+    let mut bounds: Option<Bounds> = None;
+    for frame in frames {
+        for (x, y, pixel) in frame.enumerate_pixels() {
+            if pixel[3] == 0 {
+                continue
+            }
+            let ((left, right), (bottom, top)) = bounds.unwrap_or(((x, x), (y, y)));
+            bounds = Some(((left.min(x), right.max(x)), (bottom.max(y), top.min(y))));
+        }
+    }
+    bounds.unwrap_or_else(|| full_bounds(size))
+}
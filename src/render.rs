@@ -0,0 +1,296 @@
+//! Drawing rooms the way the game would on its first frame, for previewing them
+
+use std::convert::TryFrom;
+
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
+
+use crate::file_structs::{
+    FormFile, GameObject, Layer, LayerKind, RgbaColor, RoomEntry, Tile, TpagRef, TILE_FLIP, TILE_INDEX_MASK,
+    TILE_MIRROR, TILE_ROTATE,
+};
+
+const WHITE: [u8; 4] = [0xff; 4];
+
+/// Draw the room at `room`: its background colour, then its layers from the deepest up (or
+/// its backgrounds, tiles, instances and foregrounds in a room from GMS1).
+///
+/// Instances are drawn with their object's sprite, or the nearest ancestor's, on the frame
+/// they start on, scaled, rotated and tinted the way they are placed. Instances without a
+/// sprite, sequences, paths and effects aren't drawn.
+pub fn render_room(file: &FormFile, room: usize) -> RgbaImage {
+    let entry = &file.rooms()[room];
+    let mut canvas = RgbaImage::new(entry.width, entry.height);
+    if entry.draw_bg_color {
+        // room colours are GameMaker colours too, so red is in the low byte
+        let [_, blue, green, red] = entry.argb.to_be_bytes();
+        for pixel in canvas.pixels_mut() {
+            *pixel = Rgba([red, green, blue, 0xff]);
+        }
+    }
+
+    let mut renderer = Renderer { file, canvas };
+    if entry.layers.is_empty() {
+        renderer.legacy_room(entry);
+    } else {
+        let mut layers = entry.layers.iter().filter(|layer| layer.is_visible).collect::<Vec<_>>();
+        layers.sort_by_key(|layer| std::cmp::Reverse(layer.depth));
+        for layer in layers {
+            renderer.layer(entry, layer);
+        }
+    }
+    renderer.canvas
+}
+
+struct Renderer<'a> {
+    file: &'a FormFile,
+    canvas: RgbaImage,
+}
+
+impl<'a> Renderer<'a> {
+    fn sprite_frame(&self, sprite_index: i32, frame: usize) -> Option<RgbaImage> {
+        let index = usize::try_from(sprite_index).ok()?;
+        let frames = self.file.sprt.as_ref()?.sprites.get(index)?.tpag_offsets.len();
+        self.file.get_sprite_frame(index, frame % frames.max(1))
+    }
+
+    fn sprite_origin(&self, sprite_index: i32) -> (f32, f32) {
+        self.file.sprt.as_ref()
+            .and_then(|sprt| sprt.sprites.get(sprite_index as usize))
+            .map(|sprite| (sprite.origin.0 as f32, sprite.origin.1 as f32))
+            .unwrap_or((0.0, 0.0))
+    }
+
+    fn background_image(&self, background_index: i32) -> Option<RgbaImage> {
+        let background = self.file.backgrounds().get(usize::try_from(background_index).ok()?)?;
+        if background.tpag_offset == TpagRef(0) {
+            return None;
+        }
+        Some(self.file.get_tpag_subimage(background.tpag_offset))
+    }
+
+    fn instance(&mut self, instance: &GameObject) {
+        if self.file.objects().get(instance.bg_def_index as usize).is_none() {
+            return;
+        }
+        let sprite_index = self.file.effective_object(instance.bg_def_index as usize).sprite_index;
+        if let Some(image) = self.sprite_frame(sprite_index, instance.frame as usize) {
+            let origin = self.sprite_origin(sprite_index);
+            let position = (instance.x as f32, instance.y as f32);
+            let scale = (instance.scale_x, instance.scale_y);
+            draw(&mut self.canvas, &image, position, origin, scale, instance.rotation, rgba(&instance.color));
+        }
+    }
+
+    fn legacy_tile(&mut self, tile: &Tile) {
+        let image = match self.background_image(tile.bg_def_index) {
+            Some(image) => image,
+            None => return,
+        };
+        let (x, y) = match (u32::try_from(tile.source_x), u32::try_from(tile.source_y)) {
+            (Ok(x), Ok(y)) if x + tile.width <= image.width() && y + tile.height <= image.height() => (x, y),
+            _ => return,
+        };
+        let part = image.view(x, y, tile.width, tile.height).to_image();
+        // tile tints are GameMaker colours, so blue is in the low byte
+        let [alpha, blue, green, red] = tile.argb_tint.to_be_bytes();
+        let tint = [red, green, blue, alpha];
+        draw(&mut self.canvas, &part, (tile.x as f32, tile.y as f32), (0.0, 0.0), (tile.scale_x, tile.scale_y), 0.0, tint);
+    }
+
+    fn layer(&mut self, entry: &RoomEntry, layer: &Layer) {
+        let offset = (layer.x_offset, layer.y_offset);
+        match &layer.kind {
+            LayerKind::Background { visible: true, sprite_index, horizontal_tile, vertical_tile, stretch, color, first_frame, .. } => {
+                let image = match self.sprite_frame(*sprite_index, *first_frame as usize) {
+                    Some(image) => image,
+                    None => return,
+                };
+                let (width, height) = (image.width() as f32, image.height() as f32);
+                let scale = if *stretch && width > 0.0 && height > 0.0 {
+                    (entry.width as f32 / width, entry.height as f32 / height)
+                } else {
+                    (1.0, 1.0)
+                };
+                let tint = rgba(color);
+                // start a tile to the left of (and above) the room when repeating, so it's covered
+                let starts = |start: f32, size: f32, room: u32, repeat: bool| -> Vec<f32> {
+                    if !repeat || size <= 0.0 {
+                        return vec![start];
+                    }
+                    let first = start - ((start / size).ceil()) * size;
+                    (0..).map(|i| first + i as f32 * size).take_while(|&at| at < room as f32).collect()
+                };
+                for y in starts(offset.1, height * scale.1, entry.height, *vertical_tile) {
+                    for x in starts(offset.0, width * scale.0, entry.width, *horizontal_tile) {
+                        draw(&mut self.canvas, &image, (x, y), (0.0, 0.0), scale, 0.0, tint);
+                    }
+                }
+            }
+            LayerKind::Instances { .. } => {
+                for instance in entry.layer_instances(layer) {
+                    self.instance(instance);
+                }
+            }
+            LayerKind::Tiles { background_index, width, tiles, .. } => {
+                let tile_set = match self.file.backgrounds().get(*background_index as usize).and_then(|bg| bg.tile_set.as_ref()) {
+                    Some(tile_set) => tile_set.clone(),
+                    None => return,
+                };
+                let image = match self.background_image(*background_index) {
+                    Some(image) => image,
+                    None => return,
+                };
+                let (tile_width, tile_height) = (tile_set.tile_width, tile_set.tile_height);
+                let columns = (*width).max(1);
+                for (i, &tile) in tiles.iter().enumerate() {
+                    let index = tile & TILE_INDEX_MASK;
+                    if index == 0 || tile_set.columns == 0 {
+                        continue;
+                    }
+                    // each tile has a border of `border_x` by `border_y` pixels around it
+                    let (column, row) = (index % tile_set.columns, index / tile_set.columns);
+                    let source_x = tile_set.border_x + column * (tile_width + tile_set.border_x * 2);
+                    let source_y = tile_set.border_y + row * (tile_height + tile_set.border_y * 2);
+                    if source_x + tile_width > image.width() || source_y + tile_height > image.height() {
+                        continue;
+                    }
+
+                    let mut part = image.view(source_x, source_y, tile_width, tile_height).to_image();
+                    if tile & TILE_MIRROR != 0 {
+                        part = imageops::flip_horizontal(&part);
+                    }
+                    if tile & TILE_FLIP != 0 {
+                        part = imageops::flip_vertical(&part);
+                    }
+                    if tile & TILE_ROTATE != 0 {
+                        part = imageops::rotate90(&part);
+                    }
+                    let (x, y) = ((i as u32 % columns) * tile_width, (i as u32 / columns) * tile_height);
+                    let position = (offset.0 + x as f32, offset.1 + y as f32);
+                    draw(&mut self.canvas, &part, position, (0.0, 0.0), (1.0, 1.0), 0.0, WHITE);
+                }
+            }
+            LayerKind::Assets(assets) => {
+                for tile in &assets.legacy_tiles {
+                    self.legacy_tile(tile);
+                }
+                for sprite in &assets.sprites {
+                    if let Some(image) = self.sprite_frame(sprite.asset_index, sprite.frame_index as usize) {
+                        let origin = self.sprite_origin(sprite.asset_index);
+                        let position = (sprite.x as f32, sprite.y as f32);
+                        let scale = (sprite.scale_x, sprite.scale_y);
+                        draw(&mut self.canvas, &image, position, origin, scale, sprite.rotation, rgba(&sprite.color));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn legacy_background(&mut self, entry: &RoomEntry, foreground: bool) {
+        for background in &entry.backgrounds {
+            if !background.enabled || background.foreground != foreground {
+                continue;
+            }
+            let image = match self.background_image(background.bg_def_index as i32) {
+                Some(image) => image,
+                None => continue,
+            };
+            let (width, height) = (image.width() as i32, image.height() as i32);
+            let (x, y) = (background.x as i32, background.y as i32);
+            let starts = |start: i32, size: i32, room: u32, repeat: bool| -> Vec<i32> {
+                if !repeat || size <= 0 {
+                    return vec![start];
+                }
+                (start.rem_euclid(size) - size..room as i32).step_by(size as usize).collect()
+            };
+            for y in starts(y, height, entry.height, background.tile_y) {
+                for x in starts(x, width, entry.width, background.tile_x) {
+                    draw(&mut self.canvas, &image, (x as f32, y as f32), (0.0, 0.0), (1.0, 1.0), 0.0, WHITE);
+                }
+            }
+        }
+    }
+
+    /// A room from GMS1, where tiles and instances are drawn in order of depth between the
+    /// backgrounds and foregrounds
+    fn legacy_room(&mut self, entry: &RoomEntry) {
+        enum Item<'r> {
+            Tile(&'r Tile),
+            Instance(&'r GameObject),
+        }
+
+        self.legacy_background(entry, false);
+        let objects = self.file.objects();
+        let mut items = entry.tiles.iter()
+            .map(|tile| (tile.tile_depth, Item::Tile(tile)))
+            .chain(entry.game_objects.iter().map(|instance| {
+                let depth = objects.get(instance.bg_def_index as usize).map(|object| object.depth).unwrap_or(0);
+                (depth, Item::Instance(instance))
+            }))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|&(depth, _)| std::cmp::Reverse(depth));
+        for (_, item) in items {
+            match item {
+                Item::Tile(tile) => self.legacy_tile(tile),
+                Item::Instance(instance) => self.instance(instance),
+            }
+        }
+        self.legacy_background(entry, true);
+    }
+}
+
+fn rgba(color: &RgbaColor) -> [u8; 4] {
+    [color.r, color.g, color.b, color.a]
+}
+
+/// Draw `image` with its `origin` at `position`, scaled and then rotated `angle` degrees
+/// anticlockwise around it, multiplying each pixel by `tint`
+fn draw(canvas: &mut RgbaImage, image: &RgbaImage, position: (f32, f32), origin: (f32, f32), scale: (f32, f32), angle: f32, tint: [u8; 4]) {
+    if scale.0 == 0.0 || scale.1 == 0.0 || image.width() == 0 || image.height() == 0 {
+        return;
+    }
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // from the sprite's pixels to the room's, with y pointing down
+    let to_room = |x: f32, y: f32| {
+        let (x, y) = ((x - origin.0) * scale.0, (y - origin.1) * scale.1);
+        (position.0 + x * cos + y * sin, position.1 - x * sin + y * cos)
+    };
+
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let corners = [to_room(0.0, 0.0), to_room(width, 0.0), to_room(0.0, height), to_room(width, height)];
+    let clamp = |value: f32, max: u32| value.max(0.0).min(max as f32) as u32;
+    let left = clamp(corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min).floor(), canvas.width());
+    let right = clamp(corners.iter().map(|c| c.0).fold(f32::NEG_INFINITY, f32::max).ceil(), canvas.width());
+    let top = clamp(corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min).floor(), canvas.height());
+    let bottom = clamp(corners.iter().map(|c| c.1).fold(f32::NEG_INFINITY, f32::max).ceil(), canvas.height());
+
+    for y in top..bottom {
+        for x in left..right {
+            // the sprite pixel under the centre of this one
+            let (dx, dy) = (x as f32 + 0.5 - position.0, y as f32 + 0.5 - position.1);
+            let source_x = (dx * cos - dy * sin) / scale.0 + origin.0;
+            let source_y = (dx * sin + dy * cos) / scale.1 + origin.1;
+            if source_x < 0.0 || source_y < 0.0 || source_x >= width || source_y >= height {
+                continue;
+            }
+            let Rgba(source) = *image.get_pixel(source_x as u32, source_y as u32);
+            let tinted = [0, 1, 2, 3].map(|i| (source[i] as u32 * tint[i] as u32 / 255) as u8);
+            blend(canvas.get_pixel_mut(x, y), tinted);
+        }
+    }
+}
+
+/// `source` over `target`, with neither premultiplied
+fn blend(target: &mut Rgba<u8>, source: [u8; 4]) {
+    let alpha = source[3] as u32;
+    if alpha == 0 {
+        return;
+    }
+    let target_alpha = target[3] as u32 * (255 - alpha) / 255;
+    let out_alpha = alpha + target_alpha;
+    for i in 0..3 {
+        target[i] = ((source[i] as u32 * alpha + target[i] as u32 * target_alpha) / out_alpha) as u8;
+    }
+    target[3] = out_alpha as u8;
+}
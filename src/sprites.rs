@@ -0,0 +1,410 @@
+//! Adding sprites from mods, set up by the `sprite.json` next to their frames, and changing
+//! how many frames sprites have
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use image::RgbaImage;
+
+use crate::atlas::{self, FrameEdit};
+use crate::file_structs::{FormFile, MissingSection, SpriteId, SprtEntry, TpagEntry, TpagRef};
+use crate::json::{self, Json};
+use crate::masks::{self, MaskShape};
+
+/// Names `sprite.json` uses for each [`SprtEntry::bbox_mode`]
+const BOUNDS_MODES: [&str; 3] = ["automatic", "full", "manual"];
+/// Bounding box mode GameMaker gives sprites whose bounds were set by hand
+const MANUAL_BOUNDS: u32 = 2;
+/// Names `sprite.json` uses for each [`SprtEntry::collision_type`]
+const COLLISION_TYPES: [&str; 3] = ["rectangle", "precise", "rotated_rectangle"];
+/// Names `sprite.json` uses for each [`SprtEntry::playback_speed_type`]
+const SPEED_TYPES: [&str; 2] = ["frames_per_second", "frames_per_game_frame"];
+
+/// What a `sprite.json` sets for its sprite. Extracting sprites writes every field, and a
+/// mod's can leave any out to keep the sprite's own value, or the default for a new sprite.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpriteSettings {
+    pub size: Option<(u32, u32)>,
+    pub origin: Option<(u32, u32)>,
+    /// `((left, right), (bottom, top))`, as in [`SprtEntry::bounds`]
+    pub bounds: Option<((u32, u32), (u32, u32))>,
+    /// Index into [`BOUNDS_MODES`]. Manual if left out when `bounds` are set.
+    pub bounds_mode: Option<u32>,
+    /// Index into [`COLLISION_TYPES`]
+    pub collision: Option<u32>,
+    /// Shape to make the collision masks in when the sprite changes
+    pub mask_shape: Option<MaskShape>,
+    /// Whether each frame has its own collision mask, rather than one shared by them all
+    pub mask_per_frame: Option<bool>,
+    pub playback_speed: Option<f32>,
+    /// Index into [`SPEED_TYPES`]
+    pub playback_speed_type: Option<u32>,
+}
+
+impl SpriteSettings {
+    /// Everything about `sprite` that `sprite.json` holds
+    pub fn of(sprite: &SprtEntry) -> Self {
+        SpriteSettings {
+            size: Some(sprite.size),
+            origin: Some(sprite.origin),
+            bounds: Some(sprite.bounds),
+            bounds_mode: Some(sprite.bbox_mode),
+            collision: Some(sprite.collision_type),
+            mask_shape: Some(MaskShape::of_collision(sprite.collision_type)),
+            mask_per_frame: sprite.masks.as_ref().map(|masks| masks.len() > 1),
+            playback_speed: Some(sprite.playback_speed),
+            playback_speed_type: Some(sprite.playback_speed_type),
+        }
+    }
+
+    /// Only the settings that differ from `original`, so a `sprite.json` copied from the
+    /// extracted files along with frames that have since grown leaves the size to them
+    pub fn changed_from(&self, original: &SpriteSettings) -> Self {
+        fn changed<T: PartialEq + Copy>(value: Option<T>, original: Option<T>) -> Option<T> {
+            value.filter(|&value| Some(value) != original)
+        }
+        SpriteSettings {
+            size: changed(self.size, original.size),
+            origin: changed(self.origin, original.origin),
+            bounds: changed(self.bounds, original.bounds),
+            bounds_mode: changed(self.bounds_mode, original.bounds_mode),
+            collision: changed(self.collision, original.collision),
+            mask_shape: changed(self.mask_shape, original.mask_shape),
+            mask_per_frame: changed(self.mask_per_frame, original.mask_per_frame),
+            playback_speed: changed(self.playback_speed, original.playback_speed),
+            playback_speed_type: changed(self.playback_speed_type, original.playback_speed_type),
+        }
+    }
+
+    /// Read settings such as
+    ///
+    /// ```json
+    /// {
+    ///   "size": [32, 32],
+    ///   "origin": [16, 32],
+    ///   "bounds": {"left": 4, "top": 0, "right": 27, "bottom": 31},
+    ///   "bounds_mode": "manual",
+    ///   "collision": "precise",
+    ///   "mask_shape": "ellipse",
+    ///   "mask_per_frame": false,
+    ///   "playback_speed": 15,
+    ///   "playback_speed_type": "frames_per_second"
+    /// }
+    /// ```
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let json = json::parse(text)?;
+        let pair = |name| match json.get(name) {
+            Some(pair) => match pair.as_array() {
+                [x, y] => Ok(Some((whole(x, name)?, whole(y, name)?))),
+                _ => Err(format!("'{}' should be [x, y]", name)),
+            },
+            None => Ok(None),
+        };
+        let bounds = match json.get("bounds") {
+            Some(bounds) => {
+                let edge = |name| bounds.get(name)
+                    .ok_or_else(|| format!("'bounds' is missing '{}'", name))
+                    .and_then(|value| whole(value, name));
+                Some(((edge("left")?, edge("right")?), (edge("bottom")?, edge("top")?)))
+            }
+            None => None,
+        };
+        let playback_speed = match json.get("playback_speed") {
+            Some(speed) => Some(speed.as_f64().ok_or("'playback_speed' should be a number")? as f32),
+            None => None,
+        };
+        let mask_shape = match json.get("mask_shape") {
+            Some(shape) => Some(shape.as_str().and_then(MaskShape::from_name).ok_or_else(|| {
+                let names = MaskShape::ALL.iter().map(|shape| shape.name()).collect::<Vec<_>>();
+                format!("'mask_shape' should be one of {}", names.join(", "))
+            })?),
+            None => None,
+        };
+        let mask_per_frame = match json.get("mask_per_frame") {
+            Some(per_frame) => Some(per_frame.as_bool().ok_or("'mask_per_frame' should be true or false")?),
+            None => None,
+        };
+
+        Ok(SpriteSettings {
+            size: pair("size")?,
+            origin: pair("origin")?,
+            bounds,
+            bounds_mode: one_of(&json, "bounds_mode", &BOUNDS_MODES)?,
+            collision: one_of(&json, "collision", &COLLISION_TYPES)?,
+            mask_shape,
+            mask_per_frame,
+            playback_speed,
+            playback_speed_type: one_of(&json, "playback_speed_type", &SPEED_TYPES)?,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::from_json(&text)
+    }
+
+    /// The settings as `sprite.json`, in the format [`from_json`](Self::from_json) reads
+    pub fn to_json(&self) -> String {
+        let pair = |(x, y): (u32, u32)| Json::Array(vec![Json::Number(x as f64), Json::Number(y as f64)]);
+        let name = |names: &[&str], value: u32| Json::string(names.get(value as usize).copied().unwrap_or("unknown"));
+        let mut fields = Vec::new();
+        if let Some(size) = self.size {
+            fields.push(("size", pair(size)));
+        }
+        if let Some(origin) = self.origin {
+            fields.push(("origin", pair(origin)));
+        }
+        if let Some(((left, right), (bottom, top))) = self.bounds {
+            fields.push(("bounds", Json::object(vec![
+                ("left", Json::Number(left as f64)),
+                ("top", Json::Number(top as f64)),
+                ("right", Json::Number(right as f64)),
+                ("bottom", Json::Number(bottom as f64)),
+            ])));
+        }
+        if let Some(mode) = self.bounds_mode {
+            fields.push(("bounds_mode", name(&BOUNDS_MODES, mode)));
+        }
+        if let Some(collision) = self.collision {
+            fields.push(("collision", name(&COLLISION_TYPES, collision)));
+        }
+        if let Some(shape) = self.mask_shape {
+            fields.push(("mask_shape", Json::string(shape.name())));
+        }
+        if let Some(per_frame) = self.mask_per_frame {
+            fields.push(("mask_per_frame", Json::Bool(per_frame)));
+        }
+        if let Some(speed) = self.playback_speed {
+            // through its shortest decimal, so 0.1 isn't written as 0.10000000149011612
+            fields.push(("playback_speed", Json::Number(speed.to_string().parse().unwrap())));
+        }
+        if let Some(speed_type) = self.playback_speed_type {
+            fields.push(("playback_speed_type", name(&SPEED_TYPES, speed_type)));
+        }
+        Json::object(fields).to_pretty()
+    }
+
+    /// Set everything these settings hold on `sprite`, apart from how its masks are made. A new
+    /// size applies to all its frames. Fails, changing nothing, if there's no SPRT section, or
+    /// no TPAG section for a new size to go in.
+    pub fn apply(&self, file: &mut FormFile, sprite: SpriteId) -> Result<(), MissingSection> {
+        let frames = match file.sprt.as_ref().ok_or(MissingSection("SPRT"))?.sprites.get(sprite.0) {
+            Some(entry) => entry.tpag_offsets.clone(),
+            None => return Ok(()),
+        };
+        if let Some((width, height)) = self.size {
+            let tpag = file.tpag.as_mut().ok_or(MissingSection("TPAG"))?;
+            for frame in frames {
+                if let Some(index) = tpag.index_of(frame) {
+                    tpag.texture_pages[index].size = (width as u16, height as u16);
+                }
+            }
+        }
+
+        let sprite = &mut file.sprt.as_mut().unwrap().sprites[sprite.0];
+        if let Some(size) = self.size {
+            sprite.size = size;
+        }
+        if let Some(origin) = self.origin {
+            sprite.origin = origin;
+        }
+        if let Some(bounds) = self.bounds {
+            sprite.bounds = bounds;
+        }
+        if let Some(mode) = self.bounds_mode.or(self.bounds.map(|_| MANUAL_BOUNDS)) {
+            sprite.bbox_mode = mode;
+        }
+        if let Some(collision) = self.collision {
+            sprite.collision_type = collision;
+        }
+        if let Some(speed) = self.playback_speed {
+            sprite.playback_speed = speed;
+        }
+        if let Some(speed_type) = self.playback_speed_type {
+            sprite.playback_speed_type = speed_type;
+        }
+        Ok(())
+    }
+
+    /// Make `sprite`'s collision masks again from its frames, in the shape and number these
+    /// settings ask for, or else the ones its collision type and current masks suggest
+    pub fn regenerate_masks(&self, file: &mut FormFile, sprite: SpriteId) {
+        let entry = match file.resolve(sprite) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let shape = self.mask_shape.unwrap_or_else(|| MaskShape::of_collision(entry.collision_type));
+        let per_frame = self.mask_per_frame
+            .unwrap_or_else(|| entry.masks.as_ref().is_some_and(|masks| masks.len() > 1));
+        masks::regenerate(file, sprite, shape, per_frame);
+    }
+}
+
+fn whole(value: &Json, name: &str) -> Result<u32, String> {
+    value.as_f64()
+        .filter(|value| value.fract() == 0.0 && *value >= 0.0)
+        .map(|value| value as u32)
+        .ok_or_else(|| format!("'{}' should be a whole number of pixels", name))
+}
+
+/// Index in `names` of the name in the field `name`
+fn one_of(json: &Json, name: &str, names: &[&str]) -> Result<Option<u32>, String> {
+    match json.get(name) {
+        Some(value) => value.as_str()
+            .and_then(|value| names.iter().position(|&other| other == value))
+            .map(|index| Some(index as u32))
+            .ok_or_else(|| format!("'{}' should be one of {}", name, names.join(", "))),
+        None => Ok(None),
+    }
+}
+
+/// Add a sprite called `name` showing `frames`, returning the edits that put the frames on
+/// the texture pages, for [`atlas::replace_frames`](crate::atlas::replace_frames)
+///
+/// The sprite is as big as its largest frame, with each frame drawn from its top left. Unless
+/// `settings` say otherwise its origin is the top left too, its bounds are the part of its
+/// frames that isn't transparent, and it plays at the speed of the first sprite. It has no
+/// collision masks until [`SpriteSettings::regenerate_masks`] is given its frames on the pages.
+///
+/// Fails, changing nothing, without the STRG, SPRT, TPAG and TXTR sections to add to.
+pub fn add_sprite(
+    file: &mut FormFile,
+    name: &str,
+    frames: Vec<RgbaImage>,
+    settings: &SpriteSettings,
+) -> Result<Vec<FrameEdit>, MissingSection> {
+    if file.strg.is_none() {
+        return Err(MissingSection("STRG"));
+    }
+    if file.sprt.is_none() {
+        return Err(MissingSection("SPRT"));
+    }
+    let size = frames.iter().fold((0, 0), fit);
+    let tpag_offsets = push_frames(file, &frames, size)?;
+
+    let name_offset = file.strg.as_mut().unwrap().intern(name);
+    let sprt = file.sprt.as_mut().unwrap();
+    // fields nothing is known about yet are copied from a sprite that's already there
+    let template = sprt.sprites.first();
+    sprt.sprites.push(SprtEntry {
+        name_offset,
+        size,
+        bounds: masks::opaque_bounds(&frames, size),
+        transparent: template.map(|sprite| sprite.transparent).unwrap_or(0),
+        smooth: template.map(|sprite| sprite.smooth).unwrap_or(0),
+        preload: template.map(|sprite| sprite.preload).unwrap_or(0),
+        bbox_mode: 0,
+        collision_type: 0,
+        origin: (0, 0),
+        unk3: template.map(|sprite| sprite.unk3).unwrap_or(0),
+        unk4: template.map(|sprite| sprite.unk4).unwrap_or(0),
+        playback_speed: template.map(|sprite| sprite.playback_speed).unwrap_or(1.0),
+        playback_speed_type: template.map(|sprite| sprite.playback_speed_type).unwrap_or(0),
+        tpag_offsets,
+        unk_floats: Vec::new(),
+        // made once the frames are on the pages
+        masks: Some(Vec::new()),
+        trailing: Vec::new(),
+    });
+    let sprite = SpriteId(sprt.sprites.len() - 1);
+    settings.apply(file, sprite)?;
+    file.reindex();
+
+    Ok(frames.into_iter()
+        .enumerate()
+        .map(|(frame, image)| FrameEdit { sprite, frame, image })
+        .collect())
+}
+
+/// Add `frames` to the end of `sprite`'s animation, returning the edits that put them on the
+/// texture pages. Frames bigger than the sprite grow it, and its bounds, to fit.
+///
+/// Fails, changing nothing, without the SPRT, TPAG and TXTR sections to add to.
+pub fn add_frames(file: &mut FormFile, sprite: SpriteId, frames: Vec<RgbaImage>) -> Result<Vec<FrameEdit>, MissingSection> {
+    let entry = match file.sprt.as_ref().ok_or(MissingSection("SPRT"))?.sprites.get(sprite.0) {
+        Some(entry) => entry,
+        None => return Ok(Vec::new()),
+    };
+    let first = entry.tpag_offsets.len();
+    let (width, height) = frames.iter().fold(entry.size, fit);
+    let tpag_offsets = push_frames(file, &frames, (width, height))?;
+
+    let entry = &mut file.sprt.as_mut().unwrap().sprites[sprite.0];
+    if (width, height) != entry.size {
+        // the new frames are drawn from the top left, so that's where the bounds grow from
+        let ((_, right), (bottom, _)) = entry.bounds;
+        entry.bounds = ((0, right.max(width - 1)), (bottom.max(height - 1), 0));
+        entry.size = (width, height);
+    }
+    entry.tpag_offsets.extend(tpag_offsets);
+
+    Ok(frames.into_iter()
+        .enumerate()
+        .map(|(i, image)| FrameEdit { sprite, frame: first + i, image })
+        .collect())
+}
+
+/// Cut `sprite`'s animation down to its first `count` frames. The TPAG entries of the frames
+/// left out are removed, and their parts of the texture pages cleared, unless something else
+/// still shows them. Fails, changing nothing, without the SPRT and TPAG sections.
+pub fn truncate_frames(file: &mut FormFile, sprite: SpriteId, count: usize) -> Result<(), MissingSection> {
+    if file.tpag.is_none() {
+        return Err(MissingSection("TPAG"));
+    }
+    let sprt = file.sprt.as_mut().ok_or(MissingSection("SPRT"))?;
+    let removed = match sprt.sprites.get_mut(sprite.0) {
+        Some(entry) if entry.tpag_offsets.len() > count => entry.tpag_offsets.split_off(count),
+        _ => return Ok(()),
+    };
+
+    let in_use = used_tpag_entries(file);
+    let tpag = file.tpag.as_mut().unwrap();
+    let mut released = removed.into_iter()
+        .filter(|tpag_offset| !in_use.contains(tpag_offset))
+        .filter_map(|tpag_offset| tpag.remove(tpag_offset))
+        .map(|entry| (entry.texture_index as usize, entry.sprite_bounds))
+        .filter(|&(_, ((_, _), (width, height)))| width > 0 && height > 0)
+        .collect::<Vec<_>>();
+    // parts of the pages can be shared, by entries that showed the same image
+    released.retain(|&(texture, bounds)| !tpag.texture_pages.iter()
+        .any(|entry| entry.texture_index as usize == texture && entry.sprite_bounds == bounds));
+    released.sort_unstable();
+    released.dedup();
+    atlas::clear_parts(file, &released);
+    Ok(())
+}
+
+/// Every TPAG entry a sprite, background or font refers to
+fn used_tpag_entries(file: &FormFile) -> HashSet<TpagRef> {
+    let sprites = file.sprt.iter()
+        .flat_map(|sprt| &sprt.sprites)
+        .flat_map(|sprite| sprite.tpag_offsets.iter().copied());
+    let backgrounds = file.backgrounds().iter().map(|background| background.tpag_offset);
+    let fonts = file.font.iter().flat_map(|font| &font.fonts).map(|font| font.entire_font_tpag);
+    sprites.chain(backgrounds).chain(fonts).collect()
+}
+
+/// `size` grown to fit `frame`
+fn fit((width, height): (u32, u32), frame: &RgbaImage) -> (u32, u32) {
+    (width.max(frame.width()), height.max(frame.height()))
+}
+
+/// Add a TPAG entry for each of `frames` of a sprite of `size`, drawn from its top left. They
+/// have no part of a page until [`atlas::replace_frames`] finds room for one.
+fn push_frames(file: &mut FormFile, frames: &[RgbaImage], size: (u32, u32)) -> Result<Vec<TpagRef>, MissingSection> {
+    // the atlas finds room for the frames, on the last page if there's space, and makes any
+    // new pages like it, so there has to be one
+    let last_page = file.txtr.as_ref()
+        .and_then(|txtr| txtr.files.len().checked_sub(1))
+        .ok_or(MissingSection("TXTR"))?;
+    let tpag = file.tpag.as_mut().ok_or(MissingSection("TPAG"))?;
+    Ok(frames.iter()
+        .map(|frame| tpag.push(TpagEntry {
+            sprite_bounds: ((0, 0), (0, 0)),
+            unk2: ((0, 0), (frame.width() as u16, frame.height() as u16)),
+            size: (size.0 as u16, size.1 as u16),
+            texture_index: last_page as u16,
+        }))
+        .collect())
+}
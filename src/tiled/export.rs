@@ -0,0 +1,500 @@
+use super::*;
+use image::GenericImageView;
+use std::convert::TryFrom;
+use crate::file_structs::{
+    FormFile, GameObject, Layer, LayerKind, RgbaColor, RoomEntry, StringRef, Tile, TpagRef, TILE_FLIP,
+    TILE_INDEX_MASK, TILE_MIRROR, TILE_ROTATE,
+};
+
+/// The background, source position and size of a legacy tile, which tiles showing the same
+/// part of the same background share an image for
+type LegacyTileKey = (i32, i32, i32, u32, u32);
+
+/// Tile size to use when the room has no tile layers to take it from
+const DEFAULT_TILE_SIZE: u32 = 16;
+
+/// Turn the room at `room` into a Tiled map, with a layer for each of its layers (or for its
+/// backgrounds, tiles and instances in a room from GMS1).
+///
+/// Tile layers use a tileset made from their background, and instances and sprites on asset
+/// layers are tile objects from a collection of the first frame of each sprite. Legacy tiles
+/// are tile objects from a collection of the parts of each background they show. Sequences and
+/// nine slice sprites have no equivalent in Tiled and are left out.
+pub fn export_room(file: &FormFile, room: usize) -> TiledMap {
+    let entry = &file.rooms()[room];
+    let mut exporter = Exporter::new(file, entry);
+
+    let layers = if entry.layers.is_empty() {
+        exporter.legacy_layers(entry)
+    } else {
+        let mut layers = entry.layers.iter().collect::<Vec<_>>();
+        // deeper layers are drawn first, and Tiled draws the first layer at the bottom
+        layers.sort_by_key(|layer| std::cmp::Reverse(layer.depth));
+        layers.into_iter().map(|layer| exporter.layer(entry, layer)).collect()
+    };
+
+    let (tile_width, tile_height) = exporter.tile_size;
+    let mut properties = vec![
+        (String::from("speed"), PropertyValue::Int(entry.speed as i64)),
+    ];
+    if entry.persistent {
+        properties.push((String::from("persistent"), PropertyValue::Bool(true)));
+    }
+
+    TiledMap {
+        width: entry.width.div_ceil(tile_width),
+        height: entry.height.div_ceil(tile_height),
+        tile_width,
+        tile_height,
+        // room colours are stored as BGR
+        background_color: Some(entry.argb)
+            .filter(|_| entry.draw_bg_color)
+            .map(|bgr| ((bgr & 0xff) << 16) | (bgr & 0xff00) | ((bgr >> 16) & 0xff)),
+        properties,
+        tilesets: exporter.tilesets(),
+        layers,
+        images: exporter.images,
+    }
+}
+
+struct Exporter<'a> {
+    file: &'a FormFile,
+    tile_size: (u32, u32),
+    /// Tilesets made from the backgrounds of tile layers, with the background index of each
+    backgrounds: Vec<(i32, Tileset)>,
+    /// First gid of the collection of sprites, with the tile id of each sprite being its index
+    sprites_gid: u32,
+    sprite_tiles: Vec<(u32, TiledImage)>,
+    /// First gid of the collection of legacy tiles, after every sprite
+    legacy_gid: u32,
+    legacy_tiles: Vec<(LegacyTileKey, TiledImage)>,
+    images: Vec<(String, RgbaImage)>,
+    next_object_id: u32,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(file: &'a FormFile, entry: &RoomEntry) -> Self {
+        let mut exporter = Exporter {
+            file,
+            tile_size: (DEFAULT_TILE_SIZE, DEFAULT_TILE_SIZE),
+            backgrounds: Vec::new(),
+            sprites_gid: 0,
+            sprite_tiles: Vec::new(),
+            legacy_gid: 0,
+            legacy_tiles: Vec::new(),
+            images: Vec::new(),
+            next_object_id: 1,
+        };
+
+        // the tilesets of tile layers come first, so their gids are known before any others
+        let mut next_gid = 1;
+        for layer in &entry.layers {
+            if let LayerKind::Tiles { background_index, .. } = layer.kind {
+                if exporter.backgrounds.iter().any(|&(index, _)| index == background_index) {
+                    continue;
+                }
+                if let Some(tileset) = exporter.background_tileset(background_index, next_gid) {
+                    if exporter.backgrounds.is_empty() {
+                        exporter.tile_size = (tileset.tile_width.max(1), tileset.tile_height.max(1));
+                    }
+                    next_gid += match tileset.kind {
+                        TilesetKind::Image { tile_count, .. } => tile_count,
+                        TilesetKind::Collection(_) => 0,
+                    };
+                    exporter.backgrounds.push((background_index, tileset));
+                }
+            }
+        }
+
+        let sprite_count = file.sprt.as_ref().map(|sprt| sprt.sprites.len()).unwrap_or(0) as u32;
+        exporter.sprites_gid = next_gid;
+        exporter.legacy_gid = next_gid + sprite_count;
+        exporter
+    }
+
+    fn name(&self, offset: StringRef) -> String {
+        self.file.resolve(offset)
+            .map(str::to_owned)
+            .unwrap_or_default()
+    }
+
+    fn add_image(&mut self, source: &str, image: RgbaImage) -> TiledImage {
+        let tiled_image = TiledImage { source: source.to_owned(), width: image.width(), height: image.height() };
+        if !self.images.iter().any(|(other, _)| other == source) {
+            self.images.push((source.to_owned(), image));
+        }
+        tiled_image
+    }
+
+    fn background_image(&mut self, background_index: i32) -> Option<(TiledImage, RgbaImage)> {
+        let background = self.file.backgrounds().get(usize::try_from(background_index).ok()?)?;
+        if background.tpag_offset == TpagRef(0) {
+            return None;
+        }
+        let image = self.file.get_tpag_subimage(background.tpag_offset);
+        let source = format!("{}.png", self.name(background.name_offset));
+        Some((self.add_image(&source, image.clone()), image))
+    }
+
+    fn background_tileset(&mut self, background_index: i32, first_gid: u32) -> Option<Tileset> {
+        let background = self.file.backgrounds().get(usize::try_from(background_index).ok()?)?;
+        let tile_set = background.tile_set.clone()?;
+        let name = self.name(background.name_offset);
+        let (image, _) = self.background_image(background_index)?;
+        Some(Tileset {
+            first_gid,
+            name,
+            tile_width: tile_set.tile_width,
+            tile_height: tile_set.tile_height,
+            kind: TilesetKind::Image {
+                image,
+                margin: tile_set.border_x,
+                spacing: tile_set.border_x * 2,
+                columns: tile_set.columns,
+                tile_count: tile_set.tile_count,
+            },
+        })
+    }
+
+    /// Gid of the first frame of a sprite, adding it to the collection if it isn't there yet
+    fn sprite_gid(&mut self, sprite_index: i32) -> Option<u32> {
+        let index = u32::try_from(sprite_index).ok()?;
+        if self.sprite_tiles.iter().any(|&(id, _)| id == index) {
+            return Some(self.sprites_gid + index);
+        }
+
+        let sprite = self.file.sprt.as_ref()?.sprites.get(index as usize)?;
+        let image = self.file.get_sprite_frame(index as usize, 0)?;
+        let source = format!("{}.png", self.name(sprite.name_offset));
+        let image = self.add_image(&source, image);
+        self.sprite_tiles.push((index, image));
+        Some(self.sprites_gid + index)
+    }
+
+    /// Gid of the part of a background a legacy tile shows
+    fn legacy_tile_gid(&mut self, tile: &Tile) -> Option<u32> {
+        let key = (tile.bg_def_index, tile.source_x, tile.source_y, tile.width, tile.height);
+        if let Some(id) = self.legacy_tiles.iter().position(|(other, _)| *other == key) {
+            return Some(self.legacy_gid + id as u32);
+        }
+
+        let (background, image) = self.background_image(tile.bg_def_index)?;
+        let (x, y) = (u32::try_from(tile.source_x).ok()?, u32::try_from(tile.source_y).ok()?);
+        if x + tile.width > image.width() || y + tile.height > image.height() {
+            return None;
+        }
+        let part = image.view(x, y, tile.width, tile.height).to_image();
+        let stem = background.source.trim_end_matches(".png");
+        let source = format!("{}_{}_{}_{}x{}.png", stem, x, y, tile.width, tile.height);
+        let part = self.add_image(&source, part);
+        self.legacy_tiles.push((key, part));
+        Some(self.legacy_gid + self.legacy_tiles.len() as u32 - 1)
+    }
+
+    fn tilesets(&self) -> Vec<Tileset> {
+        let collection = |first_gid: u32, name: &str, tiles: Vec<(u32, TiledImage)>| Tileset {
+            first_gid,
+            name: name.to_owned(),
+            tile_width: tiles.iter().map(|(_, image)| image.width).max().unwrap_or(0),
+            tile_height: tiles.iter().map(|(_, image)| image.height).max().unwrap_or(0),
+            kind: TilesetKind::Collection(tiles),
+        };
+
+        let mut tilesets = self.backgrounds.iter().map(|(_, tileset)| tileset.clone()).collect::<Vec<_>>();
+        if !self.sprite_tiles.is_empty() {
+            let mut sprites = self.sprite_tiles.clone();
+            sprites.sort_by_key(|&(id, _)| id);
+            tilesets.push(collection(self.sprites_gid, "sprites", sprites));
+        }
+        if !self.legacy_tiles.is_empty() {
+            let tiles = self.legacy_tiles.iter()
+                .enumerate()
+                .map(|(id, (_, image))| (id as u32, image.clone()))
+                .collect();
+            tilesets.push(collection(self.legacy_gid, "legacy_tiles", tiles));
+        }
+        tilesets
+    }
+
+    fn object_id(&mut self) -> u32 {
+        self.next_object_id += 1;
+        self.next_object_id - 1
+    }
+
+    /// A tile object showing a sprite drawn at `position` the way GameMaker draws it, with its
+    /// origin at `position`, scaled and then rotated `angle` degrees anticlockwise around it
+    fn sprite_object(&mut self, sprite_index: i32, position: (f32, f32), scale: (f32, f32), angle: f32) -> TiledObject {
+        let (x, y) = position;
+        let mut object = TiledObject {
+            id: self.object_id(),
+            name: String::new(),
+            kind: String::new(),
+            gid: None,
+            x,
+            y,
+            width: 0.0,
+            height: 0.0,
+            rotation: 0.0,
+            properties: Vec::new(),
+        };
+        let gid = match self.sprite_gid(sprite_index) {
+            Some(gid) => gid,
+            None => {
+                // a point can't show scale or rotation, so keep them as properties
+                if scale != (1.0, 1.0) {
+                    object.properties.push((String::from("scale_x"), PropertyValue::Float(scale.0 as f64)));
+                    object.properties.push((String::from("scale_y"), PropertyValue::Float(scale.1 as f64)));
+                }
+                if angle != 0.0 {
+                    object.properties.push((String::from("rotation"), PropertyValue::Float(angle as f64)));
+                }
+                return object;
+            }
+        };
+
+        let sprite = &self.file.sprt.as_ref().unwrap().sprites[sprite_index as usize];
+        let image = &self.sprite_tiles.iter().find(|&&(id, _)| id == sprite_index as u32).unwrap().1;
+        let (width, height) = (image.width as f32, image.height as f32);
+        let (origin_x, origin_y) = (sprite.origin.0 as f32, sprite.origin.1 as f32);
+        let (corner_x, corner_y) = sprite_corner((width, height), (origin_x, origin_y), scale, angle);
+
+        let mut flags = 0;
+        if scale.0 < 0.0 {
+            flags |= FLIPPED_HORIZONTALLY;
+        }
+        if scale.1 < 0.0 {
+            flags |= FLIPPED_VERTICALLY;
+        }
+        object.gid = Some(gid | flags);
+        object.x = round(x + corner_x);
+        object.y = round(y + corner_y);
+        object.width = round(width * scale.0.abs());
+        object.height = round(height * scale.1.abs());
+        object.rotation = round((-angle).rem_euclid(360.0));
+        object
+    }
+
+    fn instance_object(&mut self, instance: &GameObject) -> TiledObject {
+        let file = self.file;
+        let object = file.objects().get(instance.bg_def_index as usize);
+        let sprite_index = match object {
+            Some(_) => file.effective_object(instance.bg_def_index as usize).sprite_index,
+            None => -1,
+        };
+        let mut tiled = self.sprite_object(
+            sprite_index,
+            (instance.x as f32, instance.y as f32),
+            (instance.scale_x, instance.scale_y),
+            instance.rotation,
+        );
+        tiled.kind = object.map(|object| self.name(object.name_offset)).unwrap_or_default();
+        tiled.properties.insert(0, (String::from("instance_id"), PropertyValue::Int(instance.instance_id as i64)));
+        if instance.creation_code_id >= 0 {
+            let code = file.code_entries().get(instance.creation_code_id as usize)
+                .map(|entry| self.name(entry.name_offset))
+                .unwrap_or_default();
+            tiled.properties.push((String::from("creation_code"), PropertyValue::String(code)));
+        }
+        if instance.image_speed != 1.0 {
+            tiled.properties.push((String::from("image_speed"), PropertyValue::Float(instance.image_speed as f64)));
+        }
+        if instance.frame != 0 {
+            tiled.properties.push((String::from("frame"), PropertyValue::Int(instance.frame as i64)));
+        }
+        push_color(&mut tiled.properties, &instance.color);
+        tiled
+    }
+
+    fn legacy_tile_object(&mut self, tile: &Tile) -> TiledObject {
+        let gid = self.legacy_tile_gid(tile);
+        TiledObject {
+            id: self.object_id(),
+            name: String::new(),
+            kind: String::new(),
+            gid,
+            x: tile.x as f32,
+            y: round(tile.y as f32 + tile.height as f32 * tile.scale_y),
+            width: round(tile.width as f32 * tile.scale_x),
+            height: round(tile.height as f32 * tile.scale_y),
+            rotation: 0.0,
+            properties: vec![
+                (String::from("depth"), PropertyValue::Int(tile.tile_depth as i64)),
+                (String::from("instance_id"), PropertyValue::Int(tile.instance_id as i64)),
+            ],
+        }
+    }
+
+    fn layer(&mut self, entry: &RoomEntry, layer: &Layer) -> TiledLayer {
+        let mut properties = vec![(String::from("depth"), PropertyValue::Int(layer.depth as i64))];
+        if layer.horizontal_speed != 0.0 || layer.vertical_speed != 0.0 {
+            properties.push((String::from("hspeed"), PropertyValue::Float(layer.horizontal_speed as f64)));
+            properties.push((String::from("vspeed"), PropertyValue::Float(layer.vertical_speed as f64)));
+        }
+
+        let kind = match &layer.kind {
+            LayerKind::Instances { .. } => {
+                let instances = entry.layer_instances(layer).collect::<Vec<_>>();
+                TiledLayerKind::Objects(instances.into_iter().map(|instance| self.instance_object(instance)).collect())
+            }
+            LayerKind::Tiles { background_index, width, height, tiles } => {
+                let first_gid = self.backgrounds.iter()
+                    .find(|&&(index, _)| index == *background_index)
+                    .map(|(_, tileset)| tileset.first_gid);
+                let data = tiles.iter()
+                    .map(|&tile| first_gid.map(|first_gid| tile_gid(first_gid, tile)).unwrap_or(0))
+                    .collect();
+                TiledLayerKind::Tiles { width: *width, height: *height, data }
+            }
+            LayerKind::Assets(assets) => {
+                let mut objects = Vec::new();
+                for tile in &assets.legacy_tiles {
+                    objects.push(self.legacy_tile_object(tile));
+                }
+                for instance in &assets.sprites {
+                    let mut object = self.sprite_object(
+                        instance.asset_index,
+                        (instance.x as f32, instance.y as f32),
+                        (instance.scale_x, instance.scale_y),
+                        instance.rotation,
+                    );
+                    object.name = self.name(instance.name_offset);
+                    object.kind = self.file.sprt.as_ref()
+                        .and_then(|sprt| sprt.sprites.get(instance.asset_index as usize))
+                        .map(|sprite| self.name(sprite.name_offset))
+                        .unwrap_or_default();
+                    if instance.animation_speed != 1.0 {
+                        object.properties.push((String::from("animation_speed"), PropertyValue::Float(instance.animation_speed as f64)));
+                    }
+                    if instance.frame_index != 0.0 {
+                        object.properties.push((String::from("frame"), PropertyValue::Float(instance.frame_index as f64)));
+                    }
+                    push_color(&mut object.properties, &instance.color);
+                    objects.push(object);
+                }
+                TiledLayerKind::Objects(objects)
+            }
+            LayerKind::Background { visible, foreground, sprite_index, horizontal_tile, vertical_tile, stretch, color, .. } => {
+                if !visible {
+                    properties.push((String::from("background_visible"), PropertyValue::Bool(false)));
+                }
+                if *foreground {
+                    properties.push((String::from("foreground"), PropertyValue::Bool(true)));
+                }
+                if *stretch {
+                    properties.push((String::from("stretch"), PropertyValue::Bool(true)));
+                }
+                push_color(&mut properties, color);
+                let image = self.sprite_gid(*sprite_index)
+                    .map(|_| self.sprite_tiles.iter().find(|&&(id, _)| id == *sprite_index as u32).unwrap().1.clone());
+                TiledLayerKind::Image { image, repeat_x: *horizontal_tile, repeat_y: *vertical_tile }
+            }
+            LayerKind::Path | LayerKind::Effect { .. } | LayerKind::Unknown { .. } => {
+                let kind = match &layer.kind {
+                    LayerKind::Path => String::from("path"),
+                    LayerKind::Effect { effect_type_offset, .. } => {
+                        properties.push((String::from("effect"), PropertyValue::String(self.name(*effect_type_offset))));
+                        String::from("effect")
+                    }
+                    _ => layer.kind.id().to_string(),
+                };
+                properties.push((String::from("kind"), PropertyValue::String(kind)));
+                TiledLayerKind::Objects(Vec::new())
+            }
+        };
+
+        TiledLayer {
+            name: self.name(layer.name_offset),
+            offset: (layer.x_offset, layer.y_offset),
+            visible: layer.is_visible,
+            properties,
+            kind,
+        }
+    }
+
+    /// Layers for a room without any, made from its backgrounds, tiles and instances
+    fn legacy_layers(&mut self, entry: &RoomEntry) -> Vec<TiledLayer> {
+        let layer = |name: String, kind| TiledLayer {
+            name,
+            offset: (0.0, 0.0),
+            visible: true,
+            properties: Vec::new(),
+            kind,
+        };
+
+        let mut backgrounds = Vec::new();
+        let mut foregrounds = Vec::new();
+        for (i, background) in entry.backgrounds.iter().enumerate() {
+            if !background.enabled {
+                continue;
+            }
+            let image = self.background_image(background.bg_def_index as i32).map(|(image, _)| image);
+            let mut background_layer = layer(format!("background_{}", i), TiledLayerKind::Image {
+                image,
+                repeat_x: background.tile_x,
+                repeat_y: background.tile_y,
+            });
+            background_layer.offset = (background.x as f32, background.y as f32);
+            if background.foreground {
+                foregrounds.push(background_layer);
+            } else {
+                backgrounds.push(background_layer);
+            }
+        }
+
+        let tiles = entry.tiles.iter().map(|tile| self.legacy_tile_object(tile)).collect();
+        let instances = entry.game_objects.iter().map(|instance| self.instance_object(instance)).collect();
+        backgrounds.push(layer(String::from("Tiles"), TiledLayerKind::Objects(tiles)));
+        backgrounds.push(layer(String::from("Instances"), TiledLayerKind::Objects(instances)));
+        backgrounds.extend(foregrounds);
+        backgrounds
+    }
+}
+
+/// Where the bottom left corner of a sprite ends up relative to its origin, once it's been
+/// scaled and rotated `angle` degrees anticlockwise around it
+pub(super) fn sprite_corner((width, height): (f32, f32), (origin_x, origin_y): (f32, f32), (scale_x, scale_y): (f32, f32), angle: f32) -> (f32, f32) {
+    let left = (-origin_x * scale_x).min((width - origin_x) * scale_x);
+    let bottom = (-origin_y * scale_y).max((height - origin_y) * scale_y);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    (left * cos + bottom * sin, -left * sin + bottom * cos)
+}
+
+/// The gid of a tile in a tile layer, turning GameMaker's mirror, flip and rotate (which is
+/// applied last) into Tiled's flags
+fn tile_gid(first_gid: u32, tile: u32) -> u32 {
+    let index = tile & TILE_INDEX_MASK;
+    if index == 0 {
+        return 0;
+    }
+
+    let (mirror, flip) = (tile & TILE_MIRROR != 0, tile & TILE_FLIP != 0);
+    let (horizontal, vertical, diagonal) = if tile & TILE_ROTATE != 0 {
+        (!flip, mirror, true)
+    } else {
+        (mirror, flip, false)
+    };
+    let mut gid = first_gid + index;
+    if horizontal {
+        gid |= FLIPPED_HORIZONTALLY;
+    }
+    if vertical {
+        gid |= FLIPPED_VERTICALLY;
+    }
+    if diagonal {
+        gid |= FLIPPED_DIAGONALLY;
+    }
+    gid
+}
+
+fn push_color(properties: &mut Properties, color: &RgbaColor) {
+    let argb = u32::from_be_bytes([color.a, color.r, color.g, color.b]);
+    if argb != 0xffff_ffff {
+        properties.push((String::from("color"), PropertyValue::Color(argb)));
+    }
+}
+
+/// Drop the noise rotating leaves, so positions stay whole numbers where they can
+fn round(value: f32) -> f32 {
+    let rounded = (value * 1000.0).round() / 1000.0;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
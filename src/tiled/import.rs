@@ -0,0 +1,722 @@
+use super::*;
+use super::export::sprite_corner;
+use super::read::file_stem;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use crate::file_structs::{
+    AssetInstance, AssetLayer, FormFile, GameObject, Layer, LayerKind, RgbaColor, RoomEntry, SpeedUnit, StringRef,
+    Tile, TILE_FLIP, TILE_INDEX_MASK, TILE_MIRROR, TILE_ROTATE,
+};
+
+/// How much nearer than the layer below it a new layer goes, if it has no `depth` property
+const LAYER_DEPTH_STEP: i32 = 100;
+
+/// Depth GMS1 gives tiles
+const DEFAULT_TILE_DEPTH: i32 = 1_000_000;
+
+const WHITE: RgbaColor = RgbaColor { r: 0xff, g: 0xff, b: 0xff, a: 0xff };
+
+const FLIPPED: u32 = FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY;
+
+/// Update the room at `room` from a Tiled map laid out the way [`export_room`] writes them.
+///
+/// Layers are matched to the room's layers by name. Layers the map doesn't have are removed,
+/// along with their instances, and new ones become tile, instance or background layers (or
+/// path and asset layers, with a `kind` property of `path` or `assets`). Instances and legacy
+/// tiles are matched by their `instance_id` property, and ones without it (or copies of
+/// another) get a new id. The type of an instance is its object's name, and the type of a
+/// sprite on an asset layer is the sprite's name.
+///
+/// The room and its strings are left as they were if the map can't be imported.
+pub fn import_room(file: &mut FormFile, room: usize, map: &TiledMap) -> Result<(), TiledError> {
+    let old = file.rooms().get(room).cloned()
+        .ok_or_else(|| TiledError::new(format!("there is no room {}", room)))?;
+    let strings = file.strg.clone()
+        .ok_or_else(|| TiledError::new("the file has no STRG section to name layers in"))?;
+
+    let mut importer = Importer::new(file, map);
+    let first_ids = (importer.last_instance_id, importer.last_tile_id);
+    let entry = if old.layers.is_empty() {
+        importer.legacy_room(&old)
+    } else {
+        importer.room(&old)
+    };
+    let (last_instance_id, last_tile_id) = (importer.last_instance_id, importer.last_tile_id);
+    let mut entry = match entry {
+        Ok(entry) => entry,
+        Err(err) => {
+            file.strg = Some(strings);
+            return Err(err);
+        }
+    };
+    update_room_fields(&mut entry, &old, map);
+
+    file.room.as_mut().unwrap().entries[room] = entry;
+    if let Some(gen8) = file.gen8.as_mut() {
+        if last_instance_id != first_ids.0 {
+            gen8.last_instance_id = last_instance_id as u32;
+        }
+        if last_tile_id != first_ids.1 {
+            gen8.last_tile_id = last_tile_id as u32;
+        }
+    }
+    Ok(())
+}
+
+/// Where GameMaker draws a sprite: at its origin, scaled and then rotated `angle` degrees
+/// anticlockwise around it
+struct Placement {
+    x: i32,
+    y: i32,
+    scale: (f32, f32),
+    angle: f32,
+}
+
+struct Importer<'a> {
+    file: &'a mut FormFile,
+    map: &'a TiledMap,
+    /// Ids given to the instances and tiles imported so far, so copies get new ones
+    used_instance_ids: HashSet<i32>,
+    used_tile_ids: HashSet<i32>,
+    /// Highest id used by any room, which new ids count up from
+    last_instance_id: i32,
+    last_tile_id: i32,
+}
+
+impl<'a> Importer<'a> {
+    fn new(file: &'a mut FormFile, map: &'a TiledMap) -> Self {
+        let gen8 = file.gen8();
+        let last_instance_id = file.rooms().iter()
+            .flat_map(|room| &room.game_objects)
+            .map(|instance| instance.instance_id)
+            .chain(gen8.map(|gen8| gen8.last_instance_id as i32))
+            .max()
+            .unwrap_or(0);
+        let last_tile_id = file.rooms().iter()
+            .flat_map(|room| room.tiles.iter().chain(room.layers.iter().flat_map(|layer| match &layer.kind {
+                LayerKind::Assets(assets) => &assets.legacy_tiles[..],
+                _ => &[],
+            })))
+            .map(|tile| tile.instance_id)
+            .chain(gen8.map(|gen8| gen8.last_tile_id as i32))
+            .max()
+            .unwrap_or(0);
+
+        Importer {
+            file,
+            map,
+            used_instance_ids: HashSet::new(),
+            used_tile_ids: HashSet::new(),
+            last_instance_id,
+            last_tile_id,
+        }
+    }
+
+    fn name(&self, offset: StringRef) -> String {
+        self.file.resolve(offset)
+            .map(str::to_owned)
+            .unwrap_or_default()
+    }
+
+    fn intern(&mut self, name: &str) -> StringRef {
+        self.file.strg.as_mut().unwrap().intern(name)
+    }
+
+    /// Index of the entry whose name is at one of `offsets`
+    fn object_index(&self, layer: &TiledLayer, object: &TiledObject) -> Result<i32, TiledError> {
+        self.file.object(&object.kind)
+            .map(|object| object.id.0 as i32)
+            .ok_or_else(|| object_error(layer, object, &format!("there is no object named '{}'", object.kind)))
+    }
+
+    fn sprite_index(&self, name: &str) -> Option<i32> {
+        self.file.sprite(name).map(|sprite| sprite.id.0 as i32)
+    }
+
+    fn background_index(&self, name: &str) -> Option<i32> {
+        self.file.background(name).map(|background| background.id.0 as i32)
+    }
+
+    fn code_index(&self, name: &str) -> Option<i32> {
+        self.file.code_entry(name).map(|entry| entry.id.0 as i32)
+    }
+
+    /// The size of the tile a tile object shows, and the sprite it's the first frame of if
+    /// it's from the collection of sprites
+    fn tile_of(&self, gid: u32) -> Option<((u32, u32), Option<i32>)> {
+        let tileset = self.map.tileset_of(gid)?;
+        let id = (gid & !FLIPPED).checked_sub(tileset.first_gid)?;
+        let size = match &tileset.kind {
+            TilesetKind::Image { .. } => (tileset.tile_width, tileset.tile_height),
+            TilesetKind::Collection(tiles) => {
+                let (_, image) = tiles.iter().find(|&&(other, _)| other == id)?;
+                (image.width, image.height)
+            }
+        };
+        let sprite = Some(id as i32).filter(|_| tileset.name == "sprites");
+        Some((size, sprite))
+    }
+
+    /// Where GameMaker should draw an object, undoing what exporting a sprite as a tile object
+    /// does. Points keep their scale and rotation in properties.
+    fn placement(&self, object: &TiledObject) -> Placement {
+        let tile = object.gid.and_then(|gid| Some((gid, self.tile_of(gid)?)));
+        let (gid, ((width, height), sprite)) = match tile {
+            Some(tile) => tile,
+            None => return Placement {
+                x: object.x.round() as i32,
+                y: object.y.round() as i32,
+                scale: (
+                    float_property(&object.properties, "scale_x").unwrap_or(1.0),
+                    float_property(&object.properties, "scale_y").unwrap_or(1.0),
+                ),
+                angle: float_property(&object.properties, "rotation").unwrap_or(0.0),
+            },
+        };
+
+        let origin = sprite
+            .and_then(|index| self.file.sprt.as_ref()?.sprites.get(index as usize))
+            .map(|sprite| (sprite.origin.0 as f32, sprite.origin.1 as f32))
+            .unwrap_or((0.0, 0.0));
+        let scale = |size: f32, width: u32, flipped: bool| {
+            let scale = if width == 0 { 1.0 } else { size / width as f32 };
+            if flipped { -scale } else { scale }
+        };
+        let scale = (
+            scale(object.width, width, gid & FLIPPED_HORIZONTALLY != 0),
+            scale(object.height, height, gid & FLIPPED_VERTICALLY != 0),
+        );
+        let angle = (-object.rotation).rem_euclid(360.0);
+        // a rotation of -0 would write different bytes than the 0 it was exported from
+        let angle = if angle == 0.0 { 0.0 } else { angle };
+        let (corner_x, corner_y) = sprite_corner((width as f32, height as f32), origin, scale, angle);
+        Placement {
+            x: (object.x - corner_x).round() as i32,
+            y: (object.y - corner_y).round() as i32,
+            scale,
+            angle,
+        }
+    }
+
+    fn instance_id(&mut self, id: Option<i32>) -> i32 {
+        let id = id.filter(|id| !self.used_instance_ids.contains(id)).unwrap_or_else(|| {
+            self.last_instance_id += 1;
+            self.last_instance_id
+        });
+        self.used_instance_ids.insert(id);
+        id
+    }
+
+    fn tile_id(&mut self, id: Option<i32>) -> i32 {
+        let id = id.filter(|id| !self.used_tile_ids.contains(id)).unwrap_or_else(|| {
+            self.last_tile_id += 1;
+            self.last_tile_id
+        });
+        self.used_tile_ids.insert(id);
+        id
+    }
+
+    fn game_object(&mut self, layer: &TiledLayer, object: &TiledObject, old: &RoomEntry) -> Result<GameObject, TiledError> {
+        let existing = int_property(&object.properties, "instance_id")
+            .map(|id| id as i32)
+            .filter(|id| !self.used_instance_ids.contains(id))
+            .and_then(|id| old.game_objects.iter().find(|instance| instance.instance_id == id));
+        let bg_def_index = match existing {
+            Some(existing) if object.kind.is_empty() => existing.bg_def_index,
+            _ if object.kind.is_empty() => return Err(object_error(layer, object, "it has no type to tell which object it is")),
+            _ => self.object_index(layer, object)?,
+        };
+        let creation_code_id = match string_property(&object.properties, "creation_code") {
+            Some(name) if !name.is_empty() => self.code_index(name)
+                .ok_or_else(|| object_error(layer, object, &format!("there is no code entry named '{}'", name)))?,
+            _ => -1,
+        };
+
+        let placement = self.placement(object);
+        Ok(GameObject {
+            x: placement.x,
+            y: placement.y,
+            bg_def_index,
+            instance_id: self.instance_id(existing.map(|instance| instance.instance_id)),
+            creation_code_id,
+            scale_x: placement.scale.0,
+            scale_y: placement.scale.1,
+            image_speed: float_property(&object.properties, "image_speed").unwrap_or(1.0),
+            frame: int_property(&object.properties, "frame").and_then(|frame| u32::try_from(frame).ok()).unwrap_or(0),
+            color: color_property(&object.properties, "color").unwrap_or(WHITE),
+            rotation: placement.angle,
+            vari_index: existing.map(|instance| instance.vari_index).unwrap_or(-1),
+        })
+    }
+
+    /// Whether an object on an asset layer (or the Tiles layer of a GMS1 room) is a legacy tile
+    /// rather than a sprite
+    fn is_legacy_tile(&self, object: &TiledObject) -> bool {
+        property(&object.properties, "instance_id").is_some() || object.gid
+            .and_then(|gid| self.map.tileset_of(gid))
+            .map(|tileset| tileset.name == "legacy_tiles")
+            .unwrap_or(false)
+    }
+
+    /// The background and part of it a legacy tile object shows, from the name exporting it
+    /// gave its image: `<background>_<x>_<y>_<width>x<height>.png`
+    fn legacy_tile_source(&self, object: &TiledObject) -> Option<(i32, i32, i32, u32, u32)> {
+        let gid = object.gid?;
+        let tileset = self.map.tileset_of(gid)?;
+        let id = (gid & !FLIPPED).checked_sub(tileset.first_gid)?;
+        let source = match &tileset.kind {
+            TilesetKind::Collection(tiles) => &tiles.iter().find(|&&(other, _)| other == id)?.1.source,
+            TilesetKind::Image { .. } => return None,
+        };
+
+        let mut parts = file_stem(source).rsplitn(4, '_');
+        let mut size = parts.next()?.splitn(2, 'x');
+        let (width, height) = (size.next()?.parse().ok()?, size.next()?.parse().ok()?);
+        let y = parts.next()?.parse().ok()?;
+        let x = parts.next()?.parse().ok()?;
+        let background = self.background_index(parts.next()?)?;
+        Some((background, x, y, width, height))
+    }
+
+    fn legacy_tile(&mut self, layer: &TiledLayer, object: &TiledObject, old_tiles: &[Tile]) -> Result<Tile, TiledError> {
+        let existing = int_property(&object.properties, "instance_id")
+            .map(|id| id as i32)
+            .filter(|id| !self.used_tile_ids.contains(id))
+            .and_then(|id| old_tiles.iter().find(|tile| tile.instance_id == id));
+        let source = self.legacy_tile_source(object)
+            .or_else(|| existing.map(|tile| (tile.bg_def_index, tile.source_x, tile.source_y, tile.width, tile.height)));
+        let (bg_def_index, source_x, source_y, width, height) = source
+            .ok_or_else(|| object_error(layer, object, "it isn't a tile from the legacy_tiles tileset"))?;
+
+        let scale = |size: f32, tile_size: u32| if tile_size == 0 { 1.0 } else { size / tile_size as f32 };
+        Ok(Tile {
+            x: object.x.round() as i32,
+            // tile objects are placed by their bottom left corner
+            y: (object.y - object.height).round() as i32,
+            bg_def_index,
+            source_x,
+            source_y,
+            width,
+            height,
+            tile_depth: int_property(&object.properties, "depth")
+                .map(|depth| depth as i32)
+                .or_else(|| existing.map(|tile| tile.tile_depth))
+                .unwrap_or(DEFAULT_TILE_DEPTH),
+            instance_id: self.tile_id(existing.map(|tile| tile.instance_id)),
+            scale_x: scale(object.width, width),
+            scale_y: scale(object.height, height),
+            argb_tint: existing.map(|tile| tile.argb_tint).unwrap_or(0xffff_ffff),
+        })
+    }
+
+    fn asset_instance(&mut self, layer: &TiledLayer, object: &TiledObject, old_sprites: &[AssetInstance]) -> Result<AssetInstance, TiledError> {
+        let existing = old_sprites.iter()
+            .find(|sprite| !object.name.is_empty() && self.name(sprite.name_offset) == object.name);
+        let asset_index = if !object.kind.is_empty() {
+            self.sprite_index(&object.kind)
+                .ok_or_else(|| object_error(layer, object, &format!("there is no sprite named '{}'", object.kind)))?
+        } else {
+            object.gid
+                .and_then(|gid| self.tile_of(gid)?.1)
+                .or_else(|| existing.map(|sprite| sprite.asset_index))
+                .ok_or_else(|| object_error(layer, object, "it has no type to tell which sprite it is"))?
+        };
+        let name = match &object.name[..] {
+            "" => format!("graphic_{:08X}", object.id),
+            name => name.to_owned(),
+        };
+
+        let placement = self.placement(object);
+        Ok(AssetInstance {
+            name_offset: self.intern(&name),
+            asset_index,
+            x: placement.x,
+            y: placement.y,
+            scale_x: placement.scale.0,
+            scale_y: placement.scale.1,
+            color: color_property(&object.properties, "color").unwrap_or(WHITE),
+            animation_speed: float_property(&object.properties, "animation_speed").unwrap_or(1.0),
+            animation_speed_unit: existing
+                .map(|sprite| sprite.animation_speed_unit.clone())
+                .unwrap_or(SpeedUnit::FramesPerGameFrame),
+            frame_index: float_property(&object.properties, "frame").unwrap_or(0.0),
+            rotation: placement.angle,
+        })
+    }
+
+    fn asset_layer(&mut self, layer: &TiledLayer, objects: &[TiledObject], old: &AssetLayer) -> Result<AssetLayer, TiledError> {
+        let mut legacy_tiles = Vec::new();
+        let mut sprites = Vec::new();
+        for object in objects {
+            if self.is_legacy_tile(object) {
+                legacy_tiles.push(self.legacy_tile(layer, object, &old.legacy_tiles)?);
+            } else {
+                sprites.push(self.asset_instance(layer, object, &old.sprites)?);
+            }
+        }
+        Ok(AssetLayer {
+            legacy_tiles,
+            sprites,
+            sequences: old.sequences.clone(),
+            nine_slices: old.nine_slices.clone(),
+        })
+    }
+
+    /// An empty asset layer, with the lists asset layers have in this version
+    fn new_asset_layer(&self, room: &RoomEntry) -> AssetLayer {
+        let existing = self.file.rooms().iter()
+            .flat_map(|room| &room.layers)
+            .find_map(|layer| match &layer.kind {
+                LayerKind::Assets(assets) => Some(assets),
+                _ => None,
+            });
+        let empty = |list: &Option<Vec<AssetInstance>>| list.as_ref().map(|_| Vec::new());
+        match existing {
+            Some(assets) => AssetLayer {
+                sequences: empty(&assets.sequences),
+                nine_slices: empty(&assets.nine_slices),
+                ..AssetLayer::default()
+            },
+            None if room.has_sequences => AssetLayer {
+                sequences: Some(Vec::new()),
+                nine_slices: Some(Vec::new()),
+                ..AssetLayer::default()
+            },
+            None => AssetLayer::default(),
+        }
+    }
+
+    fn tile_data(&self, layer: &TiledLayer, data: &[u32], background_index: i32) -> Result<(i32, Vec<u32>), TiledError> {
+        let tileset = match data.iter().find(|&&gid| gid != 0) {
+            Some(&gid) => self.map.tileset_of(gid)
+                .ok_or_else(|| layer_error(layer, &format!("no tileset has gid {}", gid & !FLIPPED)))?,
+            None => return Ok((background_index, vec![0; data.len()])),
+        };
+        let background_index = self.background_index(&tileset.name)
+            .ok_or_else(|| layer_error(layer, &format!("there is no background named '{}' for its tileset", tileset.name)))?;
+
+        let tiles = data.iter()
+            .map(|&gid| {
+                if gid == 0 {
+                    return Ok(0);
+                }
+                if self.map.tileset_of(gid).map(|other| other.first_gid) != Some(tileset.first_gid) {
+                    return Err(layer_error(layer, "its tiles come from more than one tileset"));
+                }
+                let index = (gid & !FLIPPED) - tileset.first_gid;
+                if index > TILE_INDEX_MASK {
+                    return Err(layer_error(layer, &format!("tile {} is past the last tile a layer can use", index)));
+                }
+
+                // the inverse of how exporting turns GameMaker's flags into Tiled's
+                let (horizontal, vertical) = (gid & FLIPPED_HORIZONTALLY != 0, gid & FLIPPED_VERTICALLY != 0);
+                let (mirror, flip, rotate) = if gid & FLIPPED_DIAGONALLY != 0 {
+                    (vertical, !horizontal, true)
+                } else {
+                    (horizontal, vertical, false)
+                };
+                let mut tile = index;
+                if mirror {
+                    tile |= TILE_MIRROR;
+                }
+                if flip {
+                    tile |= TILE_FLIP;
+                }
+                if rotate {
+                    tile |= TILE_ROTATE;
+                }
+                Ok(tile)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((background_index, tiles))
+    }
+
+    fn layer_kind(&mut self, layer: &TiledLayer, existing: Option<&Layer>, old: &RoomEntry, instances: &mut Vec<GameObject>) -> Result<LayerKind, TiledError> {
+        let wrong_kind = || layer_error(layer, "it's a different kind of layer in the room");
+        let existing = existing.map(|existing| &existing.kind);
+        let properties = &layer.properties;
+
+        match &layer.kind {
+            TiledLayerKind::Tiles { width, height, data } => {
+                let background_index = match existing {
+                    Some(LayerKind::Tiles { background_index, .. }) => *background_index,
+                    Some(_) => return Err(wrong_kind()),
+                    None => -1,
+                };
+                let (background_index, tiles) = self.tile_data(layer, data, background_index)?;
+                Ok(LayerKind::Tiles { background_index, width: *width, height: *height, tiles })
+            }
+            TiledLayerKind::Image { image, repeat_x, repeat_y } => {
+                let (first_frame, animation_speed, animation_speed_unit) = match existing {
+                    Some(LayerKind::Background { first_frame, animation_speed, animation_speed_unit, .. }) => {
+                        (*first_frame, *animation_speed, animation_speed_unit.clone())
+                    }
+                    Some(_) => return Err(wrong_kind()),
+                    None => (0.0, 15.0, SpeedUnit::FramesPerSecond),
+                };
+                let sprite_index = match image {
+                    Some(image) => {
+                        let name = file_stem(&image.source);
+                        self.sprite_index(name)
+                            .ok_or_else(|| layer_error(layer, &format!("there is no sprite named '{}' for its image", name)))?
+                    }
+                    None => -1,
+                };
+                Ok(LayerKind::Background {
+                    visible: bool_property(properties, "background_visible").unwrap_or(true),
+                    foreground: bool_property(properties, "foreground").unwrap_or(false),
+                    sprite_index,
+                    horizontal_tile: *repeat_x,
+                    vertical_tile: *repeat_y,
+                    stretch: bool_property(properties, "stretch").unwrap_or(false),
+                    color: color_property(properties, "color").unwrap_or(WHITE),
+                    first_frame,
+                    animation_speed,
+                    animation_speed_unit,
+                })
+            }
+            TiledLayerKind::Objects(objects) => match existing {
+                Some(LayerKind::Instances { .. }) => self.instance_layer(layer, objects, old, instances),
+                Some(LayerKind::Assets(assets)) => Ok(LayerKind::Assets(self.asset_layer(layer, objects, assets)?)),
+                Some(LayerKind::Tiles { .. }) | Some(LayerKind::Background { .. }) => Err(wrong_kind()),
+                // there's nothing in the map to update these from
+                Some(kind) => Ok(kind.clone()),
+                None => match string_property(properties, "kind") {
+                    None => self.instance_layer(layer, objects, old, instances),
+                    Some("path") => Ok(LayerKind::Path),
+                    Some("assets") => {
+                        let assets = self.new_asset_layer(old);
+                        Ok(LayerKind::Assets(self.asset_layer(layer, objects, &assets)?))
+                    }
+                    Some(kind) => Err(layer_error(layer, &format!("new {} layers can't be added", kind))),
+                },
+            },
+        }
+    }
+
+    fn instance_layer(&mut self, layer: &TiledLayer, objects: &[TiledObject], old: &RoomEntry, instances: &mut Vec<GameObject>) -> Result<LayerKind, TiledError> {
+        let mut instance_ids = Vec::new();
+        for object in objects {
+            let instance = self.game_object(layer, object, old)?;
+            instance_ids.push(instance.instance_id as u32);
+            instances.push(instance);
+        }
+        Ok(LayerKind::Instances { instance_ids })
+    }
+
+    fn room(&mut self, old: &RoomEntry) -> Result<RoomEntry, TiledError> {
+        let map = self.map;
+
+        // the layer each map layer updates, taking the first unclaimed one with its name
+        let mut claimed = vec![false; old.layers.len()];
+        let matches = map.layers.iter()
+            .map(|layer| {
+                let index = (0..old.layers.len())
+                    .find(|&i| !claimed[i] && self.name(old.layers[i].name_offset) == layer.name)?;
+                claimed[index] = true;
+                Some(index)
+            })
+            .collect::<Vec<_>>();
+
+        let mut next_index = old.layers.iter().map(|layer| layer.index + 1).max().unwrap_or(0);
+        let mut layers = Vec::new();
+        let mut instances = Vec::new();
+        let mut below_depth = None;
+        for (tiled, &index) in map.layers.iter().zip(&matches) {
+            let existing = index.map(|index| &old.layers[index]);
+            let depth = int_property(&tiled.properties, "depth")
+                .map(|depth| depth as i32)
+                .or_else(|| existing.map(|layer| layer.depth))
+                .unwrap_or_else(|| below_depth.map(|depth| depth - LAYER_DEPTH_STEP).unwrap_or(0));
+            below_depth = Some(depth);
+
+            let kind = self.layer_kind(tiled, existing, old, &mut instances)?;
+            let speed = |name| float_property(&tiled.properties, name);
+            let layer = Layer {
+                name_offset: self.intern(&tiled.name),
+                index: existing.map(|layer| layer.index).unwrap_or_else(|| {
+                    next_index += 1;
+                    next_index - 1
+                }),
+                depth,
+                x_offset: tiled.offset.0,
+                y_offset: tiled.offset.1,
+                horizontal_speed: speed("hspeed").unwrap_or(0.0),
+                vertical_speed: speed("vspeed").unwrap_or(0.0),
+                is_visible: tiled.visible,
+                kind,
+                trailing: existing.map(|layer| layer.trailing.clone()).unwrap_or_default(),
+            };
+            layers.push((index, layer));
+        }
+        // layers keep their place in the room, with new ones after them
+        layers.sort_by_key(|&(index, _)| index.unwrap_or(usize::MAX));
+
+        let on_layers = old.layers.iter()
+            .flat_map(|layer| match &layer.kind {
+                LayerKind::Instances { instance_ids } => &instance_ids[..],
+                _ => &[],
+            })
+            .map(|&id| id as i32)
+            .collect::<HashSet<_>>();
+        let mut entry = old.clone();
+        entry.game_objects = merge_instances(&old.game_objects, instances, |id| on_layers.contains(&id));
+        entry.layers = layers.into_iter().map(|(_, layer)| layer).collect();
+        Ok(entry)
+    }
+
+    /// A GMS1 room updated from the `background_N`, `Tiles` and `Instances` layers exporting
+    /// it makes
+    fn legacy_room(&mut self, old: &RoomEntry) -> Result<RoomEntry, TiledError> {
+        let map = self.map;
+        let mut entry = old.clone();
+        let instances_layer = map.layers.iter().position(|layer| layer.name == "Instances");
+        // backgrounds are only exported if they're enabled
+        for background in &mut entry.backgrounds {
+            background.enabled = false;
+        }
+
+        let mut instances = Vec::new();
+        let mut tiles = Vec::new();
+        for (i, layer) in map.layers.iter().enumerate() {
+            match (&layer.kind, &layer.name[..]) {
+                (TiledLayerKind::Objects(objects), "Instances") => {
+                    for object in objects {
+                        instances.push(self.game_object(layer, object, old)?);
+                    }
+                }
+                (TiledLayerKind::Objects(objects), "Tiles") => {
+                    for object in objects {
+                        tiles.push(self.legacy_tile(layer, object, &old.tiles)?);
+                    }
+                }
+                (TiledLayerKind::Image { image, repeat_x, repeat_y }, name) if name.starts_with("background_") => {
+                    let index = name["background_".len()..].parse::<usize>().ok()
+                        .filter(|&index| index < entry.backgrounds.len())
+                        .ok_or_else(|| layer_error(layer, "the room has no background with its number"))?;
+                    let bg_def_index = match image {
+                        Some(image) => {
+                            let name = file_stem(&image.source);
+                            Some(self.background_index(name)
+                                .ok_or_else(|| layer_error(layer, &format!("there is no background named '{}' for its image", name)))?)
+                        }
+                        None => None,
+                    };
+
+                    let background = &mut entry.backgrounds[index];
+                    background.enabled = layer.visible;
+                    background.foreground = instances_layer.map(|instances| i > instances).unwrap_or(false);
+                    background.x = layer.offset.0.round() as i32 as u32;
+                    background.y = layer.offset.1.round() as i32 as u32;
+                    background.tile_x = *repeat_x;
+                    background.tile_y = *repeat_y;
+                    if let Some(bg_def_index) = bg_def_index {
+                        background.bg_def_index = bg_def_index as u32;
+                    }
+                }
+                _ => return Err(layer_error(layer, "a GMS1 room only has background_N, Tiles and Instances layers")),
+            }
+        }
+
+        entry.game_objects = merge_instances(&old.game_objects, instances, |_| true);
+        entry.tiles = tiles;
+        Ok(entry)
+    }
+}
+
+/// The room's game objects with the imported ones in place of the ones they update, leaving
+/// out any that were `replaced` without being imported, and new ones at the end
+fn merge_instances(old: &[GameObject], imported: Vec<GameObject>, replaced: impl Fn(i32) -> bool) -> Vec<GameObject> {
+    let mut instances = Vec::new();
+    for instance in old {
+        match imported.iter().find(|new| new.instance_id == instance.instance_id) {
+            Some(new) => instances.push(new.clone()),
+            None if !replaced(instance.instance_id) => instances.push(instance.clone()),
+            None => {}
+        }
+    }
+    instances.extend(imported.into_iter()
+        .filter(|new| !old.iter().any(|instance| instance.instance_id == new.instance_id)));
+    instances
+}
+
+/// The size, background colour, speed and persistence of the room
+fn update_room_fields(entry: &mut RoomEntry, old: &RoomEntry, map: &TiledMap) {
+    // exporting rounds the size up to whole tiles, so only take it if it's been changed
+    let (tile_width, tile_height) = (map.tile_width.max(1), map.tile_height.max(1));
+    if map.width != old.width.div_ceil(tile_width) {
+        entry.width = map.width * tile_width;
+    }
+    if map.height != old.height.div_ceil(tile_height) {
+        entry.height = map.height * tile_height;
+    }
+
+    match map.background_color {
+        Some(rgb) => {
+            entry.draw_bg_color = true;
+            // room colours are stored as BGR
+            let bgr = ((rgb & 0xff) << 16) | (rgb & 0xff00) | ((rgb >> 16) & 0xff);
+            entry.argb = (old.argb & 0xff00_0000) | bgr;
+        }
+        None => entry.draw_bg_color = false,
+    }
+    if let Some(speed) = int_property(&map.properties, "speed").and_then(|speed| u32::try_from(speed).ok()) {
+        entry.speed = speed;
+    }
+    entry.persistent = bool_property(&map.properties, "persistent").unwrap_or(false);
+}
+
+fn layer_error(layer: &TiledLayer, reason: &str) -> TiledError {
+    TiledError::new(format!("layer '{}' can't be imported, {}", layer.name, reason))
+}
+
+fn object_error(layer: &TiledLayer, object: &TiledObject, reason: &str) -> TiledError {
+    TiledError::new(format!("object {} on layer '{}' can't be imported, {}", object.id, layer.name, reason))
+}
+
+fn property<'p>(properties: &'p [(String, PropertyValue)], name: &str) -> Option<&'p PropertyValue> {
+    properties.iter().find(|(other, _)| other == name).map(|(_, value)| value)
+}
+
+fn int_property(properties: &[(String, PropertyValue)], name: &str) -> Option<i64> {
+    match property(properties, name)? {
+        PropertyValue::Int(value) => Some(*value),
+        PropertyValue::Float(value) => Some(value.round() as i64),
+        PropertyValue::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn float_property(properties: &[(String, PropertyValue)], name: &str) -> Option<f32> {
+    match property(properties, name)? {
+        PropertyValue::Int(value) => Some(*value as f32),
+        PropertyValue::Float(value) => Some(*value as f32),
+        PropertyValue::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn bool_property(properties: &[(String, PropertyValue)], name: &str) -> Option<bool> {
+    match property(properties, name)? {
+        PropertyValue::Bool(value) => Some(*value),
+        PropertyValue::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn string_property<'p>(properties: &'p [(String, PropertyValue)], name: &str) -> Option<&'p str> {
+    match property(properties, name)? {
+        PropertyValue::String(text) => Some(text),
+        _ => None,
+    }
+}
+
+fn color_property(properties: &[(String, PropertyValue)], name: &str) -> Option<RgbaColor> {
+    match property(properties, name)? {
+        PropertyValue::Color(argb) => {
+            let [a, r, g, b] = argb.to_be_bytes();
+            Some(RgbaColor { r, g, b, a })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,471 @@
+//! Rooms as maps for the [Tiled](https://www.mapeditor.org/) level editor, in its TMX and
+//! JSON formats
+
+mod export;
+mod import;
+mod read;
+mod xml;
+
+pub use export::export_room;
+pub use import::import_room;
+
+use image::RgbaImage;
+use crate::json::{Json, number};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use xml::Element;
+
+/// Flags in the top bits of a gid, applied diagonal first
+pub const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+pub const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+pub const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+/// Why a map couldn't be read, or imported into a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TiledError {
+    pub message: String,
+}
+
+impl TiledError {
+    fn new(message: impl Into<String>) -> Self {
+        TiledError { message: message.into() }
+    }
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// `0xAARRGGBB`
+    Color(u32),
+}
+
+pub type Properties = Vec<(String, PropertyValue)>;
+
+/// An orthogonal Tiled map, along with the images its tilesets use
+#[derive(Debug, Clone)]
+pub struct TiledMap {
+    /// Size in tiles
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// `0xRRGGBB`
+    pub background_color: Option<u32>,
+    pub properties: Properties,
+    pub tilesets: Vec<Tileset>,
+    /// Bottom layer first
+    pub layers: Vec<TiledLayer>,
+    /// Each image file a tileset or layer refers to, by file name
+    pub images: Vec<(String, RgbaImage)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub name: String,
+    /// The size of the largest tile for a collection
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub kind: TilesetKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TilesetKind {
+    /// Tiles cut from one image, `margin` pixels in from the edges and `spacing` pixels apart
+    Image { image: TiledImage, margin: u32, spacing: u32, columns: u32, tile_count: u32 },
+    /// A separate image for each tile id
+    Collection(Vec<(u32, TiledImage)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TiledImage {
+    pub source: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TiledLayer {
+    pub name: String,
+    pub offset: (f32, f32),
+    pub visible: bool,
+    pub properties: Properties,
+    pub kind: TiledLayerKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum TiledLayerKind {
+    /// `width * height` gids, row by row, with 0 for no tile
+    Tiles { width: u32, height: u32, data: Vec<u32> },
+    Objects(Vec<TiledObject>),
+    Image { image: Option<TiledImage>, repeat_x: bool, repeat_y: bool },
+}
+
+/// A tile placed anywhere on the map, or a point if it has no `gid`
+#[derive(Debug, Clone)]
+pub struct TiledObject {
+    pub id: u32,
+    pub name: String,
+    /// The object's class (`type` in older versions of Tiled)
+    pub kind: String,
+    pub gid: Option<u32>,
+    /// Bottom left corner of a tile, which it rotates around
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    /// Degrees clockwise
+    pub rotation: f32,
+    pub properties: Properties,
+}
+
+impl TiledMap {
+    fn next_object_id(&self) -> u32 {
+        self.layers.iter()
+            .filter_map(|layer| match &layer.kind {
+                TiledLayerKind::Objects(objects) => objects.iter().map(|object| object.id).max(),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0) + 1
+    }
+
+    pub fn to_tmx(&self) -> String {
+        let mut map = Element::new("map")
+            .attr("version", "1.10")
+            .attr("orientation", "orthogonal")
+            .attr("renderorder", "right-down")
+            .attr("width", self.width)
+            .attr("height", self.height)
+            .attr("tilewidth", self.tile_width)
+            .attr("tileheight", self.tile_height)
+            .attr("infinite", 0);
+        if let Some(color) = self.background_color {
+            map = map.attr("backgroundcolor", format!("#{:06x}", color));
+        }
+        map = map
+            .attr("nextlayerid", self.layers.len() + 1)
+            .attr("nextobjectid", self.next_object_id());
+        if !self.properties.is_empty() {
+            map = map.child(properties_element(&self.properties));
+        }
+
+        for tileset in &self.tilesets {
+            let mut element = Element::new("tileset")
+                .attr("firstgid", tileset.first_gid)
+                .attr("name", &tileset.name)
+                .attr("tilewidth", tileset.tile_width)
+                .attr("tileheight", tileset.tile_height);
+            element = match &tileset.kind {
+                TilesetKind::Image { image, margin, spacing, columns, tile_count } => element
+                    .attr("spacing", spacing)
+                    .attr("margin", margin)
+                    .attr("tilecount", tile_count)
+                    .attr("columns", columns)
+                    .child(image_element(image)),
+                TilesetKind::Collection(tiles) => {
+                    let mut element = element
+                        .attr("tilecount", tiles.len())
+                        .attr("columns", 0)
+                        .child(Element::new("grid")
+                            .attr("orientation", "orthogonal")
+                            .attr("width", 1)
+                            .attr("height", 1));
+                    for (id, image) in tiles {
+                        element = element.child(Element::new("tile").attr("id", id).child(image_element(image)));
+                    }
+                    element
+                }
+            };
+            map = map.child(element);
+        }
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let name = match layer.kind {
+                TiledLayerKind::Tiles { .. } => "layer",
+                TiledLayerKind::Objects(_) => "objectgroup",
+                TiledLayerKind::Image { .. } => "imagelayer",
+            };
+            let mut element = Element::new(name)
+                .attr("id", i + 1)
+                .attr("name", &layer.name);
+            if let TiledLayerKind::Tiles { width, height, .. } = layer.kind {
+                element = element.attr("width", width).attr("height", height);
+            }
+            if let TiledLayerKind::Image { repeat_x, repeat_y, .. } = layer.kind {
+                if repeat_x {
+                    element = element.attr("repeatx", 1);
+                }
+                if repeat_y {
+                    element = element.attr("repeaty", 1);
+                }
+            }
+            if !layer.visible {
+                element = element.attr("visible", 0);
+            }
+            if layer.offset != (0.0, 0.0) {
+                element = element
+                    .attr("offsetx", number(layer.offset.0 as f64))
+                    .attr("offsety", number(layer.offset.1 as f64));
+            }
+            if !layer.properties.is_empty() {
+                element = element.child(properties_element(&layer.properties));
+            }
+
+            element = match &layer.kind {
+                TiledLayerKind::Tiles { width, data, .. } => {
+                    let rows = data.chunks((*width as usize).max(1))
+                        .map(|row| row.iter().map(u32::to_string).collect::<Vec<_>>().join(","))
+                        .collect::<Vec<_>>();
+                    element.child(Element::new("data")
+                        .attr("encoding", "csv")
+                        .text(format!("\n{}\n", rows.join(",\n"))))
+                }
+                TiledLayerKind::Objects(objects) => objects.iter()
+                    .fold(element, |element, object| element.child(object_element(object))),
+                TiledLayerKind::Image { image: Some(image), .. } => element.child(image_element(image)),
+                TiledLayerKind::Image { image: None, .. } => element,
+            };
+            map = map.child(element);
+        }
+
+        map.to_document()
+    }
+
+    pub fn to_json(&self) -> String {
+        let tilesets = self.tilesets.iter()
+            .map(|tileset| {
+                let mut fields = vec![
+                    ("firstgid", Json::Number(tileset.first_gid as f64)),
+                    ("name", Json::string(&tileset.name)),
+                    ("tilewidth", Json::Number(tileset.tile_width as f64)),
+                    ("tileheight", Json::Number(tileset.tile_height as f64)),
+                ];
+                match &tileset.kind {
+                    TilesetKind::Image { image, margin, spacing, columns, tile_count } => fields.extend(vec![
+                        ("spacing", Json::Number(*spacing as f64)),
+                        ("margin", Json::Number(*margin as f64)),
+                        ("tilecount", Json::Number(*tile_count as f64)),
+                        ("columns", Json::Number(*columns as f64)),
+                        ("image", Json::string(&image.source)),
+                        ("imagewidth", Json::Number(image.width as f64)),
+                        ("imageheight", Json::Number(image.height as f64)),
+                    ]),
+                    TilesetKind::Collection(tiles) => fields.extend(vec![
+                        ("tilecount", Json::Number(tiles.len() as f64)),
+                        ("columns", Json::Number(0.0)),
+                        ("grid", Json::object(vec![
+                            ("orientation", Json::string("orthogonal")),
+                            ("width", Json::Number(1.0)),
+                            ("height", Json::Number(1.0)),
+                        ])),
+                        ("tiles", Json::Array(tiles.iter()
+                            .map(|(id, image)| Json::object(vec![
+                                ("id", Json::Number(*id as f64)),
+                                ("image", Json::string(&image.source)),
+                                ("imagewidth", Json::Number(image.width as f64)),
+                                ("imageheight", Json::Number(image.height as f64)),
+                            ]))
+                            .collect())),
+                    ]),
+                }
+                Json::object(fields)
+            })
+            .collect();
+
+        let layers = self.layers.iter()
+            .enumerate()
+            .map(|(i, layer)| {
+                let mut fields = vec![
+                    ("id", Json::Number((i + 1) as f64)),
+                    ("name", Json::string(&layer.name)),
+                ];
+                match &layer.kind {
+                    TiledLayerKind::Tiles { width, height, data } => fields.extend(vec![
+                        ("type", Json::string("tilelayer")),
+                        ("width", Json::Number(*width as f64)),
+                        ("height", Json::Number(*height as f64)),
+                        ("data", Json::Array(data.iter().map(|&gid| Json::Number(gid as f64)).collect())),
+                    ]),
+                    TiledLayerKind::Objects(objects) => fields.extend(vec![
+                        ("type", Json::string("objectgroup")),
+                        ("draworder", Json::string("topdown")),
+                        ("objects", Json::Array(objects.iter().map(object_json).collect())),
+                    ]),
+                    TiledLayerKind::Image { image, repeat_x, repeat_y } => {
+                        fields.push(("type", Json::string("imagelayer")));
+                        fields.push(("image", Json::string(image.as_ref().map(|image| &image.source[..]).unwrap_or(""))));
+                        if let Some(image) = image {
+                            fields.push(("imagewidth", Json::Number(image.width as f64)));
+                            fields.push(("imageheight", Json::Number(image.height as f64)));
+                        }
+                        fields.push(("repeatx", Json::Bool(*repeat_x)));
+                        fields.push(("repeaty", Json::Bool(*repeat_y)));
+                    }
+                }
+                fields.extend(vec![
+                    ("x", Json::Number(0.0)),
+                    ("y", Json::Number(0.0)),
+                    ("offsetx", Json::Number(layer.offset.0 as f64)),
+                    ("offsety", Json::Number(layer.offset.1 as f64)),
+                    ("opacity", Json::Number(1.0)),
+                    ("visible", Json::Bool(layer.visible)),
+                ]);
+                if !layer.properties.is_empty() {
+                    fields.push(("properties", properties_json(&layer.properties)));
+                }
+                Json::object(fields)
+            })
+            .collect();
+
+        let mut fields = vec![
+            ("type", Json::string("map")),
+            ("version", Json::string("1.10")),
+            ("orientation", Json::string("orthogonal")),
+            ("renderorder", Json::string("right-down")),
+            ("width", Json::Number(self.width as f64)),
+            ("height", Json::Number(self.height as f64)),
+            ("tilewidth", Json::Number(self.tile_width as f64)),
+            ("tileheight", Json::Number(self.tile_height as f64)),
+            ("infinite", Json::Bool(false)),
+        ];
+        if let Some(color) = self.background_color {
+            fields.push(("backgroundcolor", Json::String(format!("#{:06x}", color))));
+        }
+        fields.extend(vec![
+            ("nextlayerid", Json::Number((self.layers.len() + 1) as f64)),
+            ("nextobjectid", Json::Number(self.next_object_id() as f64)),
+        ]);
+        if !self.properties.is_empty() {
+            fields.push(("properties", properties_json(&self.properties)));
+        }
+        fields.push(("tilesets", Json::Array(tilesets)));
+        fields.push(("layers", Json::Array(layers)));
+        Json::object(fields).to_pretty()
+    }
+
+    /// Write the map to `path`, as JSON if it ends in `.json` and as TMX otherwise, with its
+    /// images alongside it
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let is_json = path.extension().map(|extension| extension == "json").unwrap_or(false);
+        std::fs::write(path, if is_json { self.to_json() } else { self.to_tmx() })?;
+
+        let folder = path.parent().unwrap_or_else(|| Path::new(""));
+        for (name, image) in &self.images {
+            image.save_with_format(folder.join(name), image::ImageFormat::PNG)?;
+        }
+        Ok(())
+    }
+}
+
+fn image_element(image: &TiledImage) -> Element {
+    Element::new("image")
+        .attr("source", &image.source)
+        .attr("width", image.width)
+        .attr("height", image.height)
+}
+
+fn object_element(object: &TiledObject) -> Element {
+    let mut element = Element::new("object").attr("id", object.id);
+    if !object.name.is_empty() {
+        element = element.attr("name", &object.name);
+    }
+    if !object.kind.is_empty() {
+        element = element.attr("type", &object.kind);
+    }
+    if let Some(gid) = object.gid {
+        element = element.attr("gid", gid);
+    }
+    element = element
+        .attr("x", number(object.x as f64))
+        .attr("y", number(object.y as f64));
+    if object.gid.is_some() {
+        element = element
+            .attr("width", number(object.width as f64))
+            .attr("height", number(object.height as f64));
+    }
+    if object.rotation != 0.0 {
+        element = element.attr("rotation", number(object.rotation as f64));
+    }
+    if !object.properties.is_empty() {
+        element = element.child(properties_element(&object.properties));
+    }
+    if object.gid.is_none() {
+        element = element.child(Element::new("point"));
+    }
+    element
+}
+
+fn object_json(object: &TiledObject) -> Json {
+    let mut fields = vec![
+        ("id", Json::Number(object.id as f64)),
+        ("name", Json::string(&object.name)),
+        ("type", Json::string(&object.kind)),
+    ];
+    match object.gid {
+        Some(gid) => fields.push(("gid", Json::Number(gid as f64))),
+        None => fields.push(("point", Json::Bool(true))),
+    }
+    fields.extend(vec![
+        ("x", Json::Number(object.x as f64)),
+        ("y", Json::Number(object.y as f64)),
+        ("width", Json::Number(object.width as f64)),
+        ("height", Json::Number(object.height as f64)),
+        ("rotation", Json::Number(object.rotation as f64)),
+        ("visible", Json::Bool(true)),
+    ]);
+    if !object.properties.is_empty() {
+        fields.push(("properties", properties_json(&object.properties)));
+    }
+    Json::object(fields)
+}
+
+/// Tiled's name for the type, and the value as it's written in TMX
+fn property_parts(value: &PropertyValue) -> (&'static str, String) {
+    match value {
+        PropertyValue::Bool(value) => ("bool", value.to_string()),
+        PropertyValue::Int(value) => ("int", value.to_string()),
+        PropertyValue::Float(value) => ("float", number(*value)),
+        PropertyValue::String(value) => ("string", value.clone()),
+        PropertyValue::Color(value) => ("color", format!("#{:08x}", value)),
+    }
+}
+
+fn properties_element(properties: &[(String, PropertyValue)]) -> Element {
+    properties.iter().fold(Element::new("properties"), |element, (name, value)| {
+        let (kind, text) = property_parts(value);
+        let mut property = Element::new("property").attr("name", name);
+        if kind != "string" {
+            property = property.attr("type", kind);
+        }
+        element.child(property.attr("value", text))
+    })
+}
+
+fn properties_json(properties: &[(String, PropertyValue)]) -> Json {
+    Json::Array(properties.iter()
+        .map(|(name, value)| {
+            let (kind, text) = property_parts(value);
+            let value = match value {
+                PropertyValue::Bool(value) => Json::Bool(*value),
+                PropertyValue::Int(value) => Json::Number(*value as f64),
+                PropertyValue::Float(value) => Json::Number(*value),
+                _ => Json::String(text),
+            };
+            Json::object(vec![("name", Json::string(name)), ("type", Json::string(kind)), ("value", value)])
+        })
+        .collect())
+}
@@ -0,0 +1,317 @@
+use super::*;
+use crate::json::{self, Json};
+use super::xml::{self, Element};
+
+impl TiledMap {
+    /// Read a map saved as TMX. Tile layers have to use CSV encoding, Tiled's default, and
+    /// group layers are flattened. No images are loaded.
+    pub fn from_tmx(text: &str) -> Result<Self, TiledError> {
+        let map = xml::parse(text).map_err(TiledError::new)?;
+        if map.name != "map" {
+            return Err(TiledError::new(format!("expected <map>, found <{}>", map.name)));
+        }
+
+        let tilesets = map.children_named("tileset")
+            .map(|tileset| {
+                let tiles = tileset.children_named("tile")
+                    .filter_map(|tile| Some((number_attr(tile, "id")? as u32, tmx_image(tile.children_named("image").next()?))))
+                    .collect::<Vec<_>>();
+                let kind = match tileset.children_named("image").next() {
+                    Some(image) => TilesetKind::Image {
+                        image: tmx_image(image),
+                        margin: number_attr(tileset, "margin").unwrap_or(0.0) as u32,
+                        spacing: number_attr(tileset, "spacing").unwrap_or(0.0) as u32,
+                        columns: number_attr(tileset, "columns").unwrap_or(0.0) as u32,
+                        tile_count: number_attr(tileset, "tilecount").unwrap_or(0.0) as u32,
+                    },
+                    None => TilesetKind::Collection(tiles),
+                };
+                Tileset {
+                    first_gid: number_attr(tileset, "firstgid").unwrap_or(1.0) as u32,
+                    // an external tileset is known by its file name
+                    name: tileset.get("name")
+                        .or_else(|| tileset.get("source").map(file_stem))
+                        .unwrap_or("")
+                        .to_owned(),
+                    tile_width: number_attr(tileset, "tilewidth").unwrap_or(0.0) as u32,
+                    tile_height: number_attr(tileset, "tileheight").unwrap_or(0.0) as u32,
+                    kind,
+                }
+            })
+            .collect();
+
+        let mut layers = Vec::new();
+        tmx_layers(&map, &mut layers)?;
+
+        Ok(TiledMap {
+            width: number_attr(&map, "width").unwrap_or(0.0) as u32,
+            height: number_attr(&map, "height").unwrap_or(0.0) as u32,
+            tile_width: number_attr(&map, "tilewidth").unwrap_or(0.0) as u32,
+            tile_height: number_attr(&map, "tileheight").unwrap_or(0.0) as u32,
+            background_color: map.get("backgroundcolor").and_then(color).map(|argb| argb & 0xff_ffff),
+            properties: tmx_properties(&map),
+            tilesets,
+            layers,
+            images: Vec::new(),
+        })
+    }
+
+    /// Read a map saved as JSON, with the same limits as `from_tmx`
+    pub fn from_json(text: &str) -> Result<Self, TiledError> {
+        let map = json::parse(text).map_err(TiledError::new)?;
+        let number = |value: &Json, name: &str| value.get(name).and_then(Json::as_f64).unwrap_or(0.0);
+        let text = |value: &Json, name: &str| value.get(name).and_then(Json::as_str).unwrap_or("").to_owned();
+
+        let tilesets = map.get("tilesets").map(Json::as_array).unwrap_or_default().iter()
+            .map(|tileset| {
+                let image = |value: &Json| TiledImage {
+                    source: text(value, "image"),
+                    width: number(value, "imagewidth") as u32,
+                    height: number(value, "imageheight") as u32,
+                };
+                let kind = match tileset.get("image") {
+                    Some(_) => TilesetKind::Image {
+                        image: image(tileset),
+                        margin: number(tileset, "margin") as u32,
+                        spacing: number(tileset, "spacing") as u32,
+                        columns: number(tileset, "columns") as u32,
+                        tile_count: number(tileset, "tilecount") as u32,
+                    },
+                    None => TilesetKind::Collection(tileset.get("tiles").map(Json::as_array).unwrap_or_default().iter()
+                        .map(|tile| (number(tile, "id") as u32, image(tile)))
+                        .collect()),
+                };
+                let name = match tileset.get("name") {
+                    Some(name) => name.as_str().unwrap_or("").to_owned(),
+                    None => file_stem(&text(tileset, "source")).to_owned(),
+                };
+                Tileset {
+                    first_gid: number(tileset, "firstgid") as u32,
+                    name,
+                    tile_width: number(tileset, "tilewidth") as u32,
+                    tile_height: number(tileset, "tileheight") as u32,
+                    kind,
+                }
+            })
+            .collect();
+
+        let mut layers = Vec::new();
+        json_layers(&map, &mut layers)?;
+
+        Ok(TiledMap {
+            width: number(&map, "width") as u32,
+            height: number(&map, "height") as u32,
+            tile_width: number(&map, "tilewidth") as u32,
+            tile_height: number(&map, "tileheight") as u32,
+            background_color: map.get("backgroundcolor").and_then(Json::as_str).and_then(color).map(|argb| argb & 0xff_ffff),
+            properties: json_properties(&map),
+            tilesets,
+            layers,
+            images: Vec::new(),
+        })
+    }
+
+    /// Read a map from `path`, as JSON if it ends in `.json` and as TMX otherwise
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TiledError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| TiledError::new(format!("failed to read '{}': {}", path.display(), err)))?;
+        if path.extension().map(|extension| extension == "json").unwrap_or(false) {
+            TiledMap::from_json(&text)
+        } else {
+            TiledMap::from_tmx(&text)
+        }
+    }
+
+    /// The tileset a gid (with or without flags) belongs to
+    pub fn tileset_of(&self, gid: u32) -> Option<&Tileset> {
+        let gid = gid & !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY);
+        self.tilesets.iter()
+            .filter(|tileset| tileset.first_gid <= gid)
+            .max_by_key(|tileset| tileset.first_gid)
+    }
+}
+
+pub(super) fn file_stem(path: &str) -> &str {
+    Path::new(path).file_stem().and_then(|stem| stem.to_str()).unwrap_or("")
+}
+
+/// `#AARRGGBB` or `#RRGGBB`, which is opaque
+fn color(text: &str) -> Option<u32> {
+    let hex = text.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some(0xff00_0000 | value),
+        8 => Some(value),
+        _ => None,
+    }
+}
+
+fn property(kind: &str, value: &str) -> PropertyValue {
+    match kind {
+        "bool" => PropertyValue::Bool(value == "true"),
+        "int" | "object" => PropertyValue::Int(value.parse().unwrap_or(0)),
+        "float" => PropertyValue::Float(value.parse().unwrap_or(0.0)),
+        "color" => color(value).map(PropertyValue::Color).unwrap_or_else(|| PropertyValue::String(value.to_owned())),
+        _ => PropertyValue::String(value.to_owned()),
+    }
+}
+
+fn number_attr(element: &Element, name: &str) -> Option<f64> {
+    element.get(name)?.trim().parse().ok()
+}
+
+fn tmx_image(image: &Element) -> TiledImage {
+    TiledImage {
+        source: image.get("source").unwrap_or("").to_owned(),
+        width: number_attr(image, "width").unwrap_or(0.0) as u32,
+        height: number_attr(image, "height").unwrap_or(0.0) as u32,
+    }
+}
+
+fn tmx_properties(element: &Element) -> Properties {
+    element.children_named("properties")
+        .flat_map(|properties| properties.children_named("property"))
+        .map(|property| {
+            let value = property.get("value").map(str::to_owned).unwrap_or_else(|| property.text.clone());
+            let name = property.get("name").unwrap_or("").to_owned();
+            (name, self::property(property.get("type").unwrap_or("string"), &value))
+        })
+        .collect()
+}
+
+fn tmx_layers(parent: &Element, layers: &mut Vec<TiledLayer>) -> Result<(), TiledError> {
+    for element in &parent.children {
+        let name = element.get("name").unwrap_or("").to_owned();
+        let kind = match &element.name[..] {
+            "group" => {
+                tmx_layers(element, layers)?;
+                continue;
+            }
+            "layer" => {
+                let data = element.children_named("data").next();
+                let encoding = data.and_then(|data| data.get("encoding"));
+                if encoding != Some("csv") {
+                    return Err(TiledError::new(format!("tile layer '{}' isn't stored as CSV", name)));
+                }
+                let data = data.unwrap().text
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|gid| !gid.is_empty())
+                    .map(|gid| gid.parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| TiledError::new(format!("tile layer '{}' has an invalid gid", name)))?;
+                TiledLayerKind::Tiles {
+                    width: number_attr(element, "width").unwrap_or(0.0) as u32,
+                    height: number_attr(element, "height").unwrap_or(0.0) as u32,
+                    data,
+                }
+            }
+            "objectgroup" => TiledLayerKind::Objects(element.children_named("object")
+                .map(|object| TiledObject {
+                    id: number_attr(object, "id").unwrap_or(0.0) as u32,
+                    name: object.get("name").unwrap_or("").to_owned(),
+                    kind: object.get("type").or_else(|| object.get("class")).unwrap_or("").to_owned(),
+                    gid: number_attr(object, "gid").map(|gid| gid as u32),
+                    x: number_attr(object, "x").unwrap_or(0.0) as f32,
+                    y: number_attr(object, "y").unwrap_or(0.0) as f32,
+                    width: number_attr(object, "width").unwrap_or(0.0) as f32,
+                    height: number_attr(object, "height").unwrap_or(0.0) as f32,
+                    rotation: number_attr(object, "rotation").unwrap_or(0.0) as f32,
+                    properties: tmx_properties(object),
+                })
+                .collect()),
+            "imagelayer" => TiledLayerKind::Image {
+                image: element.children_named("image").next().map(tmx_image),
+                repeat_x: element.get("repeatx") == Some("1"),
+                repeat_y: element.get("repeaty") == Some("1"),
+            },
+            _ => continue,
+        };
+        layers.push(TiledLayer {
+            name,
+            offset: (
+                number_attr(element, "offsetx").unwrap_or(0.0) as f32,
+                number_attr(element, "offsety").unwrap_or(0.0) as f32,
+            ),
+            visible: element.get("visible") != Some("0"),
+            properties: tmx_properties(element),
+            kind,
+        });
+    }
+    Ok(())
+}
+
+fn json_properties(value: &Json) -> Properties {
+    value.get("properties").map(Json::as_array).unwrap_or_default().iter()
+        .map(|property| {
+            let name = property.get("name").and_then(Json::as_str).unwrap_or("").to_owned();
+            let kind = property.get("type").and_then(Json::as_str).unwrap_or("string");
+            let value = match property.get("value") {
+                Some(Json::Bool(value)) => value.to_string(),
+                Some(Json::Number(value)) => value.to_string(),
+                Some(Json::String(text)) => text.clone(),
+                _ => String::new(),
+            };
+            (name, self::property(kind, &value))
+        })
+        .collect()
+}
+
+fn json_layers(parent: &Json, layers: &mut Vec<TiledLayer>) -> Result<(), TiledError> {
+    for layer in parent.get("layers").map(Json::as_array).unwrap_or_default() {
+        let number = |value: &Json, name: &str| value.get(name).and_then(Json::as_f64).unwrap_or(0.0);
+        let name = layer.get("name").and_then(Json::as_str).unwrap_or("").to_owned();
+        let kind = match layer.get("type").and_then(Json::as_str).unwrap_or("") {
+            "group" => {
+                json_layers(layer, layers)?;
+                continue;
+            }
+            "tilelayer" => {
+                let data = match layer.get("data") {
+                    Some(Json::Array(data)) => data.iter()
+                        .map(|gid| gid.as_f64().map(|gid| gid as u32))
+                        .collect::<Option<Vec<_>>>(),
+                    _ => None,
+                };
+                TiledLayerKind::Tiles {
+                    width: number(layer, "width") as u32,
+                    height: number(layer, "height") as u32,
+                    data: data.ok_or_else(|| TiledError::new(format!("tile layer '{}' isn't stored as an array", name)))?,
+                }
+            }
+            "objectgroup" => TiledLayerKind::Objects(layer.get("objects").map(Json::as_array).unwrap_or_default().iter()
+                .map(|object| TiledObject {
+                    id: number(object, "id") as u32,
+                    name: object.get("name").and_then(Json::as_str).unwrap_or("").to_owned(),
+                    kind: object.get("type").or_else(|| object.get("class")).and_then(Json::as_str).unwrap_or("").to_owned(),
+                    gid: object.get("gid").and_then(Json::as_f64).map(|gid| gid as u32),
+                    x: number(object, "x") as f32,
+                    y: number(object, "y") as f32,
+                    width: number(object, "width") as f32,
+                    height: number(object, "height") as f32,
+                    rotation: number(object, "rotation") as f32,
+                    properties: json_properties(object),
+                })
+                .collect()),
+            "imagelayer" => TiledLayerKind::Image {
+                image: layer.get("image").and_then(Json::as_str).filter(|source| !source.is_empty()).map(|source| TiledImage {
+                    source: source.to_owned(),
+                    width: number(layer, "imagewidth") as u32,
+                    height: number(layer, "imageheight") as u32,
+                }),
+                repeat_x: layer.get("repeatx").and_then(Json::as_bool).unwrap_or(false),
+                repeat_y: layer.get("repeaty").and_then(Json::as_bool).unwrap_or(false),
+            },
+            _ => continue,
+        };
+        layers.push(TiledLayer {
+            name,
+            offset: (number(layer, "offsetx") as f32, number(layer, "offsety") as f32),
+            visible: layer.get("visible").and_then(Json::as_bool).unwrap_or(true),
+            properties: json_properties(layer),
+            kind,
+        });
+    }
+    Ok(())
+}
@@ -1,8 +1,1204 @@
-//const PATH: &str = "/home/jam/Documents/GameMakerStudio2/data.win";
+use gm_data_win::file_structs::{
+    AssetInstance, AssetLayer, CodeLayout, DataWinBuilder, FormFile, LayerKind, MissingSection, ObjectEvent, ObjectId,
+    RgbaColor, SpeedUnit, SpriteId, StepEvent, TILE_INDEX_MASK,
+};
+use image::{GenericImageView, Rgba, RgbaImage};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-const PATH: &str = "data.win";
+const RED: Rgba<u8> = Rgba([0xff, 0, 0, 0xff]);
+const BLUE: Rgba<u8> = Rgba([0, 0, 0xff, 0xff]);
+const GREEN: Rgba<u8> = Rgba([0, 0xff, 0, 0xff]);
+
+/// A small game using every section the builder knows about
+fn fixture() -> DataWinBuilder {
+    let mut builder = DataWinBuilder::new();
+    let texture = builder.texture(&RgbaImage::from_pixel(32, 32, RED));
+    let frames = [
+        builder.texture_page(texture, ((0, 0), (8, 8))),
+        builder.texture_page(texture, ((8, 0), (8, 8))),
+    ];
+    let sprite = builder.sprite("spr_player", &frames);
+    let sheet = builder.texture_page(texture, ((0, 16), (16, 8)));
+    builder.font("fnt_main", "Arial", 12.0, sheet, &[('a', ((0, 0), (8, 8))), ('b', ((8, 0), (8, 8)))]);
+    let tiles = builder.texture_page(texture, ((0, 24), (24, 8)));
+    builder.tile_set("bg_tiles", tiles, (8, 8), 3);
+    let object = builder.object("obj_player", sprite as i32);
+    let room = builder.room("rm_start", (320, 240), &[(16, 32, object)]);
+    builder
+        .layer(room, "Instances", 0, LayerKind::Instances { instance_ids: vec![100_000] })
+        .layer(room, "Tiles", 100, LayerKind::Tiles {
+            background_index: 0,
+            width: 2,
+            height: 2,
+            tiles: vec![1, 0, 2, 1 | 0x1000_0000],
+        });
+    builder.sound("snd_jump", 0, b"RIFF jump");
+    builder.sound("snd_music", 1, b"OggS music");
+    // pushi.e 1; pop.v.i self.x; exit.i
+    let create = builder.code("gml_Object_obj_player_Create_0", &[
+        0x01, 0x00, 0x0f, 0x84,
+        0xff, 0xff, 0x25, 0x45, 0x00, 0x00, 0x00, 0xa0,
+        0x00, 0x00, 0x02, 0x9d,
+    ], 1, 0);
+    builder.event(object, ObjectEvent::Create, create);
+    let scr_empty = builder.code("gml_Script_scr_empty", &[0x00, 0x00, 0x02, 0x9d], 0, 2);
+    builder.script("scr_empty", scr_empty);
+    builder
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gm_data_win_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A fresh directory for the test called `name`, with the fixture in it as `data.win`
+fn game_dir(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    fs::write(dir.join("data.win"), fixture().build()).unwrap();
+    dir
+}
+
+/// Run the tool as `args` ask on the `data.win` in `dir`, with the `mods` and `files` folders
+/// next to it and no audio groups unless `args` give some, and read back the file it left
+fn run(dir: &Path, args: gm_data_win::Args) -> FormFile {
+    let path = |name| dir.join(name).to_str().unwrap().to_owned();
+    gm_data_win::main(gm_data_win::Args {
+        data_win: path("data.win"),
+        audio_groups: args.audio_groups.or(Some(Vec::new())),
+        mod_folder: path("mods"),
+        originals_folder: path("files"),
+        ..args
+    }, false);
+    FormFile::from_file(dir.join("data.win")).unwrap()
+}
 
 #[test]
 fn try_parse() {
-    gm_data_win::take_data_win_file(&std::fs::read(PATH).unwrap());
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+
+    let strg = file.strg.as_ref().unwrap();
+    let sprite = &file.sprt.as_ref().unwrap().sprites[0];
+    assert_eq!(strg.get(sprite.name_offset).unwrap(), "spr_player");
+    assert_eq!(sprite.size, (8, 8));
+    assert_eq!(file.get_tpag_info(sprite.tpag_offsets[1]), (((8, 0), (8, 8)), 0));
+    assert_eq!(*file.get_tpag_subimage(sprite.tpag_offsets[0]).get_pixel(0, 0), RED);
+
+    let font = &file.font.as_ref().unwrap().fonts[0];
+    assert_eq!(strg.get(font.font_name).unwrap(), "Arial");
+    assert_eq!(font.chars.iter().map(|c| c.character).collect::<String>(), "ab");
+
+    let sound = &file.sond.as_ref().unwrap().sounds[0];
+    assert_eq!(strg.get(sound.name_offset).unwrap(), "snd_jump");
+    assert_eq!(file.audos[0].files[sound.index_in_audiogroup as usize], b"RIFF jump");
+
+    let audio_group = gm_data_win::take_data_win_file(&fixture().build_audio_groups()[0]);
+    assert!(matches!(&audio_group[..], [gm_data_win::file_structs::Section::Audo(audo)] if audo.files[0] == b"OggS music"));
+}
+
+#[test]
+fn fixture_roundtrips() {
+    let builder = fixture();
+    assert_eq!(gm_data_win::verify_roundtrip(&builder.build()).unwrap(), None);
+    assert_eq!(gm_data_win::verify_roundtrip(&builder.build_audio_groups()[0]).unwrap(), None);
+}
+
+#[test]
+fn hand_assembled_roundtrips() {
+    // laid out as GameMaker does rather than as the builder does, with SCPT pointing ahead
+    // into STRG, strings padded to 4 bytes and STRG padded to 32
+    let data = [
+        &b"FORM\x58\x00\x00\x00"[..],
+        b"SCPT\x10\x00\x00\x00", b"\x01\x00\x00\x00\x18\x00\x00\x00", b"\x48\x00\x00\x00\x00\x00\x00\x00",
+        b"GLOB\x08\x00\x00\x00", b"\x01\x00\x00\x00\x00\x00\x00\x00",
+        b"STRG\x28\x00\x00\x00", b"\x02\x00\x00\x00\x44\x00\x00\x00\x50\x00\x00\x00",
+        b"\x05\x00\x00\x00scr_a\x00\x00\x00", b"\x03\x00\x00\x00obj\x00", &[0; 8],
+    ].concat();
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+    let strg = file.strg.as_ref().unwrap();
+    assert_eq!((strg.string_align, strg.end_align), (4, 32));
+    assert_eq!(strg.get(file.scpt.as_ref().unwrap().scripts[0].name_offset).unwrap(), "scr_a");
+    assert_eq!(file.to_bytes().unwrap(), data);
+    assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+    // a new string's pointer moves the others along, and the script's name with them
+    file.strg.as_mut().unwrap().intern("spr_b");
+    let written = file.to_bytes().unwrap();
+    assert_eq!(written[24..28], [0x4c, 0, 0, 0]);
+    assert_eq!(written.len(), 128);
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&written));
+    assert_eq!(file.strg.as_ref().unwrap().get(file.scpt.as_ref().unwrap().scripts[0].name_offset).unwrap(), "scr_a");
+}
+
+#[test]
+fn extract_and_mod() {
+    let dir = game_dir("extract_and_mod");
+    let audio_group = dir.join("audiogroup1.dat");
+    fs::write(&audio_group, &fixture().build_audio_groups()[0]).unwrap();
+    let args = || gm_data_win::Args {
+        audio_groups: Some(vec![audio_group.to_str().unwrap().to_owned()]),
+        ..Default::default()
+    };
+
+    run(&dir, gm_data_win::Args {
+        extract_audio: true,
+        extract_sprites: true,
+        extract_fonts: true,
+        extract_textures: true,
+        extract_code: true,
+        ..args()
+    });
+    assert_eq!(fs::read(dir.join("files/sounds/snd_music.ogg")).unwrap(), b"OggS music");
+    assert!(dir.join("files/sprites/spr_player/1.png").exists());
+    assert!(dir.join("files/fonts/Arial/b.png").exists());
+    assert!(dir.join("files/textures/0.png").exists());
+    assert_eq!(fs::read_to_string(dir.join("files/code/gml_Script_scr_empty.gml")).unwrap(), "");
+
+    fs::create_dir_all(dir.join("mods/sprites/spr_player")).unwrap();
+    fs::create_dir_all(dir.join("mods/sounds")).unwrap();
+    RgbaImage::from_pixel(8, 8, BLUE).save(dir.join("mods/sprites/spr_player/1.png")).unwrap();
+    fs::write(dir.join("mods/sounds/snd_music.ogg"), b"OggS modded").unwrap();
+    fs::create_dir_all(dir.join("mods/code")).unwrap();
+    fs::write(dir.join("mods/code/scr_empty.asm"), "pushi.e 3\npop.v.i global.score\nexit.i\n").unwrap();
+    fs::write(dir.join("mods/code/gml_Object_obj_player_Create_0.gml"), "x = 2; // moved\n").unwrap();
+    let file = run(&dir, args());
+    // written next to data.win first, then moved over it
+    assert!(!dir.join("data.win.tmp").exists());
+
+    let sprite = &file.sprt.as_ref().unwrap().sprites[0];
+    assert_eq!(*file.get_tpag_subimage(sprite.tpag_offsets[0]).get_pixel(0, 0), RED);
+    assert_eq!(*file.get_tpag_subimage(sprite.tpag_offsets[1]).get_pixel(0, 0), BLUE);
+
+    assert_eq!(
+        gm_data_win::disasm::disassemble(&file, 1).unwrap(),
+        "pushi.e 3\npop.v.i global.score\nexit.i\n"
+    );
+    assert_eq!(gm_data_win::decompile::decompile(&file, 0).unwrap(), "x = 2;\n");
+
+    let audio_group = gm_data_win::take_data_win_file(&fs::read(&audio_group).unwrap());
+    assert!(matches!(&audio_group[..], [gm_data_win::file_structs::Section::Audo(audo)] if audo.files[0] == b"OggS modded"));
+}
+
+#[test]
+fn missing_files() {
+    let dir = temp_dir("missing_files");
+    fs::write(dir.join("data.win"), fixture().build()).unwrap();
+    // reported as errors rather than panicking
+    for (data_win, audio_group) in [("missing.win", "audiogroup1.dat"), ("data.win", "audiogroup1.dat")] {
+        for verify_roundtrip in [false, true] {
+            gm_data_win::main(gm_data_win::Args {
+                data_win: dir.join(data_win).to_str().unwrap().to_owned(),
+                audio_groups: Some(vec![dir.join(audio_group).to_str().unwrap().to_owned()]),
+                mod_folder: dir.join("mods").to_str().unwrap().to_owned(),
+                verify_roundtrip,
+                ..Default::default()
+            }, false);
+        }
+    }
+}
+
+#[test]
+fn repack_sprites() {
+    let dir = game_dir("repack_sprites");
+    fs::create_dir_all(dir.join("mods/sprites/spr_player")).unwrap();
+    // one frame that fits on the page once it's been repacked, and one that's bigger than it
+    RgbaImage::from_pixel(12, 10, BLUE).save(dir.join("mods/sprites/spr_player/1.png")).unwrap();
+    RgbaImage::from_pixel(40, 20, GREEN).save(dir.join("mods/sprites/spr_player/0.png")).unwrap();
+    let file = run(&dir, gm_data_win::Args { mod_sprites: true, ..Default::default() });
+
+    assert_eq!(file.txtr.as_ref().unwrap().files.len(), 2);
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!(sprite.size, (40, 20));
+    assert_eq!(sprite.bounds, ((0, 39), (19, 0)));
+
+    let frame = file.resolve(sprite.tpag_offsets[0]).unwrap();
+    assert_eq!((frame.texture_index, frame.sprite_bounds.1, frame.size), (1, (40, 20), (40, 20)));
+    assert_eq!(file.get_texture(1).dimensions(), (64, 32));
+    let image = file.get_tpag_subimage(sprite.tpag_offsets[0]);
+    assert!(image.pixels().all(|&pixel| pixel == GREEN));
+
+    let frame = file.resolve(sprite.tpag_offsets[1]).unwrap();
+    assert_eq!((frame.texture_index, frame.sprite_bounds.1, frame.size), (0, (12, 10), (12, 10)));
+    let image = file.get_tpag_subimage(sprite.tpag_offsets[1]);
+    assert!(image.pixels().all(|&pixel| pixel == BLUE));
+
+    // everything else on the page moved to make room, but still shows the same pixels
+    let font = file.font("fnt_main").unwrap();
+    assert_eq!(font.sheet().unwrap().sprite_bounds.1, (16, 8));
+    let image = file.get_tpag_subimage(font.entire_font_tpag);
+    assert!(image.pixels().all(|&pixel| pixel == RED));
+    let tiles = file.get_tpag_subimage(file.backgrounds()[0].tpag_offset);
+    assert_eq!(tiles.dimensions(), (24, 8));
+    assert!(tiles.pixels().all(|&pixel| pixel == RED));
+}
+
+#[test]
+fn add_sprites() {
+    let dir = game_dir("add_sprites");
+    fs::create_dir_all(dir.join("mods/sprites/spr_auto")).unwrap();
+    fs::create_dir_all(dir.join("mods/sprites/spr_new")).unwrap();
+    fs::create_dir_all(dir.join("mods/code")).unwrap();
+    let mut frame = RgbaImage::new(12, 10);
+    for (x, y, pixel) in frame.enumerate_pixels_mut() {
+        if (2..9).contains(&x) && (3..7).contains(&y) {
+            *pixel = GREEN;
+        }
+    }
+    frame.save(dir.join("mods/sprites/spr_auto/0.png")).unwrap();
+    RgbaImage::from_pixel(16, 16, BLUE).save(dir.join("mods/sprites/spr_new/0.png")).unwrap();
+    RgbaImage::from_pixel(16, 12, GREEN).save(dir.join("mods/sprites/spr_new/1.png")).unwrap();
+    fs::write(
+        dir.join("mods/sprites/spr_new/sprite.json"),
+        r#"{"origin": [8, 15], "bounds": {"left": 2, "top": 1, "right": 13, "bottom": 15}}"#,
+    ).unwrap();
+    // new sprites are there by the time code is compiled
+    fs::write(dir.join("mods/code/gml_Object_obj_player_Create_0.gml"), "sprite_index = spr_new;\n").unwrap();
+    let file = run(&dir, gm_data_win::Args { mod_sprites: true, mod_code: true, ..Default::default() });
+
+    let auto = file.sprite("spr_auto").unwrap();
+    assert_eq!((auto.id, auto.size, auto.origin), (SpriteId(1), (12, 10), (0, 0)));
+    assert_eq!(auto.bounds, ((2, 8), (6, 3)));
+    assert_eq!(file.get_sprite_frame(1, 0).unwrap().into_raw(), frame.into_raw());
+
+    let sprite = file.sprite("spr_new").unwrap();
+    assert_eq!((sprite.id, sprite.size, sprite.origin), (SpriteId(2), (16, 16), (8, 15)));
+    assert_eq!(sprite.bounds, ((2, 13), (15, 1)));
+    assert_eq!(sprite.bbox_mode, 2);
+    // one mask filling the bounds, 2 bytes a row
+    let masks = sprite.masks.as_ref().unwrap();
+    assert_eq!((masks.len(), masks[0].len()), (1, 2 * 16));
+    assert_eq!(masks[0][0..2], [0, 0]);
+    assert_eq!(masks[0][2..4], [0b0011_1111, 0b1111_1100]);
+    let frames = sprite.frames().collect::<Vec<_>>();
+    assert_eq!((frames[0].sprite_bounds.1, frames[1].sprite_bounds.1), ((16, 16), (16, 12)));
+    assert_eq!(frames[1].size, (16, 16));
+    assert!(sprite.frame_image(0).unwrap().pixels().all(|&pixel| pixel == BLUE));
+    assert!(file.get_tpag_subimage(sprite.tpag_offsets[1]).pixels().all(|&pixel| pixel == GREEN));
+    // the original frames may have been packed elsewhere to make room, but look the same
+    let player = file.sprite("spr_player").unwrap();
+    assert_eq!(file.resolve(player.tpag_offsets[1]).unwrap().sprite_bounds.1, (8, 8));
+    assert!(file.get_tpag_subimage(player.tpag_offsets[1]).pixels().all(|&pixel| pixel == RED));
+
+    assert!(gm_data_win::disasm::disassemble(&file, 0).unwrap().starts_with("pushi.e 2\n"));
+}
+
+#[test]
+fn change_frame_counts() {
+    let dir = game_dir("change_frame_counts");
+    let frames = dir.join("mods/sprites/spr_player");
+    let inject = || run(&dir, gm_data_win::Args { mod_sprites: true, ..Default::default() });
+
+    // every frame from 0 on, but fewer than there were
+    fs::create_dir_all(&frames).unwrap();
+    RgbaImage::from_pixel(8, 8, BLUE).save(frames.join("0.png")).unwrap();
+    let file = inject();
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!(sprite.tpag_offsets.len(), 1);
+    assert_eq!(file.tpag.as_ref().unwrap().texture_pages.len(), 3);
+    assert!(sprite.frame_image(0).unwrap().pixels().all(|&pixel| pixel == BLUE));
+    // the old second frame's spot is free
+    assert_eq!(file.get_texture(0).get_pixel(8, 0), Rgba([0, 0, 0, 0]));
+    assert_eq!(file.get_texture(0).get_pixel(0, 16), RED);
+
+    // frames past the end, with a gap before the last
+    fs::remove_dir_all(&frames).unwrap();
+    fs::create_dir_all(&frames).unwrap();
+    RgbaImage::from_pixel(8, 8, GREEN).save(frames.join("1.png")).unwrap();
+    RgbaImage::from_pixel(16, 12, GREEN).save(frames.join("2.png")).unwrap();
+    RgbaImage::from_pixel(8, 8, GREEN).save(frames.join("4.png")).unwrap();
+    let file = inject();
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!(sprite.tpag_offsets.len(), 3);
+    assert_eq!((sprite.size, sprite.bounds), ((16, 12), ((0, 15), (11, 0))));
+    assert!(file.get_tpag_subimage(sprite.tpag_offsets[0]).pixels().all(|&pixel| pixel == BLUE));
+    assert_eq!(file.get_tpag_subimage(sprite.tpag_offsets[1]).dimensions(), (8, 8));
+    assert!(sprite.frame_image(2).unwrap().pixels().all(|&pixel| pixel == GREEN));
+    assert_eq!(sprite.frames().map(|frame| frame.size).collect::<Vec<_>>(), [(8, 8), (16, 12), (16, 12)]);
+    assert!(file.font("fnt_main").unwrap().sheet().is_some());
+}
+
+#[test]
+fn sprite_settings() {
+    let dir = game_dir("sprite_settings");
+    run(&dir, gm_data_win::Args { extract_sprites: true, ..Default::default() });
+    let extracted = fs::read_to_string(dir.join("files/sprites/spr_player/sprite.json")).unwrap();
+    assert_eq!(extracted, r#"{
+  "size": [8, 8],
+  "origin": [0, 0],
+  "bounds": {
+    "left": 0,
+    "top": 0,
+    "right": 7,
+    "bottom": 7
+  },
+  "bounds_mode": "automatic",
+  "collision": "rectangle",
+  "mask_shape": "rectangle",
+  "mask_per_frame": false,
+  "playback_speed": 1,
+  "playback_speed_type": "frames_per_second"
+}
+"#);
+
+    // a copy of the extracted settings, with a new origin and speed, and a frame that's grown
+    fs::create_dir_all(dir.join("mods/sprites/spr_player")).unwrap();
+    let edited = extracted
+        .replace(r#""origin": [0, 0]"#, r#""origin": [4, 8]"#)
+        .replace(r#""playback_speed": 1,"#, r#""playback_speed": 0.25,"#)
+        .replace("frames_per_second", "frames_per_game_frame");
+    fs::write(dir.join("mods/sprites/spr_player/sprite.json"), edited).unwrap();
+    RgbaImage::from_pixel(10, 8, BLUE).save(dir.join("mods/sprites/spr_player/1.png")).unwrap();
+    let file = run(&dir, gm_data_win::Args { mod_sprites: true, ..Default::default() });
+
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!((sprite.origin, sprite.size, sprite.bounds), ((4, 8), (10, 8), ((0, 9), (7, 0))));
+    assert_eq!((sprite.playback_speed, sprite.playback_speed_type, sprite.bbox_mode), (0.25, 1, 0));
+    assert_eq!(sprite.tpag_offsets.len(), 2);
+
+    // a bad setting leaves the sprite alone
+    fs::write(dir.join("mods/sprites/spr_player/sprite.json"), r#"{"bounds_mode": "round"}"#).unwrap();
+    let file = run(&dir, gm_data_win::Args { mod_sprites: true, ..Default::default() });
+    assert_eq!(file.sprite("spr_player").unwrap().bbox_mode, 0);
+}
+
+#[test]
+fn collision_masks() {
+    let dir = game_dir("collision_masks");
+    let folder = dir.join("mods/sprites/spr_player");
+    let mod_sprites = || gm_data_win::Args { mod_sprites: true, ..Default::default() };
+    // the sprite is 8 wide, so a byte a row
+    let solid = |mask: &[u8], (x, y): (usize, usize)| mask[y] & (0x80 >> x) != 0;
+
+    // a square in one frame and a dot in the other, each with a mask of its own
+    fs::create_dir_all(&folder).unwrap();
+    let mut square = RgbaImage::new(8, 8);
+    for y in 2..6 {
+        for x in 2..6 {
+            square.put_pixel(x, y, BLUE);
+        }
+    }
+    square.save(folder.join("0.png")).unwrap();
+    let mut dot = RgbaImage::new(8, 8);
+    dot.put_pixel(7, 7, BLUE);
+    dot.save(folder.join("1.png")).unwrap();
+    fs::write(folder.join("sprite.json"), r#"{"mask_shape": "precise", "mask_per_frame": true}"#).unwrap();
+    let file = run(&dir, mod_sprites());
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!((sprite.bounds, sprite.collision_type), (((2, 7), (7, 2)), 1));
+    let masks = sprite.masks.as_ref().unwrap();
+    assert_eq!(masks.len(), 2);
+    assert_eq!(masks[0], [0, 0, 0b0011_1100, 0b0011_1100, 0b0011_1100, 0b0011_1100, 0, 0]);
+    assert_eq!(masks[1], [0, 0, 0, 0, 0, 0, 0, 1]);
+
+    run(&dir, gm_data_win::Args { extract_sprites: true, ..Default::default() });
+    let mask = image::open(dir.join("files/masks/spr_player/0.png")).unwrap().to_luma();
+    assert_eq!((mask.get_pixel(2, 2)[0], mask.get_pixel(1, 2)[0]), (0xff, 0));
+    assert!(!dir.join("files/sprites/spr_player/mask.png").exists());
+
+    // one ellipse across the whole sprite
+    fs::write(
+        folder.join("sprite.json"),
+        r#"{"bounds_mode": "full", "mask_shape": "ellipse", "mask_per_frame": false}"#,
+    ).unwrap();
+    let file = run(&dir, mod_sprites());
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!(sprite.bounds, ((0, 7), (7, 0)));
+    let masks = sprite.masks.as_ref().unwrap();
+    assert_eq!(masks.len(), 1);
+    assert!(solid(&masks[0], (3, 0)) && solid(&masks[0], (0, 4)) && solid(&masks[0], (4, 4)));
+    assert!(!solid(&masks[0], (0, 0)) && !solid(&masks[0], (7, 7)));
+}
+
+#[test]
+fn sprites_without_sections() {
+    use gm_data_win::sprites::{self, SpriteSettings};
+
+    // new frames need a texture page to go on
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+    file.txtr = None;
+    let pages = file.tpag.as_ref().unwrap().texture_pages.len();
+    let frames = vec![RgbaImage::new(4, 4)];
+    let err = sprites::add_sprite(&mut file, "spr_new", frames.clone(), &SpriteSettings::default()).unwrap_err();
+    assert_eq!(err, MissingSection("TXTR"));
+    let err = sprites::add_frames(&mut file, SpriteId(0), frames).unwrap_err();
+    assert_eq!(err, MissingSection("TXTR"));
+    assert_eq!(file.tpag.as_ref().unwrap().texture_pages.len(), pages);
+    assert!(file.sprite("spr_new").is_none());
+
+    file.sprt = None;
+    let settings = SpriteSettings { origin: Some((1, 1)), ..Default::default() };
+    assert_eq!(settings.apply(&mut file, SpriteId(0)), Err(MissingSection("SPRT")));
+    assert_eq!(sprites::truncate_frames(&mut file, SpriteId(0), 1), Err(MissingSection("SPRT")));
+}
+
+#[test]
+fn truncated_file_errors() {
+    // FORM claims 0x10 bytes but only holds a lone section header
+    let data = b"FORM\x10\x00\x00\x00STRG\x08\x00\x00\x00";
+    let err = gm_data_win::try_take_data_win_file(data).unwrap_err();
+    assert_eq!(err.section(), Some("FORM"));
+    assert_eq!(err.offset(), Some(8));
+
+    // STRG with a string pointer past the end of the section, reported where the pointer is
+    let data = b"FORM\x14\x00\x00\x00STRG\x0c\x00\x00\x00\x01\x00\x00\x00\x00\x10\x00\x00\x00\x00\x00\x00";
+    let err = gm_data_win::try_take_data_win_file(data).unwrap_err();
+    assert_eq!(err.section(), Some("STRG"));
+    assert_eq!(err.to_string(), "STRG at 0x14: offset 0x1000 points outside section");
+}
+
+#[test]
+fn write_unchanged_strings() {
+    // STRG with two strings, the second one padded to 4 bytes
+    let data = b"FORM\x28\x00\x00\x00STRG\x20\x00\x00\x00\
+        \x02\x00\x00\x00\x1c\x00\x00\x00\x24\x00\x00\x00\
+        \x02\x00\x00\x00hi\x00\x00\
+        \x03\x00\x00\x00abc\x00\
+        \x00\x00\x00\x00";
+    let sections = gm_data_win::try_take_data_win_file(data).unwrap();
+    let file = gm_data_win::file_structs::FormFile::from_sections(sections);
+    assert_eq!(file.to_bytes().unwrap(), data);
+}
+
+#[test]
+fn vari_size() {
+    // a GMS2 VARI with one variable, then whatever comes after it
+    let vari = |tail: &[u8]| {
+        let mut body = [1u32, 1, 0, 0x100, 0, 0, 0, 0].iter().flat_map(|val| val.to_le_bytes()).collect::<Vec<_>>();
+        body.extend_from_slice(tail);
+        let mut data = b"FORM".to_vec();
+        data.extend_from_slice(&(body.len() as u32 + 8).to_le_bytes());
+        data.extend_from_slice(b"VARI");
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(&body);
+        data
+    };
+
+    // padding is fine
+    let file = FormFile::from_sections(gm_data_win::try_take_data_win_file(&vari(&[0; 4])).unwrap());
+    assert_eq!(file.vari.as_ref().unwrap().variables.len(), 1);
+
+    // anything else that doesn't make up a whole entry isn't
+    let error = gm_data_win::try_take_data_win_file(&vari(&[0, 0, 0xff, 0])).unwrap_err();
+    assert_eq!((error.section(), error.offset()), (Some("VARI"), Some(0x32)));
+}
+
+#[test]
+fn verify_roundtrip() {
+    let data = b"FORM\x28\x00\x00\x00STRG\x20\x00\x00\x00\
+        \x02\x00\x00\x00\x1c\x00\x00\x00\x24\x00\x00\x00\
+        \x02\x00\x00\x00hi\x00\x00\
+        \x03\x00\x00\x00abc\x00\
+        \x00\x00\x00\x00";
+    assert_eq!(gm_data_win::verify_roundtrip(data).unwrap(), None);
+
+    // non-zero padding after "hi" can't be reproduced
+    let mut data = data.to_vec();
+    data[0x23] = 0xff;
+    let mismatch = gm_data_win::verify_roundtrip(&data).unwrap().unwrap();
+    assert_eq!(mismatch.offset, 0x23);
+    assert_eq!(mismatch.section, "STRG");
+    assert_eq!(mismatch.to_string(), "first difference at 0x23 in STRG");
+}
+
+#[test]
+fn keeps_every_section() {
+    let mut sections = gm_data_win::take_data_win_file(&fixture().build());
+    // after GEN8, where GameMaker puts it
+    sections.insert(1, gm_data_win::file_structs::Section::Unk {
+        name: String::from("LANG"),
+        data: vec![1, 0, 0, 0],
+        offset: 0,
+    });
+    let data = gm_data_win::file_structs::write_data_win_file(&sections);
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+
+    let strg = file.strg.as_ref().unwrap();
+    assert_eq!(file.gen8().unwrap().bytecode_version(), 16);
+    assert_eq!(strg.get(file.objects()[0].name_offset).unwrap(), "obj_player");
+    assert_eq!(file.rooms()[0].game_objects[0].x, 16);
+    assert_eq!(file.unknown_section("LANG"), Some(&[1, 0, 0, 0][..]));
+    assert_eq!(
+        file.section_order().collect::<Vec<_>>(),
+        ["GEN8", "LANG", "SOND", "AGRP", "SPRT", "BGND", "SCPT", "GLOB", "FONT", "OBJT", "ROOM", "TPAG", "CODE", "VARI", "FUNC", "STRG", "TXTR", "AUDO"]
+    );
+    assert_eq!(file.to_bytes().unwrap(), data);
+}
+
+#[test]
+fn unknown_section_pointers() {
+    use gm_data_win::file_structs::{write_data_win_file, Section};
+    use gm_data_win::take_data_win_file;
+
+    // PATH before STRG, like in real files, with a pointer into itself and one to a string
+    let path = |data: Vec<u8>| Section::Unk { name: String::from("PATH"), data, offset: 0 };
+    let mut sections = take_data_win_file(&fixture().build());
+    let at = sections.iter().position(|section| matches!(section, Section::Sprt(_))).unwrap() + 1;
+    sections.insert(at, path(vec![0; 12]));
+    let data = write_data_win_file(&sections);
+    let offset = match &take_data_win_file(&data)[at] {
+        Section::Unk { offset, .. } => *offset,
+        _ => unreachable!(),
+    };
+    let name = FormFile::from_sections(take_data_win_file(&data)).name_to_offset("spr_player").unwrap().0;
+    sections[at] = path([1, offset + 16, name].iter().flat_map(|val| val.to_le_bytes()).collect());
+    let data = write_data_win_file(&sections);
+
+    let mut file = FormFile::from_sections(take_data_win_file(&data));
+    assert_eq!(file.to_bytes().unwrap(), data);
+    // a new string moves every string PATH may point to
+    file.strg.as_mut().unwrap().intern("spr_hero");
+    assert_eq!(
+        file.to_bytes().unwrap_err().to_string(),
+        "STRG would move, and the pointers PATH may have into it can't be relocated"
+    );
+
+    // and a new sprite moves PATH itself
+    let mut file = FormFile::from_sections(take_data_win_file(&data));
+    let sprite = file.sprt.as_ref().unwrap().sprites[0].clone();
+    file.sprt.as_mut().unwrap().sprites.push(sprite);
+    assert_eq!(
+        file.to_bytes().unwrap_err().to_string(),
+        "PATH would move, and the pointers in it can't be relocated"
+    );
+}
+
+#[test]
+fn typed_references() {
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+    let object = &file.objects()[0];
+    let sprite_id = object.sprite().unwrap();
+    assert_eq!(sprite_id, SpriteId(0));
+    assert_eq!(object.parent(), None);
+    assert_eq!(file.rooms()[0].game_objects[0].object(), Some(ObjectId(0)));
+    assert_eq!(file.resolve(file.scpt.as_ref().unwrap().scripts[0].code()).unwrap().length, 4);
+    assert!(file.resolve(SpriteId(1)).is_none());
+
+    let sprite = file.resolve(sprite_id).unwrap();
+    assert_eq!(file.resolve(sprite.name_offset), Some("spr_player"));
+    let frame = sprite.tpag_offsets[1];
+    assert_eq!(file.resolve(frame).unwrap().sprite_bounds, ((8, 0), (8, 8)));
+
+    // a string added since loading moves STRG around, but references to it and to the
+    // original strings still point at the right ones once written out
+    let renamed = file.strg.as_mut().unwrap().intern("spr_hero");
+    file.sprt.as_mut().unwrap().sprites[0].name_offset = renamed;
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&file.to_bytes().unwrap()));
+    let sprite = file.resolve(SpriteId(0)).unwrap();
+    assert_eq!(file.resolve(sprite.name_offset), Some("spr_hero"));
+    assert_eq!(file.resolve(file.objects()[0].name_offset), Some("obj_player"));
+    assert_eq!(file.resolve(sprite.tpag_offsets[1]).unwrap().sprite_bounds, ((8, 0), (8, 8)));
+}
+
+#[test]
+fn lookup_by_name() {
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+    let sprite = file.sprite("spr_player").unwrap();
+    assert_eq!(sprite.id, SpriteId(0));
+    assert_eq!(sprite.frames().map(|frame| frame.sprite_bounds).collect::<Vec<_>>(), [((0, 0), (8, 8)), ((8, 0), (8, 8))]);
+    assert_eq!(sprite.frame_image(1).unwrap().dimensions(), (8, 8));
+    assert_eq!(file.sound("snd_jump").unwrap().data(), Some(&b"RIFF jump"[..]));
+    // audiogroup1.dat isn't loaded
+    assert_eq!(file.sound("snd_music").unwrap().data(), None);
+    assert_eq!(file.background("bg_tiles").unwrap().tile_set.as_ref().unwrap().columns, 3);
+    assert_eq!(file.font("fnt_main").unwrap().font_name(), Some("Arial"));
+    assert_eq!(file.object("obj_player").unwrap().sprite().unwrap().name, "spr_player");
+    let room = file.room("rm_start").unwrap();
+    assert_eq!(room.width, 320);
+    let (instance, object) = room.instances().next().unwrap();
+    assert_eq!((instance.x, object.unwrap().name), (16, "obj_player"));
+    assert_eq!(file.script("scr_empty").unwrap().code().unwrap().length, 4);
+    assert_eq!(file.code_entry("gml_Script_scr_empty").unwrap().id.0, 1);
+    assert!(file.sprite("obj_player").is_none());
+
+    let strg = file.strg.as_mut().unwrap();
+    let existing = strg.find("spr_player").unwrap();
+    assert_eq!(strg.intern("spr_player"), existing);
+    let renamed = strg.intern("spr_hero");
+    assert_eq!(strg.find("spr_hero"), Some(renamed));
+    file.sprt.as_mut().unwrap().sprites[0].name_offset = renamed;
+    assert!(file.sprite("spr_player").is_none());
+    assert!(file.sprite("spr_hero").is_none());
+    file.reindex();
+    assert_eq!(file.sprite("spr_hero").unwrap().id, SpriteId(0));
+}
+
+#[test]
+fn code_layouts() {
+    for inline in [false, true] {
+        let mut builder = fixture();
+        if inline {
+            builder.inline_code();
+        }
+        let data = builder.build();
+        assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+        let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+        let code = file.code.as_ref().unwrap();
+        let strg = file.strg.as_ref().unwrap();
+        let layout = if inline { CodeLayout::Inline } else { CodeLayout::Shared };
+        assert_eq!(code.layout, layout);
+
+        let entry = &file.code_entries()[1];
+        assert_eq!(strg.get(entry.name_offset).unwrap(), "gml_Script_scr_empty");
+        assert_eq!(code.bytecode_of(entry), [0x00, 0x00, 0x02, 0x9d]);
+        assert_eq!(entry.arguments_count, if inline { 0 } else { 2 });
+    }
+}
+
+#[test]
+fn scripts() {
+    let mut builder = fixture();
+    builder.global_init(1);
+    let data = builder.build();
+    assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+    let strg = file.strg.as_ref().unwrap();
+    let (script, code) = file.script_by_name("scr_empty").unwrap();
+    assert_eq!(script.code_index(), 1);
+    assert!(!script.is_constructor());
+    assert_eq!(strg.get(code.name_offset).unwrap(), "gml_Script_scr_empty");
+    assert!(file.script_by_name("scr_missing").is_none());
+    assert_eq!(file.glob.as_ref().unwrap().code_ids, [1]);
+}
+
+#[test]
+fn object_events() {
+    let mut builder = fixture();
+    builder.event(0, ObjectEvent::Step(StepEvent::BeginStep), 1);
+    builder.event(0, ObjectEvent::Collision(0), 1);
+    let data = builder.build();
+    assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+    let strg = file.strg.as_ref().unwrap();
+    let events = file.objects()[0].events().map(|(event, _)| event.to_string()).collect::<Vec<_>>();
+    assert_eq!(events, ["Create_0", "Step_1", "Collision_0"]);
+
+    let create = file.object_event_code("obj_player", ObjectEvent::Create);
+    assert_eq!(create.len(), 1);
+    assert_eq!(create[0].0, 0);
+    assert_eq!(strg.get(create[0].1.name_offset).unwrap(), "gml_Object_obj_player_Create_0");
+    assert_eq!(file.object_event_code("obj_player", ObjectEvent::Step(StepEvent::BeginStep))[0].0, 1);
+    assert!(file.object_event_code("obj_player", ObjectEvent::Step(StepEvent::Step)).is_empty());
+    assert_eq!(ObjectEvent::new(3, 2), ObjectEvent::Step(StepEvent::EndStep));
+    assert_eq!(ObjectEvent::new(3, 7), ObjectEvent::Unknown { event_type: 3, subtype: 7 });
+}
+
+#[test]
+fn object_hierarchy() {
+    let mut builder = fixture();
+    let enemy = builder.object("obj_enemy", -1);
+    let slime = builder.object("obj_slime", -1);
+    let boss = builder.object("obj_boss", 0);
+    builder.parent(enemy, 0).parent(slime, enemy).parent(boss, enemy);
+    builder.event(enemy, ObjectEvent::Step(StepEvent::Step), 1);
+    builder.event(slime, ObjectEvent::Create, 1);
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+
+    let hierarchy = file.object_hierarchy();
+    assert_eq!(hierarchy.tree(), "obj_player\n    obj_enemy\n        obj_slime\n        obj_boss\n");
+    assert_eq!(hierarchy.ancestors(2).collect::<Vec<_>>(), [1, 0]);
+    assert_eq!(hierarchy.descendants(0), [1, 2, 3]);
+    assert!(hierarchy.to_dot().contains("    \"obj_enemy\" -> \"obj_slime\";\n"));
+
+    // the slime overrides Create, and inherits Step from obj_enemy
+    let events = file.inherited_events(2).into_iter()
+        .map(|(event, owner, _)| (event, owner))
+        .collect::<Vec<_>>();
+    assert_eq!(events, [(ObjectEvent::Create, 2), (ObjectEvent::Step(StepEvent::Step), 1)]);
+    assert_eq!(file.inherited_event(3, ObjectEvent::Create).unwrap().0, 0);
+    assert_eq!(file.effective_object(2).sprite_index, 0);
+    assert_eq!(file.effective_object(1).texture_mask_index, -1);
+}
+
+#[test]
+fn room_layers() {
+    let mut builder = fixture();
+    let white = || RgbaColor { r: 0xff, g: 0xff, b: 0xff, a: 0xff };
+    let instance = AssetInstance {
+        name_offset: builder.string("graphic_1"),
+        asset_index: 0,
+        x: 40,
+        y: 48,
+        scale_x: 2.0,
+        scale_y: 1.0,
+        color: white(),
+        animation_speed: 1.0,
+        animation_speed_unit: SpeedUnit::FramesPerGameFrame,
+        frame_index: 1.0,
+        rotation: 90.0,
+    };
+    let effect_type = builder.string("_filter_tintfilter");
+    let rooms = [builder.room("rm_gms2", (64, 64), &[]), builder.room("rm_gms23", (64, 64), &[])];
+    builder
+        .layer(rooms[0], "Assets", 50, LayerKind::Assets(AssetLayer {
+            sprites: vec![instance.clone()],
+            ..AssetLayer::default()
+        }))
+        .layer(rooms[0], "Background", 200, LayerKind::Background {
+            visible: true,
+            foreground: false,
+            sprite_index: 0,
+            horizontal_tile: true,
+            vertical_tile: false,
+            stretch: false,
+            color: white(),
+            first_frame: 0.0,
+            animation_speed: 15.0,
+            animation_speed_unit: SpeedUnit::FramesPerSecond,
+        })
+        .layer(rooms[1], "Assets", 50, LayerKind::Assets(AssetLayer {
+            sequences: Some(vec![instance.clone()]),
+            nine_slices: Some(Vec::new()),
+            ..AssetLayer::default()
+        }))
+        .layer(rooms[1], "Effect", 10, LayerKind::Effect { effect_type_offset: effect_type, properties: Vec::new() })
+        .layer(rooms[1], "Path", 0, LayerKind::Path);
+    let data = builder.build();
+    assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+    let strg = file.strg.as_ref().unwrap();
+    let start = &file.rooms()[0];
+    let player = start.layer_instances(&start.layers[0]).collect::<Vec<_>>();
+    assert_eq!(player.len(), 1);
+    assert_eq!(player[0].x, 16);
+    match &start.layers[1].kind {
+        LayerKind::Tiles { width, height, tiles, .. } => {
+            assert_eq!((*width, *height), (2, 2));
+            assert_eq!(tiles.iter().map(|tile| tile & TILE_INDEX_MASK).collect::<Vec<_>>(), [1, 0, 2, 1]);
+        }
+        kind => panic!("expected a tile layer, got {:?}", kind),
+    }
+    assert_eq!(start.layers[1].depth, 100);
+
+    let gms2 = &file.rooms()[1];
+    match &gms2.layers[0].kind {
+        LayerKind::Assets(assets) => {
+            assert!(assets.sequences.is_none());
+            assert_eq!(strg.get(assets.sprites[0].name_offset).unwrap(), "graphic_1");
+            assert_eq!((assets.sprites[0].x, assets.sprites[0].rotation), (40, 90.0));
+        }
+        kind => panic!("expected an asset layer, got {:?}", kind),
+    }
+    assert!(matches!(gms2.layers[1].kind, LayerKind::Background { horizontal_tile: true, .. }));
+
+    let gms23 = &file.rooms()[2];
+    match &gms23.layers[0].kind {
+        LayerKind::Assets(assets) => {
+            assert!(assets.sprites.is_empty());
+            assert_eq!(assets.sequences.as_ref().unwrap()[0].y, 48);
+            assert_eq!(assets.nine_slices.as_ref().map(Vec::len), Some(0));
+        }
+        kind => panic!("expected an asset layer, got {:?}", kind),
+    }
+    assert_eq!(gms23.layers.iter().map(|layer| layer.kind.id()).collect::<Vec<_>>(), [3, 6, 0]);
+}
+
+#[test]
+fn tiled_export() {
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+    let map = gm_data_win::tiled::export_room(&file, 0);
+    assert_eq!((map.width, map.height, map.tile_width), (40, 30, 8));
+
+    let tmx = map.to_tmx();
+    assert!(tmx.contains("<tileset firstgid=\"1\" name=\"bg_tiles\" tilewidth=\"8\" tileheight=\"8\" spacing=\"0\" margin=\"0\" tilecount=\"3\" columns=\"3\">"));
+    // the tile layer is deeper, so it's drawn first
+    assert!(tmx.contains("<layer id=\"1\" name=\"Tiles\" width=\"2\" height=\"2\">"));
+    assert!(tmx.contains("<data encoding=\"csv\">\n2,0,\n3,2147483650\n</data>"));
+    assert!(tmx.contains("<object id=\"1\" type=\"obj_player\" gid=\"4\" x=\"16\" y=\"40\" width=\"8\" height=\"8\">"));
+    assert!(tmx.contains("<property name=\"instance_id\" type=\"int\" value=\"100000\"/>"));
+
+    let json = map.to_json();
+    assert!(json.contains("\"data\": [2, 0, 3, 2147483650]"));
+    assert!(json.contains("\"type\": \"obj_player\""));
+
+    let dir = temp_dir("tiled_export");
+    map.save(dir.join("rm_start.tmx")).unwrap();
+    assert!(dir.join("bg_tiles.png").exists());
+    assert_eq!(image::open(dir.join("spr_player.png")).unwrap().to_rgba().dimensions(), (8, 8));
+}
+
+#[test]
+fn tiled_import() {
+    use gm_data_win::tiled::{import_room, TiledLayerKind, TiledMap};
+
+    let original = fixture().build();
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&original));
+    let map = gm_data_win::tiled::export_room(&file, 0);
+
+    // an unchanged map, whether TMX or JSON, imports back to the same room
+    import_room(&mut file, 0, &TiledMap::from_tmx(&map.to_tmx()).unwrap()).unwrap();
+    import_room(&mut file, 0, &TiledMap::from_json(&map.to_json()).unwrap()).unwrap();
+    assert_eq!(file.to_bytes().unwrap(), original);
+
+    let mut map = TiledMap::from_tmx(&map.to_tmx()).unwrap();
+    for layer in &mut map.layers {
+        match &mut layer.kind {
+            TiledLayerKind::Objects(objects) => {
+                // move the player, and add a copy of it that needs a new instance id
+                objects[0].x += 8.0;
+                let mut copy = objects[0].clone();
+                copy.id = 2;
+                copy.y += 16.0;
+                objects.push(copy);
+            }
+            TiledLayerKind::Tiles { data, .. } => data[1] = 3 | gm_data_win::tiled::FLIPPED_DIAGONALLY,
+            TiledLayerKind::Image { .. } => {}
+        }
+    }
+    import_room(&mut file, 0, &map).unwrap();
+
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&file.to_bytes().unwrap()));
+    let room = &file.rooms()[0];
+    let positions = room.game_objects.iter()
+        .map(|instance| (instance.instance_id, instance.x, instance.y))
+        .collect::<Vec<_>>();
+    assert_eq!(positions, [(100_000, 24, 32), (100_001, 24, 48)]);
+    match &room.layers[0].kind {
+        LayerKind::Instances { instance_ids } => assert_eq!(instance_ids, &[100_000, 100_001]),
+        other => panic!("expected an instance layer, found {:?}", other),
+    }
+    match &room.layers[1].kind {
+        // a diagonal flip alone is a rotation and a flip
+        LayerKind::Tiles { tiles, .. } => assert_eq!(tiles[1], 2 | 0x6000_0000),
+        other => panic!("expected a tile layer, found {:?}", other),
+    }
+
+    // objects have to name an object that exists
+    let mut file = file;
+    let mut map = gm_data_win::tiled::export_room(&file, 0);
+    if let TiledLayerKind::Objects(objects) = &mut map.layers[1].kind {
+        objects[0].kind = String::from("obj_missing");
+    }
+    let error = import_room(&mut file, 0, &map).unwrap_err();
+    assert_eq!(error.to_string(), "object 1 on layer 'Instances' can't be imported, there is no object named 'obj_missing'");
+}
+
+#[test]
+fn render_room() {
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&fixture().build()));
+    let image = gm_data_win::render::render_room(&file, 0);
+    assert_eq!(image.dimensions(), (320, 240));
+    let black = Rgba([0, 0, 0, 0xff]);
+    // the first tile, then an empty one, and the player's 8x8 sprite at (16, 32)
+    assert_eq!(*image.get_pixel(0, 0), RED);
+    assert_eq!(*image.get_pixel(8, 0), black);
+    assert_eq!(*image.get_pixel(23, 39), RED);
+    assert_eq!(*image.get_pixel(24, 32), black);
+
+    // scaled, rotated and tinted
+    let instance = &mut file.room.as_mut().unwrap().entries[0].game_objects[0];
+    instance.scale_x = 2.0;
+    instance.rotation = 90.0;
+    instance.color = RgbaColor { r: 0x80, g: 0xff, b: 0xff, a: 0xff };
+    let image = gm_data_win::render::render_room(&file, 0);
+    // 16 wide and 8 high, turned to stand up from (16, 32)
+    assert_eq!(*image.get_pixel(16, 31), Rgba([0x80, 0, 0, 0xff]));
+    assert_eq!(*image.get_pixel(16, 15), black);
+    assert_eq!(*image.get_pixel(23, 16), Rgba([0x80, 0, 0, 0xff]));
+    assert_eq!(*image.get_pixel(24, 16), black);
+
+    // background colours have red in the low byte
+    file.room.as_mut().unwrap().entries[0].argb = 0x0030_2010;
+    let image = gm_data_win::render::render_room(&file, 0);
+    assert_eq!(*image.get_pixel(300, 200), Rgba([0x10, 0x20, 0x30, 0xff]));
+
+    let dir = temp_dir("render_room");
+    fs::write(dir.join("data.win"), file.to_bytes().unwrap()).unwrap();
+    run(&dir, gm_data_win::Args {
+        command: Some(gm_data_win::Command::RenderRoom {
+            room: String::from("rm_start"),
+            output: Some(dir.join("rm_start.png").to_str().unwrap().to_owned()),
+        }),
+        ..Default::default()
+    });
+    assert_eq!(image::open(dir.join("rm_start.png")).unwrap().to_rgba().into_raw(), image.into_raw());
+}
+
+/// The fixture with a script calling a function, and VARI and FUNC entries naming every
+/// reference, along with the string id of "hello"
+fn scripted_fixture() -> (DataWinBuilder, u32) {
+    let mut builder = fixture();
+    let hello = builder.string_id("hello");
+    let show_message = builder.string_id("show_message");
+    builder.code("gml_Script_scr_test", &[
+        0x00, 0x00, 0x06, 0xc0, hello as u8, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x02, 0xd9, show_message as u8, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x05, 0x9e,
+        0x00, 0x00, 0x0f, 0x84,
+        0x02, 0x00, 0x00, 0xb8,
+        0x00, 0x00, 0x02, 0x9d,
+    ], 0, 0);
+    builder.variable("x", -1, &[(0, 4)]);
+    builder.function("show_message", &[(2, 8)]);
+    (builder, hello)
+}
+
+#[test]
+fn disassemble() {
+    let (builder, hello) = scripted_fixture();
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+    assert_eq!(
+        gm_data_win::disasm::disassemble(&file, 0).unwrap(),
+        "pushi.e 1\npop.v.i self.x\nexit.i\n"
+    );
+    assert_eq!(
+        gm_data_win::disasm::disassemble(&file, 2).unwrap(),
+        "push.s \"hello\"@0\ncall.i show_message(argc=1)\npopz.v\npushi.e 0\nbf [0]\nexit.i\n:[0]\n"
+            .replace("@0", &format!("@{}", hello))
+    );
+    let error = gm_data_win::disasm::disassemble(&file, 3).unwrap_err();
+    assert_eq!(error.to_string(), "CODE has no entry 3");
+}
+
+#[test]
+fn assemble() {
+    let (builder, _) = scripted_fixture();
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+
+    // the disassembly assembles back to the same bytecode, once references are linked
+    let bytecode = |file: &FormFile| {
+        let code = file.code.as_ref().unwrap();
+        code.bytecode_of(&code.entries[2]).to_vec()
+    };
+    let original = bytecode(&file);
+    let listing = gm_data_win::disasm::disassemble(&file, 2).unwrap();
+    gm_data_win::asm::assemble_into(&mut file, 2, &listing).unwrap();
+    assert_eq!(bytecode(&file), original);
+
+    let strg = file.strg.as_mut().unwrap();
+    let error = gm_data_win::asm::assemble("pushi.e 1\nb [3]\n", CodeLayout::Shared, strg).unwrap_err();
+    assert_eq!(error.to_string(), "line 2: no label [3]");
+    let mut stripped = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+    stripped.strg = None;
+    let error = gm_data_win::asm::assemble_into(&mut stripped, 2, "exit.i\n").unwrap_err();
+    assert_eq!(error.to_string(), "no STRG section");
+    stripped.code = None;
+    assert_eq!(stripped.replace_bytecode(2, &[], &[]), Err(MissingSection("CODE")));
+
+    // growing the first script moves the others, and new names get strings and VARI/FUNC entries
+    let listing = "push.s \"new\\n\"\ncall.i show_message(argc=1)\npopz.v\n\
+        pushi.e 2\npop.v.i self.y\npushloc.v local.i\npop.v.v self.x\ncall.i scr_new(argc=0)\nexit.i\n";
+    gm_data_win::asm::assemble_into(&mut file, 0, listing).unwrap();
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&file.to_bytes().unwrap()));
+    let strg = file.strg.as_ref().unwrap();
+    let new = strg.strings.iter().position(|string| string == "new\n").unwrap();
+    assert_eq!(
+        gm_data_win::disasm::disassemble(&file, 0).unwrap(),
+        listing.replace("\\n\"", &format!("\\n\"@{}", new))
+    );
+    assert_eq!(
+        gm_data_win::disasm::disassemble(&file, 2).unwrap(),
+        format!("push.s \"hello\"@{}\ncall.i show_message(argc=1)\npopz.v\npushi.e 0\nbf [0]\nexit.i\n:[0]\n",
+            strg.strings.iter().position(|string| string == "hello").unwrap())
+    );
+
+    let vari = file.vari.as_ref().unwrap();
+    let names = vari.variables.iter()
+        .map(|variable| (&strg.get(variable.name_offset).unwrap()[..], variable.instance_type, variable.id))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [("x", -1, 0), ("y", -1, 1), ("i", -7, 0)]);
+    assert_eq!(vari.header, Some((2, 2, 1)));
+    let func = file.func.as_ref().unwrap();
+    assert_eq!(func.functions.iter().map(|function| function.occurrences).collect::<Vec<_>>(), [2, 1]);
+    assert_eq!(file.code_entries()[0].locals_count, 1);
+}
+
+#[test]
+fn occurrence_chains() {
+    for inline in [false, true] {
+        let mut builder = fixture();
+        let scr_empty = builder.string_id("scr_empty");
+        // push.v self.x; pop.v.v self.x; call.i scr_empty(argc=0)
+        builder.code("gml_Script_scr_move", &[
+            0xff, 0xff, 0x05, 0xc0, 0x00, 0x00, 0x00, 0xa0,
+            0xff, 0xff, 0x55, 0x45, 0x00, 0x00, 0x00, 0xa0,
+            0x00, 0x00, 0x02, 0xd9, scr_empty as u8, 0x00, 0x00, 0x00,
+        ], 0, 0);
+        builder.variable("score", -5, &[]);
+        builder.variable("x", -1, &[(0, 4), (2, 0), (2, 8)]);
+        builder.function("scr_empty", &[(2, 16)]);
+        if inline {
+            builder.inline_code();
+        }
+        let data = builder.build();
+        assert_eq!(gm_data_win::verify_roundtrip(&data).unwrap(), None);
+
+        let file = FormFile::from_sections(gm_data_win::take_data_win_file(&data));
+        let strg = file.strg.as_ref().unwrap();
+        let x = &file.variables()[1];
+        assert_eq!(strg.get(x.name_offset).unwrap(), "x");
+        assert_eq!((x.instance_type, x.id, x.occurrences), if inline { (0, 0, 3) } else { (-1, 1, 3) });
+        assert_eq!(file.vari.as_ref().unwrap().header.is_some(), !inline);
+
+        let occurrences = file.variable_occurrences(1)
+            .iter()
+            .map(|occurrence| (occurrence.code_entry, occurrence.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(occurrences, [(0, 4), (2, 0), (2, 8)]);
+        assert_eq!(file.variable_occurrences(1)[0].address, x.first_address);
+        assert!(file.variable_occurrences(0).is_empty());
+
+        let function = &file.functions()[0];
+        assert_eq!(strg.get(function.name_offset).unwrap(), "scr_empty");
+        let calls = file.function_occurrences(0)
+            .iter()
+            .map(|occurrence| (occurrence.code_entry, occurrence.offset))
+            .collect::<Vec<_>>();
+        assert_eq!(calls, [(2, 16)]);
+    }
+}
+
+#[test]
+fn decompile() {
+    let (builder, _) = scripted_fixture();
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+    let listing = "\
+        push.v self.x\npushi.e 5\ncmp.i.v GT\nbf [0]\npushi.e 0\npop.v.i self.x\nb [1]\n\
+        :[0]\npush.v self.x\npushi.e 2\nmul.i.v\npushi.e 1\nadd.i.v\npop.v.v self.y\n:[1]\n\
+        pushloc.v local.i\npushi.e 10\ncmp.i.v LT\nbf [2]\n\
+        pushloc.v local.i\npushi.e 1\nadd.i.v\npop.v.v local.i\nb [1]\n:[2]\n\
+        pushi.e 0\npushenv [4]\n:[3]\npushi.e 1\npop.v.i self.x\n:[4]\npopenv [3]\n\
+        push.v self.x\ndup.v\npushi.e 1\ncmp.i.v EQ\nbt [5]\nb [6]\n\
+        :[5]\npushi.e 2\npop.v.i self.y\nb [7]\n:[6]\npushi.e 3\npop.v.i self.y\nb [7]\n:[7]\npopz.v\n\
+        push.s \"hi\"\nconv.s.v\ncall.i show_message(argc=1)\npopz.v\n\
+        pushi.e 3\ndup.i\npushi.e 0\ncmp.i.i LE\nbt [9]\n\
+        :[8]\npush.v self.x\npushi.e 1\nadd.i.v\npop.v.v self.x\n\
+        pushi.e 1\nsub.i.i\ndup.i\nconv.i.b\nbt [8]\n:[9]\npopz.i\n\
+        :[10]\npush.v self.x\npushi.e 1\nsub.i.v\npop.v.v self.x\npush.v self.x\npushi.e 0\ncmp.i.v LE\nbf [10]\n\
+        push.v self.a\nconv.v.b\nbf [11]\npush.v self.b\nconv.v.b\nb [12]\n:[11]\npushi.e 0\n:[12]\n\
+        conv.b.v\nret.v\n";
+    gm_data_win::asm::assemble_into(&mut file, 2, listing).unwrap();
+
+    assert_eq!(gm_data_win::decompile::decompile(&file, 2).unwrap(), "\
+var i;
+if (x > 5) {
+    x = 0;
+} else {
+    y = x * 2 + 1;
+}
+while (i < 10) {
+    i = i + 1;
+}
+with (obj_player) {
+    x = 1;
+}
+switch (x) {
+    case 1:
+        y = 2;
+        break;
+    default:
+        y = 3;
+        break;
+}
+show_message(\"hi\");
+repeat (3) {
+    x = x + 1;
+}
+do {
+    x = x - 1;
+} until (x <= 0);
+return a && b;
+");
+
+    // anything unrecognised is kept as disassembly
+    gm_data_win::asm::assemble_into(&mut file, 1, "b [0]\npushi.e 1\n:[0]\nexit.i\n").unwrap();
+    assert_eq!(
+        gm_data_win::decompile::decompile(&file, 1).unwrap(),
+        "// Failed to decompile: unstructured branch at 0x0\n// b [0]\n// pushi.e 1\n// :[0]\n// exit.i\n"
+    );
+    // including switch cases running into the end of the entry
+    gm_data_win::asm::assemble_into(&mut file, 1, "push.v self.x\ndup.v\npushi.e 1\ncmp.i.v EQ\nbt [0]\n:[0]\n").unwrap();
+    assert!(gm_data_win::decompile::decompile(&file, 1).unwrap()
+        .starts_with("// Failed to decompile: expected a branch after the switch cases at 0x18\n"));
+    assert_eq!(gm_data_win::decompile::decompile(&file, 3).unwrap_err().to_string(), "CODE has no entry 3");
+}
+
+#[test]
+fn compile() {
+    let (builder, _) = scripted_fixture();
+    let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+
+    // compiled code decompiles back to the same source
+    let source = "\
+var i;
+if (x > 5) {
+    x = 0;
+} else {
+    y = x * 2 + 1;
+}
+while (i < 10) {
+    i = i + 1;
+}
+with (obj_player) {
+    x = 1;
+}
+switch (x) {
+    case 1:
+        y = 2;
+        break;
+    default:
+        y = 3;
+        break;
+}
+show_message(\"hi\");
+repeat (3) {
+    x = x + 1;
+}
+do {
+    x = x - 1;
+} until (x <= 0);
+return a && b;
+";
+    gm_data_win::compile::compile_into(&mut file, 2, source).unwrap();
+    let file = FormFile::from_sections(gm_data_win::take_data_win_file(&file.to_bytes().unwrap()));
+    assert_eq!(gm_data_win::decompile::decompile(&file, 2).unwrap(), source);
+    assert_eq!(file.code_entries()[2].locals_count, 1);
+
+    let mut file = file;
+    let error = gm_data_win::compile::compile_into(&mut file, 2, "x = 1;\nif (x > ) {}").unwrap_err();
+    assert_eq!(error.to_string(), "line 2, column 9: expected an expression, found ')'");
+    let error = gm_data_win::compile::compile_into(&mut file, 2, "x += 1;\nbreak;").unwrap_err();
+    assert_eq!((error.line, error.column), (2, 1));
+    // strings of code that didn't compile aren't left in STRG
+    let strings = file.strg.as_ref().unwrap().strings.len();
+    gm_data_win::compile::compile_into(&mut file, 2, "s = \"unused\";\nbreak;").unwrap_err();
+    assert_eq!(file.strg.as_ref().unwrap().strings.len(), strings);
+    let mut stripped = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+    stripped.strg = None;
+    let error = gm_data_win::compile::compile_into(&mut stripped, 2, "x = 1;\n").unwrap_err();
+    assert_eq!(error.to_string(), "no STRG section");
+
+    // arrays and other instances are laid out differently in GMS1
+    for inline in [false, true] {
+        let mut builder = fixture();
+        if inline {
+            builder.inline_code();
+        }
+        let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+        let source = "a[2] = 3;\nglobal.b = a[1] + obj_player.c;\nother.d += 1;\n";
+        gm_data_win::compile::compile_into(&mut file, 1, source).unwrap();
+        let file = FormFile::from_sections(gm_data_win::take_data_win_file(&file.to_bytes().unwrap()));
+        assert_eq!(
+            gm_data_win::decompile::decompile(&file, 1).unwrap(),
+            "a[2] = 3;\nglobal.b = a[1] + obj_player.c;\nother.d = other.d + 1;\n"
+        );
+    }
+
+    // the bytecode version in GEN8 wins over the one CODE's layout suggests, for compiling
+    // and reading the code back alike
+    for (version, layout, read) in [(14, CodeLayout::Inline, "push.v global.b"), (16, CodeLayout::Shared, "pushglb.v global.b")] {
+        let mut builder = fixture();
+        builder.bytecode_version(version);
+        let mut file = FormFile::from_sections(gm_data_win::take_data_win_file(&builder.build()));
+        assert_eq!(file.code.as_ref().unwrap().layout, CodeLayout::Shared);
+        assert_eq!(file.bytecode_layout(), layout);
+        gm_data_win::compile::compile_into(&mut file, 1, "x = global.b;\n").unwrap();
+        assert!(gm_data_win::disasm::disassemble(&file, 1).unwrap().starts_with(read));
+        assert_eq!(gm_data_win::decompile::decompile(&file, 1).unwrap(), "x = global.b;\n");
+    }
 }